@@ -1,16 +1,27 @@
-use clap::{value_parser, Arg, ArgMatches, Command};
-use hf_hub::api::sync::Api;
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command, ValueEnum};
+use half::{bf16, f16};
 use ndarray::Axis;
 use ndarray_stats::QuantileExt;
-use ratchet::{shape, Device, DeviceRequest, Tensor};
-use ratchet_loader::gguf::gguf::{self, Header};
-use ratchet_models::registry::{AvailableModels, Quantization, WhisperVariants as RegistryWhisper};
+use ratchet::{quantize, shape, Device, DeviceRequest, Shape, Tensor, Q8_0F};
+use ratchet_loader::gguf::gguf::{Header, TensorInfo, UnknownTensorInfo, Value};
+use ratchet_loader::gguf::{ratchet_to_gguf, LoadProgress, WeightLoader, Weights, Writer};
+use ratchet_loader::safetensors::{Safetensors, SafetensorsDType};
+use ratchet_loader::{GgmlDType, MappedFile};
+use ratchet_models::gemma::Gemma;
+use ratchet_models::llama::{HfLlama, Llama};
+use ratchet_models::registry::{
+    AvailableModels, EmbedderVariants, GemmaVariants, LlamaVariants, PhiVariants, Quantization,
+    WhisperVariants as RegistryWhisper,
+};
+use ratchet_models::store::{resources, ModelStore, Resource};
 use ratchet_models::whisper::options::DecodingOptionsBuilder;
 use ratchet_models::whisper::transcribe::transcribe;
 use ratchet_models::{phi2::Phi2, whisper::Whisper};
 use ratchet_nn::Module;
-use std::io::Write;
-use std::path::Path;
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
 use std::process::Command as TermCommand;
 use tokenizers::Tokenizer;
 
@@ -76,21 +87,20 @@ pub fn start_logger() {
     }
 }
 
-fn handle_whisper(matches: &ArgMatches, api: Api) {
-    let quantization = matches
-        .get_one::<Quantization>("quantization")
-        .unwrap_or(&Quantization::Q8_0);
-
+fn handle_whisper(matches: &ArgMatches, store: &mut ModelStore) {
     let mut whisper = if let Some(variant) = matches.get_one::<RegistryWhisper>("variant") {
         let model = AvailableModels::Whisper(variant.clone());
-        let repo = api.model(model.repo_id());
-        let model_path = repo.get(&model.model_id(quantization.clone())).unwrap();
+        let quantization = matches
+            .get_one::<Quantization>("quantization")
+            .cloned()
+            .unwrap_or_else(|| model.default_quantization());
+        let weights = Resource::new(model.repo_id(), model.model_id(quantization));
+        let model_path = store.fetch(&weights).unwrap();
         println!("MODEL PATH: {}", model_path.display());
 
-        let mut reader = std::io::BufReader::new(std::fs::File::open(model_path).unwrap());
         let device = Device::request_device(DeviceRequest::GPU).unwrap();
-        let header = gguf::Header::read(&mut reader).unwrap();
-        Whisper::load(header, variant.clone(), &mut reader, device).unwrap()
+        let (header, mut weights) = load_weights(&model_path, &device).unwrap();
+        Whisper::load(header, variant.clone(), &mut weights, device).unwrap()
     } else {
         panic!("Model not found");
     };
@@ -106,18 +116,21 @@ fn handle_whisper(matches: &ArgMatches, api: Api) {
     };
 }
 
-fn handle_phi2(matches: &ArgMatches, api: Api) -> anyhow::Result<()> {
+fn handle_phi2(matches: &ArgMatches, store: &mut ModelStore) -> anyhow::Result<()> {
     let _ = env_logger::builder().is_test(true).try_init();
-    let model_repo = api.model("FL33TW00D-HF/phi2".to_string());
-    let model_path = model_repo.get("phi2-q8_0.gguf").unwrap();
+    let model = AvailableModels::Phi(PhiVariants::Phi2);
+    let model_path = store.fetch(&Resource::new(
+        model.repo_id(),
+        model.model_id(Quantization::Q8_0),
+    ))?;
     println!("MODEL PATH: {}", model_path.display());
-    let mut reader = std::io::BufReader::new(std::fs::File::open(model_path)?);
     let device = Device::request_device(DeviceRequest::GPU)?;
-    let content = Header::read(&mut reader)?;
-    let mut model = Phi2::load(content, &mut reader, &device)?;
+    let (header, mut weights) = load_weights(&model_path, &device)?;
+    let tokenizer_repo = model.tokenizer_repo();
+    let mut model = Phi2::load(header, &mut weights, &device)?;
 
-    let tokenizer =
-        Tokenizer::from_file(concat!("../../", "/models/microsoft/phi-2/tokenizer.json")).unwrap();
+    let tokenizer_path = store.fetch(&Resource::new(tokenizer_repo, "tokenizer.json"))?;
+    let tokenizer = Tokenizer::from_file(tokenizer_path).map_err(anyhow::Error::msg)?;
 
     let prompt = if let Some(prompt) = matches.get_one::<String>("prompt") {
         prompt
@@ -166,9 +179,816 @@ fn handle_phi2(matches: &ArgMatches, api: Api) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Resolves the GGUF to load, preferring a local `--model` path over the model store.
+fn model_path(
+    matches: &ArgMatches,
+    store: &mut ModelStore,
+    model: AvailableModels,
+) -> anyhow::Result<PathBuf> {
+    if let Some(path) = matches.get_one::<String>("model") {
+        return Ok(PathBuf::from(path));
+    }
+    let quantization = matches
+        .get_one::<Quantization>("quantization")
+        .cloned()
+        .unwrap_or_else(|| model.default_quantization());
+    store.fetch(&Resource::new(
+        model.repo_id(),
+        model.model_id(quantization),
+    ))
+}
+
+/// Load every weight of a GGUF file in parallel, drawing a progress bar on stderr.
+fn load_weights(path: &Path, device: &Device) -> anyhow::Result<(Header, Weights)> {
+    let file = MappedFile::open(path)?;
+    let header = Header::read(&mut file.reader())?;
+    let weights = WeightLoader::new(&header, &file)
+        .with_progress(print_load_progress)
+        .load(device)?;
+    eprintln!();
+    Ok((header, weights))
+}
+
+fn print_load_progress(progress: LoadProgress) {
+    const WIDTH: usize = 40;
+    let LoadProgress {
+        loaded_bytes,
+        total_bytes,
+    } = progress;
+    let fraction = loaded_bytes as f64 / total_bytes.max(1) as f64;
+    let filled = (fraction * WIDTH as f64) as usize;
+    eprint!(
+        "\rLoading weights [{}{}] {:>3.0}% ({}/{} MiB)",
+        "#".repeat(filled),
+        " ".repeat(WIDTH - filled),
+        fraction * 100.0,
+        loaded_bytes >> 20,
+        total_bytes >> 20
+    );
+}
+
+fn load_tokenizer(
+    matches: &ArgMatches,
+    store: &mut ModelStore,
+    tokenizer_repo: &str,
+) -> anyhow::Result<Tokenizer> {
+    let tokenizer_path = match matches.get_one::<String>("tokenizer") {
+        Some(path) => PathBuf::from(path),
+        None => store.fetch(&Resource::new(tokenizer_repo, "tokenizer.json"))?,
+    };
+    Tokenizer::from_file(tokenizer_path).map_err(anyhow::Error::msg)
+}
+
+/// Greedy decoding, printing tokens as they are generated.
+///
+/// `step` runs the model on the provided tokens and returns the logits on the CPU.
+fn greedy_decode(
+    matches: &ArgMatches,
+    tokenizer: &Tokenizer,
+    eos: Option<i32>,
+    device: &Device,
+    mut step: impl FnMut(Tensor, usize) -> anyhow::Result<Tensor>,
+) -> anyhow::Result<()> {
+    let prompt = matches.get_one::<String>("prompt").unwrap();
+    let max_tokens = matches.get_one::<usize>("max-tokens").unwrap();
+
+    let encoding = tokenizer.encode(prompt.as_str(), true).unwrap();
+    let mut tokens = encoding
+        .get_ids()
+        .iter()
+        .map(|&x| x as i32)
+        .collect::<Vec<_>>();
+
+    print!("{}", prompt);
+    std::io::stdout().flush().unwrap();
+    let mut all_tokens = tokens.clone();
+    let mut loop_cnt = 0;
+    let start_time = std::time::Instant::now();
+    while Some(tokens[tokens.len() - 1]) != eos && loop_cnt < *max_tokens {
+        let input = Tensor::from_data(tokens.clone(), shape![1, tokens.len()], device.clone());
+        let logits = step(input, tokens.len())?;
+
+        tokens = logits
+            .to_ndarray_view::<f32>()
+            .map_axis(Axis(2), |row| row.argmax_skipnan().unwrap())
+            .iter()
+            .map(|&x| x as i32)
+            .collect::<Vec<_>>();
+        let u32_toks = tokens.iter().map(|&x| x as u32).collect::<Vec<_>>();
+        print!("{}", tokenizer.decode(&u32_toks, true).unwrap());
+        std::io::stdout().flush().unwrap();
+        all_tokens.extend(tokens.clone());
+        loop_cnt += 1;
+    }
+    let elapsed = start_time.elapsed();
+    println!("\nElapsed time: {:?}", elapsed);
+    println!(
+        "tok/sec: {}",
+        all_tokens.len() as f64 / elapsed.as_secs_f64()
+    );
+    Ok(())
+}
+
+fn handle_llama(matches: &ArgMatches, store: &mut ModelStore) -> anyhow::Result<()> {
+    let variant = matches.get_one::<LlamaVariants>("variant").unwrap();
+    let model_path = model_path(matches, store, AvailableModels::Llama(variant.clone()))?;
+    println!("MODEL PATH: {}", model_path.display());
+
+    let device = Device::request_device(DeviceRequest::GPU)?;
+    let (header, mut weights) = load_weights(&model_path, &device)?;
+    let mut model = Llama::load(header, &mut weights, &device)?;
+    let tokenizer = load_tokenizer(matches, store, variant.tokenizer_repo())?;
+
+    let eos = model.config.eos_token_id.map(|t| t as i32);
+    greedy_decode(matches, &tokenizer, eos, &device, |input, n_tokens| {
+        let result = model.schedule(input)?.full()?.resolve()?;
+        let logits = result.to(&Device::CPU)?;
+        model.cache_mut().update(n_tokens);
+        Ok(logits)
+    })
+}
+
+fn handle_gemma(matches: &ArgMatches, store: &mut ModelStore) -> anyhow::Result<()> {
+    let variant = matches.get_one::<GemmaVariants>("variant").unwrap();
+    let model_path = model_path(matches, store, AvailableModels::Gemma(variant.clone()))?;
+    println!("MODEL PATH: {}", model_path.display());
+
+    let device = Device::request_device(DeviceRequest::GPU)?;
+    let (header, mut weights) = load_weights(&model_path, &device)?;
+    let mut model = Gemma::load(header, &mut weights, &device)?;
+    let tokenizer = load_tokenizer(matches, store, variant.tokenizer_repo())?;
+
+    let eos = model.config.eos_token_id.map(|t| t as i32);
+    greedy_decode(matches, &tokenizer, eos, &device, |input, n_tokens| {
+        let result = model.schedule(input)?.full()?.resolve()?;
+        let logits = result.to(&Device::CPU)?;
+        model.cache_mut().update(n_tokens);
+        Ok(logits)
+    })
+}
+
+/// A tensor to convert, `ggml_dtype` is the encoding returned by the source's [ReadFn].
+struct ConvertEntry {
+    name: String,
+    shape: Shape,
+    ggml_dtype: GgmlDType,
+}
+
+type ReadFn = Box<dyn FnMut(&str) -> anyhow::Result<Vec<u8>>>;
+
+/// Opens a GGUF, `.safetensors` or sharded `model.safetensors.index.json` for conversion.
+///
+/// Safetensors tensors are renamed to their GGUF names, so the returned entries and `ReadFn`
+/// use GGUF names.
+fn convert_source(
+    input: &str,
+) -> anyhow::Result<(Vec<(String, Value)>, Vec<ConvertEntry>, ReadFn)> {
+    if Path::new(input)
+        .extension()
+        .is_some_and(|ext| ext == "gguf")
+    {
+        let mut reader = BufReader::new(std::fs::File::open(input)?);
+        let header = Header::read(&mut reader)?;
+        if let Some((name, unknown)) = header.unknown_tensors.iter().next() {
+            anyhow::bail!(
+                "Cannot convert {name}, its GGML type id {} is unknown",
+                unknown.dtype_id
+            );
+        }
+        let mut metadata = header
+            .metadata
+            .iter()
+            .filter(|(key, _)| *key != "general.alignment")
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect::<Vec<_>>();
+        metadata.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        let mut infos = header.tensor_infos.iter().collect::<Vec<_>>();
+        infos.sort_by_key(|(_, info)| info.offset);
+        let entries = infos
+            .into_iter()
+            .map(|(name, info)| ConvertEntry {
+                name: name.clone(),
+                shape: info.shape.clone(),
+                ggml_dtype: info.ggml_dtype,
+            })
+            .collect();
+        let read = move |name: &str| {
+            header.tensor_infos[name].read_bytes(&mut reader, header.tensor_data_offset)
+        };
+        return Ok((metadata, entries, Box::new(read)));
+    }
+
+    //Safetensors only hold tensors, the architecture & hyperparameters are in `config.json`
+    let config = Path::new(input)
+        .parent()
+        .unwrap_or(Path::new("."))
+        .join("config.json");
+    let json = std::fs::read_to_string(&config).map_err(|e| {
+        anyhow::anyhow!(
+            "converting safetensors needs the config.json of the checkpoint, failed to read {}: {e}",
+            config.display()
+        )
+    })?;
+    let hf = HfLlama::from_json(&json)?;
+    let metadata = hf.metadata()?;
+
+    let mut st = Safetensors::open(input)?;
+    let mut dtypes = HashMap::new();
+    let mut names = HashMap::new();
+    let mut entries = vec![];
+    for (name, info) in st.tensor_infos() {
+        let Some(gguf_name) = hf.tensor_name(name)? else {
+            continue;
+        };
+        //BF16 has no GGUF equivalent, it is widened to F32 on read
+        let ggml_dtype = match info.dtype {
+            SafetensorsDType::F32 | SafetensorsDType::BF16 => GgmlDType::F32,
+            SafetensorsDType::F16 => GgmlDType::F16,
+            dt => anyhow::bail!("tensor {name} has unsupported dtype {dt:?}"),
+        };
+        dtypes.insert(gguf_name.clone(), info.dtype);
+        names.insert(gguf_name.clone(), name.clone());
+        entries.push(ConvertEntry {
+            name: gguf_name,
+            shape: info.shape(),
+            ggml_dtype,
+        });
+    }
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    let read = move |name: &str| {
+        let raw = st.tensor_bytes(&names[name])?;
+        let raw = match dtypes[name] {
+            SafetensorsDType::BF16 => {
+                let widened = bytemuck::pod_collect_to_vec::<u8, bf16>(&raw)
+                    .into_iter()
+                    .map(bf16::to_f32)
+                    .collect::<Vec<_>>();
+                bytemuck::cast_slice(&widened).to_vec()
+            }
+            _ => raw,
+        };
+        hf.permute(name, raw)
+    };
+    Ok((metadata, entries, Box::new(read)))
+}
+
+/// Output type for a tensor, already quantized tensors & vectors (norms, biases) are kept as is.
+fn convert_dtype(entry: &ConvertEntry, quantization: &Quantization) -> GgmlDType {
+    let is_float = matches!(entry.ggml_dtype, GgmlDType::F32 | GgmlDType::F16);
+    if !is_float || entry.shape.rank() < 2 {
+        return entry.ggml_dtype;
+    }
+    //Q8_0 blocks may not straddle rows
+    let row_numel = entry.shape[entry.shape.rank() - 1];
+    match quantization {
+        Quantization::Q8_0 if row_numel % GgmlDType::Q8_0.block_numel() == 0 => GgmlDType::Q8_0,
+        Quantization::Q8_0 => entry.ggml_dtype,
+        Quantization::F16 => GgmlDType::F16,
+        Quantization::F32 => GgmlDType::F32,
+    }
+}
+
+fn convert_tensor(
+    entry: &ConvertEntry,
+    raw: Vec<u8>,
+    dst_dtype: GgmlDType,
+) -> anyhow::Result<Vec<u8>> {
+    if entry.ggml_dtype == dst_dtype {
+        return Ok(raw);
+    }
+    let data = match entry.ggml_dtype {
+        GgmlDType::F32 => bytemuck::pod_collect_to_vec::<u8, f32>(&raw),
+        GgmlDType::F16 => bytemuck::pod_collect_to_vec::<u8, f16>(&raw)
+            .into_iter()
+            .map(f16::to_f32)
+            .collect(),
+        dt => anyhow::bail!("cannot convert from {dt:?}"),
+    };
+    match dst_dtype {
+        GgmlDType::F32 => Ok(bytemuck::cast_slice(&data).to_vec()),
+        GgmlDType::F16 => {
+            let half = data.into_iter().map(f16::from_f32).collect::<Vec<_>>();
+            Ok(bytemuck::cast_slice(&half).to_vec())
+        }
+        GgmlDType::Q8_0 => {
+            let tensor = Tensor::from_data(data, entry.shape.clone(), Device::CPU);
+            let (_, bytes) = ratchet_to_gguf(&quantize::<Q8_0F>(&tensor))?;
+            Ok(bytes)
+        }
+        dt => anyhow::bail!("cannot convert to {dt:?}"),
+    }
+}
+
+/// Converts an F32/F16 GGUF or safetensors checkpoint into a Ratchet-ready GGUF, tensor by
+/// tensor. GGUF names & metadata are copied verbatim. Safetensors checkpoints of the Llama
+/// family are mapped to the GGUF layout using their `config.json`, other architectures are
+/// rejected.
+fn handle_convert(matches: &ArgMatches) -> anyhow::Result<()> {
+    let input = matches.get_one::<String>("input").unwrap();
+    let output = matches.get_one::<String>("output").unwrap();
+    let quantization = matches.get_one::<Quantization>("quantization").unwrap();
+
+    let (metadata, entries, mut read) = convert_source(input)?;
+    let mut writer = Writer::new(BufWriter::new(std::fs::File::create(output)?));
+    for (key, value) in metadata {
+        writer.add_metadata(key, value);
+    }
+    //llama.cpp file types, ALL_F32 = 0, MOSTLY_F16 = 1, MOSTLY_Q8_0 = 7
+    let file_type = match quantization {
+        Quantization::F32 => 0,
+        Quantization::F16 => 1,
+        Quantization::Q8_0 => {
+            writer.add_metadata("general.quantization_version", Value::U32(2));
+            7
+        }
+    };
+    writer.add_metadata("general.file_type", Value::U32(file_type));
+
+    let dst_dtypes = entries
+        .iter()
+        .map(|entry| convert_dtype(entry, quantization))
+        .collect::<Vec<_>>();
+    for (entry, dst_dtype) in entries.iter().zip(&dst_dtypes) {
+        writer.add_tensor_info(entry.name.as_str(), *dst_dtype, entry.shape.clone())?;
+    }
+    for (entry, dst_dtype) in entries.iter().zip(dst_dtypes) {
+        println!("{}: {:?} -> {dst_dtype:?}", entry.name, entry.ggml_dtype);
+        let raw = read(&entry.name)?;
+        writer.write_tensor_data(&entry.name, &convert_tensor(entry, raw, dst_dtype)?)?;
+    }
+    writer.finish()?;
+    println!("Wrote {output}");
+    Ok(())
+}
+
+fn read_header(path: &str) -> anyhow::Result<Header> {
+    let mut reader = BufReader::new(std::fs::File::open(path)?);
+    Header::read(&mut reader).map_err(|e| anyhow::anyhow!("failed to read {path}: {e}"))
+}
+
+fn value_to_json(value: &Value) -> serde_json::Value {
+    match value {
+        Value::U8(v) => json!(v),
+        Value::I8(v) => json!(v),
+        Value::U16(v) => json!(v),
+        Value::I16(v) => json!(v),
+        Value::U32(v) => json!(v),
+        Value::I32(v) => json!(v),
+        Value::U64(v) => json!(v),
+        Value::I64(v) => json!(v),
+        Value::F32(v) => json!(v),
+        Value::F64(v) => json!(v),
+        Value::Bool(v) => json!(v),
+        Value::String(v) => json!(v),
+        Value::Array(values) => values.iter().map(value_to_json).collect(),
+    }
+}
+
+/// Human readable value, long arrays (e.g. vocabularies) are truncated.
+fn fmt_value(value: &Value) -> String {
+    const MAX_ITEMS: usize = 8;
+    match value {
+        Value::Array(values) => {
+            let items = values
+                .iter()
+                .take(MAX_ITEMS)
+                .map(fmt_value)
+                .collect::<Vec<_>>()
+                .join(", ");
+            if values.len() > MAX_ITEMS {
+                format!("[{items}, ... ({} items)]", values.len())
+            } else {
+                format!("[{items}]")
+            }
+        }
+        value => value_to_json(value).to_string(),
+    }
+}
+
+/// A tensor listed in a GGUF header, which may have a GGML type we don't know.
+struct InspectTensor {
+    dtype: String,
+    shape: Vec<usize>,
+    offset: u64,
+    /// `None` when the GGML type is unknown, as its block size is too.
+    bytes: Option<usize>,
+    supported: bool,
+}
+
+impl From<&TensorInfo> for InspectTensor {
+    fn from(info: &TensorInfo) -> Self {
+        Self {
+            dtype: format!("{:?}", info.ggml_dtype),
+            shape: info.shape.to_vec(),
+            offset: info.offset,
+            bytes: Some(info.size_in_bytes()),
+            supported: info.ggml_dtype.is_supported(),
+        }
+    }
+}
+
+impl From<&UnknownTensorInfo> for InspectTensor {
+    fn from(info: &UnknownTensorInfo) -> Self {
+        Self {
+            dtype: format!("Unknown({})", info.dtype_id),
+            shape: info.shape.to_vec(),
+            offset: info.offset,
+            bytes: None,
+            supported: false,
+        }
+    }
+}
+
+fn fmt_tensor(tensor: &InspectTensor) -> String {
+    format!("{} {:?}", tensor.dtype, tensor.shape)
+}
+
+fn header_tensors(header: &Header) -> BTreeMap<&String, InspectTensor> {
+    let known = header
+        .tensor_infos
+        .iter()
+        .map(|(n, i)| (n, InspectTensor::from(i)));
+    let unknown = header
+        .unknown_tensors
+        .iter()
+        .map(|(n, i)| (n, InspectTensor::from(i)));
+    known.chain(unknown).collect()
+}
+
+fn sorted_metadata(header: &Header) -> BTreeMap<&String, &Value> {
+    header.metadata.iter().collect()
+}
+
+fn sorted_tensors(header: &Header) -> Vec<(&String, InspectTensor)> {
+    let mut tensors = header_tensors(header).into_iter().collect::<Vec<_>>();
+    tensors.sort_by_key(|(_, tensor)| tensor.offset);
+    tensors
+}
+
+#[derive(Default)]
+struct DTypeStats {
+    tensors: usize,
+    params: usize,
+    bytes: usize,
+}
+
+/// Bytes of tensors with an unknown GGML type are not counted.
+fn dtype_stats(header: &Header) -> BTreeMap<String, DTypeStats> {
+    let mut stats = BTreeMap::<String, DTypeStats>::new();
+    for tensor in header_tensors(header).into_values() {
+        let entry = stats.entry(tensor.dtype).or_default();
+        entry.tensors += 1;
+        entry.params += tensor.shape.iter().product::<usize>();
+        entry.bytes += tensor.bytes.unwrap_or(0);
+    }
+    stats
+}
+
+/// Tensors Ratchet cannot load, as their [GgmlDType] is unsupported or unknown.
+fn unsupported_tensors(header: &Header) -> Vec<(&String, InspectTensor)> {
+    sorted_tensors(header)
+        .into_iter()
+        .filter(|(_, tensor)| !tensor.supported)
+        .collect()
+}
+
+fn inspect_json(header: &Header) -> serde_json::Value {
+    let metadata = sorted_metadata(header)
+        .into_iter()
+        .map(|(key, value)| (key.clone(), value_to_json(value)))
+        .collect::<serde_json::Map<_, _>>();
+    let tensors = sorted_tensors(header)
+        .into_iter()
+        .map(|(name, tensor)| {
+            json!({
+                "name": name,
+                "dtype": tensor.dtype,
+                "shape": tensor.shape,
+                "offset": tensor.offset,
+                "params": tensor.shape.iter().product::<usize>(),
+                "bytes": tensor.bytes,
+            })
+        })
+        .collect::<Vec<_>>();
+    let stats = dtype_stats(header);
+    let dtypes = stats
+        .iter()
+        .map(|(dtype, stats)| {
+            let stats = json!({
+                "tensors": stats.tensors,
+                "params": stats.params,
+                "bytes": stats.bytes,
+            });
+            (dtype.clone(), stats)
+        })
+        .collect::<serde_json::Map<_, _>>();
+    let unsupported = unsupported_tensors(header)
+        .into_iter()
+        .map(|(name, tensor)| json!({ "name": name, "dtype": tensor.dtype }))
+        .collect::<Vec<_>>();
+    json!({
+        "version": format!("{:?}", header.magic),
+        "tensor_data_offset": header.tensor_data_offset,
+        "metadata": metadata,
+        "tensors": tensors,
+        "dtypes": dtypes,
+        "total_params": stats.values().map(|s| s.params).sum::<usize>(),
+        "total_bytes": stats.values().map(|s| s.bytes).sum::<usize>(),
+        "unsupported": unsupported,
+    })
+}
+
+fn print_inspect(path: &str, header: &Header) {
+    println!("{path}: {:?}", header.magic);
+    println!("\nMetadata ({} keys):", header.metadata.iter().count());
+    for (key, value) in sorted_metadata(header) {
+        println!("  {key} = {}", fmt_value(value));
+    }
+
+    let tensors = sorted_tensors(header);
+    let width = tensors
+        .iter()
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or(0);
+    println!("\nTensors ({}):", tensors.len());
+    for (name, tensor) in &tensors {
+        let bytes = tensor.bytes.map_or("?".to_string(), |b| b.to_string());
+        println!(
+            "  {name:<width$}  {:<24}  offset {:>12}  {bytes:>12} bytes",
+            fmt_tensor(tensor),
+            tensor.offset,
+        );
+    }
+
+    let stats = dtype_stats(header);
+    println!(
+        "\n  {:<6}  {:>8}  {:>14}  {:>14}",
+        "dtype", "tensors", "params", "bytes"
+    );
+    for (dtype, s) in &stats {
+        println!(
+            "  {dtype:<6}  {:>8}  {:>14}  {:>14}",
+            s.tensors, s.params, s.bytes
+        );
+    }
+    println!(
+        "  {:<6}  {:>8}  {:>14}  {:>14}",
+        "total",
+        tensors.len(),
+        stats.values().map(|s| s.params).sum::<usize>(),
+        stats.values().map(|s| s.bytes).sum::<usize>()
+    );
+
+    let unsupported = unsupported_tensors(header);
+    if !unsupported.is_empty() {
+        println!("\nUnsupported by Ratchet ({}):", unsupported.len());
+        for (name, tensor) in unsupported {
+            println!("  {name}: {}", tensor.dtype);
+        }
+    }
+}
+
+/// Keys present in either map, paired with their values in `a` & `b`, where they differ.
+fn diff_maps<'a, V>(
+    a: BTreeMap<&'a String, V>,
+    b: BTreeMap<&'a String, V>,
+    eq: impl Fn(&V, &V) -> bool,
+) -> Vec<(&'a String, Option<V>, Option<V>)> {
+    let mut keys = a.keys().chain(b.keys()).copied().collect::<Vec<_>>();
+    keys.sort();
+    keys.dedup();
+    let (mut a, mut b) = (a, b);
+    keys.into_iter()
+        .filter_map(|key| {
+            let (va, vb) = (a.remove(key), b.remove(key));
+            match (&va, &vb) {
+                (Some(x), Some(y)) if eq(x, y) => None,
+                _ => Some((key, va, vb)),
+            }
+        })
+        .collect()
+}
+
+/// Compares metadata values, and tensor dtypes & shapes. Tensor offsets are ignored.
+fn handle_inspect_diff(a_path: &str, b_path: &str, json: bool) -> anyhow::Result<()> {
+    let (a, b) = (read_header(a_path)?, read_header(b_path)?);
+    let metadata = diff_maps(sorted_metadata(&a), sorted_metadata(&b), |x, y| {
+        value_to_json(x) == value_to_json(y)
+    });
+    let tensors = diff_maps(header_tensors(&a), header_tensors(&b), |x, y| {
+        x.dtype == y.dtype && x.shape == y.shape
+    });
+
+    if json {
+        let tensor_json = |tensor: &Option<InspectTensor>| {
+            tensor.as_ref().map(|tensor| {
+                json!({
+                    "dtype": tensor.dtype,
+                    "shape": tensor.shape,
+                })
+            })
+        };
+        let diff = json!({
+            "a": a_path,
+            "b": b_path,
+            "metadata": metadata.iter().map(|(key, va, vb)| json!({
+                "key": key,
+                "a": va.map(value_to_json),
+                "b": vb.map(value_to_json),
+            })).collect::<Vec<_>>(),
+            "tensors": tensors.iter().map(|(name, ta, tb)| json!({
+                "name": name,
+                "a": tensor_json(ta),
+                "b": tensor_json(tb),
+            })).collect::<Vec<_>>(),
+        });
+        println!("{}", serde_json::to_string_pretty(&diff)?);
+        return Ok(());
+    }
+
+    println!("--- {a_path}\n+++ {b_path}");
+    if metadata.is_empty() && tensors.is_empty() {
+        println!("Headers are identical");
+        return Ok(());
+    }
+    let print_diff = |key: &str, va: Option<String>, vb: Option<String>| match (va, vb) {
+        (Some(va), Some(vb)) => println!("~ {key}: {va} -> {vb}"),
+        (Some(va), None) => println!("- {key} = {va}"),
+        (None, Some(vb)) => println!("+ {key} = {vb}"),
+        (None, None) => unreachable!(),
+    };
+    if !metadata.is_empty() {
+        println!("\nMetadata ({} differences):", metadata.len());
+        for (key, va, vb) in metadata {
+            print_diff(key, va.map(fmt_value), vb.map(fmt_value));
+        }
+    }
+    if !tensors.is_empty() {
+        println!("\nTensors ({} differences):", tensors.len());
+        for (name, ta, tb) in tensors {
+            print_diff(
+                name,
+                ta.as_ref().map(fmt_tensor),
+                tb.as_ref().map(fmt_tensor),
+            );
+        }
+    }
+    Ok(())
+}
+
+fn handle_inspect(matches: &ArgMatches) -> anyhow::Result<()> {
+    let file = matches.get_one::<String>("file").unwrap();
+    let json = matches.get_flag("json");
+    if let Some(other) = matches.get_one::<String>("diff") {
+        return handle_inspect_diff(file, other, json);
+    }
+    let header = read_header(file)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&inspect_json(&header))?);
+    } else {
+        print_inspect(file, &header);
+    }
+    Ok(())
+}
+
+fn parse_variant<V: ValueEnum>(variant: Option<&String>, default: &str) -> anyhow::Result<V> {
+    let variant = variant.map(String::as_str).unwrap_or(default);
+    V::from_str(variant, true).map_err(|e| anyhow::anyhow!("invalid variant {variant}: {e}"))
+}
+
+/// Resolves a model family & optional variant, e.g `llama --variant qwen2-1.5b`.
+fn available_model(family: &str, variant: Option<&String>) -> anyhow::Result<AvailableModels> {
+    Ok(match family {
+        "whisper" => AvailableModels::Whisper(parse_variant(variant, "small")?),
+        "phi2" => AvailableModels::Phi(PhiVariants::Phi2),
+        "phi3" => AvailableModels::Phi(PhiVariants::Phi3),
+        "llama" => AvailableModels::Llama(parse_variant::<LlamaVariants>(variant, "llama3.2-1b")?),
+        "gemma" => AvailableModels::Gemma(parse_variant::<GemmaVariants>(variant, "gemma-2b")?),
+        "embedder" => {
+            AvailableModels::Embedder(parse_variant::<EmbedderVariants>(variant, "bge-small")?)
+        }
+        "moondream" => AvailableModels::Moondream,
+        other => anyhow::bail!("unknown model {other}"),
+    })
+}
+
+fn handle_models_list(store: &ModelStore, verify: bool) -> anyhow::Result<()> {
+    println!("Model store: {}", store.root().display());
+    let entries = store.entries().collect::<Vec<_>>();
+    if entries.is_empty() {
+        println!("No models stored, add one with `ratchet-cli models pull`.");
+        return Ok(());
+    }
+    let width = entries
+        .iter()
+        .map(|e| e.repo_id.len() + e.filename.len() + 1)
+        .max()
+        .unwrap_or(0);
+    let mut corrupt = 0;
+    for entry in &entries {
+        let name = format!("{}/{}", entry.repo_id, entry.filename);
+        let status = if !verify {
+            ""
+        } else if store
+            .verify(&Resource::new(&entry.repo_id, &entry.filename))
+            .is_ok()
+        {
+            "  ok"
+        } else {
+            corrupt += 1;
+            "  CHECKSUM MISMATCH"
+        };
+        println!(
+            "  {name:<width$}  {:>10} MiB  {}{status}",
+            entry.size >> 20,
+            &entry.sha256[..12],
+        );
+    }
+    println!(
+        "\n{} files, {} MiB",
+        entries.len(),
+        entries.iter().map(|e| e.size).sum::<u64>() >> 20
+    );
+    if corrupt > 0 {
+        anyhow::bail!(
+            "{corrupt} files failed verification, re-pull them with `ratchet-cli models pull`"
+        );
+    }
+    Ok(())
+}
+
+fn handle_models(matches: &ArgMatches, store: &mut ModelStore) -> anyhow::Result<()> {
+    match matches.subcommand() {
+        Some(("list", matches)) => handle_models_list(store, matches.get_flag("verify"))?,
+        Some(("pull", matches)) => {
+            let family = matches.get_one::<String>("model").unwrap();
+            let model = available_model(family, matches.get_one::<String>("variant"))?;
+            let quantization = matches
+                .get_one::<Quantization>("quantization")
+                .cloned()
+                .unwrap_or_else(|| model.default_quantization());
+            for resource in resources(&model, quantization) {
+                let path = store.fetch_verified(&resource)?;
+                println!("{}", path.display());
+            }
+        }
+        Some(("rm", matches)) => {
+            let repo_id = matches.get_one::<String>("repo").unwrap();
+            let file = matches.get_one::<String>("file").map(String::as_str);
+            let removed = store.remove(repo_id, file)?;
+            if removed.is_empty() {
+                anyhow::bail!("{repo_id} is not in the model store");
+            }
+            for entry in removed {
+                println!("Removed {}/{}", entry.repo_id, entry.filename);
+            }
+        }
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+/// Arguments shared by the decoder-only LLM subcommands.
+fn llm_args(cmd: Command) -> Command {
+    cmd.arg(
+        Arg::new("quantization")
+            .short('q')
+            .long("quantization")
+            .help("Model quantization to use, defaults to Q8_0.")
+            .value_parser(value_parser!(Quantization)),
+    )
+    .arg(
+        Arg::new("model")
+            .long("model")
+            .help("Path to a local GGUF file, overrides the variant."),
+    )
+    .arg(
+        Arg::new("tokenizer")
+            .long("tokenizer")
+            .help("Path to a local tokenizer.json."),
+    )
+    .arg(
+        Arg::new("prompt")
+            .short('p')
+            .long("prompt")
+            .required(true)
+            .help("Input prompt."),
+    )
+    .arg(
+        Arg::new("max-tokens")
+            .short('m')
+            .long("max-tokens")
+            .default_value("256")
+            .value_parser(value_parser!(usize))
+            .help("Maximum number of tokens to generate."),
+    )
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     env_logger::init();
-    let matches = Command::new("ratchet")
+    let matches = Command::new("ratchet-cli")
         .about("LLM & VLM CLI")
         .version("0.1.0")
         .subcommand_required(true)
@@ -190,8 +1010,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     Arg::new("quantization")
                         .short('q')
                         .long("quantization")
-                        .default_value("f32")
-                        .help("Model quantization to use.")
+                        .help("Model quantization to use, defaults to F32.")
                         .value_parser(value_parser!(Quantization)),
                 )
                 .arg(
@@ -223,13 +1042,167 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                         .help("Maximum number of tokens to generate."),
                 ),
         )
+        .subcommand(llm_args(
+            Command::new("llama")
+                .long_about(
+                    "Cross-platform, GPU accelerated implementation of the Llama model family (Llama, Mistral, Qwen2).",
+                )
+                .arg(
+                    Arg::new("variant")
+                        .short('v')
+                        .long("variant")
+                        .default_value("llama3.2-1b")
+                        .help("Model variant to use.")
+                        .value_parser(value_parser!(LlamaVariants)),
+                ),
+        ))
+        .subcommand(llm_args(
+            Command::new("gemma")
+                .long_about(
+                    "Cross-platform, GPU accelerated implementation of Google's Gemma & Gemma 2 models.",
+                )
+                .arg(
+                    Arg::new("variant")
+                        .short('v')
+                        .long("variant")
+                        .default_value("gemma-2b")
+                        .help("Model variant to use.")
+                        .value_parser(value_parser!(GemmaVariants)),
+                ),
+        ))
+        .subcommand(
+            Command::new("convert")
+                .visible_alias("quantize")
+                .long_about(
+                    "Convert an F32/F16 GGUF or safetensors checkpoint into a Ratchet-ready GGUF, quantizing the weights.",
+                )
+                .arg(
+                    Arg::new("input")
+                        .short('i')
+                        .long("input")
+                        .required(true)
+                        .help("Path to the input GGUF, or the .safetensors or model.safetensors.index.json of a Llama, Mistral or Qwen2 checkpoint, next to its config.json."),
+                )
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .required(true)
+                        .help("Path to write the converted GGUF to."),
+                )
+                .arg(
+                    Arg::new("quantization")
+                        .short('q')
+                        .long("quantization")
+                        .default_value("q8-0")
+                        .help("Type to convert F32/F16 weights to: q8-0, f16 or f32. Weights already in a GGML block type (Q4_0, Q6_K etc.) are copied as is.")
+                        .value_parser(value_parser!(Quantization)),
+                ),
+        )
+        .subcommand(
+            Command::new("inspect")
+                .long_about(
+                    "Inspect the metadata & tensors of a GGUF file, or diff the headers of two files.",
+                )
+                .arg(
+                    Arg::new("file")
+                        .required(true)
+                        .help("Path to the GGUF file."),
+                )
+                .arg(
+                    Arg::new("diff")
+                        .long("diff")
+                        .value_name("OTHER")
+                        .help("Compare the header against another GGUF file."),
+                )
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .action(ArgAction::SetTrue)
+                        .help("Print JSON instead of a human readable summary."),
+                ),
+        )
+        .subcommand(
+            Command::new("models")
+                .long_about(
+                    "Manage the local model store at RATCHET_HOME. Stored models run without network access, set RATCHET_OFFLINE=1 to enforce it.",
+                )
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("list")
+                        .about("List the stored files.")
+                        .arg(
+                            Arg::new("verify")
+                                .long("verify")
+                                .action(ArgAction::SetTrue)
+                                .help("Re-hash every file and check it against the recorded SHA-256."),
+                        ),
+                )
+                .subcommand(
+                    Command::new("pull")
+                        .about("Download a model, its tokenizer & auxiliary resources.")
+                        .arg(
+                            Arg::new("model")
+                                .required(true)
+                                .value_parser([
+                                    "whisper",
+                                    "phi2",
+                                    "phi3",
+                                    "llama",
+                                    "gemma",
+                                    "embedder",
+                                    "moondream",
+                                ])
+                                .help("Model family to pull."),
+                        )
+                        .arg(
+                            Arg::new("variant")
+                                .short('v')
+                                .long("variant")
+                                .help("Variant within the model family, e.g `small` or `qwen2-1.5b`."),
+                        )
+                        .arg(
+                            Arg::new("quantization")
+                                .short('q')
+                                .long("quantization")
+                                .help("Model quantization to pull, defaults to F32 for Whisper & Q8_0 otherwise.")
+                                .value_parser(value_parser!(Quantization)),
+                        ),
+                )
+                .subcommand(
+                    Command::new("rm")
+                        .about("Remove the stored files of a repository.")
+                        .arg(
+                            Arg::new("repo")
+                                .required(true)
+                                .help("Repository to remove, as shown by `ratchet-cli models list`."),
+                        )
+                        .arg(
+                            Arg::new("file")
+                                .help("Only remove this file from the repository."),
+                        ),
+                ),
+        )
         .get_matches();
 
-    let api = Api::new().unwrap();
+    if let Some(matches) = matches.subcommand_matches("inspect") {
+        handle_inspect(matches)?;
+        return Ok(());
+    }
+
+    let mut store = ModelStore::open()?;
     if let Some(matches) = matches.subcommand_matches("phi2") {
-        let _ = handle_phi2(matches, api);
+        let _ = handle_phi2(matches, &mut store);
     } else if let Some(matches) = matches.subcommand_matches("whisper") {
-        handle_whisper(matches, api);
+        handle_whisper(matches, &mut store);
+    } else if let Some(matches) = matches.subcommand_matches("llama") {
+        handle_llama(matches, &mut store)?;
+    } else if let Some(matches) = matches.subcommand_matches("gemma") {
+        handle_gemma(matches, &mut store)?;
+    } else if let Some(matches) = matches.subcommand_matches("models") {
+        handle_models(matches, &mut store)?;
+    } else if let Some(matches) = matches.subcommand_matches("convert") {
+        handle_convert(matches)?;
     }
 
     Ok(())
@@ -0,0 +1,47 @@
+//! Hashes the sources kernels are generated from, so cached WGSL is never reused across a
+//! change to a kernel, see `ShaderCache`.
+use std::path::{Path, PathBuf};
+
+/// Kernels are rendered by the crate, and by the metadata derive of `ratchet-macros`.
+const KERNEL_SOURCES: &[&str] = &["src", "../ratchet-macros/src"];
+
+fn collect(dir: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect(&path, files);
+        } else {
+            files.push(path);
+        }
+    }
+}
+
+/// FNV-1a, stable across toolchains unlike `DefaultHasher`.
+fn fnv1a(hash: &mut u64, bytes: &[u8]) {
+    for b in bytes {
+        *hash ^= *b as u64;
+        *hash = hash.wrapping_mul(0x100000001b3);
+    }
+}
+
+fn main() {
+    let mut files = vec![];
+    for dir in KERNEL_SOURCES {
+        println!("cargo:rerun-if-changed={}", dir);
+        collect(Path::new(dir), &mut files);
+    }
+    files.sort();
+
+    let mut hash = 0xcbf29ce484222325u64;
+    for file in files {
+        let Ok(contents) = std::fs::read(&file) else {
+            continue;
+        };
+        fnv1a(&mut hash, file.to_string_lossy().as_bytes());
+        fnv1a(&mut hash, &contents);
+    }
+    println!("cargo:rustc-env=RATCHET_KERNEL_HASH={:016x}", hash);
+}
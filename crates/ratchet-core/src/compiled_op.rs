@@ -15,6 +15,7 @@ pub struct CompiledOp {
     workgroup_count: WorkgroupCount,
     storage_groups: RVec<GpuBindGroup>,
     offset: DynamicOffset, //offset into the metadata uniform buffer
+    inplace: bool,
     pub kernel_key: KernelKey,
     #[cfg(feature = "debug")]
     pub debug_buffer: Option<Arc<wgpu::Buffer>>,
@@ -69,6 +70,10 @@ impl CompiledOp {
         self.offset
     }
 
+    pub fn inplace(&self) -> bool {
+        self.inplace
+    }
+
     pub fn storage_groups(&self) -> &RVec<GpuBindGroup> {
         &self.storage_groups
     }
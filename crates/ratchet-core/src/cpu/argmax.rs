@@ -0,0 +1,41 @@
+use crate::cpu::utils::cpu_store_result;
+use crate::{ArgMax, CPUOperation, DType, InvariantError, OperationError, Tensor, TensorDType};
+use half::{bf16, f16};
+
+impl CPUOperation for ArgMax {
+    fn apply_cpu(&self, dst: Tensor) -> Result<Tensor, OperationError> {
+        let ArgMax { input, dim } = self;
+        match input.dt() {
+            DType::F32 => argmax::<f32>(input, *dim, &dst)?,
+            DType::F16 => argmax::<f16>(input, *dim, &dst)?,
+            DType::BF16 => argmax::<bf16>(input, *dim, &dst)?,
+            dtype => Err(InvariantError::UnsupportedDType(dtype))?,
+        }
+
+        Ok(dst)
+    }
+}
+
+fn argmax<T>(input: &Tensor, dim: usize, dst: &Tensor) -> Result<(), OperationError>
+where
+    T: TensorDType + PartialOrd,
+{
+    let N = input.shape()[dim];
+    let input = input.to_vec::<T>()?;
+    let result = input
+        .chunks(N)
+        .map(|row| {
+            let mut best = 0;
+            for (i, val) in row.iter().enumerate() {
+                if *val > row[best] {
+                    best = i;
+                }
+            }
+            best as i32
+        })
+        .collect::<Vec<_>>();
+
+    cpu_store_result(dst, &result);
+
+    Ok(())
+}
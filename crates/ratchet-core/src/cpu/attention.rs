@@ -0,0 +1,103 @@
+use crate::cpu::utils::cpu_store_result;
+use crate::{
+    Attention, AttentionMask, CPUOperation, DType, InvariantError, OperationError, Tensor,
+    TensorDType,
+};
+use half::{bf16, f16};
+use num::Float;
+use num_traits::NumAssignOps;
+
+impl CPUOperation for Attention {
+    fn apply_cpu(&self, dst: Tensor) -> Result<Tensor, OperationError> {
+        match self.query.dt() {
+            DType::F32 => attention::<f32>(self, &dst)?,
+            DType::F16 => attention::<f16>(self, &dst)?,
+            DType::BF16 => attention::<bf16>(self, &dst)?,
+            dtype => Err(InvariantError::UnsupportedDType(dtype))?,
+        }
+        Ok(dst)
+    }
+}
+
+/// Reference implementation, accumulates in f32 to match the GPU kernel.
+fn attention<T>(op: &Attention, dst: &Tensor) -> Result<(), OperationError>
+where
+    T: TensorDType + Float + NumAssignOps,
+{
+    let [B, H, L, D]: [usize; 4] = op.query.shape().try_into()?;
+    let [_, KVH, S, _]: [usize; 4] = op.key.shape().try_into()?;
+    let (offset, n_rep) = (op.causal_offset(), op.n_rep());
+    let (mask_batch_stride, mask_head_stride) = op.mask_strides();
+
+    let to_f32 = |t: &Tensor| -> Result<Vec<f32>, OperationError> {
+        Ok(t.to_vec::<T>()?
+            .into_iter()
+            .map(|x| x.to_f32().unwrap())
+            .collect())
+    };
+    let q = to_f32(&op.query)?;
+    let k = to_f32(&op.key)?;
+    let v = to_f32(&op.value)?;
+    let mask = match &op.mask {
+        AttentionMask::Additive(m) => Some(to_f32(m)?),
+        _ => None,
+    };
+
+    let mut result = vec![T::zero(); B * H * L * D];
+    let mut scores = vec![0f32; S];
+    let mut acc = vec![0f32; D];
+    for bh in 0..B * H {
+        let kvh = (bh / H) * KVH + (bh % H) / n_rep;
+        let kv = &k[kvh * S * D..(kvh + 1) * S * D];
+        let vv = &v[kvh * S * D..(kvh + 1) * S * D];
+        let m_offset = (bh / H) * mask_batch_stride + (bh % H) * mask_head_stride;
+        for i in 0..L {
+            let q_row = &q[(bh * L + i) * D..(bh * L + i + 1) * D];
+            let visible = match op.mask {
+                AttentionMask::Causal => (i + offset + 1).min(S),
+                _ => S,
+            };
+
+            let mut max = f32::NEG_INFINITY;
+            for (j, s) in scores.iter_mut().enumerate().take(visible) {
+                let k_row = &kv[j * D..(j + 1) * D];
+                let mut dot = q_row.iter().zip(k_row).map(|(a, b)| a * b).sum::<f32>();
+                dot *= op.scale;
+                if let Some(cap) = op.softcap {
+                    dot = (dot / cap).tanh() * cap;
+                }
+                if let Some(m) = &mask {
+                    dot += m[m_offset + i * S + j];
+                }
+                *s = dot;
+                max = max.max(dot);
+            }
+
+            //A fully masked row has no probability mass, its output is zero rather than NaN
+            let mut sum = 0f32;
+            for s in scores.iter_mut().take(visible) {
+                *s = if max == f32::NEG_INFINITY {
+                    0.
+                } else {
+                    (*s - max).exp()
+                };
+                sum += *s;
+            }
+            let inv_sum = if sum > 0. { sum.recip() } else { 0. };
+
+            acc.fill(0.);
+            for (j, p) in scores.iter().enumerate().take(visible) {
+                let v_row = &vv[j * D..(j + 1) * D];
+                acc.iter_mut().zip(v_row).for_each(|(a, v)| *a += p * v);
+            }
+
+            let out = &mut result[(bh * L + i) * D..(bh * L + i + 1) * D];
+            out.iter_mut()
+                .zip(&acc)
+                .for_each(|(o, a)| *o = T::from(a * inv_sum).unwrap());
+        }
+    }
+
+    cpu_store_result(dst, &result);
+    Ok(())
+}
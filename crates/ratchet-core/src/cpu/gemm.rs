@@ -1,6 +1,6 @@
 use crate::{
-    cpu::cpu_store_result, CPUOperation, DType, InvariantError, Matmul, MatmulSpec, OperationError,
-    Shape, Strides, Tensor, TensorDType,
+    cpu::cpu_store_result, dequantize, CPUOperation, DType, InvariantError, Matmul, MatmulSpec,
+    OperationError, Shape, Strides, Tensor, TensorDType,
 };
 use anyhow::{anyhow, Result};
 use core::str::FromStr;
@@ -174,14 +174,51 @@ impl CPUOperation for Matmul {
             cpu_store_result(dst, &result);
             Ok(())
         }
+
+        //GGML blocks are dequantized up front, then run through the float kernel
+        fn run_qgemm<T: TensorDType + num_traits::FromPrimitive>(
+            spec: MatmulSpec,
+            lhs: &Tensor,
+            rhs: &Tensor,
+            dst: &Tensor,
+        ) -> Result<(), OperationError> {
+            let lhs = dequantize(lhs.deep_clone())
+                .to_vec::<f32>()?
+                .into_iter()
+                .map(|x| T::from_f32(x).unwrap())
+                .collect::<Vec<_>>();
+            let rhs = rhs.to_vec::<T>()?;
+
+            let result = if spec.trans_dst() {
+                gemm_impl::<T>(spec, &rhs, &lhs)?
+            } else {
+                gemm_impl::<T>(spec, &lhs, &rhs)?
+            };
+            cpu_store_result(dst, &result);
+            Ok(())
+        }
         let spec = self.compute_spec();
 
-        let Matmul { lhs, rhs, .. } = self;
+        let Matmul {
+            lhs, rhs, epilogue, ..
+        } = self;
+        //Epilogues are only fused on the GPU
+        if !epilogue.is_empty() {
+            return Err(OperationError::UnknownError(anyhow!(
+                "Matmul epilogues are not supported on the CPU: {:?}",
+                epilogue
+            )));
+        }
 
         match self.lhs.dt() {
             DType::F32 => run_gemm::<f32>(spec, lhs, rhs, &dst),
             DType::F16 => run_gemm::<f16>(spec, lhs, rhs, &dst),
             DType::BF16 => run_gemm::<bf16>(spec, lhs, rhs, &dst),
+            dt if dt.is_ggml_block() => match rhs.dt() {
+                DType::F32 => run_qgemm::<f32>(spec, lhs, rhs, &dst),
+                DType::F16 => run_qgemm::<f16>(spec, lhs, rhs, &dst),
+                dtype => Err(InvariantError::UnsupportedDType(dtype))?,
+            },
             dtype => Err(InvariantError::UnsupportedDType(dtype))?,
         }?;
         Ok(dst)
@@ -1,6 +1,9 @@
+mod argmax;
+mod attention;
 mod binary;
 pub mod gemm;
 mod norm;
+pub mod reference;
 pub mod reindex;
 pub mod rope;
 mod softmax;
@@ -21,14 +24,19 @@ pub fn apply_operation(op: LazyOp, dst: Tensor) -> Result<Tensor, OperationError
     match op {
         LazyOp::Binary(b) => b.apply_cpu(dst),
         LazyOp::Cast(c) => cpu_cast(c, dst),
+        LazyOp::Attention(a) => a.apply_cpu(dst),
         LazyOp::Matmul(m) => m.apply_cpu(dst),
         LazyOp::Softmax(s) => s.apply_cpu(dst),
+        LazyOp::ArgMax(a) => a.apply_cpu(dst),
         LazyOp::RoPE(r) => cpu_rope(r, dst),
         LazyOp::Unary(u) => u.apply_cpu(dst),
         LazyOp::Reindex(r) => r.apply_cpu(dst),
         LazyOp::Concat(c) => cpu_concat(c, dst),
         LazyOp::Norm(n) => n.apply_cpu(dst),
         LazyOp::Conv(_c) => todo!(),
+        LazyOp::Fused(_) => Err(OperationError::UnknownError(anyhow!(
+            "Fused elementwise kernels only run on the GPU"
+        ))),
         LazyOp::Select(i) => cpu_index_select(i, dst),
         LazyOp::IndexWrite(_i) => todo!(),
         LazyOp::Cache(_c) => todo!(),
@@ -109,6 +117,7 @@ pub fn cpu_index_select(i: IndexSelect, dst: Tensor) -> Result<Tensor, Operation
         DType::F16 => index_select::<f16>(i, dst),
         DType::BF16 => index_select::<bf16>(i, dst),
         DType::Q8_0F(_) => qindex_select(i, dst),
+        dt if dt.is_ggml_block() => qindex_select(i, dst),
         dtype => Err(InvariantError::UnsupportedDType(dtype).into()),
     }
 }
@@ -7,6 +7,7 @@ use crate::{
     shape, CPUOperation, DType, GroupNorm, InvariantError, Norm, NormOp, OperationError, Shape,
     Tensor, TensorDType,
 };
+use anyhow::anyhow;
 use core::iter::Sum;
 use half::{bf16, f16};
 use num::Float;
@@ -14,6 +15,13 @@ use num_traits::NumOps;
 
 impl CPUOperation for NormOp {
     fn apply_cpu(&self, dst: Tensor) -> Result<Tensor, OperationError> {
+        //Epilogues are only fused on the GPU
+        if !self.epilogue().is_empty() {
+            return Err(OperationError::UnknownError(anyhow!(
+                "Norm epilogues are not supported on the CPU: {:?}",
+                self.epilogue()
+            )));
+        }
         match self {
             NormOp::LayerNorm(n) => apply_layer_norm(n, dst),
             NormOp::RMSNorm(n) => apply_rms_norm(n, dst),
@@ -28,6 +36,7 @@ fn apply_layer_norm(
         scale,
         bias,
         eps,
+        ..
     }: &Norm,
     dst: Tensor,
 ) -> Result<Tensor, OperationError> {
@@ -152,6 +161,7 @@ fn apply_rms_norm(
         scale,
         bias,
         eps,
+        ..
     }: &Norm,
     dst: Tensor,
 ) -> Result<Tensor, OperationError> {
@@ -0,0 +1,1008 @@
+//! # Reference executor
+//!
+//! A naive implementation of every [LazyOp] in f64, written for clarity rather than speed.
+//! It is the ground truth of differential tests: [compare] runs the reference on the inputs of
+//! every operation of a resolved graph, and reports the error of the kernels in ULPs, with no
+//! Python or GPU required.
+//!
+//! Values are only rounded where an operation demands it, i.e by a cast to another dtype.
+use crate::{
+    dequantize, Attention, AttentionMask, BinaryOp, Cache, Concat, Conv, DType, Device,
+    FusedElementwise, FusedStep, GroupNorm, IndexSelect, IndexWrite, LazyOp, Matmul, Norm, NormOp,
+    Reindex, RoPE, Shape, Tensor, TensorId, UnaryOp,
+};
+use anyhow::{anyhow, bail};
+use half::{bf16, f16};
+use rustc_hash::FxHashMap;
+use std::fmt;
+
+/// The values of a tensor in f64, in row-major order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RefTensor {
+    shape: Shape,
+    data: Vec<f64>,
+}
+
+impl RefTensor {
+    pub fn new(shape: Shape, data: Vec<f64>) -> Self {
+        assert_eq!(shape.numel(), data.len());
+        Self { shape, data }
+    }
+
+    /// The values of a resolved tensor, dequantized if need be.
+    pub fn from_tensor(tensor: &Tensor) -> anyhow::Result<Self> {
+        if !tensor.resolved() {
+            bail!("{:?} is not resolved", tensor.id());
+        }
+        let tensor = match tensor.device() {
+            Device::CPU => tensor.clone(),
+            Device::GPU(_) => tensor.to(&Device::CPU)?,
+        };
+        let data = match tensor.dt() {
+            DType::F32 => tensor.to_vec::<f32>()?.into_iter().map(f64::from).collect(),
+            DType::F16 => tensor.to_vec::<f16>()?.into_iter().map(f64::from).collect(),
+            DType::BF16 => tensor
+                .to_vec::<bf16>()?
+                .into_iter()
+                .map(f64::from)
+                .collect(),
+            DType::I32 => tensor.to_vec::<i32>()?.into_iter().map(f64::from).collect(),
+            DType::U32 => tensor.to_vec::<u32>()?.into_iter().map(f64::from).collect(),
+            _ => dequantize(tensor.deep_clone())
+                .to_vec::<f32>()?
+                .into_iter()
+                .map(f64::from)
+                .collect(),
+        };
+        Ok(Self::new(tensor.shape().clone(), data))
+    }
+
+    pub fn shape(&self) -> &Shape {
+        &self.shape
+    }
+
+    pub fn data(&self) -> &[f64] {
+        &self.data
+    }
+}
+
+/// Evaluate the graph rooted at `tensor` in f64. Its `Const` leaves must be resolved.
+///
+/// `IndexWrite` and `Cache` overwrite their sources when resolved, so evaluate graphs which
+/// contain them beforehand.
+pub fn evaluate(tensor: &Tensor) -> anyhow::Result<RefTensor> {
+    let mut values = FxHashMap::default();
+    for t in tensor.execution_order() {
+        let value = apply(t, &values)?;
+        values.insert(t.id(), value);
+    }
+    values
+        .remove(&tensor.id())
+        .ok_or_else(|| anyhow!("{:?} was not evaluated", tensor.id()))
+}
+
+/// Compare every operation of a resolved graph against the reference, evaluated on the same
+/// inputs so that errors don't compound from one operation to the next.
+pub fn compare(resolved: &Tensor) -> anyhow::Result<UlpReport> {
+    let mut report = UlpReport::default();
+    for t in resolved.execution_order() {
+        if matches!(t.op(), LazyOp::Const) {
+            continue;
+        }
+        let values = t
+            .op()
+            .srcs()
+            .iter()
+            .map(|s| Ok((s.id(), RefTensor::from_tensor(s)?)))
+            .collect::<anyhow::Result<FxHashMap<_, _>>>()?;
+        let expected = apply(t, &values)?;
+        let actual = RefTensor::from_tensor(t)?;
+        report.record(t.op().name(), t.dt(), &actual.data, &expected.data);
+    }
+    Ok(report)
+}
+
+/// The value of `dst`, computed from the values of the sources of its operation.
+fn apply(dst: &Tensor, values: &FxHashMap<TensorId, RefTensor>) -> anyhow::Result<RefTensor> {
+    let get = |t: &Tensor| {
+        values
+            .get(&t.id())
+            .ok_or_else(|| anyhow!("Missing value of {:?}", t.id()))
+    };
+    let shape = dst.shape();
+    let data = match dst.op() {
+        LazyOp::Const => return RefTensor::from_tensor(dst),
+        LazyOp::Matmul(m) => matmul(m, get(&m.lhs)?, get(&m.rhs)?, opt(&m.bias, get)?),
+        LazyOp::Conv(c) => conv(
+            c,
+            get(&c.input)?,
+            get(&c.weight)?,
+            opt(&c.bias, get)?,
+            shape,
+        ),
+        LazyOp::Binary(b) => {
+            let (lhs, rhs) = (
+                broadcast(get(b.lhs())?, shape),
+                broadcast(get(b.rhs())?, shape),
+            );
+            lhs.iter()
+                .zip(&rhs)
+                .map(|(l, r)| binary(b.op(), *l, *r))
+                .collect()
+        }
+        LazyOp::Unary(u) => get(u.input())?
+            .data
+            .iter()
+            .map(|x| unary(u.op(), *x))
+            .collect(),
+        LazyOp::Reindex(Reindex::Permute(p)) => permute(get(&p.src)?, &p.dims, shape),
+        LazyOp::Reindex(Reindex::Slice(s)) => {
+            let start = s.indices().iter().map(|r| r.start).collect::<Vec<_>>();
+            slice(get(&s.src)?, &start, shape)
+        }
+        LazyOp::Reindex(Reindex::Broadcast(b)) => broadcast(get(&b.src)?, b.to()),
+        LazyOp::Concat(c) => concat(c, get)?,
+        LazyOp::Norm(NormOp::LayerNorm(n)) => norm(n, get, false)?,
+        LazyOp::Norm(NormOp::RMSNorm(n)) => norm(n, get, true)?,
+        LazyOp::Norm(NormOp::GroupNorm(g)) => group_norm(g, get)?,
+        LazyOp::Cast(c) => cast(&get(c.input())?.data, c.dst_dt())?,
+        LazyOp::Attention(a) => attention(a, get)?,
+        LazyOp::Fused(f) => fused(f, get)?,
+        LazyOp::RoPE(r) => rope(r, get(r.input())?),
+        LazyOp::Softmax(s) => softmax(get(&s.input)?, s.dim),
+        LazyOp::ArgMax(a) => argmax(get(&a.input)?, a.dim),
+        LazyOp::View(v) => get(v.input())?.data.clone(),
+        LazyOp::Select(s) => index_select(s, get(s.src())?, get(s.indices())?),
+        LazyOp::IndexWrite(i) => index_write(i, get(&i.dst)?, get(&i.src)?),
+        LazyOp::Cache(c) => cache(c, get(&c.cache)?, get(&c.source)?, shape),
+    };
+    Ok(RefTensor::new(shape.clone(), data))
+}
+
+fn opt<'a>(
+    t: &Option<Tensor>,
+    get: impl Fn(&Tensor) -> anyhow::Result<&'a RefTensor>,
+) -> anyhow::Result<Option<&'a RefTensor>> {
+    t.as_ref().map(get).transpose()
+}
+
+/// Row-major strides of a contiguous tensor of `shape`.
+fn strides(shape: &Shape) -> Vec<usize> {
+    let mut strides = vec![1; shape.rank()];
+    for d in (0..shape.rank().saturating_sub(1)).rev() {
+        strides[d] = strides[d + 1] * shape[d + 1];
+    }
+    strides
+}
+
+/// Coordinates of the element at `index` of a contiguous tensor of `shape`.
+fn coords(shape: &Shape, mut index: usize) -> Vec<usize> {
+    let mut coords = vec![0; shape.rank()];
+    for d in (0..shape.rank()).rev() {
+        coords[d] = index % shape[d];
+        index /= shape[d];
+    }
+    coords
+}
+
+fn offset(coords: &[usize], strides: &[usize]) -> usize {
+    coords.iter().zip(strides).map(|(c, s)| c * s).sum()
+}
+
+/// Number of elements before, along & after `dim`.
+fn split(shape: &Shape, dim: usize) -> (usize, usize, usize) {
+    let outer = shape[..dim].iter().product();
+    let inner = shape[dim + 1..].iter().product();
+    (outer, shape[dim], inner)
+}
+
+/// Broadcast `src` to `to`, aligning trailing dimensions.
+fn broadcast(src: &RefTensor, to: &Shape) -> Vec<f64> {
+    let leading = to.rank() - src.shape.rank();
+    let src_strides = strides(&src.shape);
+    (0..to.numel())
+        .map(|i| {
+            let index = coords(to, i)
+                .iter()
+                .skip(leading)
+                .zip(src.shape.iter().zip(&src_strides))
+                .map(|(c, (dim, stride))| if *dim == 1 { 0 } else { c * stride })
+                .sum::<usize>();
+            src.data[index]
+        })
+        .collect()
+}
+
+/// Round `x` to the nearest value of `dt`, integers are truncated and saturated.
+fn round(dt: DType, x: f64) -> anyhow::Result<f64> {
+    Ok(match dt {
+        DType::F32 => x as f32 as f64,
+        DType::F16 => f16::from_f64(x).to_f64(),
+        DType::BF16 => bf16::from_f64(x).to_f64(),
+        DType::I32 => x as i32 as f64,
+        DType::U32 => x as u32 as f64,
+        dt => bail!("Cannot cast to {:?}", dt),
+    })
+}
+
+fn cast(x: &[f64], dt: DType) -> anyhow::Result<Vec<f64>> {
+    x.iter().map(|x| round(dt, *x)).collect()
+}
+
+fn unary(op: &UnaryOp, x: f64) -> f64 {
+    match op {
+        UnaryOp::Gelu => {
+            let c = (2. / std::f64::consts::PI).sqrt();
+            0.5 * x * (1. + (c * (x + 0.044715 * x.powi(3))).tanh())
+        }
+        UnaryOp::Tanh => x.tanh(),
+        UnaryOp::Exp => x.exp(),
+        UnaryOp::Log => x.ln(),
+        UnaryOp::Sin => x.sin(),
+        UnaryOp::Cos => x.cos(),
+        UnaryOp::Abs => x.abs(),
+        UnaryOp::Sqrt => x.sqrt(),
+        UnaryOp::Relu => x.max(0.),
+        UnaryOp::Floor => x.floor(),
+        UnaryOp::Ceil => x.ceil(),
+        UnaryOp::Neg => -x,
+        UnaryOp::Silu => x / (1. + (-x).exp()),
+        UnaryOp::Sigmoid => 1. / (1. + (-x).exp()),
+    }
+}
+
+fn binary(op: &BinaryOp, lhs: f64, rhs: f64) -> f64 {
+    match op {
+        BinaryOp::Add => lhs + rhs,
+        BinaryOp::Sub => lhs - rhs,
+        BinaryOp::Mul => lhs * rhs,
+        BinaryOp::Div => lhs / rhs,
+    }
+}
+
+fn epilogue(ops: &[UnaryOp], x: f64) -> f64 {
+    ops.iter().fold(x, |x, op| unary(op, x))
+}
+
+fn matmul(m: &Matmul, lhs: &RefTensor, rhs: &RefTensor, bias: Option<&RefTensor>) -> Vec<f64> {
+    let (mut lhs_shape, mut rhs_shape) = (lhs.shape.clone(), rhs.shape.clone());
+    if lhs_shape.rank() < 2 {
+        lhs_shape.insert(m.trans_lhs as usize, 1);
+    }
+    if rhs_shape.rank() < 2 {
+        rhs_shape.insert(!m.trans_rhs as usize, 1);
+    }
+    while lhs_shape.rank() < rhs_shape.rank() {
+        lhs_shape.insert(0, 1);
+    }
+    while rhs_shape.rank() < lhs_shape.rank() {
+        rhs_shape.insert(0, 1);
+    }
+    let rank = lhs_shape.rank();
+    let (lhs_prefix, rhs_prefix) = (&lhs_shape[..rank - 2], &rhs_shape[..rank - 2]);
+    let prefix = Shape::multi_broadcast(&[&lhs_prefix.into(), &rhs_prefix.into()]).unwrap();
+    let batched = |shape: &Shape| {
+        let mut batched = prefix.clone();
+        batched.push(shape[rank - 2]);
+        batched.push(shape[rank - 1]);
+        batched
+    };
+    let a = broadcast(
+        &RefTensor::new(lhs_shape.clone(), lhs.data.clone()),
+        &batched(&lhs_shape),
+    );
+    let b = broadcast(
+        &RefTensor::new(rhs_shape.clone(), rhs.data.clone()),
+        &batched(&rhs_shape),
+    );
+
+    let (mut M, mut K) = (lhs_shape[rank - 2], lhs_shape[rank - 1]);
+    if m.trans_lhs {
+        std::mem::swap(&mut M, &mut K);
+    }
+    let N = if m.trans_rhs {
+        rhs_shape[rank - 2]
+    } else {
+        rhs_shape[rank - 1]
+    };
+
+    let mut result = vec![0.; prefix.numel() * M * N];
+    for batch in 0..prefix.numel() {
+        let (a, b) = (&a[batch * M * K..], &b[batch * K * N..]);
+        for i in 0..M {
+            for j in 0..N {
+                let mut acc = 0.;
+                for k in 0..K {
+                    let l = if m.trans_lhs {
+                        a[k * M + i]
+                    } else {
+                        a[i * K + k]
+                    };
+                    let r = if m.trans_rhs {
+                        b[j * K + k]
+                    } else {
+                        b[k * N + j]
+                    };
+                    acc += l * r;
+                }
+                acc += bias.map_or(0., |bias| bias.data[j]);
+                let index = if m.trans_dst { j * M + i } else { i * N + j };
+                result[batch * M * N + index] = epilogue(m.epilogue.ops(), acc);
+            }
+        }
+    }
+    result
+}
+
+fn conv(
+    c: &Conv,
+    input: &RefTensor,
+    weight: &RefTensor,
+    bias: Option<&RefTensor>,
+    dst_shape: &Shape,
+) -> Vec<f64> {
+    let (C_in, L_in) = (input.shape[1], input.shape[2]);
+    let KS = weight.shape[2];
+    let [N, C_out, L_out]: [usize; 3] = dst_shape.try_into().unwrap();
+
+    let mut result = vec![0.; dst_shape.numel()];
+    for n in 0..N {
+        for co in 0..C_out {
+            for l in 0..L_out {
+                let mut acc = bias.map_or(0., |b| b.data[co]);
+                for ci in 0..C_in {
+                    for k in 0..KS {
+                        let pos = (l * c.stride + k) as isize - c.padding as isize;
+                        if pos < 0 || pos as usize >= L_in {
+                            continue;
+                        }
+                        let x = input.data[(n * C_in + ci) * L_in + pos as usize];
+                        acc += weight.data[(co * C_in + ci) * KS + k] * x;
+                    }
+                }
+                result[(n * C_out + co) * L_out + l] = acc;
+            }
+        }
+    }
+    result
+}
+
+fn permute(src: &RefTensor, dims: &[usize], dst_shape: &Shape) -> Vec<f64> {
+    let src_strides = strides(&src.shape);
+    (0..dst_shape.numel())
+        .map(|i| {
+            let index = coords(dst_shape, i)
+                .iter()
+                .zip(dims)
+                .map(|(c, d)| c * src_strides[*d])
+                .sum::<usize>();
+            src.data[index]
+        })
+        .collect()
+}
+
+fn slice(src: &RefTensor, start: &[usize], dst_shape: &Shape) -> Vec<f64> {
+    let src_strides = strides(&src.shape);
+    (0..dst_shape.numel())
+        .map(|i| {
+            let index = coords(dst_shape, i)
+                .iter()
+                .zip(start)
+                .zip(&src_strides)
+                .map(|((c, s), stride)| (c + s) * stride)
+                .sum::<usize>();
+            src.data[index]
+        })
+        .collect()
+}
+
+fn concat<'a>(
+    c: &Concat,
+    get: impl Fn(&Tensor) -> anyhow::Result<&'a RefTensor>,
+) -> anyhow::Result<Vec<f64>> {
+    let inputs = c
+        .inputs()
+        .iter()
+        .map(get)
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let (outer, _, _) = split(&inputs[0].shape, c.dim());
+    let mut result = vec![];
+    for o in 0..outer {
+        for input in inputs.iter() {
+            let block = input.data.len() / outer;
+            result.extend_from_slice(&input.data[o * block..(o + 1) * block]);
+        }
+    }
+    Ok(result)
+}
+
+/// Layer norm over the last dimension, or RMS norm if `rms`.
+fn norm<'a>(
+    n: &Norm,
+    get: impl Fn(&Tensor) -> anyhow::Result<&'a RefTensor>,
+    rms: bool,
+) -> anyhow::Result<Vec<f64>> {
+    let (input, scale) = (get(&n.input)?, get(&n.scale)?);
+    let bias = if rms { None } else { opt(&n.bias, &get)? };
+    let N = input.shape[input.shape.rank() - 1];
+    let eps = n.eps as f64;
+    let mut result = Vec::with_capacity(input.data.len());
+    for row in input.data.chunks(N) {
+        let mean = if rms {
+            0.
+        } else {
+            row.iter().sum::<f64>() / N as f64
+        };
+        let var = row.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / N as f64;
+        let rstd = 1. / (var + eps).sqrt();
+        for (j, x) in row.iter().enumerate() {
+            let shift = bias.map_or(0., |b| b.data[j]);
+            let val = (x - mean) * rstd * scale.data[j] + shift;
+            result.push(epilogue(n.epilogue.ops(), val));
+        }
+    }
+    Ok(result)
+}
+
+/// Normalizes groups of channels, dimension 1, along with every dimension after them.
+fn group_norm<'a>(
+    g: &GroupNorm,
+    get: impl Fn(&Tensor) -> anyhow::Result<&'a RefTensor>,
+) -> anyhow::Result<Vec<f64>> {
+    let Norm {
+        input,
+        scale,
+        bias,
+        eps,
+        epilogue: ops,
+    } = &g.norm;
+    let (input, scale, bias) = (get(input)?, get(scale)?, opt(bias, &get)?);
+    let C = input.shape[1];
+    let spatial = input.shape[2..].iter().product::<usize>();
+    let channels = C / g.num_groups;
+
+    let mut result = Vec::with_capacity(input.data.len());
+    for (group, values) in input.data.chunks(channels * spatial).enumerate() {
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        let var = values.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / values.len() as f64;
+        let rstd = 1. / (var + *eps as f64).sqrt();
+        for (i, x) in values.iter().enumerate() {
+            let c = (group % g.num_groups) * channels + i / spatial;
+            let shift = bias.map_or(0., |b| b.data[c]);
+            let val = (x - mean) * rstd * scale.data[c] + shift;
+            result.push(epilogue(ops.ops(), val));
+        }
+    }
+    Ok(result)
+}
+
+fn attention<'a>(
+    a: &Attention,
+    get: impl Fn(&Tensor) -> anyhow::Result<&'a RefTensor>,
+) -> anyhow::Result<Vec<f64>> {
+    let (q, k, v) = (get(&a.query)?, get(&a.key)?, get(&a.value)?);
+    let mask = match &a.mask {
+        AttentionMask::Additive(m) => Some(get(m)?),
+        _ => None,
+    };
+    let [B, H, L, D]: [usize; 4] = (&q.shape).try_into()?;
+    let (KVH, S) = (k.shape[1], k.shape[2]);
+    let (offset, n_rep) = (a.causal_offset(), a.n_rep());
+    let (mask_batch_stride, mask_head_stride) = a.mask_strides();
+
+    let mut result = vec![0.; B * H * L * D];
+    for bh in 0..B * H {
+        let kvh = (bh / H) * KVH + (bh % H) / n_rep;
+        let m_offset = (bh / H) * mask_batch_stride + (bh % H) * mask_head_stride;
+        for i in 0..L {
+            let visible = match a.mask {
+                AttentionMask::Causal => (i + offset + 1).min(S),
+                _ => S,
+            };
+            let q_row = &q.data[(bh * L + i) * D..(bh * L + i + 1) * D];
+            let scores = (0..visible)
+                .map(|j| {
+                    let k_row = &k.data[(kvh * S + j) * D..(kvh * S + j + 1) * D];
+                    let mut score = q_row.iter().zip(k_row).map(|(q, k)| q * k).sum::<f64>();
+                    score *= a.scale as f64;
+                    if let Some(cap) = a.softcap {
+                        score = (score / cap as f64).tanh() * cap as f64;
+                    }
+                    score + mask.map_or(0., |m| m.data[m_offset + i * S + j])
+                })
+                .collect::<Vec<_>>();
+            let max = scores.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+            let weights = scores.iter().map(|s| (s - max).exp()).collect::<Vec<_>>();
+            let sum = weights.iter().sum::<f64>();
+            for (j, w) in weights.iter().enumerate() {
+                let v_row = &v.data[(kvh * S + j) * D..(kvh * S + j + 1) * D];
+                let out = &mut result[(bh * L + i) * D..(bh * L + i + 1) * D];
+                for (o, v) in out.iter_mut().zip(v_row) {
+                    *o += w / sum * v;
+                }
+            }
+        }
+    }
+    Ok(result)
+}
+
+fn fused<'a>(
+    f: &FusedElementwise,
+    get: impl Fn(&Tensor) -> anyhow::Result<&'a RefTensor>,
+) -> anyhow::Result<Vec<f64>> {
+    let mut steps: Vec<Vec<f64>> = Vec::with_capacity(f.steps().len());
+    for step in f.steps() {
+        let value = match step {
+            FusedStep::Input(i) => get(&f.inputs()[*i])?.data.clone(),
+            FusedStep::Unary(op, x) => steps[*x].iter().map(|x| unary(op, *x)).collect(),
+            FusedStep::Binary(op, l, r) => steps[*l]
+                .iter()
+                .zip(&steps[*r])
+                .map(|(l, r)| binary(op, *l, *r))
+                .collect(),
+            FusedStep::Cast(dt, x) => cast(&steps[*x], *dt)?,
+        };
+        steps.push(value);
+    }
+    steps.pop().ok_or_else(|| anyhow!("Empty fused program"))
+}
+
+/// Rotates the halves of the first `dim` features of [B, H, S, D], as in GPT-NeoX.
+fn rope(r: &RoPE, input: &RefTensor) -> Vec<f64> {
+    let (S, D) = (input.shape[2], input.shape[3]);
+    let half = r.dim() / 2;
+    let mut result = input.data.clone();
+    for (row, out) in result.chunks_mut(D).enumerate() {
+        let x = &input.data[row * D..(row + 1) * D];
+        let position = (r.offset() + row % S) as f64;
+        for i in 0..half {
+            let inv_freq = (r.base() as f64).powf(-(i as f64) / half as f64);
+            let (sin, cos) = (position * inv_freq).sin_cos();
+            out[i] = x[i] * cos - x[half + i] * sin;
+            out[half + i] = x[i] * sin + x[half + i] * cos;
+        }
+    }
+    result
+}
+
+fn softmax(input: &RefTensor, dim: usize) -> Vec<f64> {
+    let (outer, N, inner) = split(&input.shape, dim);
+    let mut result = vec![0.; input.data.len()];
+    for o in 0..outer {
+        for i in 0..inner {
+            let index = |j: usize| (o * N + j) * inner + i;
+            let max = (0..N)
+                .map(|j| input.data[index(j)])
+                .fold(f64::NEG_INFINITY, f64::max);
+            let sum = (0..N)
+                .map(|j| (input.data[index(j)] - max).exp())
+                .sum::<f64>();
+            for j in 0..N {
+                result[index(j)] = (input.data[index(j)] - max).exp() / sum;
+            }
+        }
+    }
+    result
+}
+
+/// The first index wins ties, and a NaN is never larger.
+fn argmax(input: &RefTensor, dim: usize) -> Vec<f64> {
+    let N = input.shape[dim];
+    input
+        .data
+        .chunks(N)
+        .map(|row| {
+            let mut best = 0;
+            for (i, x) in row.iter().enumerate() {
+                if *x > row[best] {
+                    best = i;
+                }
+            }
+            best as f64
+        })
+        .collect()
+}
+
+fn index_select(s: &IndexSelect, src: &RefTensor, indices: &RefTensor) -> Vec<f64> {
+    let (outer, N, inner) = split(&src.shape, s.dim());
+    let mut result = Vec::with_capacity(outer * indices.data.len() * inner);
+    for o in 0..outer {
+        for index in indices.data.iter() {
+            let start = (o * N + *index as usize) * inner;
+            result.extend_from_slice(&src.data[start..start + inner]);
+        }
+    }
+    result
+}
+
+/// Writes `src` contiguously into `dst`, from the element at `write_start`.
+fn index_write(i: &IndexWrite, dst: &RefTensor, src: &RefTensor) -> Vec<f64> {
+    let start = offset(&i.write_start, &strides(&dst.shape));
+    let mut result = dst.data.clone();
+    result[start..start + src.data.len()].copy_from_slice(&src.data);
+    result
+}
+
+fn cache(c: &Cache, cache: &RefTensor, source: &RefTensor, dst_shape: &Shape) -> Vec<f64> {
+    let (cache_strides, source_strides) = (strides(&cache.shape), strides(&source.shape));
+    (0..dst_shape.numel())
+        .map(|i| {
+            let mut index = coords(dst_shape, i);
+            if index[c.dim] < c.offset {
+                cache.data[offset(&index, &cache_strides)]
+            } else {
+                index[c.dim] -= c.offset;
+                source.data[offset(&index, &source_strides)]
+            }
+        })
+        .collect()
+}
+
+/// Distance between consecutive values of `dt` around `x`, 1 for integers.
+fn ulp(dt: DType, x: f64) -> f64 {
+    let (mantissa, min_exp) = match dt {
+        DType::F32 => (23, -126),
+        DType::F16 => (10, -14),
+        DType::BF16 => (7, -126),
+        _ => return 1.,
+    };
+    let exp = ((x.abs().to_bits() >> 52) & 0x7ff) as i32 - 1023;
+    2f64.powi(exp.max(min_exp) - mantissa)
+}
+
+/// Error of `actual`, a value of `dt`, in ULPs of `expected`.
+///
+/// Matching NaNs and infinities are exact, any other mismatch of special values is infinitely
+/// wrong. A finite `expected` out of the range of `dt` is expected to overflow.
+pub fn ulp_error(dt: DType, actual: f64, expected: f64) -> f64 {
+    if actual.is_nan() && expected.is_nan() {
+        return 0.;
+    }
+    let rounded = round(dt, expected).unwrap_or(expected);
+    if !actual.is_finite() || !rounded.is_finite() {
+        return if actual == rounded { 0. } else { f64::INFINITY };
+    }
+    (actual - expected).abs() / ulp(dt, expected)
+}
+
+/// ULP errors of an operation on a dtype, over every element recorded.
+#[derive(Debug, Clone)]
+pub struct UlpStats {
+    pub op: String,
+    pub dt: DType,
+    pub elements: usize,
+    /// Largest error, in ULPs of the expected value.
+    pub max: f64,
+    /// Largest error, in ULPs of the largest expected value of the output. Unlike [UlpStats::max],
+    /// this isn't inflated by cancellation, e.g in reductions with outputs near zero.
+    pub max_scaled: f64,
+    sum: f64,
+}
+
+impl UlpStats {
+    pub fn mean(&self) -> f64 {
+        self.sum / self.elements.max(1) as f64
+    }
+}
+
+/// ULP errors of the kernels against the reference, per operation and dtype.
+#[derive(Debug, Clone, Default)]
+pub struct UlpReport {
+    stats: Vec<UlpStats>,
+}
+
+impl UlpReport {
+    pub fn stats(&self) -> &[UlpStats] {
+        &self.stats
+    }
+
+    pub fn get(&self, op: &str, dt: DType) -> Option<&UlpStats> {
+        self.stats.iter().find(|s| s.op == op && s.dt == dt)
+    }
+
+    /// Record the output of `op` on `dt`.
+    pub fn record(&mut self, op: &str, dt: DType, actual: &[f64], expected: &[f64]) {
+        assert_eq!(actual.len(), expected.len());
+        let scale = expected
+            .iter()
+            .filter(|x| x.is_finite())
+            .fold(0f64, |max, x| max.max(x.abs()));
+
+        let index = match self.stats.iter().position(|s| s.op == op && s.dt == dt) {
+            Some(index) => index,
+            None => {
+                self.stats.push(UlpStats {
+                    op: op.to_string(),
+                    dt,
+                    elements: 0,
+                    max: 0.,
+                    max_scaled: 0.,
+                    sum: 0.,
+                });
+                self.stats.len() - 1
+            }
+        };
+        let stats = &mut self.stats[index];
+        for (a, e) in actual.iter().zip(expected) {
+            let error = ulp_error(dt, *a, *e);
+            let scaled = if error == 0. || error.is_infinite() {
+                error
+            } else {
+                (a - e).abs() / ulp(dt, scale)
+            };
+            stats.elements += 1;
+            stats.sum += error;
+            stats.max = stats.max.max(error);
+            stats.max_scaled = stats.max_scaled.max(scaled);
+        }
+    }
+
+    pub fn merge(&mut self, other: UlpReport) {
+        for s in other.stats {
+            match self.stats.iter_mut().find(|t| t.op == s.op && t.dt == s.dt) {
+                Some(t) => {
+                    t.elements += s.elements;
+                    t.sum += s.sum;
+                    t.max = t.max.max(s.max);
+                    t.max_scaled = t.max_scaled.max(s.max_scaled);
+                }
+                None => self.stats.push(s),
+            }
+        }
+    }
+}
+
+impl fmt::Display for UlpReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<12} {:<6} {:>10} {:>12} {:>12} {:>12}",
+            "op", "dtype", "elements", "max ulp", "mean ulp", "max scaled"
+        )?;
+        let mut stats = self.stats.iter().collect::<Vec<_>>();
+        stats.sort_by_key(|s| (s.op.clone(), s.dt.to_string()));
+        for s in stats {
+            writeln!(
+                f,
+                "{:<12} {:<6} {:>10} {:>12.2} {:>12.3} {:>12.2}",
+                s.op,
+                s.dt.to_string(),
+                s.elements,
+                s.max,
+                s.mean(),
+                s.max_scaled
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compare, evaluate, ulp_error, UlpReport};
+    use crate::{shape, AttentionMask, DType, Device, Tensor};
+    use proptest::prelude::*;
+    use test_strategy::Arbitrary;
+
+    #[test]
+    fn measures_ulps() {
+        let next = |x: f32| f32::from_bits(x.to_bits() + 1) as f64;
+        assert_eq!(ulp_error(DType::F32, next(1.), 1.), 1.);
+        assert_eq!(ulp_error(DType::F32, next(-3.), -3.), 1.);
+        assert_eq!(ulp_error(DType::F16, 1. + 2f64.powi(-10), 1.), 1.);
+        assert_eq!(ulp_error(DType::BF16, 1. + 2f64.powi(-7), 1.), 1.);
+        assert_eq!(ulp_error(DType::I32, 2., 5.), 3.);
+        assert_eq!(ulp_error(DType::F16, f64::INFINITY, 1e6), 0.);
+        assert_eq!(ulp_error(DType::F32, f64::NAN, f64::NAN), 0.);
+        assert!(ulp_error(DType::F32, f64::NAN, 1.).is_infinite());
+    }
+
+    #[test]
+    fn evaluates_graph() -> anyhow::Result<()> {
+        let a = Tensor::from_data([1f32, 2., 3., 4., 5., 6.], shape![2, 3], Device::CPU);
+        let b = Tensor::from_data([1f32, 0., 0., 1., 1., 1.], shape![3, 2], Device::CPU);
+        let c = a.matmul(b, false, false)?.permute(&[1, 0])?;
+
+        let result = evaluate(&c)?;
+        assert_eq!(result.shape(), &shape![2, 2]);
+        assert_eq!(result.data(), &[4., 10., 5., 11.]);
+        Ok(())
+    }
+
+    #[test]
+    fn evaluates_group_norm() -> anyhow::Result<()> {
+        //Both groups have a variance of 1
+        let x = Tensor::from_data(
+            [3f32, 5., 3., 5., 0., 2., 0., 2.],
+            shape![1, 4, 2],
+            Device::CPU,
+        );
+        let scale = Tensor::from_data([1f32, 2., 3., 4.], shape![4], Device::CPU);
+        let bias = Tensor::from_data([0f32, 0., 1., 1.], shape![4], Device::CPU);
+        let result = evaluate(&x.group_norm(2, scale, Some(bias), 0.)?)?;
+        assert_eq!(result.data(), &[-1., 1., -2., 2., -2., 4., -3., 5.]);
+        Ok(())
+    }
+
+    #[test]
+    fn evaluates_conv() -> anyhow::Result<()> {
+        let x = Tensor::from_data([1f32, 2., 3., 4.], shape![1, 1, 4], Device::CPU);
+        let w = Tensor::from_data([1f32, 0., -1., 1., 1., 1.], shape![2, 1, 3], Device::CPU);
+        let b = Tensor::from_data([0f32, 10.], shape![2], Device::CPU);
+        let result = evaluate(&x.conv1d(w, Some(b), 1, 1)?)?;
+        assert_eq!(result.shape(), &shape![1, 2, 4]);
+        assert_eq!(result.data(), &[-2., -2., -2., 3., 13., 16., 19., 17.]);
+        Ok(())
+    }
+
+    #[test]
+    fn evaluates_cache() -> anyhow::Result<()> {
+        let cache = Tensor::from_data(
+            (10..18).map(|x| x as f32).collect::<Vec<_>>(),
+            shape![1, 4, 2],
+            Device::CPU,
+        );
+        let source = Tensor::from_data([1f32, 2.], shape![1, 1, 2], Device::CPU);
+        let result = evaluate(&cache.cache(source, 1, 2)?)?;
+        assert_eq!(result.shape(), &shape![1, 3, 2]);
+        assert_eq!(result.data(), &[10., 11., 12., 13., 1., 2.]);
+        Ok(())
+    }
+
+    #[test]
+    fn evaluates_index_write() -> anyhow::Result<()> {
+        let dst = Tensor::from_data([0f32, 1., 2., 3., 4., 5.], shape![2, 3], Device::CPU);
+        let src = Tensor::from_data([8f32, 9.], shape![1, 2], Device::CPU);
+        let result = evaluate(&dst.index_write(src, crate::rvec![1, 1])?)?;
+        assert_eq!(result.data(), &[0., 1., 2., 3., 8., 9.]);
+        Ok(())
+    }
+
+    const UNARY: usize = 14;
+    const STEPS: usize = UNARY + 17;
+
+    #[derive(Arbitrary, Debug)]
+    struct GraphProblem {
+        #[strategy(1..=2usize)]
+        B: usize,
+        #[strategy(1..=16usize)]
+        M: usize,
+        #[strategy(1..=32usize)]
+        N: usize,
+        #[strategy(prop_oneof![Just(DType::F32), Just(DType::F16), Just(DType::BF16)])]
+        dt: DType,
+        #[strategy(proptest::collection::vec(0..STEPS, 1..=6))]
+        steps: Vec<usize>,
+        argmax: bool,
+    }
+
+    fn randn(shape: crate::Shape, dt: DType) -> anyhow::Result<Tensor> {
+        Tensor::randn::<f32>(shape, Device::CPU).cast(dt)
+    }
+
+    /// Apply the operation `step` to `x` of shape [B, M, N], and maybe some others to keep
+    /// values in a range which every kernel handles.
+    fn apply_step(x: Tensor, step: usize, dt: DType) -> anyhow::Result<Tensor> {
+        let [B, M, N]: [usize; 3] = x.shape().try_into()?;
+        Ok(match step {
+            0 => x.gelu()?,
+            1 => x.tanh()?,
+            2 => x.sin()?,
+            3 => x.cos()?,
+            4 => x.abs()?,
+            5 => x.relu()?,
+            6 => x.floor()?,
+            7 => x.ceil()?,
+            8 => x.neg()?,
+            9 => x.silu()?,
+            10 => x.sigmoid()?,
+            11 => x.tanh()?.exp()?,
+            12 => x.sigmoid()?.log()?,
+            13 => x.abs()?.sqrt()?,
+            s if s < UNARY + 4 => {
+                let y = randn(shape![B, M, N], dt)?;
+                match s - UNARY {
+                    0 => x.add(y)?,
+                    1 => x.sub(y)?,
+                    2 => x.mul(y)?,
+                    _ => x.div(y.tanh()?.exp()?)?,
+                }
+            }
+            s => match s - UNARY - 4 {
+                0 => x.tanh()?.softmax(2)?,
+                1 => {
+                    let (scale, bias) = (randn(shape![N], dt)?, randn(shape![N], dt)?);
+                    x.layer_norm(scale, Some(bias), 1e-5)?
+                }
+                2 => x.rms_norm(randn(shape![N], dt)?, 1e-5)?,
+                3 => x.permute(&[0, 2, 1])?,
+                4 => x.slice(&[0..B, 0..M, 0..N.div_ceil(2)])?,
+                5 => Tensor::cat(crate::rvec![x, randn(shape![B, M, 4], dt)?], 2)?,
+                //Only f32 & f16 GEMM kernels are exercised here
+                6 if dt != DType::BF16 => x.matmul(randn(shape![B, N, M], dt)?, false, false)?,
+                7 => {
+                    let indices = (0..M).map(|i| ((i * 7) % M) as i32).collect::<Vec<_>>();
+                    let indices = Tensor::from_data(indices, shape![M], Device::CPU);
+                    x.index_select(indices, 1)?
+                }
+                8 => x
+                    .slice(&[0..B, 0..M, 0..1])?
+                    .broadcast_to(shape![B, M, N])?,
+                9 => x.view(shape![1, B * M, N])?,
+                10 => {
+                    let via = if dt == DType::F32 {
+                        DType::F16
+                    } else {
+                        DType::F32
+                    };
+                    x.cast(via)?.cast(dt)?
+                }
+                11 if dt == DType::F32 && N % 8 == 0 => x
+                    .view(shape![B, 1, M, N])?
+                    .rope(N, 10000., 2)?
+                    .view(shape![B, M, N])?,
+                12 => {
+                    let (k, v) = (
+                        randn(shape![B, 1, M, N], dt)?,
+                        randn(shape![B, 1, M, N], dt)?,
+                    );
+                    let scale = 1. / (N as f32).sqrt();
+                    x.view(shape![B, 1, M, N])?
+                        .attention(k, v, AttentionMask::Causal, scale)?
+                        .view(shape![B, M, N])?
+                }
+                _ => x,
+            },
+        })
+    }
+
+    /// Largest error of each operation, in ULPs of the largest element of its output.
+    fn tolerance(op: &str) -> f64 {
+        match op {
+            "Permute" | "Slice" | "Broadcast" | "Concat" | "IndexSelect" | "View" | "Cast"
+            | "ArgMax" | "Abs" | "Neg" | "Relu" | "Floor" | "Ceil" => 0.,
+            "Matmul" | "Norm" | "Softmax" | "RoPE" | "Attention" => 256.,
+            _ => 16.,
+        }
+    }
+
+    fn run_graph(problem: GraphProblem) -> anyhow::Result<UlpReport> {
+        let GraphProblem {
+            B,
+            M,
+            N,
+            dt,
+            steps,
+            argmax,
+        } = problem;
+        let mut x = randn(shape![B, M, N], dt)?;
+        for step in steps {
+            x = apply_step(x, step, dt)?;
+        }
+        if argmax {
+            x = x.argmax(2)?;
+        }
+        compare(&x.resolve()?)
+    }
+
+    #[test]
+    fn kernels_match_reference() {
+        let mut runner = proptest::test_runner::TestRunner::new(ProptestConfig::with_cases(64));
+        let result = runner.run(&any::<GraphProblem>(), |problem| {
+            let graph = format!("{:?}", problem);
+            let ours = run_graph(problem).map_err(|e| TestCaseError::fail(e.to_string()))?;
+            for s in ours.stats() {
+                prop_assert!(
+                    s.max_scaled <= tolerance(&s.op),
+                    "{} on {} is off by {} ULPs in {}",
+                    s.op,
+                    s.dt,
+                    s.max_scaled,
+                    graph
+                );
+            }
+            Ok(())
+        });
+        result.unwrap();
+    }
+}
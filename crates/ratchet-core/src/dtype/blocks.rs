@@ -3,7 +3,7 @@
 ///
 /// We closely follow the memory layout of the original GGUF implementation,
 /// but often need 2 variants of each block type for devices that don't support f16.
-use crate::{rvec, Align, BufferSegment, DType, RVec, TensorDType};
+use crate::{rvec, Align, BufferSegment, DType, RVec, TensorDType, MIN_STORAGE_BUFFER_SIZE};
 use derive_new::new;
 use half::f16;
 use num_traits::{AsPrimitive, Float, FromPrimitive, NumAssign};
@@ -217,3 +217,60 @@ impl Quantized for Q4_KH {
         DType::Q4_KH(Q4_KH::default())
     }
 }
+
+// ================== GGML blocks ==================
+/// # GGML Block
+///
+/// Quantization formats that are stored exactly as the packed GGML blocks found in GGUF files.
+///
+/// Unlike [Q8_0F] or [Q4_KF], these are not split into segments. Kernels read the block scales
+/// straight out of the packed bytes (unpacking f16 scales in f32), so a single variant serves
+/// devices with and without `SHADER_F16`.
+pub trait GGMLBlock {
+    /// Number of elements in a block
+    const BLCK_NUMEL: usize;
+    /// Size of the block in bytes
+    const TYPE_SIZE: usize;
+
+    fn dt() -> DType;
+}
+
+macro_rules! ggml_block {
+    ($name:ident, $numel:expr, $size:expr) => {
+        #[derive(Debug, Copy, Clone, PartialEq, Default)]
+        pub struct $name;
+
+        impl GGMLBlock for $name {
+            const BLCK_NUMEL: usize = $numel;
+            const TYPE_SIZE: usize = $size;
+
+            fn dt() -> DType {
+                DType::$name($name)
+            }
+        }
+
+        impl Segments for $name {
+            fn segments(&self, numel: usize) -> RVec<BufferSegment> {
+                let nbytes = numel / Self::BLCK_NUMEL * Self::TYPE_SIZE;
+                let nbytes = nbytes.max(MIN_STORAGE_BUFFER_SIZE).align_for_copy();
+                rvec![BufferSegment::new(0, nbytes as u64)]
+            }
+        }
+    };
+}
+
+ggml_block!(Q4_0, QK4_0, 2 + QK4_0 / 2);
+ggml_block!(Q4_1, QK4_1, 2 * 2 + QK4_1 / 2);
+ggml_block!(Q5_0, QK5_0, 2 + 4 + QK5_0 / 2);
+ggml_block!(Q5_1, QK5_1, 2 * 2 + 4 + QK5_1 / 2);
+ggml_block!(Q2_K, QK_K, QK_K / 16 + QK_K / 4 + 2 * 2);
+ggml_block!(Q3_K, QK_K, QK_K / 8 + QK_K / 4 + 12 + 2);
+ggml_block!(Q5_K, QK_K, 2 * 2 + K_SCALE_SIZE + QK_K / 8 + QK_K / 2);
+ggml_block!(Q6_K, QK_K, QK_K / 2 + QK_K / 4 + QK_K / 16 + 2);
+
+const _: () = assert!(Q4_0::TYPE_SIZE == 18);
+const _: () = assert!(Q5_1::TYPE_SIZE == 24);
+const _: () = assert!(Q2_K::TYPE_SIZE == 84);
+const _: () = assert!(Q3_K::TYPE_SIZE == 110);
+const _: () = assert!(Q5_K::TYPE_SIZE == 176);
+const _: () = assert!(Q6_K::TYPE_SIZE == 210);
@@ -20,6 +20,14 @@ pub enum DType {
     Q8_0F(Q8_0F), //Equivalent to GGUF Q8_0, with f32
     Q4_KH(Q4_KH), //Equivalent to GGUF Q4_K, with f16
     Q4_KF(Q4_KF), //Equivalent to GGUF Q4_K, with f32
+    Q4_0(Q4_0),   //GGUF Q4_0 blocks, stored verbatim
+    Q4_1(Q4_1),   //GGUF Q4_1 blocks, stored verbatim
+    Q5_0(Q5_0),   //GGUF Q5_0 blocks, stored verbatim
+    Q5_1(Q5_1),   //GGUF Q5_1 blocks, stored verbatim
+    Q2_K(Q2_K),   //GGUF Q2_K blocks, stored verbatim
+    Q3_K(Q3_K),   //GGUF Q3_K blocks, stored verbatim
+    Q5_K(Q5_K),   //GGUF Q5_K blocks, stored verbatim
+    Q6_K(Q6_K),   //GGUF Q6_K blocks, stored verbatim
 }
 
 impl std::fmt::Display for DType {
@@ -48,6 +56,14 @@ impl DType {
             DType::Q8_0F(_) => "Q8_0F",
             DType::Q4_KH(_) => "Q4_KH",
             DType::Q4_KF(_) => "Q4_KF",
+            DType::Q4_0(_) => "Q4_0",
+            DType::Q4_1(_) => "Q4_1",
+            DType::Q5_0(_) => "Q5_0",
+            DType::Q5_1(_) => "Q5_1",
+            DType::Q2_K(_) => "Q2_K",
+            DType::Q3_K(_) => "Q3_K",
+            DType::Q5_K(_) => "Q5_K",
+            DType::Q6_K(_) => "Q6_K",
         }
     }
 
@@ -73,6 +89,14 @@ impl DType {
             DType::Q8_0F(_) => std::mem::size_of::<BlockQ8_0F>(),
             DType::Q4_KH(_) => std::mem::size_of::<BlockQ4_KH>(),
             DType::Q4_KF(_) => std::mem::size_of::<BlockQ4_KF>(),
+            DType::Q4_0(_) => Q4_0::TYPE_SIZE,
+            DType::Q4_1(_) => Q4_1::TYPE_SIZE,
+            DType::Q5_0(_) => Q5_0::TYPE_SIZE,
+            DType::Q5_1(_) => Q5_1::TYPE_SIZE,
+            DType::Q2_K(_) => Q2_K::TYPE_SIZE,
+            DType::Q3_K(_) => Q3_K::TYPE_SIZE,
+            DType::Q5_K(_) => Q5_K::TYPE_SIZE,
+            DType::Q6_K(_) => Q6_K::TYPE_SIZE,
         }
     }
 
@@ -80,9 +104,39 @@ impl DType {
         matches!(
             self,
             DType::Q8_0H(_) | DType::Q8_0F(_) | DType::Q4_KH(_) | DType::Q4_KF(_)
+        ) || self.is_ggml_block()
+    }
+
+    /// Quantized types stored as verbatim GGML blocks, see [GGMLBlock].
+    pub fn is_ggml_block(self) -> bool {
+        matches!(
+            self,
+            DType::Q4_0(_)
+                | DType::Q4_1(_)
+                | DType::Q5_0(_)
+                | DType::Q5_1(_)
+                | DType::Q2_K(_)
+                | DType::Q3_K(_)
+                | DType::Q5_K(_)
+                | DType::Q6_K(_)
         )
     }
 
+    /// Returns the number of elements in a block of a GGML block type.
+    pub fn ggml_block_numel(self) -> Option<usize> {
+        match self {
+            DType::Q4_0(_) => Some(Q4_0::BLCK_NUMEL),
+            DType::Q4_1(_) => Some(Q4_1::BLCK_NUMEL),
+            DType::Q5_0(_) => Some(Q5_0::BLCK_NUMEL),
+            DType::Q5_1(_) => Some(Q5_1::BLCK_NUMEL),
+            DType::Q2_K(_) => Some(Q2_K::BLCK_NUMEL),
+            DType::Q3_K(_) => Some(Q3_K::BLCK_NUMEL),
+            DType::Q5_K(_) => Some(Q5_K::BLCK_NUMEL),
+            DType::Q6_K(_) => Some(Q6_K::BLCK_NUMEL),
+            _ => None,
+        }
+    }
+
     pub fn is_q8(self) -> bool {
         matches!(self, DType::Q8_0H(_) | DType::Q8_0F(_))
     }
@@ -102,6 +156,8 @@ impl DType {
             DType::Q8_0F(_) => DType::F32,
             DType::Q4_KH(_) => DType::F16,
             DType::Q4_KF(_) => DType::F32,
+            //GGML blocks are dequantized in f32, and accept either F32 or F16 activations
+            dt if dt.is_ggml_block() => DType::F32,
             _ => *self,
         }
     }
@@ -112,6 +168,14 @@ impl DType {
             DType::Q8_0H(q) => q.segments(numel),
             DType::Q4_KF(q) => q.segments(numel),
             DType::Q4_KH(q) => q.segments(numel),
+            DType::Q4_0(q) => q.segments(numel),
+            DType::Q4_1(q) => q.segments(numel),
+            DType::Q5_0(q) => q.segments(numel),
+            DType::Q5_1(q) => q.segments(numel),
+            DType::Q2_K(q) => q.segments(numel),
+            DType::Q3_K(q) => q.segments(numel),
+            DType::Q5_K(q) => q.segments(numel),
+            DType::Q6_K(q) => q.segments(numel),
             _ => {
                 let mut total_bytes = numel * self.size_of();
                 total_bytes = max(total_bytes, MIN_STORAGE_BUFFER_SIZE).align_for_copy();
@@ -1,22 +1,30 @@
-use crate::gpu::{GpuUniform, PoolError, StaticResourcePoolAccessor, WgpuDevice};
-use crate::CompiledOp;
+use crate::gpu::{
+    write_padded, Align, CpuUniform, GpuUniform, PoolError, StaticResourcePoolAccessor, WgpuDevice,
+    WorkgroupCount,
+};
+use crate::{
+    CompiledOp, DeviceStorage, GPUBuffer, LazyOp, OperationError, Specialization, Storage, Tensor,
+    TensorId, MIN_STORAGE_BUFFER_SIZE,
+};
 use derive_new::new;
-#[cfg(not(feature = "debug"))]
-use std::marker::PhantomData;
+use rustc_hash::FxHashSet;
 use wgpu::SubmissionIndex;
 
 /// # Executable
 ///
 /// A linear sequence of compiled operations, with a single uniform buffer
 /// containing metadata for all operations.
+///
+/// An executable created by [Tensor::compile] owns every buffer and bind group of its graph,
+/// and can be [run](Executable::run) again with new inputs.
 #[derive(new)]
-pub struct Executable<'t> {
+pub struct Executable {
     steps: Vec<CompiledOp>,
     gpu_uniform: GpuUniform,
-    #[cfg(feature = "debug")]
-    debug_list: Vec<&'t Tensor>,
-    #[cfg(not(feature = "debug"))]
-    _phantom: PhantomData<&'t ()>,
+    /// The tensor computed by each step.
+    dsts: Vec<Tensor>,
+    output: Tensor,
+    device: WgpuDevice,
 }
 
 //this error ExecutionError
@@ -26,11 +34,204 @@ pub enum ExecutionError {
     PipelineNotFound(#[from] PoolError),
     #[error("Failed during debugging: {0}")]
     DebuggingError(&'static str),
+    #[error("Tensor {0:?} is not an input of the executable")]
+    UnknownInput(TensorId),
+    #[error("Input {0:?} expected {1}, got {2}")]
+    InputMismatch(TensorId, String, String),
+    #[error("Input {0:?} has no storage")]
+    MissingStorage(TensorId),
+    #[error("Failed to write metadata of {0:?}: {1}")]
+    MetadataError(TensorId, #[source] OperationError),
 }
 
-impl Executable<'_> {
+impl Executable {
+    /// Run the captured graph again, after replacing the contents of its inputs.
+    ///
+    /// Each pair is an input captured in the graph, i.e a constant the output depends on, and
+    /// its new value of the same shape and dtype. Inputs which are not provided keep their
+    /// contents. Metadata is re-encoded from the captured operations.
+    ///
+    /// The returned tensor is the captured output, its buffer is overwritten by the next run.
+    pub fn run(&self, inputs: &[(&Tensor, &Tensor)]) -> Result<Tensor, ExecutionError> {
+        let leaves = self
+            .output
+            .execution_order()
+            .into_iter()
+            .filter(|t| matches!(t.op(), LazyOp::Const))
+            .map(|t| t.id())
+            .collect::<Vec<_>>();
+        for (input, value) in inputs {
+            if !leaves.contains(&input.id()) {
+                return Err(ExecutionError::UnknownInput(input.id()));
+            }
+            if input.shape() != value.shape() || input.dt() != value.dt() {
+                return Err(ExecutionError::InputMismatch(
+                    input.id(),
+                    format!("{:?} {:?}", input.shape(), input.dt()),
+                    format!("{:?} {:?}", value.shape(), value.dt()),
+                ));
+            }
+        }
+
+        let dsts = self.dsts.iter().collect::<Vec<_>>();
+        let (uniform, _) = self.specialize(&dsts)?;
+        self.replay(inputs, uniform, None)?;
+        Ok(self.output.clone())
+    }
+
+    /// Run the captured operations on behalf of `graph`, a rebuild of the captured graph with
+    /// different shapes, e.g for another binding of a [Symbol](crate::Symbol).
+    ///
+    /// Returns `None` if `graph` can't be computed by the captured pipelines and buffers: it
+    /// differs in structure, a kernel key differs, or a tensor doesn't fit. Otherwise the
+    /// inputs of `graph` are copied into the captured inputs, and its root is given the
+    /// captured output buffer.
+    pub(crate) fn rebind(&self, graph: &Tensor) -> Result<Option<Tensor>, ExecutionError> {
+        let captured = self.output.execution_order();
+        let rebuilt = graph.execution_order();
+        if captured.len() != rebuilt.len() {
+            return Ok(None);
+        }
+
+        let step_ids = self.dsts.iter().map(|t| t.id()).collect::<FxHashSet<_>>();
+        let mut inputs = vec![];
+        let mut dsts = vec![];
+        for (&old, &new) in captured.iter().zip(rebuilt.iter()) {
+            if old.op().name() != new.op().name() || old.dt() != new.dt() {
+                return Ok(None);
+            }
+            if matches!(old.op(), LazyOp::View(_)) {
+                continue;
+            }
+            let capacity = old
+                .storage()
+                .as_ref()
+                .and_then(|s| s.try_gpu().ok())
+                .map(|b| b.size)
+                .ok_or(ExecutionError::MissingStorage(old.id()))?;
+            if new.num_bytes() as u64 > capacity {
+                return Ok(None);
+            }
+
+            if step_ids.contains(&old.id()) {
+                dsts.push(new);
+            } else if old.id() != new.id() {
+                inputs.push((old, new));
+            }
+        }
+
+        let (uniform, specializations) = self.specialize(&dsts)?;
+        let matches = self
+            .steps
+            .iter()
+            .zip(specializations.iter())
+            .all(|(step, s)| step.kernel_key == s.key);
+        if !matches {
+            return Ok(None);
+        }
+
+        let counts = specializations
+            .into_iter()
+            .map(|s| s.workload.workgroup_count)
+            .collect::<Vec<_>>();
+        self.replay(&inputs, uniform, Some(&counts))?;
+
+        let output = self.output.storage();
+        let output = output
+            .as_ref()
+            .and_then(|s| s.try_gpu().ok())
+            .ok_or(ExecutionError::MissingStorage(self.output.id()))?;
+        let size = (graph
+            .num_bytes()
+            .max(MIN_STORAGE_BUFFER_SIZE)
+            .align_for_copy() as u64)
+            .min(output.size);
+        graph.update_storage(Storage::GPU(GPUBuffer::sub_range(
+            output.inner.clone(),
+            output.offset(),
+            size,
+            graph.dt().size_of(),
+        )));
+        Ok(Some(graph.clone()))
+    }
+
+    /// Encode the metadata of every step for `dsts`, the tensors computed by each step.
+    fn specialize(
+        &self,
+        dsts: &[&Tensor],
+    ) -> Result<(CpuUniform, Vec<Specialization>), ExecutionError> {
+        let mut uniform = CpuUniform::new();
+        let mut specializations = Vec::with_capacity(dsts.len());
+        for (step, dst) in self.steps.iter().zip(dsts.iter()) {
+            let specialization = dst
+                .specialize(&mut uniform, step.inplace())
+                .map_err(|e| ExecutionError::MetadataError(dst.id(), e))?;
+            debug_assert_eq!(specialization.offset as u32, step.offset());
+            specializations.push(specialization);
+        }
+        Ok((uniform, specializations))
+    }
+
+    /// Copy each value into the input it replaces, write the uniform and dispatch every step.
+    fn replay(
+        &self,
+        inputs: &[(&Tensor, &Tensor)],
+        uniform: CpuUniform,
+        workgroup_counts: Option<&[WorkgroupCount]>,
+    ) -> Result<(), ExecutionError> {
+        let device = &self.device;
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        for (input, value) in inputs {
+            let dst_storage = input.storage();
+            let dst = dst_storage
+                .as_ref()
+                .and_then(|s| s.try_gpu().ok())
+                .ok_or(ExecutionError::MissingStorage(input.id()))?;
+            match value.storage().as_ref() {
+                Some(Storage::CPU(src)) => {
+                    write_padded(device, &dst.inner, dst.offset(), src.inner().as_bytes());
+                }
+                Some(Storage::GPU(src)) => encoder.copy_buffer_to_buffer(
+                    &src.inner,
+                    src.offset(),
+                    &dst.inner,
+                    dst.offset(),
+                    value.num_bytes().align_for_copy() as u64,
+                ),
+                None => return Err(ExecutionError::MissingStorage(value.id())),
+            }
+        }
+        device.queue().submit(Some(encoder.finish()));
+        self.gpu_uniform.write(uniform, device);
+
+        let index = match workgroup_counts {
+            Some(counts) => self.dispatch_with(device, counts)?,
+            None => self.dispatch(device)?,
+        };
+        device.poll(wgpu::MaintainBase::WaitForSubmissionIndex(index));
+        Ok(())
+    }
+}
+
+impl Executable {
     #[cfg(not(feature = "gpu-profiling"))]
     pub fn dispatch(&self, device: &WgpuDevice) -> Result<SubmissionIndex, ExecutionError> {
+        let counts = self
+            .steps
+            .iter()
+            .map(|s| s.workgroup_count().clone())
+            .collect::<Vec<_>>();
+        self.dispatch_with(device, &counts)
+    }
+
+    /// Dispatch every step with its own number of workgroups.
+    #[cfg(not(feature = "gpu-profiling"))]
+    fn dispatch_with(
+        &self,
+        device: &WgpuDevice,
+        workgroup_counts: &[WorkgroupCount],
+    ) -> Result<SubmissionIndex, ExecutionError> {
         let pipeline_resources = device.pipeline_resources();
         let mut encoder =
             device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
@@ -40,7 +241,7 @@ impl Executable<'_> {
                 label: Some("ratchet inference pass"),
                 timestamp_writes: None,
             });
-            for step in self.steps.iter() {
+            for (step, workgroup_count) in self.steps.iter().zip(workgroup_counts) {
                 cpass.set_pipeline(pipeline_resources.get(step.pipeline_handle())?);
 
                 for (group_index, bind_group) in step.storage_groups().iter().enumerate() {
@@ -51,7 +252,7 @@ impl Executable<'_> {
                 let uniform_group = self.gpu_uniform.bind_group();
                 cpass.set_bind_group(uniform_group_index, uniform_group, &[step.offset()]);
 
-                let [x_count, y_count, z_count] = step.workgroup_count().as_slice();
+                let [x_count, y_count, z_count] = workgroup_count.as_slice();
                 cpass.dispatch_workgroups(x_count, y_count, z_count);
             }
         }
@@ -63,10 +264,10 @@ impl Executable<'_> {
         &self,
         device: &WgpuDevice,
     ) -> Result<SubmissionIndex, ExecutionError> {
-        use crate::{wgpu_buffer_to_cpu_buffer, DeviceStorage};
+        use crate::wgpu_buffer_to_cpu_buffer;
 
         let pipeline_resources = device.pipeline_resources();
-        assert!(self.debug_list.len() == self.steps.len());
+        assert!(self.dsts.len() == self.steps.len());
 
         let mut last_index = None;
         for (step_index, step) in self.steps.iter().enumerate() {
@@ -92,14 +293,13 @@ impl Executable<'_> {
                 cpass.dispatch_workgroups(x_count, y_count, z_count);
             }
 
-            let result_t = self.debug_list[step_index].clone();
+            let result_t = self.dsts[step_index].clone();
             let gpu_storage = result_t.storage();
-            let result_buf = &gpu_storage
+            let result_buf = gpu_storage
                 .as_ref()
                 .ok_or(ExecutionError::DebuggingError("Failed to get result buf."))?
                 .try_gpu()
-                .map_err(|_| ExecutionError::DebuggingError("Failed to get result buf."))?
-                .inner;
+                .map_err(|_| ExecutionError::DebuggingError("Failed to get result buf."))?;
 
             let debug_buffer = step
                 .debug_buffer
@@ -107,7 +307,13 @@ impl Executable<'_> {
                 .ok_or(ExecutionError::DebuggingError(
                     "Failed to get debug buffer.",
                 ))?;
-            encoder.copy_buffer_to_buffer(result_buf, 0, debug_buffer, 0, debug_buffer.size());
+            encoder.copy_buffer_to_buffer(
+                &result_buf.inner,
+                result_buf.offset(),
+                debug_buffer,
+                0,
+                debug_buffer.size(),
+            );
 
             let index = device.queue().submit(Some(encoder.finish()));
             last_index = Some(index);
@@ -116,7 +322,7 @@ impl Executable<'_> {
         //Dump all of our debug results
         for (si, step) in self.steps.iter().enumerate() {
             let d = device.clone();
-            let dt = self.debug_list[si].dt();
+            let dt = self.dsts[si].dt();
             let debug_buffer = step.debug_buffer.clone().unwrap();
             let alignment = dt.size_of();
             let kernel_key = step.kernel_key.clone();
@@ -178,3 +384,48 @@ impl Executable<'_> {
         Ok(index)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::ExecutionError;
+    use crate::{shape, Device, DeviceRequest, Tensor};
+
+    fn graph(x: Tensor, w: Tensor) -> anyhow::Result<Tensor> {
+        Ok(x.matmul(w, false, false)?.gelu()?)
+    }
+
+    #[test]
+    fn replays_with_new_inputs() -> anyhow::Result<()> {
+        let device = Device::request_device(DeviceRequest::GPU)?;
+        let x = Tensor::randn::<f32>(shape![4, 16], Device::CPU).to(&device)?;
+        let w = Tensor::randn::<f32>(shape![16, 8], Device::CPU).to(&device)?;
+        let exe = graph(x.clone(), w.clone())?.compile()?;
+
+        for _ in 0..3 {
+            let value = Tensor::randn::<f32>(shape![4, 16], Device::CPU);
+            let w_cpu = w.to(&Device::CPU)?;
+            let ground = graph(value.clone(), w_cpu)?.resolve()?;
+
+            let result = exe.run(&[(&x, &value)])?.to(&Device::CPU)?;
+            ground.all_close(&result, 1e-3, 1e-3)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_unknown_inputs() -> anyhow::Result<()> {
+        let device = Device::request_device(DeviceRequest::GPU)?;
+        let x = Tensor::randn::<f32>(shape![4, 16], Device::CPU).to(&device)?;
+        let w = Tensor::randn::<f32>(shape![16, 8], Device::CPU).to(&device)?;
+        let exe = graph(x.clone(), w)?.compile()?;
+
+        let other = Tensor::randn::<f32>(shape![4, 16], Device::CPU);
+        let err = exe.run(&[(&other, &other)]).unwrap_err();
+        assert!(matches!(err, ExecutionError::UnknownInput(_)));
+
+        let wrong = Tensor::randn::<f32>(shape![4, 8], Device::CPU);
+        let err = exe.run(&[(&x, &wrong)]).unwrap_err();
+        assert!(matches!(err, ExecutionError::InputMismatch(..)));
+        Ok(())
+    }
+}
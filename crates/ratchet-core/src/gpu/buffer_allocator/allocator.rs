@@ -1,4 +1,4 @@
-use super::TensorUsageRecord;
+use super::{plan_offsets, PlannerOptions, TensorUsageRecord};
 use crate::{
     gpu::{
         BufferDescriptor, BufferPool, BufferUsagesExt, CpuUniform, GpuBufferHandle,
@@ -17,6 +17,41 @@ pub enum AllocatorError {
     BufferNotFound,
 }
 
+/// The buffer assigned to a tensor during graph allocation.
+///
+/// Intermediates are bound as a sub-range of a larger arena, see [plan_offsets].
+#[derive(Clone, Debug, derive_new::new)]
+pub struct Allocation {
+    pub buffer: PooledGPUBuffer,
+    pub offset: u64,
+}
+
+/// Write `contents` into `buffer` at `offset`, padding the tail to [wgpu::COPY_BUFFER_ALIGNMENT].
+///
+/// Writes must be a multiple of 4 bytes, so the aligned prefix is written as is and only the
+/// remaining tail is padded. This avoids copying large (e.g memory mapped) contents.
+pub(crate) fn write_padded(
+    device: &WgpuDevice,
+    buffer: &wgpu::Buffer,
+    offset: u64,
+    contents: &[u8],
+) {
+    let align = wgpu::COPY_BUFFER_ALIGNMENT as usize;
+    let aligned_len = contents.len() - contents.len() % align;
+    let (prefix, tail) = contents.split_at(aligned_len);
+
+    if !prefix.is_empty() {
+        device.queue().write_buffer(buffer, offset, prefix);
+    }
+    if !tail.is_empty() || contents.is_empty() {
+        let mut padded_tail = vec![0u8; align];
+        padded_tail[..tail.len()].copy_from_slice(tail);
+        device
+            .queue()
+            .write_buffer(buffer, offset + aligned_len as u64, &padded_tail);
+    }
+}
+
 pub struct BufferAllocator {
     pool: RwLock<BufferPool>,
 }
@@ -57,17 +92,8 @@ impl BufferAllocator {
         contents: Cow<'_, [u8]>,
         device: &WgpuDevice,
     ) -> PooledGPUBuffer {
-        //cannot write content to a buffer if it is less than 4 bytes
-        let contents = if contents.len() < wgpu::COPY_BUFFER_ALIGNMENT as _ {
-            let mut min_contents = vec![0u8; wgpu::COPY_BUFFER_ALIGNMENT as _];
-            min_contents[..contents.len()].copy_from_slice(contents.as_ref());
-            Cow::Owned(min_contents)
-        } else {
-            contents
-        };
-
         let buf = self.pool.write().get_or_create(desc, device, true);
-        device.queue().write_buffer(&buf.inner, 0, &contents);
+        write_padded(device, &buf.inner, 0, &contents);
         device.queue().submit(None);
         device.poll(wgpu::Maintain::Wait);
         buf
@@ -92,42 +118,6 @@ impl BufferAllocator {
         resource
     }
 
-    /// # Graph memory allocation
-    ///
-    /// Greedy algorithm, that takes the first buffer larger than the request
-    /// In future, since we know the entire graph and sizes, we can
-    /// do better.
-    fn graph_allocate(
-        &self,
-        descriptor: BufferDescriptor,
-        free: &mut Vec<PooledGPUBuffer>,
-        device: &WgpuDevice,
-    ) -> PooledGPUBuffer {
-        let required_size = descriptor.size as _;
-        let mut closest_index = None;
-        let mut closest_size_diff: Option<usize> = None;
-        for (idx, buffer) in free.iter().enumerate() {
-            let current_size = buffer.descriptor.size as _;
-            if current_size >= required_size {
-                let size_diff = usize::abs_diff(current_size, required_size);
-
-                if closest_size_diff.map_or(true, |diff| size_diff < diff) {
-                    closest_index = Some(idx);
-                    closest_size_diff = Some(size_diff);
-                }
-            }
-        }
-
-        if std::env::var("RATCHET_DEBUG").is_ok() {
-            return self.create_buffer(&descriptor, device, true);
-        }
-
-        match closest_index {
-            Some(idx) => free.remove(idx),
-            None => self.create_buffer(&descriptor, device, true),
-        }
-    }
-
     /// # Inplace operations
     ///
     /// If an operation supports inplace, we need to "lease" the buffer
@@ -161,12 +151,21 @@ impl BufferAllocator {
         true_source
     }
 
+    fn reserved_bytes(t: &Tensor, reserved: &FxHashMap<TensorId, usize>) -> usize {
+        reserved
+            .get(&t.id())
+            .copied()
+            .unwrap_or_default()
+            .max(t.num_bytes())
+    }
+
     //To calculate the tensor usage records, we do the following:
     //1. Traverse topologically sorted graph in reverse order
     //2. When we encounter the last consumer of a tensor, we start recording the interval.
     //3. When we encounter the producer of a tensor, we stop recording the interval.
     fn calculate_usage_records(
         execution_order: &[&Tensor],
+        reserved: &FxHashMap<TensorId, usize>,
     ) -> FxHashMap<TensorId, TensorUsageRecord> {
         let mut records =
             FxHashMap::with_capacity_and_hasher(execution_order.len(), Default::default());
@@ -188,7 +187,7 @@ impl BufferAllocator {
                         last_consumer: topo_len - iter,
                         #[cfg(debug_assertions)]
                         last_consumer_id: t.id(),
-                        size: true_source.num_bytes(),
+                        size: Self::reserved_bytes(true_source, reserved),
                     });
             }
 
@@ -204,48 +203,80 @@ impl BufferAllocator {
         records
     }
 
+    //Pairs of records that are bound in the same dispatch, with at least one of them writable.
+    //WebGPU tracks buffer usage per buffer, not per range, so these can't share an arena.
+    fn calculate_conflicts(
+        execution_order: &[&Tensor],
+        records: &TensorUsageRecords,
+    ) -> Vec<(usize, usize)> {
+        let indices = records
+            .0
+            .iter()
+            .enumerate()
+            .map(|(idx, r)| (r.id.unwrap(), idx))
+            .collect::<FxHashMap<_, _>>();
+
+        let mut conflicts = vec![];
+        for t in execution_order.iter().filter(|t| !t.resolved()) {
+            let Some(&dst) = indices.get(&Self::determine_tensor_source(t).id()) else {
+                continue;
+            };
+            for source in t.op().srcs() {
+                let src_id = Self::determine_tensor_source(source).id();
+                if let Some(&src) = indices.get(&src_id).filter(|&&src| src != dst) {
+                    conflicts.push((dst, src));
+                }
+            }
+        }
+        conflicts
+    }
+
     //https://arxiv.org/pdf/2001.03288.pdf + inplace support
     //Takes in const assignments as inplace may be performed on constants
-    pub fn greedy_by_size(
+    pub fn plan_intermediates(
         &self,
         execution_order: &[&Tensor],
-        assignments: &mut FxHashMap<TensorId, PooledGPUBuffer>,
+        reserved: &FxHashMap<TensorId, usize>,
+        assignments: &mut FxHashMap<TensorId, Allocation>,
         device: &WgpuDevice,
     ) -> Result<(), DeviceError> {
-        let record_map = Self::calculate_usage_records(execution_order);
+        let mut record_map = Self::calculate_usage_records(execution_order, reserved);
+        //The output is given a buffer of its own, so it doesn't keep an arena alive
+        let output_source = Self::determine_tensor_source(execution_order.last().unwrap());
+        record_map.remove(&output_source.id());
+
         let records = TensorUsageRecords::from(record_map);
-        let mut shared_objects: Vec<PooledGPUBuffer> = Vec::with_capacity(records.0.len());
+        let conflicts = Self::calculate_conflicts(execution_order, &records);
+        let options = PlannerOptions {
+            max_arena_size: device.compute_limits().max_storage_buffer_binding_size as _,
+            ..Default::default()
+        };
+        let plan = plan_offsets(&records.0, &conflicts, options);
+        if log::log_enabled!(log::Level::Debug) {
+            log::debug!("{}", plan.stats(&records.0, options.alignment));
+        }
 
-        for record in records.0.iter() {
-            let record_producer = record.producer.unwrap();
-            let mut best_obj = None;
-            for obj in shared_objects.iter() {
-                let mut suitable = true;
-                for inner_r in records.0.iter() {
-                    let max_first = std::cmp::max(record_producer, inner_r.producer.unwrap());
-                    let min_last = std::cmp::min(record.last_consumer, inner_r.last_consumer);
-                    if max_first <= min_last && assignments.get(&inner_r.id.unwrap()) == Some(obj) {
-                        suitable = false;
-                        break;
-                    }
-                }
-                if suitable {
-                    best_obj = Some(obj);
-                }
-            }
-            if let Some(obj) = best_obj {
-                assignments.insert(record.id.unwrap(), (*obj).clone());
-            } else {
-                //let rounded_size = (record.size - 1).next_power_of_two();
-                let rounded_size = record.size;
-                let buf = self.create_buffer(
-                    &BufferDescriptor::new(rounded_size as _, BufferUsages::standard(), false),
-                    device,
-                    false,
-                );
-                shared_objects.push(buf.clone());
-                assignments.insert(record.id.unwrap(), buf);
-            }
+        let arenas = plan
+            .arenas
+            .iter()
+            .map(|&size| {
+                let descriptor = BufferDescriptor::new(size as _, BufferUsages::standard(), false);
+                self.create_buffer(&descriptor, device, false)
+            })
+            .collect::<Vec<_>>();
+        for (record, placement) in records.0.iter().zip(plan.placements.iter()) {
+            let allocation =
+                Allocation::new(arenas[placement.arena].clone(), placement.offset as _);
+            assignments.insert(record.id.unwrap(), allocation);
+        }
+        if !assignments.contains_key(&output_source.id()) {
+            let descriptor = BufferDescriptor::new(
+                Self::reserved_bytes(output_source, reserved) as _,
+                BufferUsages::standard(),
+                false,
+            );
+            let buffer = self.create_buffer(&descriptor, device, false);
+            assignments.insert(output_source.id(), Allocation::new(buffer, 0));
         }
 
         //Loop through and add inplace assignments
@@ -256,8 +287,8 @@ impl BufferAllocator {
             for source in t.op().srcs() {
                 let true_source = Self::determine_tensor_source(source);
                 if true_source.id() != source.id() {
-                    if let Some(buf) = assignments.get(&true_source.id()) {
-                        assignments.insert(source.id(), buf.clone());
+                    if let Some(allocation) = assignments.get(&true_source.id()) {
+                        assignments.insert(source.id(), allocation.clone());
                     }
                 }
             }
@@ -272,37 +303,36 @@ impl BufferAllocator {
 
     /// # Graph memory allocation
     ///
-    /// Simple greedy algorithm
-    /// 1. Iterate over all tensors in reverse order (leaf -> root)
-    /// 2. For each tensor, loop through it's input values.
-    ///     a. Assign a buffer for each input value, if it is not already assigned
-    ///     b. If the input value is an inplace operation, traverse upwards until we find
-    ///        the "true" buffer source (i.e the first non-inplace operation).
-    /// 3. We release our **output** buffer, because the value is no longer needed,
-    ///    and earlier tensors can use it.
+    /// 1. Calculate the usage interval of every intermediate, traversing upwards through
+    ///    inplace operations to find the "true" buffer source (i.e the first non-inplace operation).
+    /// 2. Plan the offset of every intermediate within a few large arenas, see [plan_offsets].
+    /// 3. Allocate the arenas, intermediates are bound as sub-ranges.
+    ///
+    /// `reserved` holds the number of bytes to set aside for some tensors, if larger than their
+    /// own size, see [DynamicExecutable](crate::DynamicExecutable).
     pub fn allocate_cfg(
         &self,
         execution_order: &[&Tensor],
+        reserved: &FxHashMap<TensorId, usize>,
         device: &WgpuDevice,
-    ) -> Result<FxHashMap<TensorId, PooledGPUBuffer>, DeviceError> {
-        let mut free = Vec::with_capacity(execution_order.len()); //TODO: switch to BTreeMap
+    ) -> Result<FxHashMap<TensorId, Allocation>, DeviceError> {
         let mut assignments =
             FxHashMap::with_capacity_and_hasher(execution_order.len(), Default::default());
         //Assignments already needs all of the constants in it.
         for t in execution_order.iter().rev().filter(|t| t.resolved()) {
             //Consts are immediately resolved
+            //Tensors resolved in a previous pass may still live within an arena
             let storage_guard = t.storage();
-            let pooled = storage_guard
+            let gpu_buf = storage_guard
                 .as_ref()
                 .ok_or(AllocatorError::BufferNotFound)?
-                .try_gpu()?
-                .inner
-                .clone();
-            assignments.insert(t.id(), pooled);
+                .try_gpu()?;
+            let allocation = Allocation::new(gpu_buf.inner.clone(), gpu_buf.offset());
+            assignments.insert(t.id(), allocation);
         }
 
         //Allocate intermediates
-        self.greedy_by_size(execution_order, &mut assignments, device)?;
+        self.plan_intermediates(execution_order, reserved, &mut assignments, device)?;
 
         //The output tensor is a special case.
         //We know we need an allocation for the output.
@@ -311,20 +341,7 @@ impl BufferAllocator {
         //more efficiently in future.
         let output = execution_order.last().unwrap();
         let output_source = Self::determine_tensor_source(output);
-        let output_buffer = assignments
-            .get(&output_source.id())
-            .cloned()
-            .unwrap_or_else(|| {
-                self.graph_allocate(
-                    BufferDescriptor::new(
-                        output_source.num_bytes() as _,
-                        BufferUsages::standard(),
-                        false,
-                    ),
-                    &mut free,
-                    device,
-                )
-            });
+        let output_buffer = assignments[&output_source.id()].clone();
         assignments.insert(output.id(), output_buffer);
 
         log::debug!(
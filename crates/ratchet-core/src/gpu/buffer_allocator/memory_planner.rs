@@ -0,0 +1,377 @@
+//! # Memory planning
+//!
+//! Static planning of intermediate memory, given the usage interval & size of every tensor.
+//! Implements the "greedy by size" strategies for both shared objects and offset calculation
+//! from https://arxiv.org/pdf/2001.03288.pdf.
+//!
+//! The planners are pure functions of [TensorUsageRecord]s, so they can be compared & tested
+//! without a device.
+use super::TensorUsageRecord;
+use crate::Align;
+use std::cmp::Reverse;
+
+/// Where a tensor lives within a [MemoryPlan].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Placement {
+    pub arena: usize,
+    pub offset: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryPlan {
+    /// Placement of each record, indexed the same as the planned records.
+    pub placements: Vec<Placement>,
+    /// Size of each arena in bytes.
+    pub arenas: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PlannerOptions {
+    /// Alignment of every offset, must satisfy `min_storage_buffer_offset_alignment`.
+    pub alignment: usize,
+    /// Arenas are not grown past this size, unless a single tensor requires it.
+    pub max_arena_size: usize,
+}
+
+impl Default for PlannerOptions {
+    fn default() -> Self {
+        Self {
+            alignment: usize::STORAGE_BUFFER_OFFSET_ALIGNMENT,
+            max_arena_size: usize::MAX,
+        }
+    }
+}
+
+fn aligned_size(record: &TensorUsageRecord, alignment: usize) -> usize {
+    record.size + record.size.calculate_alignment(alignment)
+}
+
+fn overlaps(a: &TensorUsageRecord, b: &TensorUsageRecord) -> bool {
+    let max_first = std::cmp::max(a.producer.unwrap_or(0), b.producer.unwrap_or(0));
+    let min_last = std::cmp::min(a.last_consumer, b.last_consumer);
+    max_first <= min_last
+}
+
+/// Indices of `records`, largest first.
+fn size_order(records: &[TensorUsageRecord]) -> Vec<usize> {
+    let mut order = (0..records.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&i| Reverse(records[i].size));
+    order
+}
+
+/// # Offset calculation
+///
+/// Greedy by size offset assignment. Each record, largest first, is placed into the smallest gap
+/// between the records it overlaps with in time, or at the end of the arena if no gap fits.
+///
+/// `conflicts` are pairs of record indices that must not share an arena. WebGPU forbids binding
+/// a buffer as both read only & read write in the same dispatch, so the output of an operation
+/// must live in a different arena to its inputs.
+pub fn plan_offsets(
+    records: &[TensorUsageRecord],
+    conflicts: &[(usize, usize)],
+    options: PlannerOptions,
+) -> MemoryPlan {
+    let mut neighbours = vec![vec![]; records.len()];
+    for &(a, b) in conflicts {
+        neighbours[a].push(b);
+        neighbours[b].push(a);
+    }
+
+    let mut placements: Vec<Option<Placement>> = vec![None; records.len()];
+    let mut arenas: Vec<usize> = vec![];
+    let mut residents: Vec<Vec<usize>> = vec![];
+
+    for idx in size_order(records) {
+        let record = &records[idx];
+        let size = aligned_size(record, options.alignment);
+
+        let mut placed = None;
+        for (arena, resident) in residents.iter().enumerate() {
+            if neighbours[idx]
+                .iter()
+                .any(|&n| placements[n].is_some_and(|p| p.arena == arena))
+            {
+                continue;
+            }
+
+            let mut live = resident
+                .iter()
+                .filter(|&&r| overlaps(record, &records[r]))
+                .map(|&r| {
+                    let offset = placements[r].unwrap().offset;
+                    (
+                        offset,
+                        offset + aligned_size(&records[r], options.alignment),
+                    )
+                })
+                .collect::<Vec<_>>();
+            live.sort_unstable();
+
+            let mut prev_end = 0;
+            let mut best_gap: Option<(usize, usize)> = None;
+            for (start, end) in live {
+                if start >= prev_end {
+                    let gap = start - prev_end;
+                    if gap >= size && best_gap.is_none_or(|(smallest, _)| gap < smallest) {
+                        best_gap = Some((gap, prev_end));
+                    }
+                }
+                prev_end = prev_end.max(end);
+            }
+            let offset = best_gap.map_or(prev_end, |(_, offset)| offset);
+
+            if offset > 0 && offset + size > options.max_arena_size {
+                continue;
+            }
+            placed = Some(Placement { arena, offset });
+            break;
+        }
+
+        let placement = placed.unwrap_or_else(|| {
+            arenas.push(0);
+            residents.push(vec![]);
+            Placement {
+                arena: arenas.len() - 1,
+                offset: 0,
+            }
+        });
+        arenas[placement.arena] = arenas[placement.arena].max(placement.offset + size);
+        residents[placement.arena].push(idx);
+        placements[idx] = Some(placement);
+    }
+
+    MemoryPlan {
+        placements: placements.into_iter().map(Option::unwrap).collect(),
+        arenas,
+    }
+}
+
+/// # Shared objects
+///
+/// Greedy by size shared objects, every record is assigned a whole buffer which it shares with
+/// records it doesn't overlap with in time. Each object is represented as an arena of its own.
+pub fn plan_shared_objects(records: &[TensorUsageRecord], alignment: usize) -> MemoryPlan {
+    let mut placements: Vec<Option<Placement>> = vec![None; records.len()];
+    let mut objects: Vec<usize> = vec![];
+    let mut residents: Vec<Vec<usize>> = vec![];
+
+    for idx in size_order(records) {
+        let record = &records[idx];
+        let suitable = residents
+            .iter()
+            .position(|resident| !resident.iter().any(|&r| overlaps(record, &records[r])));
+        let arena = suitable.unwrap_or_else(|| {
+            //Records are visited largest first, so the first record sizes the object
+            objects.push(aligned_size(record, alignment));
+            residents.push(vec![]);
+            objects.len() - 1
+        });
+        residents[arena].push(idx);
+        placements[idx] = Some(Placement { arena, offset: 0 });
+    }
+
+    MemoryPlan {
+        placements: placements.into_iter().map(Option::unwrap).collect(),
+        arenas: objects,
+    }
+}
+
+impl MemoryPlan {
+    pub fn total_bytes(&self) -> usize {
+        self.arenas.iter().sum()
+    }
+
+    /// Compares this plan against allocating every tensor separately, [plan_shared_objects] and
+    /// the theoretical lower bound.
+    pub fn stats(&self, records: &[TensorUsageRecord], alignment: usize) -> PlanStats {
+        let shared_objects = plan_shared_objects(records, alignment);
+        PlanStats {
+            n_tensors: records.len(),
+            naive_bytes: records.iter().map(|r| aligned_size(r, alignment)).sum(),
+            shared_objects_bytes: shared_objects.total_bytes(),
+            n_shared_objects: shared_objects.arenas.len(),
+            planned_bytes: self.total_bytes(),
+            n_arenas: self.arenas.len(),
+            lower_bound: lower_bound(records, alignment),
+        }
+    }
+}
+
+/// Peak number of bytes live at any point in the execution order, no plan can use less.
+pub fn lower_bound(records: &[TensorUsageRecord], alignment: usize) -> usize {
+    let mut events = records
+        .iter()
+        .flat_map(|r| {
+            let size = aligned_size(r, alignment) as isize;
+            [
+                (r.producer.unwrap_or(0), 0, size),
+                //Intervals are inclusive, so frees are processed after the last consumer
+                (r.last_consumer, 1, -size),
+            ]
+        })
+        .collect::<Vec<_>>();
+    events.sort_unstable();
+
+    let (mut live, mut peak) = (0isize, 0isize);
+    for (_, _, delta) in events {
+        live += delta;
+        peak = peak.max(live);
+    }
+    peak as usize
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlanStats {
+    pub n_tensors: usize,
+    /// Every tensor allocated separately.
+    pub naive_bytes: usize,
+    /// See [plan_shared_objects].
+    pub shared_objects_bytes: usize,
+    pub n_shared_objects: usize,
+    pub planned_bytes: usize,
+    pub n_arenas: usize,
+    /// See [lower_bound].
+    pub lower_bound: usize,
+}
+
+impl std::fmt::Display for PlanStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} tensors: naive {}kb, shared objects {}kb ({} buffers), planned {}kb ({} arenas), lower bound {}kb",
+            self.n_tensors,
+            self.naive_bytes / 1024,
+            self.shared_objects_bytes / 1024,
+            self.n_shared_objects,
+            self.planned_bytes / 1024,
+            self.n_arenas,
+            self.lower_bound / 1024,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TensorId;
+    use proptest::prelude::*;
+
+    fn record(producer: usize, last_consumer: usize, size: usize) -> TensorUsageRecord {
+        TensorUsageRecord {
+            id: None,
+            producer: Some(producer),
+            last_consumer,
+            #[cfg(debug_assertions)]
+            last_consumer_id: TensorId::new(),
+            size,
+        }
+    }
+
+    fn validate(
+        plan: &MemoryPlan,
+        records: &[TensorUsageRecord],
+        conflicts: &[(usize, usize)],
+        alignment: usize,
+    ) {
+        for (i, (a, pa)) in records.iter().zip(&plan.placements).enumerate() {
+            assert_eq!(pa.offset % alignment, 0);
+            assert!(pa.offset + a.size <= plan.arenas[pa.arena]);
+            for (b, pb) in records.iter().zip(&plan.placements).skip(i + 1) {
+                if pa.arena != pb.arena || !overlaps(a, b) {
+                    continue;
+                }
+                let disjoint = pa.offset + a.size <= pb.offset || pb.offset + b.size <= pa.offset;
+                assert!(disjoint, "{:?} & {:?} overlap in memory", a, b);
+            }
+        }
+        for &(a, b) in conflicts {
+            assert_ne!(plan.placements[a].arena, plan.placements[b].arena);
+        }
+    }
+
+    #[test]
+    fn test_chain() {
+        // a -> b -> c -> d, each tensor is consumed by the next operation
+        let records = vec![
+            record(0, 1, 1024),
+            record(1, 2, 4096),
+            record(2, 3, 1024),
+            record(3, 4, 512),
+        ];
+        let conflicts = vec![(0, 1), (1, 2), (2, 3)];
+        let options = PlannerOptions::default();
+        let plan = plan_offsets(&records, &conflicts, options);
+        validate(&plan, &records, &conflicts, options.alignment);
+
+        let stats = plan.stats(&records, options.alignment);
+        assert_eq!(plan.arenas.len(), 2);
+        assert_eq!(stats.naive_bytes, 6656);
+        assert_eq!(stats.planned_bytes, 4096 + 1024);
+        assert!(stats.lower_bound <= stats.planned_bytes);
+    }
+
+    #[test]
+    fn test_gap_reuse() {
+        let records = vec![
+            record(0, 1, 4096),
+            record(0, 5, 2048),
+            record(2, 5, 3072),
+            record(2, 5, 512),
+        ];
+        let options = PlannerOptions::default();
+        let plan = plan_offsets(&records, &[], options);
+        validate(&plan, &records, &[], options.alignment);
+        //The last tensor fits in the gap between the previous two
+        assert_eq!(plan.placements[3].offset, 3072);
+        assert_eq!(plan.arenas, vec![6144]);
+        assert_eq!(plan.total_bytes(), lower_bound(&records, options.alignment));
+    }
+
+    #[test]
+    fn test_max_arena_size() {
+        let records = vec![record(0, 3, 1024), record(0, 3, 1024), record(0, 3, 4096)];
+        let options = PlannerOptions {
+            alignment: 256,
+            max_arena_size: 2048,
+        };
+        let plan = plan_offsets(&records, &[], options);
+        validate(&plan, &records, &[], options.alignment);
+        //The oversized tensor gets an arena of its own
+        assert_eq!(plan.arenas, vec![4096, 2048]);
+    }
+
+    #[test]
+    fn test_shared_objects() {
+        let records = vec![record(0, 1, 4096), record(2, 3, 1024), record(1, 2, 1024)];
+        let plan = plan_shared_objects(&records, 256);
+        validate(&plan, &records, &[], 256);
+        assert_eq!(plan.arenas, vec![4096, 1024]);
+    }
+
+    fn arb_records() -> impl Strategy<Value = Vec<TensorUsageRecord>> {
+        prop::collection::vec((0usize..32, 0usize..8, 1usize..1 << 16), 1..48).prop_map(|v| {
+            v.into_iter()
+                .map(|(producer, duration, size)| record(producer, producer + duration, size))
+                .collect()
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn test_plan_is_valid(records in arb_records(), max_arena_size in 1usize..1 << 18) {
+            //Conflict every tensor with the next one produced
+            let conflicts = (1..records.len())
+                .filter(|&i| overlaps(&records[i - 1], &records[i]))
+                .map(|i| (i - 1, i))
+                .collect::<Vec<_>>();
+            let options = PlannerOptions { alignment: 256, max_arena_size };
+            let plan = plan_offsets(&records, &conflicts, options);
+            validate(&plan, &records, &conflicts, options.alignment);
+
+            let stats = plan.stats(&records, options.alignment);
+            prop_assert!(stats.lower_bound <= stats.planned_bytes);
+            prop_assert!(stats.planned_bytes <= stats.naive_bytes);
+        }
+    }
+}
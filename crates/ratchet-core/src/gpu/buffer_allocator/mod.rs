@@ -1,5 +1,7 @@
 mod allocator;
+mod memory_planner;
 mod tensor_usage_record;
 
 pub use allocator::*;
+pub use memory_planner::*;
 pub use tensor_usage_record::*;
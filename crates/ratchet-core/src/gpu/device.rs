@@ -25,6 +25,10 @@ pub struct WgpuDevice {
     kernel_module_pool: Arc<KernelModulePool>,
     device_limits: DeviceLimits,
     device_features: DeviceFeatures,
+    #[cfg(not(target_arch = "wasm32"))]
+    pipeline_cache: Option<Arc<PipelineCache>>,
+    #[cfg(not(target_arch = "wasm32"))]
+    poller: Arc<Poller>,
     device: Arc<wgpu::Device>,
     queue: Arc<wgpu::Queue>,
 }
@@ -66,6 +70,10 @@ impl WgpuDevice {
         {
             required_features |= wgpu::Features::TIMESTAMP_QUERY;
         }
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            required_features |= adapter.features() & wgpu::Features::PIPELINE_CACHE;
+        }
 
         let mut device_descriptor = wgpu::DeviceDescriptor {
             label: Some("Ratchet"),
@@ -103,6 +111,11 @@ impl WgpuDevice {
 
         log::warn!("Device features: {:?}", features);
 
+        #[cfg(not(target_arch = "wasm32"))]
+        let pipeline_cache =
+            PipelineCache::new(&device, &adapter.get_info(), ShaderCache::global().dir())
+                .map(Arc::new);
+
         Ok(Self {
             queue: Arc::new(queue),
             ordinal: 0,
@@ -115,6 +128,10 @@ impl WgpuDevice {
             device: Arc::new(device),
             device_limits: limits,
             device_features: features,
+            #[cfg(not(target_arch = "wasm32"))]
+            pipeline_cache,
+            #[cfg(not(target_arch = "wasm32"))]
+            poller: Arc::new(Poller::default()),
         })
     }
 
@@ -267,9 +284,11 @@ impl WgpuDevice {
     pub fn allocate_cfg(
         &self,
         execution_order: &[&Tensor],
+        reserved: &FxHashMap<TensorId, usize>,
         device: &WgpuDevice,
-    ) -> Result<FxHashMap<TensorId, PooledGPUBuffer>, DeviceError> {
-        self.buffer_allocator.allocate_cfg(execution_order, device)
+    ) -> Result<FxHashMap<TensorId, Allocation>, DeviceError> {
+        self.buffer_allocator
+            .allocate_cfg(execution_order, reserved, device)
     }
 
     pub fn begin_pass(&self) {
@@ -283,6 +302,34 @@ impl WgpuDevice {
     pub fn compute_limits(&self) -> &DeviceLimits {
         &self.device_limits
     }
+
+    /// Completes once all work submitted to the queue so far has finished, without blocking.
+    pub async fn work_done(&self) {
+        let (tx, rx) = futures_intrusive::channel::shared::oneshot_channel();
+        #[cfg(not(target_arch = "wasm32"))]
+        let guard = self.track_callback();
+        self.queue.on_submitted_work_done(move || {
+            tx.send(()).expect("Failed to send work done");
+            #[cfg(not(target_arch = "wasm32"))]
+            drop(guard);
+        });
+        rx.receive().await;
+    }
+
+    /// Poll the device from a background thread until the returned guard is dropped, see
+    /// [Poller].
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn track_callback(&self) -> PollGuard {
+        self.poller.track(&self.device)
+    }
+
+    /// The wgpu pipeline cache, if the backend supports one, see [ShaderCache].
+    pub(crate) fn pipeline_cache(&self) -> Option<&wgpu::PipelineCache> {
+        #[cfg(not(target_arch = "wasm32"))]
+        return self.pipeline_cache.as_deref().map(PipelineCache::inner);
+        #[cfg(target_arch = "wasm32")]
+        return None;
+    }
 }
 
 #[derive(Clone)]
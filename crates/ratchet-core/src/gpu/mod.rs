@@ -2,10 +2,13 @@ mod align;
 mod buffer_allocator;
 mod device;
 mod pools;
+mod shader_cache;
 mod uniform;
 mod wgsl;
 mod workload;
 
+#[cfg(not(target_arch = "wasm32"))]
+mod poller;
 #[cfg(feature = "gpu-profiling")]
 mod profiler;
 
@@ -13,10 +16,13 @@ pub use align::*;
 pub use buffer_allocator::*;
 pub use device::*;
 pub use pools::*;
+pub use shader_cache::*;
 pub use uniform::*;
 pub use wgsl::*;
 pub use workload::*;
 
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) use poller::*;
 #[cfg(feature = "gpu-profiling")]
 pub use profiler::*;
 
@@ -0,0 +1,76 @@
+use parking_lot::{Condvar, Mutex};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// # Poller
+///
+/// On native, wgpu only runs callbacks (buffer mapping, work done) from `Device::poll`.
+/// The poller calls it from a background thread while callbacks are outstanding, so futures
+/// completed by them can be awaited without blocking the caller.
+///
+/// The thread is spawned by the first [Poller::track], and exits once every [PollGuard] has
+/// been dropped. A single thread serves every session using the device.
+#[derive(Debug, Default)]
+pub(crate) struct Poller {
+    state: Mutex<PollerState>,
+    /// Notified whenever the number of outstanding callbacks changes.
+    changed: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct PollerState {
+    pending: usize,
+    running: bool,
+}
+
+impl Poller {
+    const MIN_BACKOFF: Duration = Duration::from_micros(50);
+    const MAX_BACKOFF: Duration = Duration::from_millis(5);
+
+    /// Poll `device` until the returned guard is dropped, i.e by the callback it is moved into.
+    pub(crate) fn track(self: &Arc<Self>, device: &Arc<wgpu::Device>) -> PollGuard {
+        let mut state = self.state.lock();
+        state.pending += 1;
+        if !state.running {
+            state.running = true;
+            let (poller, device) = (self.clone(), device.clone());
+            std::thread::Builder::new()
+                .name("ratchet-poller".to_string())
+                .spawn(move || poller.run(&device))
+                .expect("Failed to spawn poller thread");
+        }
+        self.changed.notify_one();
+        PollGuard(self.clone())
+    }
+
+    fn run(&self, device: &wgpu::Device) {
+        let mut backoff = Self::MIN_BACKOFF;
+        loop {
+            let queue_empty = device.poll(wgpu::Maintain::Wait).is_queue_empty();
+            let mut state = self.state.lock();
+            if state.pending == 0 {
+                state.running = false;
+                return;
+            }
+            if queue_empty {
+                //The callback is registered but its work not yet submitted, polling would
+                //return immediately, so sleep until a callback is tracked or released
+                self.changed.wait_for(&mut state, backoff);
+                backoff = (backoff * 2).min(Self::MAX_BACKOFF);
+            } else {
+                backoff = Self::MIN_BACKOFF;
+            }
+        }
+    }
+}
+
+/// An outstanding callback, see [Poller::track].
+#[derive(Debug)]
+pub(crate) struct PollGuard(Arc<Poller>);
+
+impl Drop for PollGuard {
+    fn drop(&mut self) {
+        self.0.state.lock().pending -= 1;
+        self.0.changed.notify_one();
+    }
+}
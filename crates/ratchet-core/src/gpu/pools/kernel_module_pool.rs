@@ -1,4 +1,7 @@
-use crate::{Kernel, KernelKey, KernelSource, OperationError, Tensor, WgpuDevice, WorkgroupSize};
+use crate::{
+    gpu::ShaderCache, Kernel, KernelKey, KernelSource, OperationError, Tensor, WgpuDevice,
+    WorkgroupSize,
+};
 
 use super::static_resource_pool::{StaticResourcePool, StaticResourcePoolReadLockAccessor};
 use std::hash::Hash;
@@ -48,9 +51,18 @@ impl KernelModulePool {
     ) -> KernelModuleHandle {
         self.pool.get_or_create(desc, |desc| {
             log::info!("Creating kernel module: {}", desc.key);
-            let source = desc
-                .create_kernel_source(kernel, inplace, dst, workgroup_size)
-                .expect("Failed to create kernel source");
+            let cache = ShaderCache::global();
+            let cache_key = ShaderCache::key(desc.key.as_str(), device.compute_features());
+            let source = match cache.get(&cache_key) {
+                Some(source) => KernelSource(source.to_string().into()),
+                None => {
+                    let source = desc
+                        .create_kernel_source(kernel, inplace, dst, workgroup_size)
+                        .expect("Failed to create kernel source");
+                    cache.insert(cache_key, &source.0);
+                    source
+                }
+            };
 
             let shader_module_desc = wgpu::ShaderModuleDescriptor {
                 label: Some(desc.key.as_str()),
@@ -56,7 +56,7 @@ impl ComputePipelinePool {
                     zero_initialize_workgroup_memory: false,
                     ..Default::default()
                 },
-                cache: None,
+                cache: device.pipeline_cache(),
             })
         })
     }
@@ -1,16 +1,11 @@
 #![cfg(feature = "gpu-profiling")]
 use itertools::Itertools;
 use std::collections::HashMap;
-use tabled::settings::{object::Rows, Alignment, Modify, Panel, Style};
 use tabled::{Table, Tabled};
 use wgpu::QuerySet;
 
 use super::WgpuDevice;
-
-//used for formatting table cells
-fn float2(n: &f64) -> String {
-    format!("{:.2}", n)
-}
+use crate::table::{float2, runtime_table};
 
 #[derive(Tabled)]
 struct SummaryTableEntry {
@@ -47,12 +42,7 @@ pub fn build_summary_table(
 
     let total = elapsed.iter().map(|e| e.elapsed).sum::<usize>() / 1_000;
 
-    Table::new(&elapsed)
-        .with(Style::modern())
-        .with(Modify::new(Rows::first()).with(Alignment::center()))
-        .with(Modify::new(Rows::new(1..)).with(Alignment::left()))
-        .with(Panel::footer(format!("{} total runtime (μs)", total)))
-        .to_owned()
+    runtime_table(&elapsed, total as u128)
 }
 
 #[derive(Tabled)]
@@ -84,12 +74,7 @@ pub fn build_individual_table(elapsed_map: HashMap<usize, (String, usize)>) -> T
 
     let total = elapsed.iter().map(|e| e.elapsed).sum::<usize>() / 1_000;
 
-    Table::new(&elapsed)
-        .with(Style::modern())
-        .with(Modify::new(Rows::first()).with(Alignment::center()))
-        .with(Modify::new(Rows::new(1..)).with(Alignment::left()))
-        .with(Panel::footer(format!("{} total runtime (μs)", total)))
-        .to_owned()
+    runtime_table(&elapsed, total as u128)
 }
 
 pub struct Profiler {
@@ -0,0 +1,293 @@
+use crate::gpu::DeviceFeatures;
+use parking_lot::{Mutex, RwLock};
+use rustc_hash::{FxHashMap, FxHasher};
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+
+/// Generated WGSL is only valid for the build of the crate which generated it: the hash of
+/// the kernel sources is computed by `build.rs`.
+const CACHE_VERSION: &str = concat!(env!("CARGO_PKG_VERSION"), "-", env!("RATCHET_KERNEL_HASH"));
+
+/// # Shader Cache
+///
+/// Generated WGSL sources, keyed by kernel key, device features and a hash of the kernel
+/// sources, shared by every device of the process.
+///
+/// On a warm start, kernel modules are created from cached sources instead of being generated.
+/// The cache is persisted to a directory on native targets, set with `RATCHET_SHADER_CACHE` or
+/// [ShaderCache::set_dir]. The directory also holds the wgpu pipeline cache, on backends which
+/// support it. In the browser, sources are loaded with [ShaderCache::extend] and the new ones
+/// retrieved with [ShaderCache::take_pending], e.g to be stored in IndexedDB.
+#[derive(Default)]
+pub struct ShaderCache {
+    entries: RwLock<FxHashMap<String, Arc<str>>>,
+    /// Keys inserted since the last [ShaderCache::take_pending].
+    pending: Mutex<Vec<String>>,
+    dir: RwLock<Option<PathBuf>>,
+}
+
+impl ShaderCache {
+    /// The cache shared by every device.
+    pub fn global() -> &'static ShaderCache {
+        static CACHE: OnceLock<ShaderCache> = OnceLock::new();
+        CACHE.get_or_init(|| {
+            let cache = ShaderCache::default();
+            if let Some(dir) = std::env::var_os("RATCHET_SHADER_CACHE") {
+                cache.set_dir(dir);
+            }
+            cache
+        })
+    }
+
+    /// Persist sources to `dir`, reading any stored by a previous process.
+    pub fn set_dir(&self, dir: impl Into<PathBuf>) {
+        let dir = dir.into();
+        log::info!("Caching shaders in {}", dir.display());
+        *self.dir.write() = Some(dir);
+    }
+
+    pub fn dir(&self) -> Option<PathBuf> {
+        self.dir.read().clone()
+    }
+
+    /// Key of the source of the kernel `kernel_key` on a device with `features`.
+    pub fn key(kernel_key: &str, features: &DeviceFeatures) -> String {
+        format!(
+            "{}_{}{}_{}",
+            CACHE_VERSION,
+            if features.SHADER_F16 { "f16" } else { "f32" },
+            if features.SUBGROUP { "_subgroup" } else { "" },
+            kernel_key
+        )
+    }
+
+    /// Whether `key` was created by this build of the crate, other sources are never used.
+    pub fn is_current(key: &str) -> bool {
+        key.strip_prefix(CACHE_VERSION)
+            .is_some_and(|rest| rest.starts_with('_'))
+    }
+
+    pub fn get(&self, key: &str) -> Option<Arc<str>> {
+        if let Some(source) = self.entries.read().get(key) {
+            return Some(source.clone());
+        }
+        let source: Arc<str> = self.read_file(key)?.into();
+        self.entries.write().insert(key.to_string(), source.clone());
+        Some(source)
+    }
+
+    pub fn insert(&self, key: String, source: &str) {
+        if let Err(e) = self.write_file(&key, source) {
+            log::warn!("Failed to cache shader {}: {}", key, e);
+        }
+        self.pending.lock().push(key.clone());
+        self.entries.write().insert(key, source.into());
+    }
+
+    /// Add sources loaded from elsewhere, which are not pending.
+    pub fn extend(&self, entries: impl IntoIterator<Item = (String, String)>) {
+        let mut cache = self.entries.write();
+        for (key, source) in entries {
+            cache.insert(key, source.into());
+        }
+    }
+
+    /// Sources inserted since the last call.
+    pub fn take_pending(&self) -> Vec<(String, Arc<str>)> {
+        let keys = std::mem::take(&mut *self.pending.lock());
+        let entries = self.entries.read();
+        keys.into_iter()
+            .filter_map(|key| entries.get(&key).cloned().map(|source| (key, source)))
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.read().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Kernel keys can be longer than a file name, so files are named by hash and start with
+    /// the length of the source and the full key.
+    fn path(dir: &Path, key: &str) -> PathBuf {
+        let mut hasher = FxHasher::default();
+        key.hash(&mut hasher);
+        dir.join("shaders")
+            .join(format!("{:016x}.wgsl", hasher.finish()))
+    }
+
+    fn read_file(&self, key: &str) -> Option<String> {
+        let path = Self::path(self.dir.read().as_ref()?, key);
+        let contents = std::fs::read_to_string(path).ok()?;
+        let (header, source) = contents.split_once('\n')?;
+        let (len, stored_key) = header.strip_prefix("// ")?.split_once(' ')?;
+        let complete = len.parse::<usize>().ok() == Some(source.len());
+        (stored_key == key && complete).then(|| source.to_string())
+    }
+
+    fn write_file(&self, key: &str, source: &str) -> std::io::Result<()> {
+        let Some(dir) = self.dir.read().clone() else {
+            return Ok(());
+        };
+        let path = Self::path(&dir, key);
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        write_atomic(
+            &path,
+            format!("// {} {}\n{}", source.len(), key, source).as_bytes(),
+        )
+    }
+}
+
+/// Write `contents` to a temporary file next to `path`, and rename it into place.
+///
+/// Readers, including other processes sharing the directory, either see the previous file or
+/// the complete new one.
+fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let suffix = format!(
+        "{}-{}.tmp",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    );
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(suffix);
+    let tmp = PathBuf::from(tmp);
+    let result = std::fs::write(&tmp, contents).and_then(|_| std::fs::rename(&tmp, path));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    result
+}
+
+/// A wgpu pipeline cache, written back to the [ShaderCache] directory when the device is
+/// dropped.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) struct PipelineCache {
+    cache: wgpu::PipelineCache,
+    path: PathBuf,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl PipelineCache {
+    /// `None` if the backend doesn't support pipeline caches, or no directory is set.
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        adapter: &wgpu::AdapterInfo,
+        dir: Option<PathBuf>,
+    ) -> Option<Self> {
+        if !device.features().contains(wgpu::Features::PIPELINE_CACHE) {
+            return None;
+        }
+        let key = wgpu::util::pipeline_cache_key(adapter)?;
+        let path = dir?.join("pipelines").join(key);
+        let data = std::fs::read(&path).ok();
+        //SAFETY: the data was written by `get_data` for this adapter, and wgpu validates it
+        //against the driver, falling back to an empty cache.
+        let cache = unsafe {
+            device.create_pipeline_cache(&wgpu::PipelineCacheDescriptor {
+                label: Some("ratchet pipeline cache"),
+                data: data.as_deref(),
+                fallback: true,
+            })
+        };
+        Some(Self { cache, path })
+    }
+
+    pub(crate) fn inner(&self) -> &wgpu::PipelineCache {
+        &self.cache
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for PipelineCache {
+    fn drop(&mut self) {
+        let Some(data) = self.cache.get_data() else {
+            return;
+        };
+        let write = std::fs::create_dir_all(self.path.parent().unwrap())
+            .and_then(|_| write_atomic(&self.path, &data));
+        if let Err(e) = write {
+            log::warn!("Failed to write pipeline cache: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ShaderCache;
+    use crate::gpu::DeviceFeatures;
+
+    fn key(features: &DeviceFeatures) -> String {
+        ShaderCache::key("softmax_f32_f32_128_1_1_oop__vec4", features)
+    }
+
+    #[test]
+    fn keys_depend_on_features() {
+        let f16 = DeviceFeatures {
+            SHADER_F16: true,
+            SUBGROUP: false,
+        };
+        let f32 = DeviceFeatures {
+            SHADER_F16: false,
+            SUBGROUP: false,
+        };
+        assert_ne!(key(&f16), key(&f32));
+        assert!(ShaderCache::is_current(&key(&f16)));
+        assert!(!ShaderCache::is_current("0.0.0_f16_softmax"));
+    }
+
+    #[test]
+    fn tracks_pending_sources() {
+        let cache = ShaderCache::default();
+        cache.extend([("a".to_string(), "fn a() {}".to_string())]);
+        cache.insert("b".to_string(), "fn b() {}");
+
+        assert_eq!(cache.get("a").as_deref(), Some("fn a() {}"));
+        let pending = cache.take_pending();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].0, "b");
+        assert!(cache.take_pending().is_empty());
+    }
+
+    #[test]
+    fn persists_to_directory() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!("ratchet-shaders-{}", std::process::id()));
+        let writer = ShaderCache::default();
+        writer.set_dir(&dir);
+        writer.insert("key".to_string(), "fn main() {}\n");
+
+        let reader = ShaderCache::default();
+        assert!(reader.get("key").is_none());
+        reader.set_dir(&dir);
+        assert_eq!(reader.get("key").as_deref(), Some("fn main() {}\n"));
+        assert!(reader.get("other").is_none());
+
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_truncated_files() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!("ratchet-truncated-{}", std::process::id()));
+        let writer = ShaderCache::default();
+        writer.set_dir(&dir);
+        writer.insert("key".to_string(), "fn main() {}\n");
+
+        let path = ShaderCache::path(&dir, "key");
+        let contents = std::fs::read(&path)?;
+        std::fs::write(&path, &contents[..contents.len() - 3])?;
+        let reader = ShaderCache::default();
+        reader.set_dir(&dir);
+        assert!(reader.get("key").is_none());
+
+        //No temporary files are left behind
+        let files = std::fs::read_dir(path.parent().unwrap())?.count();
+        assert_eq!(files, 1);
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
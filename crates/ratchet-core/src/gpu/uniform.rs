@@ -5,7 +5,7 @@ use crate::{
     rvec, OperationError,
 };
 
-use super::{BindGroupDescriptor, GpuBindGroup, PooledGPUBuffer, WgpuDevice};
+use super::{write_padded, BindGroupDescriptor, GpuBindGroup, PooledGPUBuffer, WgpuDevice};
 use encase::DynamicUniformBuffer;
 
 ///We use a single uniform buffer for all operations to hold their parameters.
@@ -61,6 +61,20 @@ impl GpuUniform {
     pub fn bind_group(&self) -> &GpuBindGroup {
         &self.bind_group
     }
+
+    /// Overwrite the buffer with freshly encoded metadata, keeping the bind group valid.
+    ///
+    /// The new contents must fit in the buffer, i.e be encoded from the same operations.
+    pub(crate) fn write(&self, uniform: CpuUniform, device: &WgpuDevice) {
+        let contents = uniform.into_inner();
+        assert!(
+            contents.len() as u64 <= self.buf.size(),
+            "Uniform of {} bytes does not fit in a buffer of {} bytes",
+            contents.len(),
+            self.buf.size()
+        );
+        write_padded(device, &self.buf.inner, 0, &contents);
+    }
 }
 
 impl std::ops::Deref for CpuUniform {
@@ -248,6 +248,156 @@ impl WgslKernelBuilder {
             _ => {}
         }
     }
+
+    /// Writes `fn dequantize(index: u32) -> f32` for a GGML block type, reading the packed
+    /// blocks from the `array<u32>` binding named `src`.
+    /// Follows `dequantize_row_*` in ggml-quants.c.
+    pub(crate) fn write_dequantize(&mut self, dtype: DType, src: &str) {
+        self.write_global(wgsl! {
+            fn load_u8(byte: u32) -> u32 {
+                return ('src[byte >> 2u] >> ((byte & 3u) * 8u)) & 0xFFu;
+            }
+
+            fn load_i8(byte: u32) -> i32 {
+                return (i32(load_u8(byte)) << 24u) >> 24u;
+            }
+
+            fn load_u32(byte: u32) -> u32 {
+                return load_u8(byte) | (load_u8(byte + 1u) << 8u) | (load_u8(byte + 2u) << 16u) | (load_u8(byte + 3u) << 24u);
+            }
+
+            //Blocks are not 4 byte aligned, so f16 scales are unpacked by hand
+            fn load_f16(byte: u32) -> f32 {
+                return unpack2x16float(load_u8(byte) | (load_u8(byte + 1u) << 8u)).x;
+            }
+        });
+
+        match dtype {
+            DType::Q4_0(_) | DType::Q4_1(_) => {
+                let (type_size, qs, min) = match dtype {
+                    DType::Q4_0(_) => ("18u", "2u", "-8.0 * d"),
+                    _ => ("20u", "4u", "load_f16(base + 2u)"),
+                };
+                self.write_global(wgsl! {
+                    fn dequantize(index: u32) -> f32 {
+                        let base = (index / 32u) * 'type_size;
+                        let l = index % 32u;
+                        let d = load_f16(base);
+                        let packed = load_u8(base + 'qs + (l % 16u));
+                        let q = select(packed >> 4u, packed & 0xFu, l < 16u);
+                        return f32(q) * d + 'min;
+                    }
+                });
+            }
+            DType::Q5_0(_) | DType::Q5_1(_) => {
+                let (type_size, qh, min) = match dtype {
+                    DType::Q5_0(_) => ("22u", "2u", "-16.0 * d"),
+                    _ => ("24u", "4u", "load_f16(base + 2u)"),
+                };
+                self.write_global(wgsl! {
+                    fn dequantize(index: u32) -> f32 {
+                        let base = (index / 32u) * 'type_size;
+                        let l = index % 32u;
+                        let d = load_f16(base);
+                        let qh = load_u32(base + 'qh);
+                        let packed = load_u8(base + 'qh + 4u + (l % 16u));
+                        let low = select(packed >> 4u, packed & 0xFu, l < 16u);
+                        let q = low | (((qh >> l) & 1u) << 4u);
+                        return f32(q) * d + 'min;
+                    }
+                });
+            }
+            DType::Q2_K(_) => {
+                self.write_global(wgsl! {
+                    fn dequantize(index: u32) -> f32 {
+                        let base = (index / 256u) * 84u;
+                        let e = index % 256u;
+                        let n = e / 128u;
+                        let j = (e % 128u) / 32u;
+                        let h = (e % 32u) / 16u;
+                        let l = e % 16u;
+
+                        let sc = load_u8(base + n * 8u + j * 2u + h);
+                        let q = (load_u8(base + 16u + 32u * n + 16u * h + l) >> (2u * j)) & 3u;
+                        let d = load_f16(base + 80u);
+                        let dmin = load_f16(base + 82u);
+                        return d * f32(sc & 0xFu) * f32(q) - dmin * f32(sc >> 4u);
+                    }
+                });
+            }
+            DType::Q3_K(_) => {
+                self.write_global(wgsl! {
+                    fn dequantize(index: u32) -> f32 {
+                        let base = (index / 256u) * 110u;
+                        let e = index % 256u;
+                        let n = e / 128u;
+                        let j = (e % 128u) / 32u;
+                        let i = e % 32u;
+
+                        //16 x 6 bit signed scales, low nibbles first then 2 bit high parts
+                        let sub = n * 8u + j * 2u + i / 16u;
+                        let scales = base + 96u;
+                        let low_byte = load_u8(scales + sub % 8u);
+                        let low = select(low_byte >> 4u, low_byte & 0xFu, sub < 8u);
+                        let high = (load_u8(scales + 8u + sub % 4u) >> (2u * (sub / 4u))) & 3u;
+                        let scale = i32(low | (high << 4u)) - 32;
+
+                        let hbit = (load_u8(base + i) >> (4u * n + j)) & 1u;
+                        let q = i32((load_u8(base + 32u + 32u * n + i) >> (2u * j)) & 3u) - select(4, 0, hbit == 1u);
+                        return load_f16(base + 108u) * f32(scale) * f32(q);
+                    }
+                });
+            }
+            DType::Q5_K(_) => {
+                self.write_global(wgsl! {
+                    //6 bit (scale, min) pairs packed into 12 bytes
+                    fn scale_min_k4(scales: u32, j: u32) -> vec2<f32> {
+                        if (j < 4u) {
+                            return vec2<f32>(f32(load_u8(scales + j) & 63u), f32(load_u8(scales + j + 4u) & 63u));
+                        }
+                        let sc = (load_u8(scales + j + 4u) & 0xFu) | ((load_u8(scales + j - 4u) >> 6u) << 4u);
+                        let m = (load_u8(scales + j + 4u) >> 4u) | ((load_u8(scales + j) >> 6u) << 4u);
+                        return vec2<f32>(f32(sc), f32(m));
+                    }
+
+                    fn dequantize(index: u32) -> f32 {
+                        let base = (index / 256u) * 176u;
+                        let e = index % 256u;
+                        let c = e / 64u;
+                        let h = (e % 64u) / 32u;
+                        let l = e % 32u;
+                        let sub = 2u * c + h;
+
+                        let sm = scale_min_k4(base + 4u, sub);
+                        let packed = load_u8(base + 48u + 32u * c + l);
+                        let low = select(packed >> 4u, packed & 0xFu, h == 0u);
+                        let hbit = (load_u8(base + 16u + l) >> sub) & 1u;
+                        let q = low + 16u * hbit;
+                        return load_f16(base) * sm.x * f32(q) - load_f16(base + 2u) * sm.y;
+                    }
+                });
+            }
+            DType::Q6_K(_) => {
+                self.write_global(wgsl! {
+                    fn dequantize(index: u32) -> f32 {
+                        let base = (index / 256u) * 210u;
+                        let e = index % 256u;
+                        let n = e / 128u;
+                        let g = (e % 128u) / 32u;
+                        let l = e % 32u;
+
+                        let packed = load_u8(base + 64u * n + l + 32u * (g & 1u));
+                        let low = select(packed >> 4u, packed & 0xFu, g < 2u);
+                        let high = (load_u8(base + 128u + 32u * n + l) >> (2u * g)) & 3u;
+                        let q = i32(low | (high << 4u)) - 32;
+                        let scale = load_i8(base + 192u + 8u * n + l / 16u + 2u * g);
+                        return load_f16(base + 208u) * f32(scale) * f32(q);
+                    }
+                });
+            }
+            _ => panic!("{dtype} is not a GGML block type"),
+        }
+    }
 }
 
 /// WGSL built-in variables.
@@ -93,7 +93,7 @@ impl Default for WorkgroupCount {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Workload {
     pub workgroup_size: WorkgroupSize,
     pub workgroup_count: WorkgroupCount,
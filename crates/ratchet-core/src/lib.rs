@@ -9,13 +9,17 @@ mod gpu;
 mod ndarray_ext;
 mod op;
 mod ops;
+mod passes;
 mod plot;
 mod quant;
 mod shape;
 mod storage;
 mod strides;
+mod symbolic;
+mod table;
 mod tensor;
 mod tensor_id;
+mod trace;
 
 pub use compiled_op::*;
 pub use cpu::*;
@@ -27,12 +31,15 @@ pub use gpu::*;
 pub use ndarray_ext::*;
 pub use op::*;
 pub use ops::*;
+pub use passes::*;
 pub use quant::*;
 pub use shape::*;
 pub use storage::*;
 pub use strides::*;
+pub use symbolic::*;
 pub use tensor::*;
 pub use tensor_id::*;
+pub use trace::*;
 
 #[cfg(feature = "plotting")]
 pub use plot::render_to_file;
@@ -1,6 +1,6 @@
 use crate::gpu::{
     BindGroupLayoutDescriptor, ComputePipelineDescriptor, CpuUniform, PipelineLayoutDescriptor,
-    PoolError, WgpuDevice,
+    PoolError, WgpuDevice, Workload,
 };
 use crate::{
     ops::*, rvec, CompiledOp, InvariantError, Kernel, KernelBuildError, KernelMetadata,
@@ -21,9 +21,12 @@ pub enum LazyOp {
     Concat(Concat),
     Norm(NormOp),
     Cast(Cast),
+    Attention(Attention),
+    Fused(FusedElementwise),
     // ---- Everything below this line shouldn't exist ----
     RoPE(RoPE),
     Softmax(Softmax),
+    ArgMax(ArgMax),
     View(View),             //Should be general class, metadata modification
     Select(IndexSelect),    //Can probably be Reindex
     IndexWrite(IndexWrite), //Above 2 should be merged
@@ -35,13 +38,16 @@ impl LazyOp {
         match self {
             LazyOp::Binary(b) => b.name(),
             LazyOp::Cast(c) => c.name(),
+            LazyOp::Attention(a) => a.name(),
             LazyOp::Matmul(m) => m.name(),
             LazyOp::Softmax(s) => s.name(),
+            LazyOp::ArgMax(a) => a.name(),
             LazyOp::Unary(u) => u.name(),
             LazyOp::Reindex(r) => r.name(),
             LazyOp::Concat(c) => c.name(),
             LazyOp::Norm(n) => n.name(),
             LazyOp::Conv(c) => c.name(),
+            LazyOp::Fused(f) => f.name(),
             LazyOp::Select(s) => s.name(),
             LazyOp::IndexWrite(iw) => iw.name(),
             LazyOp::RoPE(r) => r.name(),
@@ -55,14 +61,17 @@ impl LazyOp {
         match self {
             LazyOp::Binary(b) => b.srcs(),
             LazyOp::Cast(c) => c.srcs(),
+            LazyOp::Attention(a) => a.srcs(),
             LazyOp::Matmul(m) => m.srcs(),
             LazyOp::RoPE(r) => r.srcs(),
             LazyOp::Softmax(s) => s.srcs(),
+            LazyOp::ArgMax(a) => a.srcs(),
             LazyOp::Unary(u) => u.srcs(),
             LazyOp::Reindex(r) => r.srcs(),
             LazyOp::Concat(c) => c.srcs(),
             LazyOp::Norm(n) => n.srcs(),
             LazyOp::Conv(c) => c.srcs(),
+            LazyOp::Fused(f) => f.srcs(),
             LazyOp::Select(s) => s.srcs(),
             LazyOp::IndexWrite(iw) => iw.srcs(),
             LazyOp::Cache(c) => c.srcs(),
@@ -71,18 +80,44 @@ impl LazyOp {
         }
     }
 
+    pub fn srcs_mut(&mut self) -> RVec<&mut Tensor> {
+        match self {
+            LazyOp::Binary(b) => b.srcs_mut(),
+            LazyOp::Cast(c) => c.srcs_mut(),
+            LazyOp::Attention(a) => a.srcs_mut(),
+            LazyOp::Matmul(m) => m.srcs_mut(),
+            LazyOp::RoPE(r) => r.srcs_mut(),
+            LazyOp::Softmax(s) => s.srcs_mut(),
+            LazyOp::ArgMax(a) => a.srcs_mut(),
+            LazyOp::Unary(u) => u.srcs_mut(),
+            LazyOp::Reindex(r) => r.srcs_mut(),
+            LazyOp::Concat(c) => c.srcs_mut(),
+            LazyOp::Norm(n) => n.srcs_mut(),
+            LazyOp::Conv(c) => c.srcs_mut(),
+            LazyOp::Fused(f) => f.srcs_mut(),
+            LazyOp::Select(s) => s.srcs_mut(),
+            LazyOp::IndexWrite(iw) => iw.srcs_mut(),
+            LazyOp::Cache(c) => c.srcs_mut(),
+            LazyOp::View(v) => v.srcs_mut(),
+            LazyOp::Const => rvec![],
+        }
+    }
+
     pub fn supports_inplace(&self) -> bool {
         match self {
             LazyOp::Binary(b) => b.supports_inplace(),
             LazyOp::Cast(c) => c.supports_inplace(),
+            LazyOp::Attention(a) => a.supports_inplace(),
             LazyOp::Matmul(m) => m.supports_inplace(),
             LazyOp::RoPE(r) => r.supports_inplace(),
             LazyOp::Softmax(s) => s.supports_inplace(),
+            LazyOp::ArgMax(a) => a.supports_inplace(),
             LazyOp::Unary(u) => u.supports_inplace(),
             LazyOp::Reindex(r) => r.supports_inplace(),
             LazyOp::Concat(c) => c.supports_inplace(),
             LazyOp::Norm(n) => n.supports_inplace(),
             LazyOp::Conv(c) => c.supports_inplace(),
+            LazyOp::Fused(f) => f.supports_inplace(),
             LazyOp::Select(s) => s.supports_inplace(),
             LazyOp::IndexWrite(iw) => iw.supports_inplace(),
             LazyOp::Cache(c) => c.supports_inplace(),
@@ -100,9 +135,11 @@ impl LazyOp {
         match self {
             LazyOp::Binary(b) => b.check_invariants(),
             LazyOp::Cast(c) => c.check_invariants(),
+            LazyOp::Attention(a) => a.check_invariants(),
             LazyOp::Matmul(m) => m.check_invariants(),
             LazyOp::RoPE(r) => r.check_invariants(),
             LazyOp::Softmax(s) => s.check_invariants(),
+            LazyOp::ArgMax(a) => a.check_invariants(),
             LazyOp::Unary(u) => u.check_invariants(),
             LazyOp::Reindex(r) => match r {
                 Reindex::Permute(p) => p.check_invariants(),
@@ -112,6 +149,7 @@ impl LazyOp {
             LazyOp::Concat(c) => c.check_invariants(),
             LazyOp::Norm(n) => n.check_invariants(),
             LazyOp::Conv(c) => c.check_invariants(),
+            LazyOp::Fused(f) => f.check_invariants(),
             LazyOp::Select(s) => s.check_invariants(),
             LazyOp::IndexWrite(iw) => iw.check_invariants(),
             LazyOp::Cache(c) => c.check_invariants(),
@@ -204,6 +242,17 @@ impl std::fmt::Display for KernelSource {
     }
 }
 
+/// The shape dependent parts of a compiled operation, see [GPUOperation::specialize].
+///
+/// Two tensors with equal keys can be computed by the same pipeline, bound to the same buffers,
+/// as long as the tensors fit within them.
+#[derive(Debug, Clone)]
+pub struct Specialization {
+    pub key: KernelKey,
+    pub workload: Workload,
+    pub offset: u64,
+}
+
 /// # Operation Guards - Runtime guards for operation correctness.
 ///
 /// Guards should be implemented for all types that will be a node on the high-level CFG.
@@ -252,6 +301,11 @@ pub trait Operation: OpGuards + Debug + 'static {
     /// # Source Tensors
     fn srcs(&self) -> RVec<&Tensor>;
 
+    /// # Mutable Source Tensors
+    ///
+    /// Same order as `srcs`, used by graph passes to rewire an operation onto new inputs.
+    fn srcs_mut(&mut self) -> RVec<&mut Tensor>;
+
     /// # Supports Inplace
     ///
     /// Determine if the operation can be performed in-place.
@@ -277,6 +331,34 @@ pub trait GPUOperation: Operation {
 
     fn select_kernel(&self) -> Self::KernelEnum;
 
+    /// # Specialize
+    ///
+    /// Everything about the selected kernel which depends on the shapes of `dst` and its sources:
+    /// the kernel key, the workload and the metadata, which is written to the uniform.
+    fn specialize(
+        &self,
+        dst: &Tensor,
+        uniform: &mut CpuUniform,
+        can_inplace: bool,
+    ) -> Result<Specialization, OperationError> {
+        let kernel = self.select_kernel();
+        let kernel_element = kernel.kernel_element(dst);
+        let offset = kernel.metadata(dst, &kernel_element)?.write(uniform)?;
+        let workload = kernel.calculate_dispatch(dst)?;
+        let key = kernel.kernel_key(
+            &workload.workgroup_size,
+            can_inplace,
+            &self.srcs(),
+            dst,
+            &kernel_element,
+        );
+        Ok(Specialization {
+            key,
+            workload,
+            offset,
+        })
+    }
+
     fn compile_gpu(
         &self,
         dst: &Tensor,
@@ -286,12 +368,11 @@ pub trait GPUOperation: Operation {
         debug: bool,
     ) -> Result<CompiledOp, OperationError> {
         let kernel = self.select_kernel();
-
-        let kernel_element = kernel.kernel_element(dst);
-        let metadata = kernel.metadata(dst, &kernel_element)?;
-        let offset = metadata.write(uniform)?;
-
-        let workload = kernel.calculate_dispatch(dst)?;
+        let Specialization {
+            key,
+            workload,
+            offset,
+        } = self.specialize(dst, uniform, can_inplace)?;
 
         let storage_layout = device
             .get_or_create_bind_group_layout(&kernel.storage_bind_group_layout(can_inplace)?)?;
@@ -302,13 +383,6 @@ pub trait GPUOperation: Operation {
             entries: rvec![storage_layout, uniform_layout],
         })?;
 
-        let key = kernel.kernel_key(
-            &workload.workgroup_size,
-            can_inplace,
-            &self.srcs(),
-            dst,
-            &kernel_element,
-        );
         log::debug!("Kernel key: {}", key);
 
         let kernel_src_desc = KernelModuleDesc { key: key.clone() };
@@ -355,6 +429,7 @@ pub trait GPUOperation: Operation {
             workload.workgroup_count,
             storage_bind_groups,
             offset as _,
+            can_inplace,
             kernel_src_desc.key,
             #[cfg(feature = "debug")]
             debug_buffer,
@@ -0,0 +1,281 @@
+use derive_new::new;
+use encase::ShaderType;
+use half::f16;
+use inline_wgsl::wgsl;
+use ratchet_macros::WgslMetadata;
+
+use crate::{
+    gpu::{dtype::WgslDType, BindGroupLayoutDescriptor, WorkgroupCount},
+    rvec, shape, wgc, wgs, Array, BindingMode, BuiltIn, DType, GPUOperation, Kernel, KernelElement,
+    KernelRenderable, KernelSource, OpGuards, Operation, OperationError, RVec, Scalar, StorageView,
+    Strides, Tensor, WgslKernelBuilder, WgslPrimitive, WorkgroupSize, Workload,
+};
+
+/// Index of the largest element along the last dimension, e.g to sample greedily on the device.
+///
+/// The first index wins ties.
+#[derive(new, Debug, Clone)]
+pub struct ArgMax {
+    pub(crate) input: Tensor,
+    pub(crate) dim: usize,
+}
+
+#[derive(Debug, derive_new::new, ShaderType, WgslMetadata)]
+pub struct ArgMaxMeta {
+    M: u32,
+    N: u32,
+}
+
+impl OpGuards for ArgMax {
+    fn check_shapes(&self) {
+        let input = &self.input;
+        assert_eq!(self.dim, input.rank() - 1);
+    }
+
+    fn check_dtypes(&self) {
+        let input = &self.input;
+        assert!(input.dt().is_float());
+    }
+}
+
+impl KernelRenderable for ArgMaxKernels {
+    fn register_bindings<P: WgslPrimitive>(
+        &self,
+        builder: &mut WgslKernelBuilder,
+        _: bool,
+    ) -> Result<(), OperationError> {
+        builder.register_storage("X", BindingMode::ReadOnly, Array::<P>::default());
+        builder.register_storage("Y", BindingMode::ReadWrite, Array::<Scalar<i32>>::default());
+        builder.register_uniform();
+        Ok(())
+    }
+
+    fn render<P: WgslPrimitive>(
+        &self,
+        inplace: bool,
+        dst: &Tensor,
+        workgroup_size: &WorkgroupSize,
+    ) -> Result<KernelSource, OperationError> {
+        let device = dst.device().try_gpu()?;
+        let mut kernel_builder = WgslKernelBuilder::new(
+            workgroup_size.clone(),
+            rvec![
+                BuiltIn::LocalInvocationId,
+                BuiltIn::WorkgroupId,
+                BuiltIn::NumWorkgroups,
+            ],
+            device.compute_features().clone(),
+        );
+        self.register_bindings::<P>(&mut kernel_builder, inplace)?;
+        kernel_builder.render_metadata(&self.metadata(dst, &self.kernel_element(dst))?);
+
+        let dt = P::T::DT;
+        let BLOCK_SIZE = workgroup_size.x.render();
+
+        kernel_builder.write_global(wgsl! {
+            var<workgroup> smax: array<'dt, 'BLOCK_SIZE>;
+            var<workgroup> sidx: array<u32, 'BLOCK_SIZE>;
+        });
+
+        //An index of N marks a thread which saw no element
+        kernel_builder.write_global(wgsl! {
+            fn block_argmax(index: u32, stride: u32) {
+                if index < stride {
+                    let other = index + stride;
+                    let empty = sidx[index] == metadata.N;
+                    let larger = smax[other] > smax[index];
+                    let tied = smax[other] == smax[index] && sidx[other] < sidx[index];
+                    if sidx[other] != metadata.N && (empty || larger || tied) {
+                        smax[index] = smax[other];
+                        sidx[index] = sidx[other];
+                    }
+                }
+                workgroupBarrier();
+            }
+        });
+
+        kernel_builder.write_main(wgsl! {
+            let row = workgroup_id.y * num_workgroups.x + workgroup_id.x;
+            if row >= metadata.M {
+                return;
+            }
+            let row_start = row * metadata.N;
+            let index = local_invocation_id.x;
+
+            var best = 'dt(0.);
+            var best_idx = metadata.N;
+            for (var i: u32 = index; i < metadata.N; i += 'BLOCK_SIZE) {
+                let val = X[row_start + i];
+                if best_idx == metadata.N || val > best {
+                    best = val;
+                    best_idx = i;
+                }
+            }
+            smax[index] = best;
+            sidx[index] = best_idx;
+            workgroupBarrier();
+        });
+
+        let steps = (workgroup_size.x - 1).ilog2();
+        for i in (0..=steps).rev().map(|x| 2u32.pow(x)) {
+            let v = i.render();
+            kernel_builder.write_main(wgsl! { block_argmax(index, 'v); });
+        }
+
+        kernel_builder.write_main(wgsl! {
+            if index == 0 {
+                Y[row] = i32(select(sidx[0], 0u, sidx[0] == metadata.N));
+            }
+        });
+        Ok(kernel_builder.build()?)
+    }
+}
+
+impl Operation for ArgMax {
+    fn name(&self) -> &'static str {
+        "ArgMax"
+    }
+
+    fn compute_view(&self) -> Result<StorageView, OperationError> {
+        let mut shape = self.input.shape().clone();
+        shape.remove(self.dim);
+        if shape.rank() == 0 {
+            shape = shape![1];
+        }
+        let strides = Strides::from(&shape);
+        Ok(StorageView::new(shape, DType::I32, strides))
+    }
+
+    fn srcs(&self) -> RVec<&Tensor> {
+        rvec![&self.input]
+    }
+
+    fn srcs_mut(&mut self) -> RVec<&mut Tensor> {
+        rvec![&mut self.input]
+    }
+}
+
+impl Kernel for ArgMaxKernels {
+    type Metadata = ArgMaxMeta;
+
+    fn kernel_name(&self) -> String {
+        match self {
+            Self::Standard(_) => String::from("argmax"),
+        }
+    }
+
+    fn kernel_element(&self, _dst: &Tensor) -> KernelElement {
+        KernelElement::Scalar
+    }
+
+    fn build_kernel(
+        &self,
+        inplace: bool,
+        dst: &Tensor,
+        workgroup_size: &WorkgroupSize,
+    ) -> Result<KernelSource, OperationError> {
+        let Self::Standard(inner) = self;
+        match inner.input.dt() {
+            DType::F32 => self.render::<Scalar<f32>>(inplace, dst, workgroup_size),
+            DType::F16 => self.render::<Scalar<f16>>(inplace, dst, workgroup_size),
+            dt => Err(OperationError::CompileError(format!(
+                "Unsupported dtype {:?} for argmax",
+                dt
+            ))),
+        }
+    }
+
+    fn calculate_dispatch(&self, dst: &Tensor) -> Result<Workload, OperationError> {
+        let rows = dst.shape().numel();
+        let x = rows.min(WorkgroupCount::MAX_WGS_PER_DIM);
+        let y = WorkgroupCount::div_ceil(rows, x);
+        Ok(Workload {
+            workgroup_size: wgs![128, 1, 1],
+            workgroup_count: wgc![x as _, y as _, 1],
+        })
+    }
+
+    fn metadata(&self, dst: &Tensor, _: &KernelElement) -> Result<Self::Metadata, OperationError> {
+        let Self::Standard(inner) = self;
+        let M = dst.shape().numel() as u32;
+        let N = inner.input.shape()[inner.dim] as u32;
+        Ok(ArgMaxMeta { M, N })
+    }
+
+    fn storage_bind_group_layout(
+        &self,
+        inplace: bool,
+    ) -> Result<BindGroupLayoutDescriptor, OperationError> {
+        if inplace {
+            panic!("ArgMax cannot be done in place");
+        }
+        Ok(BindGroupLayoutDescriptor::unary())
+    }
+}
+
+pub enum ArgMaxKernels {
+    Standard(ArgMax),
+}
+
+impl GPUOperation for ArgMax {
+    type KernelEnum = ArgMaxKernels;
+
+    fn select_kernel(&self) -> Self::KernelEnum {
+        ArgMaxKernels::Standard(self.clone())
+    }
+}
+
+#[cfg(all(test, feature = "pyo3"))]
+mod tests {
+    use test_strategy::{proptest, Arbitrary};
+
+    use crate::test_util::run_py_prg;
+    use crate::{shape, DType, Device, DeviceRequest, Tensor};
+
+    fn ground_truth(a: &Tensor) -> anyhow::Result<Tensor> {
+        let prg = r#"
+import torch
+def argmax(a):
+    return torch.argmax(torch.from_numpy(a), dim=-1).int().numpy()
+"#;
+        run_py_prg(prg.to_string(), &[a], &[], DType::I32)
+    }
+
+    fn run_argmax_trial(problem: ArgMaxProblem, device: Device) {
+        let ArgMaxProblem { B, M, N } = problem;
+        let a = Tensor::randn::<f32>(shape![B, M, N], Device::CPU);
+        let ground = ground_truth(&a).unwrap();
+
+        let a = a.to(&device).unwrap();
+        let b = a.argmax(2).unwrap().resolve().unwrap();
+
+        let ours = b.to(&Device::CPU).unwrap();
+        assert_eq!(ours.shape(), &shape![B, M]);
+        assert_eq!(
+            ground.to_vec::<i32>().unwrap(),
+            ours.to_vec::<i32>().unwrap()
+        );
+    }
+
+    #[derive(Arbitrary, Debug)]
+    struct ArgMaxProblem {
+        #[strategy(1..=3usize)]
+        B: usize,
+        #[strategy(1..=64usize)]
+        M: usize,
+        #[strategy(1..=4096usize)]
+        N: usize,
+    }
+
+    #[proptest(cases = 16)]
+    fn test_argmax_gpu(prob: ArgMaxProblem) {
+        let device = Device::request_device(DeviceRequest::GPU).unwrap();
+        run_argmax_trial(prob, device);
+    }
+
+    #[proptest(cases = 16)]
+    fn test_argmax_cpu(prob: ArgMaxProblem) {
+        let device = Device::request_device(DeviceRequest::CPU).unwrap();
+        run_argmax_trial(prob, device);
+    }
+}
@@ -0,0 +1,603 @@
+use derive_new::new;
+use encase::ShaderType;
+use half::f16;
+use inline_wgsl::wgsl;
+use ratchet_macros::WgslMetadata;
+
+use crate::{
+    gpu::{dtype::WgslDType, BindGroupLayoutDescriptor},
+    rvec, wgc, wgs, Array, BindingMode, BuiltIn, DType, GPUOperation, Kernel, KernelElement,
+    KernelRenderable, KernelSource, OpGuards, Operation, OperationError, RVec, Scalar, StorageView,
+    Strides, Tensor, WgslFragment, WgslKernelBuilder, WgslPrimitive, WorkgroupSize, Workload,
+};
+
+/// Largest head dimension supported by the fused kernel.
+/// The query row is staged in workgroup memory, which must be statically sized.
+pub const MAX_HEAD_DIM: usize = 256;
+
+/// # Attention Mask
+///
+/// `Causal` masks out all keys that lie in the future of the query.
+/// When decoding with a KV cache, the key sequence is longer than the query sequence, and the
+/// queries are assumed to be the *last* `L` positions of the key sequence.
+///
+/// `Additive` adds an arbitrary tensor to the attention logits before the softmax.
+/// The mask is either `[L, S]`, `[H, L, S]` or `[B, H, L, S]`, where `B` & `H` may be 1 to
+/// broadcast (e.g. `[B, 1, L, S]` for key padding, `[1, H, L, S]` for ALiBi).
+#[derive(Debug, Clone)]
+pub enum AttentionMask {
+    None,
+    Causal,
+    Additive(Tensor),
+}
+
+/// # Attention
+///
+/// Fused scaled dot product attention: `softmax((Q @ K^T) * scale + mask) @ V`.
+///
+/// Q: [B, H, L, D]
+/// K: [B, KVH, S, D]
+/// V: [B, KVH, S, D]
+///
+/// Grouped-query (and multi-query) attention is supported when `H` is a multiple of `KVH`.
+/// Query head `h` reads key/value head `h / (H / KVH)`, so K & V are never repeated.
+///
+/// `softcap` optionally squashes the scaled logits with `tanh(x / softcap) * softcap`
+/// before the mask is applied, as in Gemma 2.
+///
+/// The attention logits are never materialized. Keys are consumed in tiles, maintaining
+/// a running maximum and denominator (online softmax), as in FlashAttention.
+#[derive(new, Debug, Clone)]
+pub struct Attention {
+    pub(crate) query: Tensor,
+    pub(crate) key: Tensor,
+    pub(crate) value: Tensor,
+    pub(crate) mask: AttentionMask,
+    pub(crate) scale: f32,
+    pub(crate) softcap: Option<f32>,
+}
+
+impl Attention {
+    pub fn query(&self) -> &Tensor {
+        &self.query
+    }
+
+    pub fn key(&self) -> &Tensor {
+        &self.key
+    }
+
+    pub fn value(&self) -> &Tensor {
+        &self.value
+    }
+
+    pub fn mask(&self) -> &AttentionMask {
+        &self.mask
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    pub fn softcap(&self) -> Option<f32> {
+        self.softcap
+    }
+
+    /// Number of query heads that share a single key/value head.
+    pub fn n_rep(&self) -> usize {
+        self.query.shape()[1] / self.key.shape()[1]
+    }
+
+    /// Element strides between the batches & heads of an additive mask.
+    /// A stride of 0 broadcasts the mask along that dimension.
+    pub fn mask_strides(&self) -> (usize, usize) {
+        let AttentionMask::Additive(m) = &self.mask else {
+            return (0, 0);
+        };
+        let plane = m.shape()[m.rank() - 2] * m.shape()[m.rank() - 1];
+        let (batches, heads) = match m.rank() {
+            4 => (m.shape()[0], m.shape()[1]),
+            3 => (1, m.shape()[0]),
+            _ => (1, 1),
+        };
+        let head_stride = if heads == 1 { 0 } else { plane };
+        let batch_stride = if batches == 1 { 0 } else { heads * plane };
+        (batch_stride, head_stride)
+    }
+
+    /// Number of cached positions preceding the first query.
+    pub fn causal_offset(&self) -> usize {
+        let rank = self.query.rank();
+        self.key.shape()[rank - 2] - self.query.shape()[rank - 2]
+    }
+}
+
+#[derive(Debug, derive_new::new, ShaderType, WgslMetadata)]
+pub struct AttentionMeta {
+    H: u32,
+    KVH: u32,
+    n_rep: u32,
+    L: u32,
+    S: u32,
+    D: u32,
+    offset: u32,
+    scale: f32,
+    softcap: f32,
+    mask_batch_stride: u32,
+    mask_head_stride: u32,
+}
+
+impl OpGuards for Attention {
+    fn check_shapes(&self) {
+        let (q, k, v) = (&self.query, &self.key, &self.value);
+        assert_eq!(q.rank(), 4);
+        //The kernels index Q, K and V as dense row-major buffers
+        assert!(q.storage_view().is_contiguous());
+        assert!(k.storage_view().is_contiguous());
+        assert!(v.storage_view().is_contiguous());
+        assert_eq!(k.shape(), v.shape());
+        assert_eq!(q.shape()[0], k.shape()[0]);
+        assert!(k.shape()[1] > 0 && q.shape()[1] % k.shape()[1] == 0);
+        assert_eq!(q.shape()[3], k.shape()[3]);
+        assert!(q.shape()[3] <= MAX_HEAD_DIM);
+        assert!(q.shape()[2] <= k.shape()[2]);
+        if let AttentionMask::Additive(m) = &self.mask {
+            assert!(m.storage_view().is_contiguous());
+            assert_eq!(m.shape()[m.rank() - 2], q.shape()[2]);
+            assert_eq!(m.shape()[m.rank() - 1], k.shape()[2]);
+            assert!((2..=4).contains(&m.rank()));
+            if m.rank() == 4 {
+                assert!(m.shape()[0] == 1 || m.shape()[0] == q.shape()[0]);
+            }
+            if m.rank() >= 3 {
+                let heads = m.shape()[m.rank() - 3];
+                assert!(heads == 1 || heads == q.shape()[1]);
+            }
+        }
+    }
+
+    fn check_dtypes(&self) {
+        let (q, k, v) = (&self.query, &self.key, &self.value);
+        assert!(q.dt().is_float());
+        assert_eq!(q.dt(), k.dt());
+        assert_eq!(q.dt(), v.dt());
+        if let AttentionMask::Additive(m) = &self.mask {
+            assert_eq!(q.dt(), m.dt());
+        }
+    }
+}
+
+impl Operation for Attention {
+    fn name(&self) -> &'static str {
+        "Attention"
+    }
+
+    fn compute_view(&self) -> Result<StorageView, OperationError> {
+        let out_shape = self.query.shape().clone();
+        let out_strides = Strides::from(&out_shape);
+        Ok(StorageView::new(out_shape, self.query.dt(), out_strides))
+    }
+
+    fn srcs(&self) -> RVec<&Tensor> {
+        match &self.mask {
+            AttentionMask::Additive(m) => rvec![&self.query, &self.key, &self.value, m],
+            _ => rvec![&self.query, &self.key, &self.value],
+        }
+    }
+
+    fn srcs_mut(&mut self) -> RVec<&mut Tensor> {
+        match &mut self.mask {
+            AttentionMask::Additive(m) => {
+                rvec![&mut self.query, &mut self.key, &mut self.value, m]
+            }
+            _ => rvec![&mut self.query, &mut self.key, &mut self.value],
+        }
+    }
+}
+
+impl GPUOperation for Attention {
+    type KernelEnum = AttentionKernels;
+
+    fn select_kernel(&self) -> Self::KernelEnum {
+        AttentionKernels::Tiled(self.clone())
+    }
+}
+
+pub enum AttentionKernels {
+    Tiled(Attention),
+}
+
+impl KernelRenderable for AttentionKernels {
+    fn register_bindings<P: WgslPrimitive>(
+        &self,
+        builder: &mut WgslKernelBuilder,
+        inplace: bool,
+    ) -> Result<(), OperationError> {
+        if inplace {
+            return Err(OperationError::InplaceError(self.kernel_name()));
+        }
+        let AttentionKernels::Tiled(inner) = self;
+        let arr = Array::<P>::default();
+        builder.register_storage("Q", BindingMode::ReadOnly, arr);
+        builder.register_storage("K", BindingMode::ReadOnly, arr);
+        builder.register_storage("V", BindingMode::ReadOnly, arr);
+        if matches!(inner.mask, AttentionMask::Additive(_)) {
+            builder.register_storage("M", BindingMode::ReadOnly, arr);
+        }
+        builder.register_storage("Y", BindingMode::ReadWrite, arr);
+        builder.register_uniform();
+        Ok(())
+    }
+
+    fn render<P: WgslPrimitive>(
+        &self,
+        inplace: bool,
+        dst: &Tensor,
+        workgroup_size: &WorkgroupSize,
+    ) -> Result<KernelSource, OperationError> {
+        let device = dst.device().try_gpu()?;
+        let mut kernel_builder = WgslKernelBuilder::new(
+            workgroup_size.clone(),
+            rvec![BuiltIn::LocalInvocationId, BuiltIn::WorkgroupId],
+            device.compute_features().clone(),
+        );
+        self.register_bindings::<P>(&mut kernel_builder, inplace)?;
+        kernel_builder.render_metadata(&self.metadata(dst, &self.kernel_element(dst))?);
+
+        let AttentionKernels::Tiled(inner) = self;
+        let dt = P::T::DT;
+        let BLOCK_SIZE = workgroup_size.x.render();
+        let MAX_D = (MAX_HEAD_DIM as u32).render();
+        let ACC_LEN = (MAX_HEAD_DIM as u32 / workgroup_size.x).render();
+        let minFloat = <f32 as WgslDType>::MIN.render();
+
+        kernel_builder.write_global(wgsl! {
+            var<workgroup> q_row: array<f32, 'MAX_D>;
+            var<workgroup> probs: array<f32, 'BLOCK_SIZE>;
+            var<workgroup> smem: array<f32, 'BLOCK_SIZE>;
+        });
+
+        kernel_builder.write_global(wgsl! {
+            fn block_sum(index: u32, stride: u32) {
+                if index < stride {
+                    smem[index] += smem[index + stride];
+                }
+                workgroupBarrier();
+            }
+
+            fn block_max(index: u32, stride: u32) {
+                if index < stride {
+                    smem[index] = max(smem[index], smem[index + stride]);
+                }
+                workgroupBarrier();
+            }
+        });
+
+        //1 workgroup per query row, per head, per batch
+        kernel_builder.write_main(wgsl! {
+            let index = local_invocation_id.x;
+            let row = workgroup_id.x;
+            let batch = workgroup_id.y / metadata.H;
+            let head = workgroup_id.y % metadata.H;
+            let kv_head = head / metadata.n_rep;
+            let q_offset = (workgroup_id.y * metadata.L + row) * metadata.D;
+            let kv_offset = (batch * metadata.KVH + kv_head) * metadata.S * metadata.D;
+
+            for (var d: u32 = index; d < metadata.D; d += 'BLOCK_SIZE) {
+                q_row[d] = f32(Q[q_offset + d]);
+            }
+            workgroupBarrier();
+
+            var running_max = 'minFloat;
+            var running_sum = 0f;
+            var acc: array<f32, 'ACC_LEN>;
+        });
+
+        let in_range = match inner.mask {
+            AttentionMask::Causal => wgsl! { j < metadata.S && j <= row + metadata.offset },
+            _ => wgsl! { j < metadata.S },
+        };
+        let mut logit = vec![WgslFragment::from(wgsl! { s *= metadata.scale; })];
+        if inner.softcap.is_some() {
+            //clamped as tanh is unstable for large inputs on some backends
+            logit.push(
+                wgsl! {
+                    s = tanh(clamp(s / metadata.softcap, -15f, 15f)) * metadata.softcap;
+                }
+                .into(),
+            );
+        }
+        if let AttentionMask::Additive(_) = inner.mask {
+            logit.push(
+                wgsl! {
+                    let m_offset = batch * metadata.mask_batch_stride + head * metadata.mask_head_stride;
+                    s += f32(M[m_offset + row * metadata.S + j]);
+                }
+                .into(),
+            );
+        }
+        let logit = logit.into_iter().collect::<WgslFragment>();
+
+        kernel_builder.write_main(wgsl! {
+            for (var tile: u32 = 0u; tile < metadata.S; tile += 'BLOCK_SIZE) {
+                let j = tile + index;
+                var s = 'minFloat;
+                if ('in_range) {
+                    s = 0f;
+                    let k_offset = kv_offset + j * metadata.D;
+                    for (var d: u32 = 0u; d < metadata.D; d++) {
+                        s = fma(q_row[d], f32(K[k_offset + d]), s);
+                    }
+                    'logit
+                }
+                smem[index] = s;
+                workgroupBarrier();
+        });
+
+        let steps = (workgroup_size.x - 1).ilog2();
+        for i in (0..=steps).rev().map(|x| 2u32.pow(x)) {
+            let v = i.render();
+            kernel_builder.write_main(wgsl! { block_max(index, 'v); });
+        }
+
+        kernel_builder.write_main(wgsl! {
+                let tile_max = max(running_max, smem[0]);
+                workgroupBarrier();
+
+                let p = select(0f, exp(s - tile_max), s > 'minFloat);
+                probs[index] = p;
+                smem[index] = p;
+                workgroupBarrier();
+        });
+
+        for i in (0..=steps).rev().map(|x| 2u32.pow(x)) {
+            let v = i.render();
+            kernel_builder.write_main(wgsl! { block_sum(index, 'v); });
+        }
+
+        kernel_builder.write_main(wgsl! {
+                let correction = exp(running_max - tile_max);
+                running_sum = fma(running_sum, correction, smem[0]);
+                running_max = tile_max;
+
+                let tile_len = min('BLOCK_SIZE, metadata.S - tile);
+                for (var a: u32 = 0u; a < 'ACC_LEN; a++) {
+                    let d = index + a * 'BLOCK_SIZE;
+                    if (d < metadata.D) {
+                        var o = acc[a] * correction;
+                        for (var t: u32 = 0u; t < tile_len; t++) {
+                            o = fma(probs[t], f32(V[kv_offset + (tile + t) * metadata.D + d]), o);
+                        }
+                        acc[a] = o;
+                    }
+                }
+                workgroupBarrier();
+            }
+
+            //A fully masked row has no probability mass, write zeros rather than NaN
+            let inv_sum = select(0f, 1f / running_sum, running_sum > 0f);
+            for (var a: u32 = 0u; a < 'ACC_LEN; a++) {
+                let d = index + a * 'BLOCK_SIZE;
+                if (d < metadata.D) {
+                    Y[q_offset + d] = 'dt(acc[a] * inv_sum);
+                }
+            }
+        });
+
+        Ok(kernel_builder.build()?)
+    }
+}
+
+impl Kernel for AttentionKernels {
+    type Metadata = AttentionMeta;
+
+    fn kernel_name(&self) -> String {
+        let AttentionKernels::Tiled(inner) = self;
+        let stem = match inner.mask {
+            AttentionMask::None => "attention",
+            AttentionMask::Causal => "attention_causal",
+            AttentionMask::Additive(_) => "attention_masked",
+        };
+        match inner.softcap {
+            Some(_) => format!("{}_softcap", stem),
+            None => stem.to_string(),
+        }
+    }
+
+    fn metadata(&self, _: &Tensor, _: &KernelElement) -> Result<Self::Metadata, OperationError> {
+        let AttentionKernels::Tiled(inner) = self;
+        let [_, H, L, D]: [usize; 4] = inner.query.shape().try_into()?;
+        let [_, KVH, S, _]: [usize; 4] = inner.key.shape().try_into()?;
+        let (mask_batch_stride, mask_head_stride) = inner.mask_strides();
+        Ok(AttentionMeta::new(
+            H as _,
+            KVH as _,
+            inner.n_rep() as _,
+            L as _,
+            S as _,
+            D as _,
+            inner.causal_offset() as _,
+            inner.scale,
+            inner.softcap.unwrap_or(0.),
+            mask_batch_stride as _,
+            mask_head_stride as _,
+        ))
+    }
+
+    fn calculate_dispatch(&self, _: &Tensor) -> Result<Workload, OperationError> {
+        let AttentionKernels::Tiled(inner) = self;
+        let [B, H, L, _]: [usize; 4] = inner.query.shape().try_into()?;
+        Ok(Workload {
+            workgroup_size: wgs![128, 1, 1],
+            workgroup_count: wgc![L as _, (B * H) as _, 1],
+        })
+    }
+
+    fn kernel_element(&self, _: &Tensor) -> KernelElement {
+        KernelElement::Scalar
+    }
+
+    fn build_kernel(
+        &self,
+        inplace: bool,
+        dst: &Tensor,
+        workgroup_size: &WorkgroupSize,
+    ) -> Result<KernelSource, OperationError> {
+        let kernel_element = self.kernel_element(dst);
+        match (dst.dt(), &kernel_element) {
+            (DType::F32, KernelElement::Scalar) => {
+                self.render::<Scalar<f32>>(inplace, dst, workgroup_size)
+            }
+            (DType::F16, KernelElement::Scalar) => {
+                self.render::<Scalar<f16>>(inplace, dst, workgroup_size)
+            }
+            _ => Err(OperationError::CompileError(format!(
+                "Unsupported dtype {:?} or kernel element {:?}",
+                dst.dt(),
+                kernel_element
+            ))),
+        }
+    }
+
+    fn storage_bind_group_layout(
+        &self,
+        inplace: bool,
+    ) -> Result<BindGroupLayoutDescriptor, OperationError> {
+        if inplace {
+            return Err(OperationError::InplaceError(self.kernel_name()));
+        }
+        let AttentionKernels::Tiled(inner) = self;
+        match inner.mask {
+            AttentionMask::Additive(_) => Ok(BindGroupLayoutDescriptor::nthary(4)),
+            _ => Ok(BindGroupLayoutDescriptor::ternary()),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "pyo3"))]
+mod tests {
+    use test_strategy::{proptest, Arbitrary};
+
+    use crate::test_util::run_py_prg;
+    use crate::{shape, AttentionMask, Device, DeviceRequest, Tensor};
+
+    fn ground_truth(q: &Tensor, k: &Tensor, v: &Tensor, causal: bool) -> anyhow::Result<Tensor> {
+        let prg = r#"
+import torch
+import torch.nn.functional as F
+def attention(q, k, v, causal):
+    (q, k, v) = (torch.from_numpy(q), torch.from_numpy(k), torch.from_numpy(v))
+    n_rep = q.shape[1] // k.shape[1]
+    (k, v) = (k.repeat_interleave(n_rep, dim=1), v.repeat_interleave(n_rep, dim=1))
+    L, S = q.shape[-2], k.shape[-2]
+    mask = None
+    if causal:
+        mask = torch.ones(L, S, dtype=torch.bool).tril(diagonal=S - L)
+    return F.scaled_dot_product_attention(q, k, v, attn_mask=mask).numpy()
+"#;
+        run_py_prg(prg.to_string(), &[q, k, v], &[&causal], q.dt())
+    }
+
+    fn run_attention_trial(problem: AttentionProblem, device: Device) {
+        let AttentionProblem {
+            B,
+            KVH,
+            n_rep,
+            L,
+            cached,
+            D,
+            causal,
+        } = problem;
+        let (S, H) = (L + cached, KVH * n_rep);
+        let q = Tensor::randn::<f32>(shape![B, H, L, D], Device::CPU);
+        let k = Tensor::randn::<f32>(shape![B, KVH, S, D], Device::CPU);
+        let v = Tensor::randn::<f32>(shape![B, KVH, S, D], Device::CPU);
+        let ground = ground_truth(&q, &k, &v, causal).unwrap();
+
+        let mask = if causal {
+            AttentionMask::Causal
+        } else {
+            AttentionMask::None
+        };
+        let scale = 1.0 / (D as f32).sqrt();
+        let (q, k, v) = (
+            q.to(&device).unwrap(),
+            k.to(&device).unwrap(),
+            v.to(&device).unwrap(),
+        );
+        let result = q.attention(k, v, mask, scale).unwrap().resolve().unwrap();
+        let ours = result.to(&Device::CPU).unwrap();
+        ground.all_close(&ours, 1e-4, 1e-4).unwrap();
+    }
+
+    #[derive(Arbitrary, Debug)]
+    struct AttentionProblem {
+        #[strategy(1..=2usize)]
+        B: usize,
+        #[strategy(1..=4usize)]
+        KVH: usize,
+        #[strategy(1..=4usize)]
+        n_rep: usize,
+        #[strategy(1..=64usize)]
+        L: usize,
+        #[strategy(0..=300usize)]
+        cached: usize,
+        #[strategy(1..=128usize)]
+        D: usize,
+        causal: bool,
+    }
+
+    #[proptest(cases = 16)]
+    fn test_attention_gpu(prob: AttentionProblem) {
+        let device = Device::request_device(DeviceRequest::GPU).unwrap();
+        run_attention_trial(prob, device);
+    }
+
+    #[proptest(cases = 16)]
+    fn test_attention_cpu(prob: AttentionProblem) {
+        let device = Device::request_device(DeviceRequest::CPU).unwrap();
+        run_attention_trial(prob, device);
+    }
+
+    #[test]
+    fn attention_decode_matches_reference() {
+        let device = Device::request_device(DeviceRequest::GPU).unwrap();
+        let problem = AttentionProblem {
+            B: 1,
+            KVH: 8,
+            n_rep: 4,
+            L: 1,
+            cached: 511,
+            D: 96,
+            causal: true,
+        };
+        run_attention_trial(problem, device);
+    }
+
+    fn run_fully_masked_trial(device: Device) {
+        let q = Tensor::zeros::<f32>(&shape![1, 1, 2, 4], &Device::CPU);
+        let k = Tensor::randn::<f32>(shape![1, 1, 2, 4], Device::CPU);
+        let v_data = vec![1., 2., 3., 4., 5., 6., 7., 8.];
+        let v = Tensor::from_data(v_data, shape![1, 1, 2, 4], Device::CPU);
+        let mask_data = vec![f32::NEG_INFINITY, f32::NEG_INFINITY, 0., 0.];
+        let mask = Tensor::from_data(mask_data, shape![2, 2], Device::CPU);
+        let (q, k, v, mask) = (
+            q.to(&device).unwrap(),
+            k.to(&device).unwrap(),
+            v.to(&device).unwrap(),
+            mask.to(&device).unwrap(),
+        );
+        let result = q
+            .attention(k, v, AttentionMask::Additive(mask), 0.5)
+            .unwrap()
+            .resolve()
+            .unwrap();
+        let ours = result.to(&Device::CPU).unwrap().to_vec::<f32>().unwrap();
+        assert_eq!(ours, vec![0., 0., 0., 0., 3., 4., 5., 6.]);
+    }
+
+    #[test]
+    fn fully_masked_rows_are_zero() {
+        run_fully_masked_trial(Device::request_device(DeviceRequest::CPU).unwrap());
+        run_fully_masked_trial(Device::request_device(DeviceRequest::GPU).unwrap());
+    }
+}
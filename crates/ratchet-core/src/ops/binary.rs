@@ -176,6 +176,10 @@ impl Operation for Binary {
         rvec![&self.lhs, &self.rhs]
     }
 
+    fn srcs_mut(&mut self) -> RVec<&mut Tensor> {
+        rvec![&mut self.lhs, &mut self.rhs]
+    }
+
     fn supports_inplace(&self) -> bool {
         true
     }
@@ -24,10 +24,10 @@ use crate::{
 /// 3. offset, where to start the write in the cache tensor, e.g [1, 5, 1024], [1, 1, 1024], offset = 5 -> [1, 6, 1024]
 #[derive(new, Debug, Clone)]
 pub struct Cache {
-    cache: Tensor,
-    source: Tensor,
-    dim: usize,
-    offset: usize,
+    pub(crate) cache: Tensor,
+    pub(crate) source: Tensor,
+    pub(crate) dim: usize,
+    pub(crate) offset: usize,
 }
 
 impl KernelRenderable for CacheKernels {
@@ -136,6 +136,10 @@ impl Operation for Cache {
         rvec![&self.cache, &self.source]
     }
 
+    fn srcs_mut(&mut self) -> RVec<&mut Tensor> {
+        rvec![&mut self.cache, &mut self.source]
+    }
+
     fn compute_view(&self) -> Result<StorageView, OperationError> {
         let mut result_shape = self.cache.shape().clone();
         result_shape[self.dim] = self.offset + self.source.shape()[self.dim];
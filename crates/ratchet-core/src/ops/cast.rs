@@ -125,6 +125,10 @@ impl Operation for Cast {
         rvec![&self.input]
     }
 
+    fn srcs_mut(&mut self) -> RVec<&mut Tensor> {
+        rvec![&mut self.input]
+    }
+
     fn supports_inplace(&self) -> bool {
         //CANNOT BE DONE INPLACE
         false
@@ -125,6 +125,10 @@ impl Operation for Concat {
     fn srcs(&self) -> RVec<&Tensor> {
         self.inputs.iter().collect()
     }
+
+    fn srcs_mut(&mut self) -> RVec<&mut Tensor> {
+        self.inputs.iter_mut().collect()
+    }
 }
 
 impl OpGuards for Concat {
@@ -14,11 +14,11 @@ use inline_wgsl::wgsl;
 
 #[derive(new, Debug, Clone)]
 pub struct Conv {
-    input: Tensor,
-    weight: Tensor,
-    bias: Option<Tensor>,
-    stride: usize,
-    padding: usize,
+    pub(crate) input: Tensor,
+    pub(crate) weight: Tensor,
+    pub(crate) bias: Option<Tensor>,
+    pub(crate) stride: usize,
+    pub(crate) padding: usize,
     //dilation: usize, TODO: implement dilation
 }
 
@@ -179,6 +179,14 @@ impl Operation for Conv {
     fn srcs(&self) -> RVec<&Tensor> {
         rvec![&self.input, &self.weight, self.bias.as_ref().unwrap()]
     }
+
+    fn srcs_mut(&mut self) -> RVec<&mut Tensor> {
+        rvec![
+            &mut self.input,
+            &mut self.weight,
+            self.bias.as_mut().unwrap()
+        ]
+    }
 }
 
 pub enum ConvKernels {
@@ -0,0 +1,598 @@
+use encase::ShaderType;
+use half::f16;
+use inline_wgsl::wgsl;
+use ratchet_macros::WgslMetadata;
+
+use crate::{
+    gpu::BindGroupLayoutDescriptor, rvec, BinaryOp, BindingMode, BuiltIn, DType, DeviceFeatures,
+    GPUOperation, InvariantError, Kernel, KernelElement, KernelKey, KernelRenderable, KernelSource,
+    LazyOp, OpGuards, Operation, OperationError, RVec, Scalar, StorageView, Strides, Tensor,
+    TensorId, Unary, UnaryOp, Vec2, Vec4, WgslKernelBuilder, WgslPrimitive, WorkgroupSize,
+    Workload,
+};
+
+/// An instruction of a [FusedElementwise] program, operands are indices of earlier steps.
+#[derive(Debug, Clone)]
+pub enum FusedStep {
+    /// Load the input at this index.
+    Input(usize),
+    Unary(UnaryOp, usize),
+    Binary(BinaryOp, usize, usize),
+    Cast(DType, usize),
+}
+
+/// A chain of elementwise operations evaluated by a single kernel, without writing the
+/// intermediates to memory.
+///
+/// The value of the last step is stored. All inputs have the shape of the output.
+#[derive(Debug, Clone)]
+pub struct FusedElementwise {
+    inputs: RVec<Tensor>,
+    steps: Vec<FusedStep>,
+}
+
+impl FusedElementwise {
+    /// One storage buffer is left for the output, out of the 8 WebGPU guarantees.
+    pub const MAX_INPUTS: usize = 7;
+
+    pub fn inputs(&self) -> &[Tensor] {
+        &self.inputs
+    }
+
+    pub fn steps(&self) -> &[FusedStep] {
+        &self.steps
+    }
+
+    /// The single step program of an elementwise operation.
+    pub fn from_op(op: &LazyOp) -> Option<Self> {
+        let mut program = ProgramBuilder::default();
+        let step = match op {
+            LazyOp::Fused(f) => return Some(f.clone()),
+            LazyOp::Unary(u) => {
+                let input = program.input(u.input());
+                FusedStep::Unary(u.op().clone(), input)
+            }
+            LazyOp::Binary(b) => {
+                let (lhs, rhs) = (program.input(b.lhs()), program.input(b.rhs()));
+                FusedStep::Binary(b.op().clone(), lhs, rhs)
+            }
+            LazyOp::Cast(c) => {
+                let input = program.input(c.input());
+                FusedStep::Cast(c.dst_dt(), input)
+            }
+            _ => return None,
+        };
+        program.steps.push(step);
+        Some(program.build())
+    }
+
+    /// Substitute the program of `producer` for the input `id`, which it computes.
+    pub fn inline(&self, id: TensorId, producer: &FusedElementwise) -> Self {
+        let mut program = ProgramBuilder::default();
+        let result = program.append(producer, |_| None);
+        program.append(self, |i| (self.inputs[i].id() == id).then_some(result));
+        program.build()
+    }
+
+    /// Whether a single kernel can evaluate the program.
+    ///
+    /// Operations are rendered in the precision they ran in before fusion, the helper functions
+    /// of unary operations can't be overloaded so they must all share a type.
+    pub fn is_supported(&self) -> bool {
+        let dts = self.step_dts();
+        let mut unary_dts = self
+            .steps
+            .iter()
+            .zip(&dts)
+            .filter(|(s, _)| matches!(s, FusedStep::Unary(..)))
+            .map(|(_, dt)| *dt);
+        let first = unary_dts.next();
+        self.inputs.len() <= Self::MAX_INPUTS
+            && dts.iter().all(|dt| matches!(dt, DType::F32 | DType::F16))
+            && unary_dts.all(|dt| Some(dt) == first)
+    }
+
+    /// The WGSL source of the kernel on a device with `features`, which needn't be present.
+    pub fn wgsl(&self, features: &DeviceFeatures) -> Result<String, OperationError> {
+        let kernel = self.select_kernel();
+        let workload = Workload::std(self.numel(), self.kernel_element());
+        Ok(kernel
+            .source(features.clone(), &workload.workgroup_size)?
+            .to_string())
+    }
+
+    fn numel(&self) -> usize {
+        self.inputs[0].shape().numel()
+    }
+
+    fn step_dts(&self) -> Vec<DType> {
+        let mut dts: Vec<DType> = Vec::with_capacity(self.steps.len());
+        for step in self.steps.iter() {
+            let dt = match step {
+                FusedStep::Input(i) => self.inputs[*i].dt(),
+                FusedStep::Unary(_, x) | FusedStep::Binary(_, x, _) => dts[*x],
+                FusedStep::Cast(dt, _) => *dt,
+            };
+            dts.push(dt);
+        }
+        dts
+    }
+
+    fn dst_dt(&self) -> DType {
+        *self.step_dts().last().unwrap()
+    }
+
+    /// The type the unary operations run in, which their helper functions are rendered with.
+    fn compute_dt(&self) -> DType {
+        self.steps
+            .iter()
+            .zip(self.step_dts())
+            .find(|(s, _)| matches!(s, FusedStep::Unary(..)))
+            .map_or_else(|| self.dst_dt(), |(_, dt)| dt)
+    }
+
+    fn kernel_element(&self) -> KernelElement {
+        let numel = self.numel();
+        if numel % 4 == 0 {
+            KernelElement::Vec4
+        } else if numel % 2 == 0 {
+            KernelElement::Vec2
+        } else {
+            KernelElement::Scalar
+        }
+    }
+
+    /// Uniquely identifies the program, given the types of the inputs.
+    fn program_key(&self) -> String {
+        self.steps
+            .iter()
+            .map(|step| match step {
+                FusedStep::Input(i) => format!("x{}", i),
+                FusedStep::Unary(op, x) => format!("{}{}", op.kernel_name(), x),
+                FusedStep::Binary(op, a, b) => format!("{}{}.{}", op.kernel_name(), a, b),
+                FusedStep::Cast(dt, x) => format!("{}{}", dt.as_wgsl(), x),
+            })
+            .collect::<Vec<_>>()
+            .join("-")
+    }
+}
+
+#[derive(Default)]
+struct ProgramBuilder {
+    inputs: RVec<Tensor>,
+    steps: Vec<FusedStep>,
+}
+
+impl ProgramBuilder {
+    /// Load `t`, returning the step holding it.
+    fn input(&mut self, t: &Tensor) -> usize {
+        let idx = match self.inputs.iter().position(|i| i.id() == t.id()) {
+            Some(idx) => idx,
+            None => {
+                self.inputs.push(t.clone());
+                self.inputs.len() - 1
+            }
+        };
+        let loaded = self
+            .steps
+            .iter()
+            .position(|s| matches!(s, FusedStep::Input(i) if *i == idx));
+        loaded.unwrap_or_else(|| {
+            self.steps.push(FusedStep::Input(idx));
+            self.steps.len() - 1
+        })
+    }
+
+    /// Append the steps of `program`, loading its inputs unless `bound` maps them to a step.
+    /// Returns the step holding its result.
+    fn append(
+        &mut self,
+        program: &FusedElementwise,
+        bound: impl Fn(usize) -> Option<usize>,
+    ) -> usize {
+        let mut map = Vec::with_capacity(program.steps.len());
+        for step in program.steps.iter() {
+            let mapped = match step {
+                FusedStep::Input(i) => {
+                    map.push(bound(*i).unwrap_or_else(|| self.input(&program.inputs[*i])));
+                    continue;
+                }
+                FusedStep::Unary(op, x) => FusedStep::Unary(op.clone(), map[*x]),
+                FusedStep::Binary(op, a, b) => FusedStep::Binary(op.clone(), map[*a], map[*b]),
+                FusedStep::Cast(dt, x) => FusedStep::Cast(*dt, map[*x]),
+            };
+            self.steps.push(mapped);
+            map.push(self.steps.len() - 1);
+        }
+        *map.last().unwrap()
+    }
+
+    fn build(self) -> FusedElementwise {
+        FusedElementwise {
+            inputs: self.inputs,
+            steps: self.steps,
+        }
+    }
+}
+
+/// Unary operations applied by a kernel to each value before it is stored.
+#[derive(Debug, Clone, Default)]
+pub struct Epilogue {
+    ops: Vec<UnaryOp>,
+}
+
+impl Epilogue {
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    pub fn ops(&self) -> &[UnaryOp] {
+        &self.ops
+    }
+
+    pub fn push(&mut self, op: UnaryOp) {
+        self.ops.push(op);
+    }
+
+    /// Distinguishes kernels with different epilogues in their [KernelKey].
+    pub fn key(&self) -> String {
+        self.ops
+            .iter()
+            .map(|op| op.kernel_name())
+            .collect::<Vec<_>>()
+            .join("_")
+    }
+
+    /// Renders `fn epilogue`, along with the helper functions it calls.
+    pub fn render<P: WgslPrimitive>(&self) -> String {
+        let accessor = P::render_type();
+        let mut body = String::new();
+        for op in self.ops.iter() {
+            body.push_str(&format!("x = {}(x);\n", op.kernel_operation()));
+        }
+
+        let mut source = Unary::render_helpers::<P>(&self.ops);
+        source.push_str(&wgsl! {
+            fn epilogue(val: 'accessor) -> 'accessor {
+                var x = val;
+                'body
+                return x;
+            }
+        });
+        source
+    }
+
+    /// `value` passed through the epilogue, if there is one.
+    pub fn apply(&self, value: &str) -> String {
+        if self.is_empty() {
+            value.to_string()
+        } else {
+            format!("epilogue({})", value)
+        }
+    }
+}
+
+#[derive(Debug, ShaderType, WgslMetadata)]
+pub struct FusedMeta {
+    numel: u32,
+}
+
+impl OpGuards for FusedElementwise {
+    fn check_shapes(&self) {
+        let shape = self.inputs[0].shape();
+        assert!(self.inputs.iter().all(|i| i.shape() == shape));
+        for (idx, step) in self.steps.iter().enumerate() {
+            match step {
+                FusedStep::Input(i) => assert!(*i < self.inputs.len()),
+                FusedStep::Unary(_, x) | FusedStep::Cast(_, x) => assert!(*x < idx),
+                FusedStep::Binary(_, a, b) => assert!(*a < idx && *b < idx),
+            }
+        }
+    }
+
+    fn check_dtypes(&self) {
+        assert!(self.is_supported(), "Unsupported fused program: {:?}", self);
+        let dts = self.step_dts();
+        for step in self.steps.iter() {
+            if let FusedStep::Binary(_, a, b) = step {
+                assert_eq!(dts[*a], dts[*b]);
+            }
+        }
+    }
+}
+
+impl Operation for FusedElementwise {
+    fn name(&self) -> &'static str {
+        "Fused"
+    }
+
+    fn compute_view(&self) -> Result<StorageView, OperationError> {
+        let shape = self.inputs[0].shape().clone();
+        let strides = Strides::from(&shape);
+        Ok(StorageView::new(shape, self.dst_dt(), strides))
+    }
+
+    fn srcs(&self) -> RVec<&Tensor> {
+        self.inputs.iter().collect()
+    }
+
+    fn srcs_mut(&mut self) -> RVec<&mut Tensor> {
+        self.inputs.iter_mut().collect()
+    }
+}
+
+pub enum FusedKernels {
+    Standard(FusedElementwise),
+}
+
+impl GPUOperation for FusedElementwise {
+    type KernelEnum = FusedKernels;
+
+    fn select_kernel(&self) -> Self::KernelEnum {
+        FusedKernels::Standard(self.clone())
+    }
+}
+
+impl FusedKernels {
+    /// Renders with the primitive of the compute type, inputs and outputs of other types are
+    /// converted on load and store.
+    fn source(
+        &self,
+        features: DeviceFeatures,
+        workgroup_size: &WorkgroupSize,
+    ) -> Result<KernelSource, OperationError> {
+        let FusedKernels::Standard(inner) = self;
+        let kernel_element = inner.kernel_element();
+        match (inner.compute_dt(), &kernel_element) {
+            (DType::F32, KernelElement::Scalar) => {
+                self.render_with::<Scalar<f32>>(features, workgroup_size)
+            }
+            (DType::F32, KernelElement::Vec2) => {
+                self.render_with::<Vec2<f32>>(features, workgroup_size)
+            }
+            (DType::F32, KernelElement::Vec4) => {
+                self.render_with::<Vec4<f32>>(features, workgroup_size)
+            }
+            (DType::F16, KernelElement::Scalar) => {
+                self.render_with::<Scalar<f16>>(features, workgroup_size)
+            }
+            (DType::F16, KernelElement::Vec2) => {
+                self.render_with::<Vec2<f16>>(features, workgroup_size)
+            }
+            (DType::F16, KernelElement::Vec4) => {
+                self.render_with::<Vec4<f16>>(features, workgroup_size)
+            }
+            (dt, _) => Err(OperationError::CompileError(format!(
+                "Unsupported dtype {:?} or kernel element {:?}",
+                dt, kernel_element
+            ))),
+        }
+    }
+
+    fn render_with<P: WgslPrimitive>(
+        &self,
+        features: DeviceFeatures,
+        workgroup_size: &WorkgroupSize,
+    ) -> Result<KernelSource, OperationError> {
+        let mut kernel_builder = WgslKernelBuilder::new(
+            workgroup_size.clone(),
+            rvec![
+                BuiltIn::WorkgroupId,
+                BuiltIn::LocalInvocationIndex,
+                BuiltIn::NumWorkgroups
+            ],
+            features,
+        );
+
+        self.register_bindings::<P>(&mut kernel_builder, false)?;
+        let FusedKernels::Standard(inner) = self;
+        kernel_builder.render_metadata(&FusedMeta {
+            numel: inner.numel() as u32,
+        });
+
+        let unary_ops = inner.steps.iter().filter_map(|s| match s {
+            FusedStep::Unary(op, _) => Some(op),
+            _ => None,
+        });
+        kernel_builder.write_global(Unary::render_helpers::<P>(unary_ops));
+
+        let n = P::W;
+        kernel_builder.write_main(wgsl! {
+            let x_offset = workgroup_id.x * 64u;
+            let index = (workgroup_id.y * num_workgroups.x * 64u) + x_offset + local_invocation_index;
+            if (index >= metadata.numel / 'n) {
+                return;
+            }
+        });
+
+        for (idx, step) in inner.steps.iter().enumerate() {
+            let value = match step {
+                FusedStep::Input(i) => format!("X{}[index]", i),
+                FusedStep::Unary(op, x) => format!("{}(v{})", op.kernel_operation(), x),
+                FusedStep::Binary(op, a, b) => {
+                    format!("v{} {} v{}", a, op.kernel_operator(), b)
+                }
+                FusedStep::Cast(dt, x) => format!("{}(v{})", accessor(*dt, n)?, x),
+            };
+            kernel_builder.write_main(format!("let v{} = {};\n", idx, value));
+        }
+        let result = inner.steps.len() - 1;
+        kernel_builder.write_main(format!("Y[index] = v{};\n", result));
+
+        Ok(kernel_builder.build()?)
+    }
+}
+
+/// The WGSL type of `width` elements of `dt`.
+fn accessor(dt: DType, width: usize) -> Result<String, OperationError> {
+    if !matches!(dt, DType::F32 | DType::F16) {
+        return Err(InvariantError::UnsupportedDType(dt).into());
+    }
+    match width {
+        1 => Ok(dt.as_wgsl().to_string()),
+        2 | 4 => Ok(format!("vec{}<{}>", width, dt.as_wgsl())),
+        _ => Err(OperationError::CompileError(format!(
+            "Fused kernels cannot be vectorized by {}",
+            width
+        ))),
+    }
+}
+
+impl KernelRenderable for FusedKernels {
+    fn register_bindings<P: WgslPrimitive>(
+        &self,
+        builder: &mut WgslKernelBuilder,
+        _: bool,
+    ) -> Result<(), OperationError> {
+        let FusedKernels::Standard(inner) = self;
+        for (i, input) in inner.inputs.iter().enumerate() {
+            let array = format!("array<{}>", accessor(input.dt(), P::W)?);
+            unsafe {
+                builder.register_storage_raw(
+                    format!("X{}", i).as_str(),
+                    BindingMode::ReadOnly,
+                    array,
+                )
+            };
+        }
+        let array = format!("array<{}>", accessor(inner.dst_dt(), P::W)?);
+        unsafe { builder.register_storage_raw("Y", BindingMode::ReadWrite, array) };
+        builder.register_uniform();
+        Ok(())
+    }
+
+    fn render<P: WgslPrimitive>(
+        &self,
+        _: bool,
+        dst: &Tensor,
+        workgroup_size: &WorkgroupSize,
+    ) -> Result<KernelSource, OperationError> {
+        let device = dst.device().try_gpu()?;
+        self.render_with::<P>(device.compute_features().clone(), workgroup_size)
+    }
+}
+
+impl Kernel for FusedKernels {
+    type Metadata = FusedMeta;
+
+    fn kernel_name(&self) -> String {
+        "fused".to_string()
+    }
+
+    fn kernel_key(
+        &self,
+        workgroup_size: &WorkgroupSize,
+        inplace: bool,
+        srcs: &[&Tensor],
+        dst: &Tensor,
+        kernel_element: &KernelElement,
+    ) -> KernelKey {
+        let FusedKernels::Standard(inner) = self;
+        KernelKey::new(
+            &self.kernel_name(),
+            srcs,
+            dst,
+            workgroup_size,
+            inplace,
+            kernel_element,
+            Some(&inner.program_key()),
+        )
+    }
+
+    fn storage_bind_group_layout(
+        &self,
+        inplace: bool,
+    ) -> Result<BindGroupLayoutDescriptor, OperationError> {
+        if inplace {
+            return Err(OperationError::InplaceError(self.kernel_name()));
+        }
+        let FusedKernels::Standard(inner) = self;
+        Ok(BindGroupLayoutDescriptor::nthary(inner.inputs.len()))
+    }
+
+    fn metadata(&self, dst: &Tensor, _: &KernelElement) -> Result<Self::Metadata, OperationError> {
+        Ok(FusedMeta {
+            numel: dst.shape().numel() as u32,
+        })
+    }
+
+    fn calculate_dispatch(&self, dst: &Tensor) -> Result<Workload, OperationError> {
+        Ok(Workload::std(dst.shape().numel(), self.kernel_element(dst)))
+    }
+
+    fn kernel_element(&self, _: &Tensor) -> KernelElement {
+        let FusedKernels::Standard(inner) = self;
+        inner.kernel_element()
+    }
+
+    fn build_kernel(
+        &self,
+        _: bool,
+        dst: &Tensor,
+        workgroup_size: &WorkgroupSize,
+    ) -> Result<KernelSource, OperationError> {
+        let device = dst.device().try_gpu()?;
+        self.source(device.compute_features().clone(), workgroup_size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        shape, Device, DeviceFeatures, Epilogue, FusedElementwise, FusedStep, Tensor, UnaryOp, Vec4,
+    };
+
+    #[test]
+    fn renders_fused_program() -> anyhow::Result<()> {
+        let a = Tensor::randn::<f32>(shape![2, 8], Device::CPU);
+        let b = Tensor::randn::<f32>(shape![2, 8], Device::CPU);
+        let product = a.mul(b)?;
+        let activation = product.clone().silu()?;
+        let root = activation.clone().half()?;
+
+        let program = |t: &Tensor| FusedElementwise::from_op(t.op()).unwrap();
+        let fused = program(&root)
+            .inline(activation.id(), &program(&activation))
+            .inline(product.id(), &program(&product));
+        assert_eq!(fused.inputs().len(), 2);
+        assert!(matches!(
+            fused.steps(),
+            [
+                FusedStep::Input(0),
+                FusedStep::Input(1),
+                FusedStep::Binary(_, 0, 1),
+                FusedStep::Unary(UnaryOp::Silu, 2),
+                FusedStep::Cast(_, 3),
+            ]
+        ));
+
+        let features = DeviceFeatures {
+            SHADER_F16: true,
+            SUBGROUP: false,
+        };
+        let src = fused.wgsl(&features)?;
+        assert_eq!(src.matches("fn sigmoid").count(), 1);
+        assert_eq!(src.matches("fn silu").count(), 1);
+        assert!(src.contains("array<vec4<f16>>"));
+        assert!(src.contains("let v2 = v0 * v1;"));
+        assert!(src.contains("let v4 = vec4<f16>(v3);"));
+        assert!(src.contains("Y[index] = v4;"));
+        Ok(())
+    }
+
+    #[test]
+    fn renders_epilogue() {
+        let mut epilogue = Epilogue::default();
+        assert_eq!(epilogue.apply("val"), "val");
+
+        epilogue.push(UnaryOp::Gelu);
+        epilogue.push(UnaryOp::Tanh);
+        let src = epilogue.render::<Vec4<f32>>();
+        assert_eq!(src.matches("fn safe_tanh").count(), 1);
+        assert!(src.contains("fn epilogue"));
+        assert!(src.contains("x = gelu(x);"));
+        assert!(src.contains("x = safe_tanh(x);"));
+        assert_eq!(epilogue.apply("val"), "epilogue(val)");
+        assert_eq!(epilogue.key(), "gelu_tanh");
+    }
+}
@@ -13,9 +13,9 @@ use crate::{
 
 #[derive(new, Debug, Clone)]
 pub struct IndexWrite {
-    dst: Tensor,
-    src: Tensor,
-    write_start: RVec<usize>,
+    pub(crate) dst: Tensor,
+    pub(crate) src: Tensor,
+    pub(crate) write_start: RVec<usize>,
 }
 
 impl IndexWrite {}
@@ -46,6 +46,10 @@ impl Operation for IndexWrite {
         rvec![&self.dst, &self.src]
     }
 
+    fn srcs_mut(&mut self) -> RVec<&mut Tensor> {
+        rvec![&mut self.dst, &mut self.src]
+    }
+
     fn supports_inplace(&self) -> bool {
         true
     }
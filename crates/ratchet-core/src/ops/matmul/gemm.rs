@@ -4,7 +4,7 @@ use ratchet_macros::WgslMetadata;
 
 use crate::{
     gpu::dtype::WgslDType, rvec, wgc, wgs, Array, BindGroupLayoutDescriptor as BGLD, BindingMode,
-    BuiltIn, DType, InvariantError, Kernel, KernelElement, KernelKey, KernelRenderable,
+    BuiltIn, DType, Epilogue, InvariantError, Kernel, KernelElement, KernelKey, KernelRenderable,
     KernelSource, Matmul, MatmulSpec, OperationError, Scalar, Strides, Tensor, Vec2, Vec4,
     WgslFragment, WgslKernelBuilder, WgslPrimitive, WorkgroupCount, WorkgroupSize, Workload,
 };
@@ -18,6 +18,7 @@ pub struct GEMM {
     trans_lhs: bool,
     trans_rhs: bool,
     trans_dst: bool,
+    epilogue: Epilogue,
     spec: MatmulSpec,
 }
 
@@ -30,6 +31,7 @@ impl GEMM {
             trans_lhs,
             trans_rhs,
             trans_dst,
+            epilogue,
         } = matmul.clone();
         Self {
             lhs,
@@ -38,6 +40,7 @@ impl GEMM {
             trans_lhs,
             trans_rhs,
             trans_dst,
+            epilogue,
             spec,
         }
     }
@@ -112,6 +115,9 @@ impl KernelRenderable for GEMM {
         self.register_bindings::<P>(&mut kernel_builder, inplace)
             .unwrap();
         kernel_builder.render_metadata(&self.metadata(dst, &self.kernel_element(dst))?);
+        if !self.epilogue.is_empty() {
+            kernel_builder.write_global(self.epilogue.render::<P>());
+        }
         self.write_indexing::<P>(&mut kernel_builder);
         self.write_getters::<P>(dst, &mut kernel_builder)?;
         self.write_readers_and_writers::<P>(&mut kernel_builder, self.spec.tile_fit())?;
@@ -142,14 +148,15 @@ impl Kernel for GEMM {
         let bias_key = if self.bias.is_some() { "bias" } else { "" };
 
         let additional = format!(
-            "{}_{}_{}_{}_{}_{}_{}",
+            "{}_{}_{}_{}_{}_{}_{}_{}",
             if a_fit { "" } else { "a_checked" },
             if b_fit { "" } else { "b_checked" },
             if out_fit { "" } else { "out_checked" },
             if self.trans_lhs { "trans_a" } else { "" },
             if self.trans_rhs { "trans_b" } else { "" },
             if self.trans_dst { "trans_dst" } else { "" },
-            bias_key
+            bias_key,
+            self.epilogue.key()
         );
 
         KernelKey::new(
@@ -278,6 +285,7 @@ impl GEMM {
     fn write_indexing<P: WgslPrimitive>(&self, builder: &mut WgslKernelBuilder) {
         let accessor = P::render_type();
         let W = P::W;
+        let store = self.epilogue.apply(&format!("{}(value)", accessor));
         builder.write_global(wgsl! {
             fn getAIndexFromCoords3D(coords : vec3<i32>) -> i32 {
                 return dot(coords, metadata.lhs_strides);
@@ -292,7 +300,7 @@ impl GEMM {
             }
 
             fn setOutputAtIndex(flatIndex : i32, value : 'accessor) {
-                result[flatIndex] = 'accessor(value);
+                result[flatIndex] = 'store;
             }
 
             fn setOutputAtCoords(d0 : i32, d1 : i32, d2 : i32, value : 'accessor) {
@@ -0,0 +1,348 @@
+use encase::ShaderType;
+use half::f16;
+use ratchet_macros::WgslMetadata;
+
+use crate::{
+    gpu::dtype::WgslDType, rvec, Array, BindGroupLayoutDescriptor as BGLD, BindingMode, BuiltIn,
+    DType, InvariantError, Kernel, KernelElement, KernelKey, KernelRenderable, KernelSource,
+    Matmul, MatmulSpec, OperationError, Scalar, Tensor, WgslKernelBuilder, WgslPrimitive,
+    WorkgroupSize, Workload,
+};
+use inline_wgsl::wgsl;
+
+/// # GGML MatMul
+///
+/// Matmul with a LHS stored as verbatim GGML blocks (Q4_0, Q5_K, Q6_K etc).
+///
+/// Each invocation computes a single output element, dequantizing the LHS row on the fly
+/// and accumulating in f32. Activations may be F32 or F16.
+///
+/// This is a reference path, it trades speed for covering every block type with one kernel:
+/// nothing is tiled or shared between invocations, and each element re-decodes the header of
+/// its block. It makes GGUF checkpoints runnable as published, Q8_0 with its tiled kernels
+/// remains the default quantization.
+#[derive(Debug, Clone)]
+pub struct GGMLMatMul {
+    lhs: Tensor,
+    rhs: Tensor,
+    bias: Option<Tensor>,
+    trans_lhs: bool,
+    trans_rhs: bool,
+    trans_dst: bool,
+    spec: MatmulSpec,
+}
+
+impl GGMLMatMul {
+    pub fn from_matmul(matmul: &Matmul, spec: MatmulSpec) -> Self {
+        let Matmul {
+            lhs,
+            rhs,
+            bias,
+            trans_lhs,
+            trans_rhs,
+            trans_dst,
+            ..
+        } = matmul.clone();
+        Self {
+            lhs,
+            rhs,
+            bias,
+            trans_lhs,
+            trans_rhs,
+            trans_dst,
+            spec,
+        }
+    }
+}
+
+#[derive(Debug, Clone, ShaderType, WgslMetadata)]
+pub struct GGMLMatMulMeta {
+    M: u32,
+    N: u32,
+    K: u32,
+    lhs_stack_stride: u32,
+    rhs_stack_stride: u32,
+    dst_numel: u32,
+}
+
+impl Kernel for GGMLMatMul {
+    type Metadata = GGMLMatMulMeta;
+
+    fn kernel_name(&self) -> String {
+        "ggml_matmul".to_string()
+    }
+
+    fn kernel_key(
+        &self,
+        workgroup_size: &WorkgroupSize,
+        inplace: bool,
+        srcs: &[&Tensor],
+        dst: &Tensor,
+        kernel_element: &KernelElement,
+    ) -> KernelKey {
+        let additional = format!(
+            "{}_{}_{}_{}",
+            if self.trans_lhs { "trans_a" } else { "" },
+            if self.trans_rhs { "trans_b" } else { "" },
+            if self.trans_dst { "trans_dst" } else { "" },
+            if self.bias.is_some() { "bias" } else { "" },
+        );
+        KernelKey::new(
+            &self.kernel_name(),
+            srcs,
+            dst,
+            workgroup_size,
+            inplace,
+            kernel_element,
+            Some(&additional),
+        )
+    }
+
+    fn metadata(&self, dst: &Tensor, _: &KernelElement) -> Result<Self::Metadata, OperationError> {
+        let spec = &self.spec;
+        let (lhs_shape, rhs_shape) = (spec.lhs_shape(), spec.rhs_shape());
+        let (M, K) = if self.trans_lhs {
+            (lhs_shape[1], lhs_shape[0])
+        } else {
+            (lhs_shape[0], lhs_shape[1])
+        };
+        let N = if self.trans_rhs {
+            rhs_shape[0]
+        } else {
+            rhs_shape[1]
+        };
+        //A stack of 1 is broadcast across the others
+        let stride = |stack: usize, numel: usize| if stack == 1 { 0 } else { numel as u32 };
+        Ok(GGMLMatMulMeta {
+            M: M as _,
+            N: N as _,
+            K: K as _,
+            lhs_stack_stride: stride(spec.lhs_stack(), lhs_shape.numel()),
+            rhs_stack_stride: stride(spec.rhs_stack(), rhs_shape.numel()),
+            dst_numel: dst.shape().numel() as _,
+        })
+    }
+
+    fn calculate_dispatch(&self, dst: &Tensor) -> Result<Workload, OperationError> {
+        Ok(Workload::std(dst.shape().numel(), self.kernel_element(dst)))
+    }
+
+    fn kernel_element(&self, _: &Tensor) -> KernelElement {
+        KernelElement::Scalar
+    }
+
+    fn build_kernel(
+        &self,
+        inplace: bool,
+        dst: &Tensor,
+        workgroup_size: &WorkgroupSize,
+    ) -> Result<KernelSource, OperationError> {
+        match self.rhs.dt() {
+            DType::F32 => self.render::<Scalar<f32>>(inplace, dst, workgroup_size),
+            DType::F16 => self.render::<Scalar<f16>>(inplace, dst, workgroup_size),
+            dt => Err(InvariantError::UnsupportedDType(dt).into()),
+        }
+    }
+
+    fn storage_bind_group_layout(&self, _: bool) -> Result<BGLD, OperationError> {
+        if !self.lhs.dt().is_ggml_block() {
+            return Err(InvariantError::UnsupportedDType(self.lhs.dt()).into());
+        }
+        match self.bias {
+            Some(_) => Ok(BGLD::ternary()),
+            None => Ok(BGLD::binary()),
+        }
+    }
+}
+
+impl KernelRenderable for GGMLMatMul {
+    fn register_bindings<P: WgslPrimitive>(
+        &self,
+        builder: &mut WgslKernelBuilder,
+        _: bool,
+    ) -> Result<(), OperationError> {
+        let ro = BindingMode::ReadOnly;
+        builder.register_storage("A", ro, Array::<Scalar<u32>>::default());
+        builder.register_storage("B", ro, Array::<P>::default());
+        if self.bias.is_some() {
+            builder.register_storage("bias", ro, Array::<P>::default());
+        }
+        builder.register_storage("Y", BindingMode::ReadWrite, Array::<P>::default());
+        builder.register_uniform();
+        Ok(())
+    }
+
+    fn render<P: WgslPrimitive>(
+        &self,
+        inplace: bool,
+        dst: &Tensor,
+        workgroup_size: &WorkgroupSize,
+    ) -> Result<KernelSource, OperationError> {
+        let device = dst.device().try_gpu()?;
+        let mut kernel_builder = WgslKernelBuilder::new(
+            workgroup_size.clone(),
+            rvec![
+                BuiltIn::WorkgroupId,
+                BuiltIn::LocalInvocationIndex,
+                BuiltIn::NumWorkgroups
+            ],
+            device.compute_features().clone(),
+        );
+        self.register_bindings::<P>(&mut kernel_builder, inplace)?;
+        kernel_builder.render_metadata(&self.metadata(dst, &self.kernel_element(dst))?);
+        kernel_builder.write_dequantize(self.lhs.dt(), "A");
+
+        let dt = P::T::DT;
+        kernel_builder.write_main(wgsl! {
+            let x_offset = workgroup_id.x * 64u;
+            let index = (workgroup_id.y * num_workgroups.x * 64u) + x_offset + local_invocation_index;
+            if (index >= metadata.dst_numel) {
+                return;
+            }
+
+            let batch = index / (metadata.M * metadata.N);
+            let rem = index % (metadata.M * metadata.N);
+        });
+
+        //(i, j) index the untransposed product, A is [M, K] & B is [K, N].
+        //Blocks are dequantized by flat index, so a transposed A is just read down its columns
+        kernel_builder.write_main(if self.trans_dst {
+            wgsl! {
+                let i = rem % metadata.M;
+                let j = rem / metadata.M;
+            }
+        } else {
+            wgsl! {
+                let i = rem / metadata.N;
+                let j = rem % metadata.N;
+            }
+        });
+
+        let (a_offset, a_index) = if self.trans_lhs {
+            ("i", "a_offset + k * metadata.M")
+        } else {
+            ("i * metadata.K", "a_offset + k")
+        };
+        let b_index = if self.trans_rhs {
+            "b_offset + j * metadata.K + k"
+        } else {
+            "b_offset + k * metadata.N + j"
+        };
+        kernel_builder.write_main(wgsl! {
+            let a_offset = batch * metadata.lhs_stack_stride + 'a_offset;
+            let b_offset = batch * metadata.rhs_stack_stride;
+
+            var acc = 0f;
+            for (var k = 0u; k < metadata.K; k++) {
+                acc += dequantize('a_index) * f32(B['b_index]);
+            }
+        });
+
+        if self.bias.is_some() {
+            let bias_index = if self.trans_dst { "i" } else { "j" };
+            kernel_builder.write_main(wgsl! { acc += f32(bias['bias_index]); });
+        }
+        kernel_builder.write_main(wgsl! { Y[index] = 'dt(acc); });
+
+        Ok(kernel_builder.build()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_strategy::{proptest, Arbitrary};
+
+    use crate::{dequantize, shape, DType, Device, DeviceRequest, Tensor};
+
+    fn random_blocks(dt: DType, numel: usize) -> Vec<u32> {
+        let n_bytes = numel / dt.ggml_block_numel().unwrap() * dt.size_of();
+        let block_numel = dt.ggml_block_numel().unwrap();
+        //Random bytes everywhere, with sane f16 scales in place of the raw ones
+        let mut bytes = (0..n_bytes)
+            .map(|_| rand::random::<u8>())
+            .collect::<Vec<_>>();
+        for block in bytes.chunks_exact_mut(dt.size_of()) {
+            for offset in scale_offsets(dt) {
+                let scale = half::f16::from_f32(rand::random::<f32>() / block_numel as f32);
+                block[offset..offset + 2].copy_from_slice(&scale.to_le_bytes());
+            }
+        }
+        bytes.resize(n_bytes.div_ceil(4) * 4, 0);
+        bytemuck::cast_slice(&bytes).to_vec()
+    }
+
+    fn scale_offsets(dt: DType) -> Vec<usize> {
+        match dt {
+            DType::Q4_0(_) | DType::Q5_0(_) => vec![0],
+            DType::Q4_1(_) | DType::Q5_1(_) | DType::Q5_K(_) => vec![0, 2],
+            DType::Q2_K(_) => vec![80, 82],
+            DType::Q3_K(_) => vec![108],
+            DType::Q6_K(_) => vec![208],
+            _ => unreachable!(),
+        }
+    }
+
+    #[derive(Arbitrary, Debug)]
+    struct GGMLMatMulProblem {
+        #[strategy(0..8usize)]
+        dt: usize,
+        #[strategy(1..4usize)]
+        B: usize,
+        #[strategy(1..17usize)]
+        M: usize,
+        #[strategy(1..3usize)]
+        k_blocks: usize,
+        #[strategy(1..65usize)]
+        N: usize,
+        trans_lhs: bool,
+    }
+
+    fn run_ggml_matmul_trial(problem: GGMLMatMulProblem) -> anyhow::Result<()> {
+        let device = Device::request_device(DeviceRequest::GPU).unwrap();
+        let GGMLMatMulProblem {
+            dt,
+            B,
+            M,
+            k_blocks,
+            N,
+            trans_lhs,
+        } = problem;
+        let dt = [
+            DType::Q4_0(Default::default()),
+            DType::Q4_1(Default::default()),
+            DType::Q5_0(Default::default()),
+            DType::Q5_1(Default::default()),
+            DType::Q2_K(Default::default()),
+            DType::Q3_K(Default::default()),
+            DType::Q5_K(Default::default()),
+            DType::Q6_K(Default::default()),
+        ][dt];
+        let K = k_blocks * dt.ggml_block_numel().unwrap();
+
+        //W is [N, K] with blocks along K. Untransposed, X is [B, M, K] & the product [B, M, N].
+        //Transposed, W is read as [K, N], X is [B, N, M] & the product [B, M, K].
+        let x_shape = if trans_lhs {
+            shape![B, N, M]
+        } else {
+            shape![B, M, K]
+        };
+        let blocks = random_blocks(dt, N * K);
+        let w = unsafe { Tensor::from_quantized(blocks, dt, shape![N, K], Device::CPU) };
+        let x = Tensor::randn::<f32>(x_shape, Device::CPU);
+        let ground = dequantize(w.deep_clone())
+            .gemm(x.clone(), None, trans_lhs, !trans_lhs, true)?
+            .resolve()?;
+
+        let w = w.to(&device)?;
+        let x = x.to(&device)?;
+        let result = w.gemm(x, None, trans_lhs, !trans_lhs, true)?.resolve()?;
+        let result = result.to(&Device::CPU)?;
+        ground.all_close(&result, 1e-3, 1e-3)?;
+        Ok(())
+    }
+
+    #[proptest(cases = 16)]
+    fn test_ggml_matmul(prob: GGMLMatMulProblem) {
+        run_ggml_matmul_trial(prob).unwrap();
+    }
+}
@@ -1,9 +1,11 @@
 mod gemm;
+mod ggml;
 mod quantized;
 mod subgroup_gemv;
 mod workgroup_gemv;
 
 pub use gemm::*;
+pub use ggml::*;
 pub use quantized::*;
 pub use subgroup_gemv::*;
 pub use workgroup_gemv::*;
@@ -12,7 +14,7 @@ use std::{cmp::Ordering, mem};
 
 use crate::{
     gpu::{BindGroupLayoutDescriptor, CpuUniform},
-    rvec, DType, Device, GPUOperation, Kernel, KernelElement, KernelKey, KernelMetadata,
+    rvec, DType, Device, Epilogue, GPUOperation, Kernel, KernelElement, KernelKey, KernelMetadata,
     KernelRenderable, KernelSource, OpGuards, Operation, OperationError, RVec, Shape, StorageView,
     Strides, Tensor, WorkgroupSize, Workload, Q4_KF, Q4_KH, Q8_0F, Q8_0H,
 };
@@ -386,6 +388,9 @@ pub struct Matmul {
     pub(crate) trans_lhs: bool,
     pub(crate) trans_rhs: bool,
     pub(crate) trans_dst: bool,
+    /// Only applied by the GEMM kernel, see [Matmul::is_gemm].
+    #[new(default)]
+    pub(crate) epilogue: Epilogue,
 }
 
 impl Matmul {
@@ -465,6 +470,16 @@ impl Matmul {
         Ok(dst_shape_final)
     }
 
+    /// Whether the GEMM kernel is selected, which is decided by the inputs alone.
+    pub fn is_gemm(&self) -> bool {
+        let is_gemv = self.rhs.shape().is_vector() && !self.trans_lhs;
+        !self.lhs.dt().is_ggml_block() && !self.lhs.dt().is_q4() && !is_gemv
+    }
+
+    pub fn epilogue(&self) -> &Epilogue {
+        &self.epilogue
+    }
+
     pub fn compute_spec(&self) -> MatmulSpec {
         MatmulSpec::new(
             &self.lhs,
@@ -501,6 +516,14 @@ impl Operation for Matmul {
             rvec![&self.lhs, &self.rhs]
         }
     }
+
+    fn srcs_mut(&mut self) -> RVec<&mut Tensor> {
+        if let Some(bias) = &mut self.bias {
+            rvec![&mut self.lhs, &mut self.rhs, bias]
+        } else {
+            rvec![&mut self.lhs, &mut self.rhs]
+        }
+    }
 }
 
 impl OpGuards for Matmul {
@@ -525,7 +548,9 @@ impl OpGuards for Matmul {
             (DType::Q4_KH(Q4_KH::default()), DType::F16),
         ];
 
-        if !allowed_pairs.contains(&(self.lhs.dt(), self.rhs.dt())) {
+        let ggml_pair =
+            self.lhs.dt().is_ggml_block() && matches!(self.rhs.dt(), DType::F32 | DType::F16);
+        if !ggml_pair && !allowed_pairs.contains(&(self.lhs.dt(), self.rhs.dt())) {
             panic!(
                 "DType mismatch: lhs: {:?}, rhs: {:?}",
                 self.lhs.dt(),
@@ -551,6 +576,7 @@ pub enum MatmulMeta {
     SubgroupGEMVMeta(SubgroupGEMVMeta),
     WorkgroupGEMVMeta(WorkgroupGEMVMeta),
     Quantized(QuantizedMeta),
+    GGML(GGMLMatMulMeta),
 }
 
 impl KernelMetadata for MatmulMeta {
@@ -560,6 +586,7 @@ impl KernelMetadata for MatmulMeta {
             MatmulMeta::SubgroupGEMVMeta(meta) => meta.render_meta(),
             MatmulMeta::WorkgroupGEMVMeta(meta) => meta.render_meta(),
             MatmulMeta::Quantized(meta) => meta.render_meta(),
+            MatmulMeta::GGML(meta) => meta.render_meta(),
         }
     }
 
@@ -569,6 +596,7 @@ impl KernelMetadata for MatmulMeta {
             MatmulMeta::SubgroupGEMVMeta(meta) => meta.write(uniform),
             MatmulMeta::WorkgroupGEMVMeta(meta) => meta.write(uniform),
             MatmulMeta::Quantized(meta) => meta.write(uniform),
+            MatmulMeta::GGML(meta) => meta.write(uniform),
         }
     }
 }
@@ -578,6 +606,7 @@ pub enum MatmulKernels {
     SubgroupGEMV(SubgroupGEMV),
     WorkgroupGEMV(WorkgroupGEMV),
     Quantized(QMatMul),
+    GGML(GGMLMatMul),
 }
 
 impl KernelRenderable for MatmulKernels {
@@ -591,6 +620,7 @@ impl KernelRenderable for MatmulKernels {
             MatmulKernels::SubgroupGEMV(kernel) => kernel.register_bindings::<P>(builder, inplace),
             MatmulKernels::WorkgroupGEMV(kernel) => kernel.register_bindings::<P>(builder, inplace),
             MatmulKernels::Quantized(kernel) => kernel.register_bindings::<P>(builder, inplace),
+            MatmulKernels::GGML(kernel) => kernel.register_bindings::<P>(builder, inplace),
         }
     }
 
@@ -605,6 +635,7 @@ impl KernelRenderable for MatmulKernels {
             MatmulKernels::SubgroupGEMV(k) => k.render::<P>(inplace, dst, workgroup_size),
             MatmulKernels::WorkgroupGEMV(k) => k.render::<P>(inplace, dst, workgroup_size),
             MatmulKernels::Quantized(k) => k.render::<P>(inplace, dst, workgroup_size),
+            MatmulKernels::GGML(k) => k.render::<P>(inplace, dst, workgroup_size),
         }
     }
 }
@@ -634,6 +665,9 @@ impl Kernel for MatmulKernels {
             MatmulKernels::Quantized(kernel) => {
                 kernel.kernel_key(workgroup_size, inplace, srcs, dst, kernel_element)
             }
+            MatmulKernels::GGML(kernel) => {
+                kernel.kernel_key(workgroup_size, inplace, srcs, dst, kernel_element)
+            }
         }
     }
 
@@ -643,6 +677,7 @@ impl Kernel for MatmulKernels {
             MatmulKernels::SubgroupGEMV(kernel) => kernel.kernel_name(),
             MatmulKernels::WorkgroupGEMV(kernel) => kernel.kernel_name(),
             MatmulKernels::Quantized(kernel) => kernel.kernel_name(),
+            MatmulKernels::GGML(kernel) => kernel.kernel_name(),
         }
     }
 
@@ -662,6 +697,7 @@ impl Kernel for MatmulKernels {
             MatmulKernels::Quantized(k) => {
                 Ok(MatmulMeta::Quantized(k.metadata(dst, kernel_element)?))
             }
+            MatmulKernels::GGML(k) => Ok(MatmulMeta::GGML(k.metadata(dst, kernel_element)?)),
         }
     }
 
@@ -671,6 +707,7 @@ impl Kernel for MatmulKernels {
             MatmulKernels::SubgroupGEMV(kernel) => kernel.calculate_dispatch(dst),
             MatmulKernels::WorkgroupGEMV(kernel) => kernel.calculate_dispatch(dst),
             MatmulKernels::Quantized(kernel) => kernel.calculate_dispatch(dst),
+            MatmulKernels::GGML(kernel) => kernel.calculate_dispatch(dst),
         }
     }
 
@@ -680,6 +717,7 @@ impl Kernel for MatmulKernels {
             MatmulKernels::SubgroupGEMV(kernel) => kernel.kernel_element(dst),
             MatmulKernels::WorkgroupGEMV(kernel) => kernel.kernel_element(dst),
             MatmulKernels::Quantized(kernel) => kernel.kernel_element(dst),
+            MatmulKernels::GGML(kernel) => kernel.kernel_element(dst),
         }
     }
 
@@ -694,6 +732,7 @@ impl Kernel for MatmulKernels {
             MatmulKernels::SubgroupGEMV(k) => k.build_kernel(inplace, dst, workgroup_size),
             MatmulKernels::WorkgroupGEMV(k) => k.build_kernel(inplace, dst, workgroup_size),
             MatmulKernels::Quantized(kernel) => kernel.build_kernel(inplace, dst, workgroup_size),
+            MatmulKernels::GGML(kernel) => kernel.build_kernel(inplace, dst, workgroup_size),
         }
     }
 
@@ -706,6 +745,7 @@ impl Kernel for MatmulKernels {
             MatmulKernels::SubgroupGEMV(kernel) => kernel.storage_bind_group_layout(inplace),
             MatmulKernels::WorkgroupGEMV(kernel) => kernel.storage_bind_group_layout(inplace),
             MatmulKernels::Quantized(kernel) => kernel.storage_bind_group_layout(inplace),
+            MatmulKernels::GGML(kernel) => kernel.storage_bind_group_layout(inplace),
         }
     }
 }
@@ -718,10 +758,15 @@ impl GPUOperation for Matmul {
             panic!("Bias must be a vector: {:?}", self.bias);
         }
 
-        if self.lhs.dt().is_quantized() && self.trans_lhs {
+        //GGML blocks are dequantized element-wise, so only the packed formats can't be transposed
+        if self.lhs.dt().is_quantized() && !self.lhs.dt().is_ggml_block() && self.trans_lhs {
             panic!("Transposed quantized inputs are not supported");
         }
 
+        if !self.epilogue.is_empty() && !self.is_gemm() {
+            panic!("Only GEMM supports epilogues: {:?}", self.epilogue);
+        }
+
         let is_gemv = self.rhs.shape().is_vector() && !self.trans_lhs;
         let is_q4 = self.lhs.dt().is_q4();
         let supports_subgroup = self
@@ -734,6 +779,10 @@ impl GPUOperation for Matmul {
 
         let spec = self.compute_spec();
 
+        if self.lhs.dt().is_ggml_block() {
+            return MatmulKernels::GGML(GGMLMatMul::from_matmul(self, spec));
+        }
+
         match (is_gemv, is_q4, supports_subgroup) {
             (true, false, true) => {
                 MatmulKernels::SubgroupGEMV(SubgroupGEMV::from_matmul(self, spec))
@@ -35,6 +35,7 @@ impl QMatMul {
             trans_lhs,
             trans_rhs,
             trans_dst,
+            ..
         } = matmul.clone();
         Self {
             lhs,
@@ -32,6 +32,7 @@ impl SubgroupGEMV {
             trans_lhs,
             trans_rhs,
             trans_dst,
+            ..
         } = matmul.clone();
         Self {
             lhs,
@@ -1,8 +1,11 @@
+mod argmax;
+mod attention;
 mod binary;
 mod cache;
 mod cast;
 mod concat;
 mod conv;
+mod fused;
 mod index_write;
 mod matmul;
 mod norm;
@@ -13,11 +16,14 @@ mod softmax;
 mod unary;
 mod view;
 
+pub use argmax::*;
+pub use attention::*;
 pub use binary::*;
 pub use cache::*;
 pub use cast::*;
 pub use concat::*;
 pub use conv::*;
+pub use fused::*;
 pub use index_write::*;
 pub use matmul::*;
 pub use norm::*;
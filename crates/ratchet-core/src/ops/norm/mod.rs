@@ -7,9 +7,10 @@ use ratchet_macros::WgslMetadata;
 
 use crate::{
     gpu::{dtype::WgslDType, BindGroupLayoutDescriptor},
-    rvec, wgc, wgs, Array, BindingMode, BuiltIn, DType, GPUOperation, Kernel, KernelElement,
-    KernelRenderable, KernelSource, OpGuards, Operation, OperationError, RVec, Scalar, StorageView,
-    Tensor, Vec2, Vec4, WgslKernelBuilder, WgslPrimitive, WorkgroupSize, Workload,
+    rvec, wgc, wgs, Array, BindingMode, BuiltIn, DType, Epilogue, GPUOperation, Kernel,
+    KernelElement, KernelKey, KernelRenderable, KernelSource, OpGuards, Operation, OperationError,
+    RVec, Scalar, StorageView, Tensor, Vec2, Vec4, WgslKernelBuilder, WgslPrimitive, WorkgroupSize,
+    Workload,
 };
 use derive_new::new;
 use inline_wgsl::wgsl;
@@ -20,6 +21,8 @@ pub struct Norm {
     pub(crate) scale: Tensor,
     pub(crate) bias: Option<Tensor>,
     pub(crate) eps: f32,
+    #[new(default)]
+    pub(crate) epilogue: Epilogue,
 }
 
 impl OpGuards for NormOp {
@@ -81,6 +84,24 @@ impl Operation for NormOp {
             },
         }
     }
+
+    fn srcs_mut(&mut self) -> RVec<&mut Tensor> {
+        match self {
+            NormOp::LayerNorm(Norm {
+                input, scale, bias, ..
+            })
+            | NormOp::GroupNorm(GroupNorm {
+                norm: Norm {
+                    input, scale, bias, ..
+                },
+                ..
+            }) => match bias {
+                Some(bias) => rvec![input, scale, bias],
+                None => rvec![input, scale],
+            },
+            NormOp::RMSNorm(Norm { input, scale, .. }) => rvec![input, scale],
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -90,6 +111,26 @@ pub enum NormOp {
     GroupNorm(GroupNorm),
 }
 
+impl NormOp {
+    fn norm(&self) -> &Norm {
+        match self {
+            NormOp::LayerNorm(n) | NormOp::RMSNorm(n) => n,
+            NormOp::GroupNorm(g) => &g.norm,
+        }
+    }
+
+    pub fn epilogue(&self) -> &Epilogue {
+        &self.norm().epilogue
+    }
+
+    pub fn epilogue_mut(&mut self) -> &mut Epilogue {
+        match self {
+            NormOp::LayerNorm(n) | NormOp::RMSNorm(n) => &mut n.epilogue,
+            NormOp::GroupNorm(g) => &mut g.norm.epilogue,
+        }
+    }
+}
+
 impl KernelRenderable for NormKernels {
     fn register_bindings<P: WgslPrimitive>(
         &self,
@@ -195,11 +236,16 @@ impl KernelRenderable for NormKernels {
         };
         kernel_builder.write_main(sigma);
 
-        let loop_core = if matches!(inner, NormOp::RMSNorm(_)) {
-            wgsl! { Y[anchor + i] = val * S[i]; }
+        let epilogue = inner.epilogue();
+        if !epilogue.is_empty() {
+            kernel_builder.write_global(epilogue.render::<P>());
+        }
+        let store = if matches!(inner, NormOp::RMSNorm(_)) {
+            epilogue.apply("val * S[i]")
         } else {
-            wgsl! { Y[anchor + i] = fma(val, S[i], B[i]); }
+            epilogue.apply("fma(val, S[i], B[i])")
         };
+        let loop_core = wgsl! { Y[anchor + i] = 'store; };
 
         kernel_builder.write_main(wgsl! {
             let denom = inverseSqrt(sigma + 'accessor(metadata.eps));
@@ -272,6 +318,26 @@ impl Kernel for NormKernels {
         }
     }
 
+    fn kernel_key(
+        &self,
+        workgroup_size: &WorkgroupSize,
+        inplace: bool,
+        srcs: &[&Tensor],
+        dst: &Tensor,
+        kernel_element: &KernelElement,
+    ) -> KernelKey {
+        let NormKernels::Standard(inner) = self;
+        KernelKey::new(
+            &self.kernel_name(),
+            srcs,
+            dst,
+            workgroup_size,
+            inplace,
+            kernel_element,
+            Some(&inner.epilogue().key()),
+        )
+    }
+
     fn metadata(&self, _: &Tensor, _: &KernelElement) -> Result<Self::Metadata, OperationError> {
         let NormKernels::Standard(inner) = self;
         let input = inner.srcs()[0];
@@ -79,6 +79,10 @@ impl Operation for Broadcast {
     fn srcs(&self) -> RVec<&Tensor> {
         rvec![&self.src]
     }
+
+    fn srcs_mut(&mut self) -> RVec<&mut Tensor> {
+        rvec![&mut self.src]
+    }
 }
 
 #[cfg(all(test, feature = "pyo3"))]
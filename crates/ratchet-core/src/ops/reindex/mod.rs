@@ -295,6 +295,14 @@ impl Operation for Reindex {
             Reindex::Broadcast(b) => b.srcs(),
         }
     }
+
+    fn srcs_mut(&mut self) -> RVec<&mut Tensor> {
+        match self {
+            Reindex::Permute(p) => p.srcs_mut(),
+            Reindex::Slice(s) => s.srcs_mut(),
+            Reindex::Broadcast(b) => b.srcs_mut(),
+        }
+    }
 }
 
 impl GPUOperation for Reindex {
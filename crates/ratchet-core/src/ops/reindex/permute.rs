@@ -61,6 +61,10 @@ impl Operation for Permute {
     fn srcs(&self) -> RVec<&Tensor> {
         rvec![&self.src]
     }
+
+    fn srcs_mut(&mut self) -> RVec<&mut Tensor> {
+        rvec![&mut self.src]
+    }
 }
 
 impl OpGuards for Permute {
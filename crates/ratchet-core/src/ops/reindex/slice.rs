@@ -66,6 +66,10 @@ impl Operation for Slice {
     fn srcs(&self) -> RVec<&Tensor> {
         rvec![&self.src]
     }
+
+    fn srcs_mut(&mut self) -> RVec<&mut Tensor> {
+        rvec![&mut self.src]
+    }
 }
 
 #[cfg(all(test, feature = "pyo3"))]
@@ -77,6 +77,10 @@ impl Operation for RoPE {
         rvec![&self.input]
     }
 
+    fn srcs_mut(&mut self) -> RVec<&mut Tensor> {
+        rvec![&mut self.input]
+    }
+
     fn supports_inplace(&self) -> bool {
         true
     }
@@ -62,6 +62,10 @@ impl Operation for IndexSelect {
     fn srcs(&self) -> RVec<&Tensor> {
         rvec![&self.src, &self.indices]
     }
+
+    fn srcs_mut(&mut self) -> RVec<&mut Tensor> {
+        rvec![&mut self.src, &mut self.indices]
+    }
 }
 
 impl OpGuards for IndexSelect {
@@ -112,6 +116,12 @@ impl KernelRenderable for IndexSelectKernels {
                 builder.register_storage("I", BindingMode::ReadOnly, index_arr);
                 builder.register_storage("Y", BindingMode::ReadWrite, Array::<P>::default());
             }
+            dt if dt.is_ggml_block() => {
+                let packed_arr = Array::<Scalar<u32>>::default();
+                builder.register_storage("E", BindingMode::ReadOnly, packed_arr);
+                builder.register_storage("I", BindingMode::ReadOnly, index_arr);
+                builder.register_storage("Y", BindingMode::ReadWrite, Array::<P>::default());
+            }
             _ => unimplemented!(),
         }
 
@@ -162,6 +172,24 @@ impl KernelRenderable for IndexSelectKernels {
                     Y[tid] = unpack(E[src_i]) * S[src_i / 8u];
                 });
             }
+            dt if dt.is_ggml_block() => {
+                kernel_builder.write_dequantize(dt, "E");
+
+                kernel_builder.write_main(wgsl! {
+                    let tid = workgroup_id.x * 64u + local_invocation_index;
+                    if (tid >= metadata.dst_numel) {
+                        return;
+                    }
+                    let id_i = (tid / metadata.right_numel) % metadata.ids_numel;
+                    let input_i = min(u32(I[id_i]), metadata.src_dim_numel - 1u);
+                    let right_rank_index = tid % metadata.right_numel;
+                    let left_rank_index = tid / (metadata.right_numel * metadata.ids_numel);
+
+                    let left_offset = left_rank_index * metadata.src_dim_numel * metadata.right_numel;
+                    let right_offset = input_i * metadata.right_numel + right_rank_index;
+                    Y[tid] = dequantize(left_offset + right_offset);
+                });
+            }
             _ => {
                 kernel_builder.write_main(wgsl! {
                     let tid = workgroup_id.x * 64u + local_invocation_index;
@@ -201,6 +229,7 @@ impl Kernel for IndexSelectKernels {
         match inner.src.dt() {
             DType::F32 | DType::F16 => Ok(BindGroupLayoutDescriptor::binary()),
             DType::Q8_0H(_) | DType::Q8_0F(_) => Ok(BindGroupLayoutDescriptor::ternary()),
+            dt if dt.is_ggml_block() => Ok(BindGroupLayoutDescriptor::binary()),
             _ => unimplemented!(),
         }
     }
@@ -228,6 +257,7 @@ impl Kernel for IndexSelectKernels {
         let IndexSelectKernels::Standard(inner) = self;
         let numel = match inner.src.dt() {
             DType::F32 | DType::F16 => dst.shape().numel(),
+            dt if dt.is_ggml_block() => dst.shape().numel(),
             DType::Q8_0H(_) | DType::Q8_0F(_) => dst.shape().numel() / 4,
             _ => unimplemented!(),
         };
@@ -275,6 +305,9 @@ impl Kernel for IndexSelectKernels {
             (DType::Q8_0F(_), KernelElement::Scalar) => {
                 self.render::<Vec4<f32>>(inplace, dst, workgroup_size)
             }
+            (dt, KernelElement::Scalar) if dt.is_ggml_block() => {
+                self.render::<Scalar<f32>>(inplace, dst, workgroup_size)
+            }
             _ => Err(OperationError::CompileError(format!(
                 "Unsupported dtype {:?} or kernel element {:?}",
                 inner.src.dt(),
@@ -193,6 +193,10 @@ impl Operation for Softmax {
         rvec![&self.input]
     }
 
+    fn srcs_mut(&mut self) -> RVec<&mut Tensor> {
+        rvec![&mut self.input]
+    }
+
     fn supports_inplace(&self) -> bool {
         true
     }
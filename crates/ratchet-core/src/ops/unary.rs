@@ -110,27 +110,7 @@ impl KernelRenderable for UnaryKernels {
 
         let UnaryKernels::Standard(inner) = self;
 
-        //Write global functions
-        match inner.op {
-            UnaryOp::Gelu => {
-                kernel_builder.write_global(Unary::render_tanh::<P>());
-                kernel_builder.write_global(Unary::render_gelu::<P>());
-            }
-            UnaryOp::Tanh => {
-                kernel_builder.write_global(Unary::render_tanh::<P>());
-            }
-            UnaryOp::Sigmoid => {
-                kernel_builder.write_global(Unary::render_sigmoid::<P>());
-            }
-            UnaryOp::Silu => {
-                kernel_builder.write_global(Unary::render_sigmoid::<P>());
-                kernel_builder.write_global(Unary::render_silu::<P>());
-            }
-            UnaryOp::Relu => {
-                kernel_builder.write_global(Unary::render_relu::<P>());
-            }
-            _ => {}
-        };
+        kernel_builder.write_global(Unary::render_helpers::<P>([&inner.op]));
 
         let n = P::W;
 
@@ -170,6 +150,42 @@ impl Unary {
         &self.input
     }
 
+    /// The global functions the given operations call, each rendered once.
+    pub(crate) fn render_helpers<'a, P: WgslPrimitive>(
+        ops: impl IntoIterator<Item = &'a UnaryOp>,
+    ) -> String {
+        let (mut tanh, mut gelu, mut sigmoid, mut silu, mut relu) =
+            (false, false, false, false, false);
+        for op in ops {
+            match op {
+                UnaryOp::Gelu => (tanh, gelu) = (true, true),
+                UnaryOp::Tanh => tanh = true,
+                UnaryOp::Sigmoid => sigmoid = true,
+                UnaryOp::Silu => (sigmoid, silu) = (true, true),
+                UnaryOp::Relu => relu = true,
+                _ => {}
+            }
+        }
+
+        let mut helpers = String::new();
+        if tanh {
+            helpers.push_str(&Self::render_tanh::<P>());
+        }
+        if gelu {
+            helpers.push_str(&Self::render_gelu::<P>());
+        }
+        if sigmoid {
+            helpers.push_str(&Self::render_sigmoid::<P>());
+        }
+        if silu {
+            helpers.push_str(&Self::render_silu::<P>());
+        }
+        if relu {
+            helpers.push_str(&Self::render_relu::<P>());
+        }
+        helpers
+    }
+
     fn render_gelu<P: WgslPrimitive>() -> String {
         let accessor = P::render_type();
         let SQRT_2_OVER_PI = Self::SQRT_2_OVER_PI;
@@ -265,6 +281,10 @@ impl Operation for Unary {
         rvec![&self.input]
     }
 
+    fn srcs_mut(&mut self) -> RVec<&mut Tensor> {
+        rvec![&mut self.input]
+    }
+
     fn supports_inplace(&self) -> bool {
         true
     }
@@ -10,6 +10,10 @@ impl View {
     pub fn input(&self) -> &Tensor {
         &self.src
     }
+
+    pub fn shape(&self) -> &Shape {
+        &self.shape
+    }
 }
 
 impl OpGuards for View {
@@ -35,4 +39,8 @@ impl Operation for View {
     fn srcs(&self) -> crate::RVec<&Tensor> {
         rvec![&self.src]
     }
+
+    fn srcs_mut(&mut self) -> crate::RVec<&mut Tensor> {
+        rvec![&mut self.src]
+    }
 }
@@ -0,0 +1,67 @@
+use super::{rewrite, Pass};
+use crate::{DType, LazyOp, Tensor, TensorError};
+
+/// Removes casts which don't change their input: casts to the same type, and round trips
+/// through a wider type, e.g `f16 -> f32 -> f16`.
+///
+/// Round trips through a narrower type are kept, they round. The intermediate cast is only
+/// bypassed if nothing outside of the graph holds it.
+pub struct CastElision;
+
+impl Pass for CastElision {
+    fn name(&self) -> &'static str {
+        "cast-elision"
+    }
+
+    fn run(&self, root: Tensor) -> Result<Tensor, TensorError> {
+        rewrite(root, |node| {
+            let LazyOp::Cast(outer) = node.tensor.op() else {
+                return Ok(None);
+            };
+            let input = outer.input();
+            if input.dt() == outer.dst_dt() {
+                return Ok(Some(input.clone()));
+            }
+            let LazyOp::Cast(inner) = input.op() else {
+                return Ok(None);
+            };
+            let src = inner.input();
+            let round_trip = src.dt() == outer.dst_dt() && is_lossless(src.dt(), inner.dst_dt());
+            Ok((round_trip && !node.is_pinned(input)).then(|| src.clone()))
+        })
+    }
+}
+
+/// Whether every value of `from` is exactly representable in `to`.
+fn is_lossless(from: DType, to: DType) -> bool {
+    from == to || matches!((from, to), (DType::F16 | DType::BF16, DType::F32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CastElision;
+    use crate::passes::tests::{input, num_nodes};
+    use crate::{shape, Device, Pass, Tensor};
+    use half::f16;
+
+    #[test]
+    fn elides_widening_round_trip() -> anyhow::Result<()> {
+        let data = (0..6).map(|i| f16::from_f32(i as f32)).collect::<Vec<_>>();
+        let a = Tensor::from_data(data, shape![2, 3], Device::CPU);
+        let root = a.clone().full()?.half()?.add(a.clone())?;
+        assert_eq!(num_nodes(&root), 4);
+
+        let root = CastElision.run(root)?;
+        assert_eq!(num_nodes(&root), 2);
+        assert!(root.op().srcs().iter().all(|s| s.id() == a.id()));
+        Ok(())
+    }
+
+    #[test]
+    fn keeps_narrowing_round_trip() -> anyhow::Result<()> {
+        let a = input(shape![2, 3]);
+        let root = a.half()?.full()?;
+        assert_eq!(num_nodes(&CastElision.run(root)?), 3);
+        Ok(())
+    }
+}
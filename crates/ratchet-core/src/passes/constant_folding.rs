@@ -0,0 +1,69 @@
+use super::{is_pure, rewrite, Pass};
+use crate::{LazyOp, Tensor, TensorError};
+
+/// Evaluates operations whose sources are all resolved `Const`s, replacing them with a `Const`
+/// holding the result. Applied bottom-up, this folds whole `Const`-only subgraphs, leaving only
+/// the root to run with the rest of the graph.
+///
+/// Not part of the default pipeline: model inputs are `Const`s too, so on a forward pass this
+/// would evaluate every node but the root one dispatch at a time.
+pub struct ConstantFolding;
+
+impl Pass for ConstantFolding {
+    fn name(&self) -> &'static str {
+        "constant-folding"
+    }
+
+    fn run(&self, root: Tensor) -> Result<Tensor, TensorError> {
+        rewrite(root, |node| {
+            let t = node.tensor;
+            let foldable = !node.is_root
+                && is_pure(t.op())
+                && !matches!(t.op(), LazyOp::Const | LazyOp::View(_))
+                && t.op()
+                    .srcs()
+                    .iter()
+                    .all(|s| matches!(s.op(), LazyOp::Const) && s.has_storage());
+            if !foldable {
+                return Ok(None);
+            }
+            t.clone().execute(false)?;
+            Ok(Some(t.as_const()))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ConstantFolding;
+    use crate::passes::tests::{input, num_nodes};
+    use crate::{shape, LazyOp, Pass};
+
+    #[test]
+    fn folds_const_subgraph() -> anyhow::Result<()> {
+        let (a, b, c) = (
+            input(shape![2, 3]),
+            input(shape![2, 3]),
+            input(shape![3, 2]),
+        );
+        let x = a.add(b)?.permute(&[1, 0])?;
+        let root = x.mul(c)?;
+        assert_eq!(num_nodes(&root), 6);
+
+        let root = ConstantFolding.run(root)?;
+        assert_eq!(num_nodes(&root), 3);
+        let srcs = root.op().srcs();
+        assert!(matches!(srcs[0].op(), LazyOp::Const) && srcs[0].resolved());
+        assert!(!root.resolved());
+
+        let expected = [0, 3, 1, 4, 2, 5]
+            .iter()
+            .zip(0..6)
+            .map(|(&i, j)| 2. * (i as f32 - 3.) * (j as f32 - 3.));
+        assert_eq!(
+            root.resolve()?.to_vec::<f32>()?,
+            expected.collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+}
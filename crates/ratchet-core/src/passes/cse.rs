@@ -0,0 +1,85 @@
+use super::{is_pure, rewrite, Pass};
+use crate::{LazyOp, RVec, Tensor, TensorError};
+use rustc_hash::FxHashMap;
+use std::collections::hash_map::Entry;
+
+/// Replaces operations with an identical operation on the same sources.
+///
+/// Operations are compared structurally, by their parameters with the sources left out, the
+/// ids of their sources and their output view.
+pub struct CommonSubexpressionElimination;
+
+impl Pass for CommonSubexpressionElimination {
+    fn name(&self) -> &'static str {
+        "cse"
+    }
+
+    fn run(&self, root: Tensor) -> Result<Tensor, TensorError> {
+        let placeholder = Tensor::placeholder();
+        let mut seen = FxHashMap::<String, Tensor>::default();
+        rewrite(root, |node| {
+            let t = node.tensor;
+            if matches!(t.op(), LazyOp::Const) || !is_pure(t.op()) {
+                return Ok(None);
+            }
+            match seen.entry(structural_key(t, &placeholder)) {
+                Entry::Occupied(e) => Ok(Some(e.get().clone())),
+                Entry::Vacant(e) => {
+                    e.insert(t.clone());
+                    Ok(None)
+                }
+            }
+        })
+    }
+}
+
+fn structural_key(t: &Tensor, placeholder: &Tensor) -> String {
+    let mut op = t.op().clone();
+    for src in op.srcs_mut() {
+        *src = placeholder.clone();
+    }
+    let srcs = t.op().srcs().iter().map(|s| s.id()).collect::<RVec<_>>();
+    format!("{:?}|{:?}|{:?}", op, srcs, t.storage_view())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CommonSubexpressionElimination;
+    use crate::passes::tests::{input, num_nodes};
+    use crate::{shape, Pass};
+
+    #[test]
+    fn merges_identical_operations() -> anyhow::Result<()> {
+        let (a, b) = (input(shape![2, 3]), input(shape![2, 3]));
+        let x = a.clone().add(b.clone())?;
+        let y = a.clone().add(b.clone())?;
+        let root = x.mul(y)?;
+        assert_eq!(num_nodes(&root), 5);
+
+        let root = CommonSubexpressionElimination.run(root)?;
+        assert_eq!(num_nodes(&root), 4);
+        let srcs = root.op().srcs();
+        assert_eq!(srcs[0].id(), srcs[1].id());
+
+        let expected = (0..6).map(|i| (2. * (i as f32 - 3.)).powi(2));
+        assert_eq!(
+            root.resolve()?.to_vec::<f32>()?,
+            expected.collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn keeps_different_parameters() -> anyhow::Result<()> {
+        let a = input(shape![2, 3, 4]);
+        let x = a.clone().permute(&[1, 0, 2])?;
+        let y = a.permute(&[1, 2, 0])?.permute(&[0, 2, 1])?;
+        let root = x.add(y)?;
+        let before = num_nodes(&root);
+        assert_eq!(
+            num_nodes(&CommonSubexpressionElimination.run(root)?),
+            before
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,126 @@
+use super::{rewrite, Pass};
+use crate::{LazyOp, Reindex, Tensor, TensorError};
+
+/// Removes nodes which don't need to run.
+///
+/// Operations which leave their input untouched, e.g a permute to the same order, are replaced by
+/// their input. Operations resolved by an earlier call to `resolve` are replaced by a `Const`,
+/// dropping the subgraph that produced them. Nodes no longer reachable from the root after any
+/// pass are dropped along with it.
+#[derive(Debug, Clone, Copy)]
+pub struct DeadNodeElimination {
+    eliminate_identities: bool,
+}
+
+impl Default for DeadNodeElimination {
+    fn default() -> Self {
+        Self {
+            eliminate_identities: true,
+        }
+    }
+}
+
+impl DeadNodeElimination {
+    /// Only prune resolved and unreachable nodes, for [PassManager::symbolic].
+    ///
+    /// [PassManager::symbolic]: super::PassManager::symbolic
+    pub fn keep_identities() -> Self {
+        Self {
+            eliminate_identities: false,
+        }
+    }
+}
+
+impl Pass for DeadNodeElimination {
+    fn name(&self) -> &'static str {
+        "dead-node-elimination"
+    }
+
+    fn run(&self, root: Tensor) -> Result<Tensor, TensorError> {
+        rewrite(root, |node| {
+            let t = node.tensor;
+            if matches!(t.op(), LazyOp::Const) {
+                return Ok(None);
+            }
+            if t.has_storage() {
+                return Ok(Some(t.as_const()));
+            }
+            if !self.eliminate_identities {
+                return Ok(None);
+            }
+            Ok(identity_src(t).cloned())
+        })
+    }
+}
+
+fn identity_src(t: &Tensor) -> Option<&Tensor> {
+    let src = match t.op() {
+        LazyOp::View(v) => v.input(),
+        LazyOp::Reindex(Reindex::Permute(p)) => {
+            let in_order = p.dims.iter().enumerate().all(|(i, &d)| i == d);
+            return in_order.then_some(&p.src);
+        }
+        LazyOp::Reindex(Reindex::Broadcast(b)) => &b.src,
+        LazyOp::Reindex(Reindex::Slice(s)) => &s.src,
+        _ => return None,
+    };
+    (src.shape() == t.shape()).then_some(src)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DeadNodeElimination;
+    use crate::passes::tests::{input, num_nodes};
+    use crate::{shape, LazyOp, Pass};
+
+    #[test]
+    fn removes_identity_reindexing() -> anyhow::Result<()> {
+        let (a, b) = (input(shape![2, 3]), input(shape![2, 3]));
+        let x = a.clone().permute(&[0, 1])?.slice(&[0..2, 0..3])?;
+        let y = b.clone().broadcast_to(shape![2, 3])?.view(shape![2, 3])?;
+        let root = x.add(y)?;
+        assert_eq!(num_nodes(&root), 7);
+
+        let root = DeadNodeElimination::default().run(root)?;
+        assert_eq!(num_nodes(&root), 3);
+        let srcs = root.op().srcs();
+        assert_eq!((srcs[0].id(), srcs[1].id()), (a.id(), b.id()));
+        Ok(())
+    }
+
+    #[test]
+    fn keeps_identities_if_asked() -> anyhow::Result<()> {
+        let (a, b) = (input(shape![2, 3]), input(shape![2, 3]));
+        let root = a.slice(&[0..2, 0..3])?.add(b)?;
+        let root = DeadNodeElimination::keep_identities().run(root)?;
+        assert_eq!(num_nodes(&root), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn keeps_reordering_permute() -> anyhow::Result<()> {
+        let a = input(shape![3, 3]);
+        let root = a.permute(&[1, 0])?.add(input(shape![3, 3]))?;
+        assert_eq!(num_nodes(&DeadNodeElimination::default().run(root)?), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn prunes_resolved_subgraphs() -> anyhow::Result<()> {
+        let (a, b) = (input(shape![2, 3]), input(shape![2, 3]));
+        let x = a.add(b)?.resolve()?;
+        let root = x.mul(input(shape![2, 3]))?;
+        assert_eq!(num_nodes(&root), 5);
+
+        let root = DeadNodeElimination::default().run(root)?;
+        assert_eq!(num_nodes(&root), 3);
+        assert!(matches!(root.op().srcs()[0].op(), LazyOp::Const));
+
+        let expected = (0..6).map(|i| 2. * (i as f32 - 3.).powi(2));
+        assert_eq!(
+            root.resolve()?.to_vec::<f32>()?,
+            expected.collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+}
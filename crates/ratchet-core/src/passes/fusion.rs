@@ -0,0 +1,307 @@
+use super::{rewrite, Node, Pass};
+use crate::{BinaryOp, FusedElementwise, LazyOp, Reindex, Tensor, TensorError};
+
+/// Merges elementwise operations into the kernel computing their input, so intermediates never
+/// round trip through global memory:
+///
+/// - A vector added to the output of a GEMM becomes its bias.
+/// - Unary operations applied to the output of a GEMM or a normalisation become its
+///   [Epilogue](crate::Epilogue).
+/// - Chains of `Unary`, `Binary` and `Cast` become a single [FusedElementwise].
+///
+/// An operation is only merged into its consumer if nothing else reads it. Fused kernels only
+/// exist on the GPU, graphs on other devices are left as they are.
+pub struct ElementwiseFusion;
+
+impl Pass for ElementwiseFusion {
+    fn name(&self) -> &'static str {
+        "elementwise-fusion"
+    }
+
+    fn run(&self, root: Tensor) -> Result<Tensor, TensorError> {
+        if !root.device().is_gpu() {
+            return Ok(root);
+        }
+        fuse(root)
+    }
+}
+
+/// [ElementwiseFusion] regardless of the device.
+pub(crate) fn fuse(root: Tensor) -> Result<Tensor, TensorError> {
+    rewrite(root, |node| {
+        Ok(fold_bias(&node)
+            .or_else(|| fold_epilogue(&node))
+            .or_else(|| fuse_elementwise(&node)))
+    })
+}
+
+/// Whether `src` may be merged into the node reading it.
+fn is_absorbable(node: &Node, src: &Tensor) -> bool {
+    !src.resolved() && !node.is_pinned(src) && node.num_uses(src) == 1
+}
+
+/// `matmul(x, w) + broadcast(b)` to `gemm(x, w, b)`.
+fn fold_bias(node: &Node) -> Option<Tensor> {
+    let t = node.tensor;
+    let LazyOp::Binary(add) = t.op() else {
+        return None;
+    };
+    if !matches!(add.op(), BinaryOp::Add) {
+        return None;
+    }
+
+    [(add.lhs(), add.rhs()), (add.rhs(), add.lhs())]
+        .into_iter()
+        .find_map(|(product, bias)| {
+            let LazyOp::Matmul(matmul) = product.op() else {
+                return None;
+            };
+            let LazyOp::Reindex(Reindex::Broadcast(broadcast)) = bias.op() else {
+                return None;
+            };
+            let bias = &broadcast.src;
+            let n = product.shape()[product.rank() - 1];
+            let foldable = matmul.is_gemm()
+                && matmul.bias.is_none()
+                && matmul.epilogue().is_empty()
+                && !matmul.trans_dst
+                && bias.shape().is_vector()
+                && bias.shape().numel() == n
+                && bias.dt() == matmul.rhs.dt()
+                && bias.dt() == product.dt()
+                && product.shape() == t.shape()
+                && is_absorbable(node, product);
+            if !foldable {
+                return None;
+            }
+
+            let mut matmul = matmul.clone();
+            matmul.bias = Some(bias.clone());
+            Some(t.with_op(LazyOp::Matmul(matmul)))
+        })
+}
+
+/// `f(gemm(..))` and `f(norm(..))` to an epilogue of the producer.
+fn fold_epilogue(node: &Node) -> Option<Tensor> {
+    let t = node.tensor;
+    let LazyOp::Unary(unary) = t.op() else {
+        return None;
+    };
+    let producer = unary.input();
+    if !is_absorbable(node, producer) {
+        return None;
+    }
+
+    let op = match producer.op() {
+        LazyOp::Matmul(matmul) if matmul.is_gemm() => {
+            let mut matmul = matmul.clone();
+            matmul.epilogue.push(unary.op().clone());
+            LazyOp::Matmul(matmul)
+        }
+        LazyOp::Norm(norm) => {
+            let mut norm = norm.clone();
+            norm.epilogue_mut().push(unary.op().clone());
+            LazyOp::Norm(norm)
+        }
+        _ => return None,
+    };
+    Some(t.with_op(op))
+}
+
+/// Merges the elementwise producers of an elementwise node into it.
+fn fuse_elementwise(node: &Node) -> Option<Tensor> {
+    let t = node.tensor;
+    let mut fused = FusedElementwise::from_op(t.op())?;
+    if !fused.is_supported() {
+        return None;
+    }
+
+    let mut absorbed = false;
+    for input in fused.inputs().to_vec() {
+        if !is_absorbable(node, &input) {
+            continue;
+        }
+        let Some(producer) = FusedElementwise::from_op(input.op()) else {
+            continue;
+        };
+        let candidate = fused.inline(input.id(), &producer);
+        if candidate.is_supported() {
+            fused = candidate;
+            absorbed = true;
+        }
+    }
+    absorbed.then(|| t.with_op(LazyOp::Fused(fused)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fuse;
+    use crate::passes::tests::{input, num_nodes};
+    use crate::{shape, Device, DeviceRequest, FusedStep, LazyOp, Tensor, UnaryOp};
+
+    #[test]
+    fn fuses_elementwise_chain() -> anyhow::Result<()> {
+        let (a, b, c) = (
+            input(shape![2, 8]),
+            input(shape![2, 8]),
+            input(shape![2, 8]),
+        );
+        let root = a.mul(b)?.add(c)?.silu()?;
+        assert_eq!(num_nodes(&root), 6);
+
+        let root = fuse(root)?;
+        assert_eq!(num_nodes(&root), 4);
+        let LazyOp::Fused(fused) = root.op() else {
+            panic!("Expected a fused root, got {:?}", root.op());
+        };
+        assert_eq!(fused.inputs().len(), 3);
+        assert!(matches!(
+            fused.steps().last(),
+            Some(FusedStep::Unary(UnaryOp::Silu, _))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn folds_bias_and_epilogue() -> anyhow::Result<()> {
+        let (x, w, bias) = (
+            input(shape![2, 3, 4]),
+            input(shape![4, 5]),
+            input(shape![5]),
+        );
+        let root = x
+            .matmul(w, false, false)?
+            .add(bias.clone())?
+            .gelu()?
+            .neg()?;
+
+        let root = fuse(root)?;
+        assert_eq!(num_nodes(&root), 4);
+        let LazyOp::Matmul(matmul) = root.op() else {
+            panic!("Expected a matmul root, got {:?}", root.op());
+        };
+        assert_eq!(matmul.bias.as_ref().map(|b| b.id()), Some(bias.id()));
+        assert!(matches!(
+            matmul.epilogue().ops(),
+            [UnaryOp::Gelu, UnaryOp::Neg]
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn folds_norm_epilogue() -> anyhow::Result<()> {
+        let (x, scale) = (input(shape![2, 4]), input(shape![4]));
+        let root = fuse(x.rms_norm(scale, 1e-5)?.silu()?)?;
+        let LazyOp::Norm(norm) = root.op() else {
+            panic!("Expected a norm root, got {:?}", root.op());
+        };
+        assert!(matches!(norm.epilogue().ops(), [UnaryOp::Silu]));
+        Ok(())
+    }
+
+    #[test]
+    fn keeps_shared_intermediates() -> anyhow::Result<()> {
+        let (a, b) = (input(shape![2, 8]), input(shape![2, 8]));
+        let shared = a.mul(b)?;
+        let root = shared.clone().gelu()?.add(shared.clone())?;
+        let shared_id = shared.id();
+        drop(shared);
+
+        let root = fuse(root)?;
+        let LazyOp::Fused(fused) = root.op() else {
+            panic!("Expected a fused root, got {:?}", root.op());
+        };
+        assert_eq!(fused.inputs().len(), 1);
+        assert_eq!(fused.inputs()[0].id(), shared_id);
+        Ok(())
+    }
+
+    #[test]
+    fn keeps_pinned_intermediates() -> anyhow::Result<()> {
+        let (a, b) = (input(shape![2, 8]), input(shape![2, 8]));
+        let held = a.mul(b)?;
+        let root = held.clone().exp()?;
+        assert_eq!(fuse(root)?.op().srcs()[0].id(), held.id());
+        Ok(())
+    }
+
+    /// Runs `graph` fused on the GPU, and unfused on the CPU, where nothing is fused.
+    fn fused_matches_unfused(
+        inputs: &[Tensor],
+        graph: impl Fn(&[Tensor]) -> anyhow::Result<Tensor>,
+        is_fused: impl Fn(&LazyOp) -> bool,
+    ) -> anyhow::Result<()> {
+        let device = Device::request_device(DeviceRequest::GPU)?;
+        let expected = graph(inputs)?.resolve()?;
+
+        let gpu_inputs = inputs
+            .iter()
+            .map(|t| t.to(&device))
+            .collect::<Result<Vec<_>, _>>()?;
+        let fused = fuse(graph(&gpu_inputs)?)?;
+        assert!(is_fused(fused.op()), "Not fused: {:?}", fused.op());
+        let result = fused.resolve()?.to(&Device::CPU)?;
+        result.all_close(&expected, 1e-3f32, 1e-3f32)
+    }
+
+    #[test]
+    fn fused_chain_matches_unfused() -> anyhow::Result<()> {
+        let inputs = [
+            Tensor::randn::<f32>(shape![4, 64], Device::CPU),
+            Tensor::randn::<f32>(shape![4, 64], Device::CPU),
+            Tensor::randn::<f32>(shape![4, 64], Device::CPU),
+        ];
+        fused_matches_unfused(
+            &inputs,
+            |x| Ok(x[0].clone().mul(x[1].clone())?.add(x[2].clone())?.silu()?),
+            |op| matches!(op, LazyOp::Fused(_)),
+        )
+    }
+
+    #[test]
+    fn folded_bias_matches_unfused() -> anyhow::Result<()> {
+        let inputs = [
+            Tensor::randn::<f32>(shape![2, 16, 32], Device::CPU),
+            Tensor::randn::<f32>(shape![32, 48], Device::CPU),
+            Tensor::randn::<f32>(shape![48], Device::CPU),
+        ];
+        fused_matches_unfused(
+            &inputs,
+            |x| {
+                let y = x[0].clone().matmul(x[1].clone(), false, false)?;
+                Ok(y.add(x[2].clone())?)
+            },
+            |op| matches!(op, LazyOp::Matmul(m) if m.bias.is_some()),
+        )
+    }
+
+    #[test]
+    fn matmul_epilogue_matches_unfused() -> anyhow::Result<()> {
+        let inputs = [
+            Tensor::randn::<f32>(shape![2, 16, 32], Device::CPU),
+            Tensor::randn::<f32>(shape![32, 48], Device::CPU),
+            Tensor::randn::<f32>(shape![48], Device::CPU),
+        ];
+        fused_matches_unfused(
+            &inputs,
+            |x| {
+                let y = x[0].clone().matmul(x[1].clone(), false, false)?;
+                Ok(y.add(x[2].clone())?.gelu()?.neg()?)
+            },
+            |op| matches!(op, LazyOp::Matmul(m) if m.epilogue().ops().len() == 2),
+        )
+    }
+
+    #[test]
+    fn norm_epilogue_matches_unfused() -> anyhow::Result<()> {
+        let inputs = [
+            Tensor::randn::<f32>(shape![8, 128], Device::CPU),
+            Tensor::randn::<f32>(shape![128], Device::CPU),
+        ];
+        fused_matches_unfused(
+            &inputs,
+            |x| Ok(x[0].clone().rms_norm(x[1].clone(), 1e-5)?.silu()?),
+            |op| matches!(op, LazyOp::Norm(n) if !n.epilogue().is_empty()),
+        )
+    }
+}
@@ -0,0 +1,262 @@
+//! Rewrites of the lazy graph, run by [Tensor::resolve] before allocation.
+mod cast_elision;
+mod constant_folding;
+mod cse;
+mod dead_node;
+mod fusion;
+mod permute_view;
+
+pub use cast_elision::CastElision;
+pub use constant_folding::ConstantFolding;
+pub use cse::CommonSubexpressionElimination;
+pub use dead_node::DeadNodeElimination;
+pub use fusion::ElementwiseFusion;
+pub use permute_view::PermuteViewCancellation;
+
+use crate::{LazyOp, RVec, Tensor, TensorError, TensorId};
+use rustc_hash::{FxHashMap, FxHashSet};
+
+/// A rewrite of the graph rooted at a tensor, which must preserve the value of the root.
+pub trait Pass {
+    fn name(&self) -> &'static str;
+
+    fn run(&self, root: Tensor) -> Result<Tensor, TensorError>;
+}
+
+/// Runs a pipeline of passes over the graph.
+///
+/// With the `plotting` feature, the graph is rendered before the first pass and after every
+/// pass, to `pass-{index}-{name}.svg`.
+pub struct PassManager {
+    passes: Vec<Box<dyn Pass>>,
+}
+
+impl PassManager {
+    pub fn new(passes: Vec<Box<dyn Pass>>) -> Self {
+        Self { passes }
+    }
+
+    pub fn with_pass(mut self, pass: impl Pass + 'static) -> Self {
+        self.passes.push(Box::new(pass));
+        self
+    }
+
+    pub fn run(&self, root: Tensor) -> Result<Tensor, TensorError> {
+        let mut graph = root;
+        #[cfg(feature = "plotting")]
+        dump(&graph, 0, "input");
+
+        let num_nodes =
+            |t: &Tensor| log::log_enabled!(log::Level::Debug).then(|| t.execution_order().len());
+        for (_idx, pass) in self.passes.iter().enumerate() {
+            let before = num_nodes(&graph);
+            graph = pass.run(graph)?;
+            if let (Some(before), Some(after)) = (before, num_nodes(&graph)) {
+                log::debug!("{}: {} -> {} nodes", pass.name(), before, after);
+            }
+            #[cfg(feature = "plotting")]
+            dump(&graph, _idx + 1, pass.name());
+        }
+        Ok(graph)
+    }
+}
+
+impl PassManager {
+    /// The passes of [PassManager::default] whose rewrites don't depend on the shapes of the
+    /// graph, for graphs built for every binding of a [Symbol](crate::Symbol).
+    ///
+    /// Identity reindexing is kept, and views aren't collapsed: whether they cancel out depends
+    /// on the bound shapes, so the optimized graphs of two bindings would differ in structure.
+    pub fn symbolic() -> Self {
+        Self::new(vec![
+            Box::new(DeadNodeElimination::keep_identities()),
+            Box::new(CastElision),
+            Box::new(CommonSubexpressionElimination),
+            Box::new(ElementwiseFusion),
+        ])
+    }
+}
+
+impl Default for PassManager {
+    /// Every pass but [ConstantFolding], see its documentation.
+    fn default() -> Self {
+        Self::new(vec![
+            Box::new(DeadNodeElimination::default()),
+            Box::new(CastElision),
+            Box::new(PermuteViewCancellation),
+            Box::new(CommonSubexpressionElimination),
+            Box::new(ElementwiseFusion),
+        ])
+    }
+}
+
+#[cfg(feature = "plotting")]
+fn dump(graph: &Tensor, idx: usize, name: &str) {
+    let fname = format!("pass-{idx:02}-{name}.svg");
+    if let Err(e) = crate::plot::render_to_file(graph, &fname) {
+        log::warn!("Failed to render {fname}: {e}");
+    }
+}
+
+/// Operations which write into one of their sources, they must run exactly where they are.
+pub(crate) fn is_pure(op: &LazyOp) -> bool {
+    !matches!(op, LazyOp::IndexWrite(_) | LazyOp::Cache(_))
+}
+
+/// A node visited by [rewrite], already rebuilt on top of its rewritten sources.
+pub(crate) struct Node<'a> {
+    pub tensor: &'a Tensor,
+    pub is_root: bool,
+    pinned: &'a FxHashSet<TensorId>,
+    uses: &'a FxHashMap<TensorId, usize>,
+    origin: &'a FxHashMap<TensorId, TensorId>,
+}
+
+impl Node<'_> {
+    /// Whether `t` is referenced from outside the graph, e.g a KV cache held by a model.
+    ///
+    /// The holder expects it to be resolved alongside the graph, so a pinned tensor must not be
+    /// replaced or bypassed.
+    pub fn is_pinned(&self, t: &Tensor) -> bool {
+        self.pinned.contains(&t.id())
+    }
+
+    /// Number of operations consuming `t` in the graph the pass started from.
+    ///
+    /// Rewrites never add consumers, so a tensor used once is only read by the current node.
+    pub fn num_uses(&self, t: &Tensor) -> usize {
+        let id = self.origin.get(&t.id()).copied().unwrap_or(t.id());
+        self.uses.get(&id).copied().unwrap_or_default()
+    }
+}
+
+/// Rewrite the graph bottom-up.
+///
+/// `f` is called once per node in execution order and may return a replacement of equal value.
+/// Replacements of pinned nodes are ignored, pinned nodes are kept verbatim, except for the
+/// root which writes into the storage the caller holds. A node is only rebuilt if one of its
+/// sources changed, so a pass which changes nothing returns the original graph.
+pub(crate) fn rewrite<F>(root: Tensor, mut f: F) -> Result<Tensor, TensorError>
+where
+    F: FnMut(Node) -> Result<Option<Tensor>, TensorError>,
+{
+    let root_id = root.id();
+    let uses = uses(&root);
+    let pinned = pinned(&root, &uses);
+    let mut rewritten: FxHashMap<TensorId, Tensor> = FxHashMap::default();
+    let mut origin: FxHashMap<TensorId, TensorId> = FxHashMap::default();
+
+    for t in root.execution_order() {
+        let srcs = t
+            .op()
+            .srcs()
+            .iter()
+            .map(|s| rewritten[&s.id()].clone())
+            .collect::<RVec<_>>();
+        let changed = srcs
+            .iter()
+            .zip(t.op().srcs())
+            .any(|(new, old)| new.id() != old.id());
+        let (is_root, is_pinned) = (t.id() == root_id, pinned.contains(&t.id()));
+
+        let node = match (changed, is_pinned) {
+            (false, _) => t.clone(),
+            (true, false) => t.with_srcs(srcs),
+            (true, true) if is_root && !matches!(t.op(), LazyOp::View(_)) => {
+                t.alias_with_srcs(srcs)
+            }
+            (true, true) => t.clone(),
+        };
+        let replacement = f(Node {
+            tensor: &node,
+            is_root,
+            pinned: &pinned,
+            uses: &uses,
+            origin: &origin,
+        })?;
+        let node = match replacement {
+            Some(replacement) if !is_pinned => replacement,
+            _ => node,
+        };
+        if node.id() != t.id() {
+            origin.insert(node.id(), t.id());
+        }
+        rewritten.insert(t.id(), node);
+    }
+
+    Ok(rewritten
+        .remove(&root_id)
+        .expect("Root is last in execution order"))
+}
+
+/// Number of edges into each tensor within the graph.
+fn uses(root: &Tensor) -> FxHashMap<TensorId, usize> {
+    let mut uses = FxHashMap::<TensorId, usize>::default();
+    for src in root.execution_order().iter().flat_map(|t| t.op().srcs()) {
+        *uses.entry(src.id()).or_default() += 1;
+    }
+    uses
+}
+
+/// Tensors with more handles than edges within the graph, plus the one passed for the root.
+fn pinned(root: &Tensor, uses: &FxHashMap<TensorId, usize>) -> FxHashSet<TensorId> {
+    root.execution_order()
+        .iter()
+        .filter(|t| {
+            let owned = uses.get(&t.id()).copied().unwrap_or_default();
+            t.strong_count() > owned + usize::from(t.id() == root.id())
+        })
+        .map(|t| t.id())
+        .collect()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use crate::{shape, Device, Tensor};
+
+    pub(crate) fn input(shape: crate::Shape) -> Tensor {
+        let data = (0..shape.numel())
+            .map(|i| i as f32 - 3.)
+            .collect::<Vec<_>>();
+        Tensor::from_data(data, shape, Device::CPU)
+    }
+
+    pub(crate) fn num_nodes(t: &Tensor) -> usize {
+        t.execution_order().len()
+    }
+
+    fn graph(a: &Tensor, b: &Tensor) -> anyhow::Result<Tensor> {
+        let x = a
+            .clone()
+            .add(b.clone())?
+            .permute(&[1, 0])?
+            .permute(&[1, 0])?;
+        let y = a.clone().add(b.clone())?.half()?.full()?;
+        let z = x.mul(y)?.view(shape![3, 2])?.permute(&[0, 1])?;
+        Ok(z)
+    }
+
+    #[test]
+    fn default_pipeline_preserves_values() -> anyhow::Result<()> {
+        let (a, b) = (input(shape![2, 3]), input(shape![2, 3]));
+        let expected = graph(&a, &b)?.execute(false)?.to_vec::<f32>()?;
+
+        let optimized = super::PassManager::default().run(graph(&a, &b)?)?;
+        assert!(num_nodes(&optimized) < num_nodes(&graph(&a, &b)?));
+        assert_eq!(optimized.resolve()?.to_vec::<f32>()?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn pinned_nodes_are_resolved() -> anyhow::Result<()> {
+        let (a, b) = (input(shape![2, 3]), input(shape![2, 3]));
+        let held = a.clone().add(b.clone())?.permute(&[0, 1])?;
+        let root = held.clone().mul(a.add(b)?)?;
+
+        let root = root.resolve()?;
+        assert!(held.resolved());
+        let expected = (0..6).map(|i| (2. * (i as f32 - 3.)).powi(2));
+        assert_eq!(root.to_vec::<f32>()?, expected.collect::<Vec<_>>());
+        Ok(())
+    }
+}
@@ -0,0 +1,122 @@
+use super::{rewrite, Pass};
+use crate::{LazyOp, OperationError, Reindex, Tensor, TensorError};
+
+/// Collapses a `Permute` of a `Permute` into a single permute, and a `View` of a `View` into a
+/// single view. Either is removed entirely if the pair cancels out.
+///
+/// The inner node is only bypassed if nothing outside of the graph holds it.
+pub struct PermuteViewCancellation;
+
+impl Pass for PermuteViewCancellation {
+    fn name(&self) -> &'static str {
+        "permute-view-cancellation"
+    }
+
+    fn run(&self, root: Tensor) -> Result<Tensor, TensorError> {
+        rewrite(root, |node| {
+            let t = node.tensor;
+            let collapsed = match t.op() {
+                LazyOp::Reindex(Reindex::Permute(outer)) => {
+                    let LazyOp::Reindex(Reindex::Permute(inner)) = outer.src.op() else {
+                        return Ok(None);
+                    };
+                    if node.is_pinned(&outer.src) {
+                        return Ok(None);
+                    }
+                    let dims = outer
+                        .dims
+                        .iter()
+                        .map(|&d| inner.dims[d])
+                        .collect::<Vec<_>>();
+                    if dims.iter().enumerate().all(|(i, &d)| i == d) {
+                        inner.src.clone()
+                    } else {
+                        inner
+                            .src
+                            .clone()
+                            .permute(&dims)
+                            .map_err(OperationError::from)?
+                    }
+                }
+                LazyOp::View(outer) => {
+                    let LazyOp::View(inner) = outer.input().op() else {
+                        return Ok(None);
+                    };
+                    if node.is_pinned(outer.input()) {
+                        return Ok(None);
+                    }
+                    let src = inner.input().clone();
+                    if src.shape() == outer.shape() {
+                        src
+                    } else {
+                        src.view(outer.shape().clone())
+                            .map_err(OperationError::from)?
+                    }
+                }
+                _ => return Ok(None),
+            };
+            Ok(Some(collapsed))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PermuteViewCancellation;
+    use crate::passes::tests::{input, num_nodes};
+    use crate::{shape, LazyOp, Pass, Reindex};
+
+    #[test]
+    fn composes_permutes() -> anyhow::Result<()> {
+        let a = input(shape![2, 3, 4]);
+        let x = a.clone().permute(&[1, 2, 0])?.permute(&[0, 2, 1])?;
+        let expected = x.clone().execute(false)?.to_vec::<f32>()?;
+        let root = a.clone().permute(&[1, 2, 0])?.permute(&[0, 2, 1])?;
+
+        let root = PermuteViewCancellation.run(root)?;
+        assert_eq!(num_nodes(&root), 2);
+        let LazyOp::Reindex(Reindex::Permute(p)) = root.op() else {
+            panic!("Expected a permute, got {}", root.op().name());
+        };
+        assert_eq!(p.dims, [1, 0, 2]);
+        assert_eq!(root.resolve()?.to_vec::<f32>()?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn cancels_inverse_permutes() -> anyhow::Result<()> {
+        let a = input(shape![2, 3, 4]);
+        let x = a.clone().permute(&[2, 0, 1])?.permute(&[1, 2, 0])?;
+        let root = x.add(input(shape![2, 3, 4]))?;
+
+        let root = PermuteViewCancellation.run(root)?;
+        assert_eq!(num_nodes(&root), 3);
+        assert_eq!(root.op().srcs()[0].id(), a.id());
+        Ok(())
+    }
+
+    #[test]
+    fn collapses_views() -> anyhow::Result<()> {
+        let a = input(shape![2, 6]);
+        let x = a.clone().view(shape![3, 4])?.view(shape![4, 3])?;
+        let y = a.clone().view(shape![3, 4])?.view(shape![2, 6])?;
+        let root = x.view(shape![2, 6])?.add(y)?;
+        assert_eq!(num_nodes(&root), 7);
+
+        let root = PermuteViewCancellation.run(root)?;
+        assert_eq!(num_nodes(&root), 2);
+        assert!(root.op().srcs().iter().all(|s| s.id() == a.id()));
+        Ok(())
+    }
+
+    #[test]
+    fn keeps_pinned_inner_permute() -> anyhow::Result<()> {
+        let a = input(shape![2, 3]);
+        let held = a.permute(&[1, 0])?;
+        let root = held.clone().permute(&[1, 0])?;
+
+        let root = PermuteViewCancellation.run(root)?;
+        assert_eq!(root.op().srcs()[0].id(), held.id());
+        Ok(())
+    }
+}
@@ -1,6 +1,7 @@
 use crate::{
     dtype::Quantized, gpu::STORAGE_BUFFER_ALIGN, DType, Device, Tensor, Q4_KF, Q4_KH, Q8_0F, Q8_0H,
 };
+use half::f16;
 use num::integer::div_floor;
 use num_traits::{AsPrimitive, Float, FromPrimitive, Zero};
 
@@ -129,8 +130,193 @@ pub fn dequantize_inner<Q: Quantized>(quantized: &[u8], elements: usize) -> Vec<
     dequantized
 }
 
+#[inline]
+fn read_f16(block: &[u8], offset: usize) -> f32 {
+    f16::from_le_bytes([block[offset], block[offset + 1]]).to_f32()
+}
+
+/// 6 bit (scale, min) pairs packed into 12 bytes, shared by Q4_K & Q5_K.
+#[inline]
+fn scale_min_k4(j: usize, q: &[u8]) -> (f32, f32) {
+    let (sc, m) = if j < 4 {
+        (q[j] & 63, q[j + 4] & 63)
+    } else {
+        (
+            (q[j + 4] & 0xF) | ((q[j - 4] >> 6) << 4),
+            (q[j + 4] >> 4) | ((q[j] >> 6) << 4),
+        )
+    };
+    (sc as f32, m as f32)
+}
+
+/// 6 bit signed scales packed into 12 bytes, used by Q3_K.
+#[inline]
+fn scale_q3_k(j: usize, q: &[u8]) -> f32 {
+    let low = if j < 8 { q[j] & 0xF } else { q[j - 8] >> 4 };
+    let high = (q[8 + j % 4] >> (2 * (j / 4))) & 3;
+    ((low | (high << 4)) as i32 - 32) as f32
+}
+
+/// Dequantizes a single GGML block into `y`.
+/// Follows `dequantize_row_*` in ggml-quants.c.
+fn dequantize_block(dt: DType, x: &[u8], y: &mut [f32]) {
+    match dt {
+        DType::Q4_0(_) => {
+            let d = read_f16(x, 0);
+            let qs = &x[2..18];
+            for j in 0..16 {
+                y[j] = ((qs[j] & 0xF) as i32 - 8) as f32 * d;
+                y[j + 16] = ((qs[j] >> 4) as i32 - 8) as f32 * d;
+            }
+        }
+        DType::Q4_1(_) => {
+            let (d, m) = (read_f16(x, 0), read_f16(x, 2));
+            let qs = &x[4..20];
+            for j in 0..16 {
+                y[j] = (qs[j] & 0xF) as f32 * d + m;
+                y[j + 16] = (qs[j] >> 4) as f32 * d + m;
+            }
+        }
+        DType::Q5_0(_) | DType::Q5_1(_) => {
+            let (d, m, qh_offset) = match dt {
+                DType::Q5_0(_) => (read_f16(x, 0), None, 2),
+                _ => (read_f16(x, 0), Some(read_f16(x, 2)), 4),
+            };
+            let qh = u32::from_le_bytes(x[qh_offset..qh_offset + 4].try_into().unwrap());
+            let qs = &x[qh_offset + 4..qh_offset + 20];
+            for j in 0..16 {
+                let xh_0 = ((qh >> j) << 4) & 0x10;
+                let xh_1 = (qh >> (j + 12)) & 0x10;
+                let x0 = (qs[j] as u32 & 0xF) | xh_0;
+                let x1 = (qs[j] as u32 >> 4) | xh_1;
+                match m {
+                    Some(m) => {
+                        y[j] = x0 as f32 * d + m;
+                        y[j + 16] = x1 as f32 * d + m;
+                    }
+                    None => {
+                        y[j] = (x0 as i32 - 16) as f32 * d;
+                        y[j + 16] = (x1 as i32 - 16) as f32 * d;
+                    }
+                }
+            }
+        }
+        DType::Q2_K(_) => {
+            let scales = &x[0..16];
+            let qs = &x[16..80];
+            let (d, min) = (read_f16(x, 80), read_f16(x, 82));
+            let mut is = 0;
+            for n in 0..2 {
+                let q = &qs[32 * n..32 * (n + 1)];
+                for j in 0..4 {
+                    let shift = 2 * j;
+                    for half in 0..2 {
+                        let sc = scales[is];
+                        is += 1;
+                        let (dl, ml) = (d * (sc & 0xF) as f32, min * (sc >> 4) as f32);
+                        for l in 0..16 {
+                            let q = (q[16 * half + l] >> shift) & 3;
+                            y[128 * n + 32 * j + 16 * half + l] = dl * q as f32 - ml;
+                        }
+                    }
+                }
+            }
+        }
+        DType::Q3_K(_) => {
+            let hmask = &x[0..32];
+            let qs = &x[32..96];
+            let scales = &x[96..108];
+            let d = read_f16(x, 108);
+            let mut is = 0;
+            for n in 0..2 {
+                let q = &qs[32 * n..32 * (n + 1)];
+                for j in 0..4 {
+                    let shift = 2 * j;
+                    let m = 1u8 << (4 * n + j);
+                    for half in 0..2 {
+                        let dl = d * scale_q3_k(is, scales);
+                        is += 1;
+                        for l in 0..16 {
+                            let i = 16 * half + l;
+                            let h = if hmask[i] & m != 0 { 0 } else { 4 };
+                            let q = ((q[i] >> shift) & 3) as i32 - h;
+                            y[128 * n + 32 * j + i] = dl * q as f32;
+                        }
+                    }
+                }
+            }
+        }
+        DType::Q5_K(_) => {
+            let (d, min) = (read_f16(x, 0), read_f16(x, 2));
+            let scales = &x[4..16];
+            let qh = &x[16..48];
+            let qs = &x[48..176];
+            for c in 0..4 {
+                let ql = &qs[32 * c..32 * (c + 1)];
+                let (sc1, m1) = scale_min_k4(2 * c, scales);
+                let (sc2, m2) = scale_min_k4(2 * c + 1, scales);
+                let (u1, u2) = (1u8 << (2 * c), 2u8 << (2 * c));
+                for l in 0..32 {
+                    let h1 = if qh[l] & u1 != 0 { 16 } else { 0 };
+                    let h2 = if qh[l] & u2 != 0 { 16 } else { 0 };
+                    y[64 * c + l] = d * sc1 * ((ql[l] & 0xF) + h1) as f32 - min * m1;
+                    y[64 * c + 32 + l] = d * sc2 * ((ql[l] >> 4) + h2) as f32 - min * m2;
+                }
+            }
+        }
+        DType::Q6_K(_) => {
+            let ql = &x[0..128];
+            let qh = &x[128..192];
+            let sc = &x[192..208];
+            let d = read_f16(x, 208);
+            for n in 0..2 {
+                let (ql, qh, sc) = (&ql[64 * n..], &qh[32 * n..], &sc[8 * n..]);
+                let y = &mut y[128 * n..];
+                for l in 0..32 {
+                    let is = l / 16;
+                    let q1 = ((ql[l] & 0xF) | ((qh[l] & 3) << 4)) as i32 - 32;
+                    let q2 = ((ql[l + 32] & 0xF) | (((qh[l] >> 2) & 3) << 4)) as i32 - 32;
+                    let q3 = ((ql[l] >> 4) | (((qh[l] >> 4) & 3) << 4)) as i32 - 32;
+                    let q4 = ((ql[l + 32] >> 4) | (((qh[l] >> 6) & 3) << 4)) as i32 - 32;
+                    let scale = |i: usize| d * (sc[i] as i8) as f32;
+                    y[l] = scale(is) * q1 as f32;
+                    y[l + 32] = scale(is + 2) * q2 as f32;
+                    y[l + 64] = scale(is + 4) * q3 as f32;
+                    y[l + 96] = scale(is + 6) * q4 as f32;
+                }
+            }
+        }
+        dt => panic!("{dt} is not a GGML block type"),
+    }
+}
+
+/// Dequantizes raw GGML blocks to f32.
+pub fn dequantize_ggml(dt: DType, quantized: &[u8], elements: usize) -> Vec<f32> {
+    let block_numel = dt
+        .ggml_block_numel()
+        .unwrap_or_else(|| panic!("{dt} is not a GGML block type"));
+    assert_eq!(elements % block_numel, 0);
+    let type_size = dt.size_of();
+
+    let mut dequantized = vec![0f32; elements];
+    for (block, y) in quantized
+        .chunks_exact(type_size)
+        .zip(dequantized.chunks_exact_mut(block_numel))
+    {
+        dequantize_block(dt, block, y);
+    }
+    dequantized
+}
+
 pub fn dequantize(quantized: Tensor) -> Tensor {
     return match quantized.dt() {
+        dt if dt.is_ggml_block() => {
+            let elements = quantized.shape().numel();
+            let original_shape = quantized.shape().clone();
+            let raw_bytes = unsafe { quantized.into_bytes().unwrap() };
+            let dequantized = dequantize_ggml(dt, &raw_bytes, elements);
+            Tensor::from_data(&dequantized, original_shape, Device::CPU)
+        }
         DType::Q8_0F(_) => {
             let elements = quantized.shape().numel();
             let original_shape = quantized.shape().clone();
@@ -166,7 +352,8 @@ pub fn dequantize(quantized: Tensor) -> Tensor {
 #[cfg(test)]
 mod tests {
     use crate::{
-        dequantize, quantize, shape, Device, Quantized, Tensor, Q4_KF, Q4_KH, Q8_0F, Q8_0H,
+        dequantize, dequantize_ggml, quantize, shape, DType, Device, Quantized, Tensor, Q2_K, Q3_K,
+        Q4_0, Q4_KF, Q4_KH, Q5_1, Q5_K, Q6_K, Q8_0F, Q8_0H,
     };
     use half::f16;
 
@@ -188,4 +375,113 @@ mod tests {
         check_qd_reflexive::<Q4_KF>(0.3, 0.3);
         check_qd_reflexive::<Q4_KH>(f16::from_f32(0.3), f16::from_f32(0.3));
     }
+
+    #[test]
+    fn test_dequantize_q4_0() {
+        //Low nibbles hold elements 0..16, high nibbles 16..32, both offset by 8
+        let mut block = f16::from_f32(0.5).to_le_bytes().to_vec();
+        block.extend((0..16u8).map(|j| (15 - j) << 4 | j));
+        let dq = dequantize_ggml(DType::Q4_0(Q4_0), &block, 32);
+        let expected = (0..16)
+            .chain((0..16).rev())
+            .map(|q| (q - 8) as f32 * 0.5)
+            .collect::<Vec<_>>();
+        assert_eq!(dq, expected);
+    }
+
+    #[test]
+    fn test_dequantize_q5_1() {
+        //5th bits come from qh, element j takes bit j
+        let mut block = f16::from_f32(0.25).to_le_bytes().to_vec();
+        block.extend(f16::from_f32(-1.0).to_le_bytes());
+        block.extend(0xAAAA_AAAAu32.to_le_bytes());
+        block.extend([0x21u8; 16]);
+        let dq = dequantize_ggml(DType::Q5_1(Q5_1), &block, 32);
+        let expected = (0..32)
+            .map(|j| {
+                let nibble = if j < 16 { 1 } else { 2 };
+                let high = if j % 2 == 1 { 16 } else { 0 };
+                (nibble + high) as f32 * 0.25 - 1.0
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(dq, expected);
+    }
+
+    //K-quant blocks hold 256 elements, so only a few hand computed values are checked
+    fn check_golden(dt: DType, block: &[u8], golden: &[(usize, f32)]) {
+        assert_eq!(block.len(), dt.size_of());
+        let dq = dequantize_ggml(dt, block, 256);
+        for &(i, expected) in golden {
+            assert_eq!(dq[i], expected, "{dt} element {i}");
+        }
+    }
+
+    #[test]
+    fn test_dequantize_q2_k() {
+        //Scale nibbles are (min 1, scale is % 8 + 1), each 2 bit shift j of 0xE4 reads j
+        let mut block = (0..16u8).map(|is| 0x10 | (is % 8 + 1)).collect::<Vec<_>>();
+        block.extend([0xE4; 64]);
+        block.extend(f16::from_f32(1.0).to_le_bytes());
+        block.extend(f16::from_f32(0.5).to_le_bytes());
+        let golden = [
+            (0, -0.5),
+            (16, -0.5),
+            (32, 2.5),
+            (48, 3.5),
+            (100, 20.5),
+            (255, 23.5),
+        ];
+        check_golden(DType::Q2_K(Q2_K), &block, &golden);
+    }
+
+    #[test]
+    fn test_dequantize_q3_k() {
+        //hmask bits 0..4 are set, so the second half of the block is offset by -4.
+        //High scale bits of 2 cancel the -32 bias, leaving scale `is`.
+        let mut block = vec![0x0F; 32];
+        block.extend([0xE4; 64]);
+        block.extend((0..8u8).map(|j| j | ((j + 8) << 4)));
+        block.extend([0xAA; 4]);
+        block.extend(f16::from_f32(0.5).to_le_bytes());
+        let golden = [
+            (0, 0.0),
+            (48, 1.5),
+            (100, 9.0),
+            (128, -16.0),
+            (200, -12.0),
+            (255, -7.5),
+        ];
+        check_golden(DType::Q3_K(Q3_K), &block, &golden);
+    }
+
+    #[test]
+    fn test_dequantize_q5_k() {
+        //Sub-block j has scale j + 1 & min 1, qh sets the 5th bit of every low nibble
+        let mut block = f16::from_f32(1.0).to_le_bytes().to_vec();
+        block.extend(f16::from_f32(2.0).to_le_bytes());
+        block.extend([1, 2, 3, 4, 1, 1, 1, 1, 0x15, 0x16, 0x17, 0x18]);
+        block.extend([0x55; 32]);
+        block.extend([0x37; 128]);
+        let golden = [(0, 21.0), (32, 4.0), (64, 67.0), (200, 159.0), (255, 22.0)];
+        check_golden(DType::Q5_K(Q5_K), &block, &golden);
+    }
+
+    #[test]
+    fn test_dequantize_q6_k() {
+        //Quarters read (1, 17, 34, 50) - 32, with signed scales i - 8
+        let mut block = vec![0x21; 128];
+        block.extend([0xE4; 64]);
+        block.extend((0..16i8).map(|i| (i - 8) as u8));
+        block.extend(f16::from_f32(0.5).to_le_bytes());
+        let golden = [
+            (0, 124.0),
+            (16, 108.5),
+            (32, 45.0),
+            (64, -4.0),
+            (127, -9.0),
+            (128, 0.0),
+            (255, 63.0),
+        ];
+        check_golden(DType::Q6_K(Q6_K), &block, &golden);
+    }
 }
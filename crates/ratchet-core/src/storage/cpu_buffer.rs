@@ -3,7 +3,7 @@ use half::f16;
 
 use crate::{storage::DeviceStorage, DType, Device, DeviceError, GPUBuffer, Shape, TensorDType};
 
-use std::{alloc::Layout, fmt::Debug, mem::MaybeUninit, sync::Arc};
+use std::{alloc::Layout, fmt::Debug, mem::MaybeUninit, ops::Range, sync::Arc};
 
 #[derive(derive_new::new, Debug, PartialEq, Eq)]
 pub struct RawCPUBuffer(*mut u8, Layout);
@@ -60,10 +60,70 @@ impl Drop for RawCPUBuffer {
     }
 }
 
+/// Read-only bytes borrowed from an external source, e.g a memory mapped file.
+#[derive(Clone)]
+pub struct MappedBuffer {
+    source: Arc<dyn AsRef<[u8]> + Send + Sync>,
+    range: Range<usize>,
+    alignment: usize,
+}
+
+impl Debug for MappedBuffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MappedBuffer")
+            .field("range", &self.range)
+            .field("alignment", &self.alignment)
+            .finish()
+    }
+}
+
+impl MappedBuffer {
+    pub fn as_bytes(&self) -> &[u8] {
+        &(*self.source).as_ref()[self.range.clone()]
+    }
+}
+
+/// Backing memory of a [CPUBuffer].
+#[derive(Debug, Clone)]
+pub enum CPUStorage {
+    Owned(RawCPUBuffer),
+    Mapped(MappedBuffer),
+}
+
+impl CPUStorage {
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            CPUStorage::Owned(raw) => raw.as_bytes(),
+            CPUStorage::Mapped(mapped) => mapped.as_bytes(),
+        }
+    }
+
+    pub fn n_bytes(&self) -> usize {
+        match self {
+            CPUStorage::Owned(raw) => raw.n_bytes(),
+            CPUStorage::Mapped(mapped) => mapped.range.len(),
+        }
+    }
+
+    pub fn alignment(&self) -> usize {
+        match self {
+            CPUStorage::Owned(raw) => raw.1.align(),
+            CPUStorage::Mapped(mapped) => mapped.alignment,
+        }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        match self {
+            CPUStorage::Owned(raw) => raw.into_bytes(),
+            CPUStorage::Mapped(mapped) => mapped.as_bytes().to_vec(),
+        }
+    }
+}
+
 /// Managed CPU buffer
 #[derive(Debug, Clone)]
 pub struct CPUBuffer {
-    inner: Arc<RawCPUBuffer>,
+    inner: Arc<CPUStorage>,
 }
 
 unsafe impl Send for CPUBuffer {}
@@ -72,10 +132,36 @@ unsafe impl Sync for CPUBuffer {}
 impl CPUBuffer {
     pub fn new(inner: RawCPUBuffer) -> Self {
         Self {
-            inner: Arc::new(inner),
+            inner: Arc::new(CPUStorage::Owned(inner)),
         }
     }
 
+    /// Borrow `range` of `source` without copying, e.g a tensor within a memory mapped file.
+    ///
+    /// Falls back to a copy if the bytes are not aligned to `alignment`.
+    pub fn from_mapped(
+        source: Arc<dyn AsRef<[u8]> + Send + Sync>,
+        range: Range<usize>,
+        alignment: usize,
+    ) -> Self {
+        let mapped = MappedBuffer {
+            source,
+            range,
+            alignment,
+        };
+        if mapped.as_bytes().as_ptr() as usize % alignment != 0 {
+            log::warn!("Mapped buffer is not {alignment} byte aligned, copying");
+            return Self::from_bytes(mapped.as_bytes(), alignment);
+        }
+        Self {
+            inner: Arc::new(CPUStorage::Mapped(mapped)),
+        }
+    }
+
+    pub fn is_mapped(&self) -> bool {
+        matches!(*self.inner, CPUStorage::Mapped(_))
+    }
+
     /// # Safety
     ///
     /// We don't check the provided shape here.
@@ -121,7 +207,7 @@ impl CPUBuffer {
         Ok(Self::from_bytes(&buf, dt.size_of()))
     }
 
-    pub fn inner(&self) -> &RawCPUBuffer {
+    pub fn inner(&self) -> &CPUStorage {
         &self.inner
     }
 
@@ -136,7 +222,10 @@ impl CPUBuffer {
     }
 
     pub fn deep_clone(&self) -> Result<Self, DeviceError> {
-        Ok(Self::new((*self.inner()).clone()))
+        //Mapped buffers are read-only, so sharing the mapping is as good as a copy
+        Ok(Self {
+            inner: Arc::new((*self.inner).clone()),
+        })
     }
 
     #[cfg(feature = "plotting")]
@@ -149,9 +238,12 @@ impl CPUBuffer {
 impl DeviceStorage for CPUBuffer {
     fn to_device(&self, device: &Device) -> Result<GPUBuffer, DeviceError> {
         let gpu_device = device.try_gpu()?;
-        let bytes = self.inner().as_bytes();
-        let layout = self.inner().1;
-        Ok(GPUBuffer::from_bytes(bytes, layout.align(), gpu_device))
+        let inner = self.inner();
+        Ok(GPUBuffer::from_bytes(
+            inner.as_bytes(),
+            inner.alignment(),
+            gpu_device,
+        ))
     }
 
     #[cfg(target_arch = "wasm32")]
@@ -1,22 +1,57 @@
 use crate::{
-    gpu::{BufferDescriptor, WgpuDevice},
+    gpu::{Align, BufferDescriptor, WgpuDevice},
     gpu::{BufferUsagesExt, PooledGPUBuffer},
     storage::{CPUBuffer, DeviceStorage},
     Device, DeviceError, Shape, TensorDType,
 };
 
 use bytemuck::NoUninit;
+use std::future::Future;
 use wgpu::BufferUsages;
 
 use crate::DType;
 
-#[derive(Clone, Debug, derive_new::new)]
+#[derive(Clone, Debug)]
 pub struct GPUBuffer {
     pub(crate) inner: PooledGPUBuffer,
     pub(crate) alignment: usize,
+    /// Intermediates are bound as a sub-range of a larger arena.
+    pub(crate) offset: u64,
+    pub(crate) size: u64,
 }
 
 impl GPUBuffer {
+    pub fn new(inner: PooledGPUBuffer, alignment: usize) -> Self {
+        let size = inner.size();
+        Self {
+            inner,
+            alignment,
+            offset: 0,
+            size,
+        }
+    }
+
+    /// `size` bytes of `inner` starting at `offset`.
+    /// `offset` must be aligned to [Align::STORAGE_BUFFER_OFFSET_ALIGNMENT].
+    pub fn sub_range(inner: PooledGPUBuffer, offset: u64, size: u64, alignment: usize) -> Self {
+        assert_eq!(offset as usize % usize::STORAGE_BUFFER_OFFSET_ALIGNMENT, 0);
+        assert!(offset + size <= inner.size());
+        Self {
+            inner,
+            alignment,
+            offset,
+            size,
+        }
+    }
+
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn range(&self) -> std::ops::Range<u64> {
+        self.offset..self.offset + self.size
+    }
+
     pub fn from_slice<T: NoUninit>(data: &[T], shape: &Shape, device: &WgpuDevice) -> Self {
         assert_eq!(data.len(), shape.numel());
         Self::from_bytes(
@@ -55,7 +90,7 @@ impl GPUBuffer {
             .unwrap();
         device.queue().submit(None);
         device.poll(wgpu::Maintain::Wait);
-        Self { inner, alignment }
+        Self::new(inner, alignment)
     }
 
     /// Returns true if the buffer has all the given usages.
@@ -78,19 +113,16 @@ impl GPUBuffer {
     pub fn deep_clone(&self, device: &WgpuDevice) -> Self {
         let clone = device
             .get_or_create_buffer(
-                &BufferDescriptor::new(self.inner.size(), self.inner.usage(), false),
+                &BufferDescriptor::new(self.size, self.inner.usage(), false),
                 true,
             )
             .unwrap();
         let mut encoder =
             device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
-        encoder.copy_buffer_to_buffer(&self.inner, 0, &clone, 0, self.inner.size());
+        encoder.copy_buffer_to_buffer(&self.inner, self.offset, &clone, 0, self.size);
         device.queue().submit(Some(encoder.finish()));
         device.poll(wgpu::Maintain::Wait);
-        Self {
-            inner: clone,
-            alignment: self.alignment,
-        }
+        Self::new(clone, self.alignment)
     }
 
     pub fn from_disk<T: TensorDType, R: std::io::BufRead + std::io::Seek>(
@@ -115,7 +147,7 @@ impl GPUBuffer {
     #[cfg(feature = "plotting")]
     pub fn plot_fmt(&self) -> String {
         let id_string = Self::trim_id(self.inner().global_id()).unwrap_or_default();
-        format!("GPU:#{}\n{} bytes", id_string, self.inner.size())
+        format!("GPU:#{}\n{} bytes", id_string, self.size)
     }
 }
 
@@ -129,7 +161,7 @@ impl DeviceStorage for GPUBuffer {
     async fn to_cpu(&self, device: &Device) -> Result<CPUBuffer, DeviceError> {
         self.validate_usages(BufferUsages::COPY_SRC)?;
         let device = device.try_gpu()?;
-        let buffer_slice = self.inner.slice(..);
+        let buffer_slice = self.inner.slice(self.range());
         let (tx, rx) = futures_intrusive::channel::shared::oneshot_channel();
         let alignment = self.alignment;
 
@@ -153,23 +185,62 @@ impl DeviceStorage for GPUBuffer {
     fn to_cpu(&self, device: &Device) -> Result<CPUBuffer, DeviceError> {
         self.validate_usages(BufferUsages::COPY_SRC)?;
         let device = device.try_gpu()?;
-        let storage = wgpu_buffer_to_cpu_buffer(&self.inner, self.alignment, device);
+        let storage =
+            wgpu_buffer_range_to_cpu_buffer(&self.inner, self.range(), self.alignment, device);
         Ok(storage)
     }
 
     fn n_bytes(&self) -> usize {
-        self.inner.size() as usize
+        self.size as usize
     }
 
     fn dump(&self, _: DType, _: bool) -> String {
         let mut result = String::new();
         let id_string = Self::trim_id(self.inner().global_id()).unwrap_or_default();
         result.push_str(&format!("GPU Buffer #{}\n", id_string));
-        result.push_str(&format!("Size: {} bytes\n", self.inner.size()));
+        result.push_str(&format!("Size: {} bytes\n", self.size));
+        if self.offset != 0 {
+            result.push_str(&format!("Offset: {} bytes\n", self.offset));
+        }
         result
     }
 }
 
+impl GPUBuffer {
+    /// Like [DeviceStorage::to_cpu], but completes once the buffer is mapped instead of
+    /// blocking the calling thread.
+    ///
+    /// The copy is submitted immediately, so it isn't delayed by work submitted before the
+    /// returned future is awaited.
+    pub(crate) fn download(
+        &self,
+        device: &WgpuDevice,
+    ) -> Result<impl Future<Output = Result<CPUBuffer, DeviceError>>, DeviceError> {
+        self.validate_usages(BufferUsages::COPY_SRC)?;
+        let buffer_slice = self.inner.slice(self.range());
+        let (tx, rx) = futures_intrusive::channel::shared::oneshot_channel();
+        let alignment = self.alignment;
+
+        #[cfg(not(target_arch = "wasm32"))]
+        let guard = device.track_callback();
+        wgpu::util::DownloadBuffer::read_buffer(
+            device,
+            device.queue(),
+            &buffer_slice,
+            move |buffer| {
+                tx.send(match buffer {
+                    Ok(db) => Ok(CPUBuffer::from_bytes(&db, alignment)),
+                    Err(error) => Err(error),
+                })
+                .expect("Failed to send result of read_buffer");
+                #[cfg(not(target_arch = "wasm32"))]
+                drop(guard);
+            },
+        );
+        Ok(async move { Ok(rx.receive().await.unwrap()?) })
+    }
+}
+
 #[cfg(target_arch = "wasm32")]
 pub async fn wgpu_buffer_to_cpu_buffer(
     src_buf: &wgpu::Buffer,
@@ -201,9 +272,19 @@ pub fn wgpu_buffer_to_cpu_buffer(
     src_buf: &wgpu::Buffer,
     alignment: usize,
     device: &WgpuDevice,
+) -> CPUBuffer {
+    wgpu_buffer_range_to_cpu_buffer(src_buf, 0..src_buf.size(), alignment, device)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn wgpu_buffer_range_to_cpu_buffer(
+    src_buf: &wgpu::Buffer,
+    range: std::ops::Range<u64>,
+    alignment: usize,
+    device: &WgpuDevice,
 ) -> CPUBuffer {
     assert!(src_buf.usage().contains(wgpu::BufferUsages::COPY_SRC));
-    let buffer_slice = src_buf.slice(..);
+    let buffer_slice = src_buf.slice(range);
     let (tx, rx) = std::sync::mpsc::channel();
 
     wgpu::util::DownloadBuffer::read_buffer(device, device.queue(), &buffer_slice, move |buffer| {
@@ -1,7 +1,11 @@
 mod cpu_buffer;
 mod gpu_buffer;
 
-use std::io::{BufRead, Seek};
+use std::{
+    io::{BufRead, Seek},
+    ops::Range,
+    sync::Arc,
+};
 
 use bytemuck::NoUninit;
 pub use cpu_buffer::*;
@@ -62,6 +66,22 @@ impl Storage {
         }
     }
 
+    /// Borrow `range` of `source` on the CPU, or upload it straight from `source` on the GPU.
+    pub fn from_mapped(
+        source: Arc<dyn AsRef<[u8]> + Send + Sync>,
+        range: Range<usize>,
+        alignment: usize,
+        device: &Device,
+    ) -> Self {
+        match device {
+            Device::CPU => Storage::CPU(CPUBuffer::from_mapped(source, range, alignment)),
+            Device::GPU(g) => {
+                let bytes = &(*source).as_ref()[range];
+                Storage::GPU(GPUBuffer::from_bytes(bytes, alignment, g))
+            }
+        }
+    }
+
     pub unsafe fn into_bytes(self) -> Vec<u8> {
         match self {
             Storage::CPU(c) => unsafe { c.into_bytes() },
@@ -0,0 +1,425 @@
+use crate::gpu::{Align, BufferDescriptor, BufferUsagesExt, WgpuDevice};
+use crate::{
+    DeviceStorage, Executable, GPUBuffer, PassManager, RVec, Shape, Storage, Tensor, TensorId,
+};
+use rustc_hash::FxHashMap;
+use wgpu::BufferUsages;
+
+/// A named dimension only bound when a graph is run, e.g the sequence length of a decoder.
+///
+/// A symbol takes values from 1 to `max`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol {
+    name: &'static str,
+    max: usize,
+}
+
+impl Symbol {
+    pub const fn new(name: &'static str, max: usize) -> Self {
+        Self { name, max }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn max(&self) -> usize {
+        self.max
+    }
+}
+
+impl std::fmt::Display for Symbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+/// A dimension of a [SymbolicShape].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Dim {
+    Known(usize),
+    Symbolic(Symbol),
+}
+
+impl Dim {
+    pub fn bind(&self, bindings: &Bindings) -> anyhow::Result<usize> {
+        match self {
+            Dim::Known(d) => Ok(*d),
+            Dim::Symbolic(s) => bindings
+                .get(*s)
+                .ok_or_else(|| anyhow::anyhow!("{} is not bound", s)),
+        }
+    }
+
+    /// The largest value the dimension takes.
+    pub fn max(&self) -> usize {
+        match self {
+            Dim::Known(d) => *d,
+            Dim::Symbolic(s) => s.max(),
+        }
+    }
+}
+
+impl From<usize> for Dim {
+    fn from(d: usize) -> Self {
+        Dim::Known(d)
+    }
+}
+
+impl From<Symbol> for Dim {
+    fn from(s: Symbol) -> Self {
+        Dim::Symbolic(s)
+    }
+}
+
+impl std::fmt::Display for Dim {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Dim::Known(d) => write!(f, "{}", d),
+            Dim::Symbolic(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+/// A [Shape] with [Symbol]ic dimensions, e.g `[1, seq_len, 384]`.
+///
+/// Inputs of a [DynamicExecutable] are declared with a symbolic shape, and made concrete for
+/// every binding when the graph is built.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SymbolicShape(RVec<Dim>);
+
+impl SymbolicShape {
+    pub fn new<D: Into<Dim>>(dims: impl IntoIterator<Item = D>) -> Self {
+        Self(dims.into_iter().map(Into::into).collect())
+    }
+
+    pub fn dims(&self) -> &[Dim] {
+        &self.0
+    }
+
+    pub fn rank(&self) -> usize {
+        self.0.len()
+    }
+
+    /// The symbols of the shape, in order of appearance.
+    pub fn symbols(&self) -> impl Iterator<Item = Symbol> + '_ {
+        self.0.iter().filter_map(|d| match d {
+            Dim::Symbolic(s) => Some(*s),
+            Dim::Known(_) => None,
+        })
+    }
+
+    /// The concrete shape for `bindings`, which must bind every symbol of the shape.
+    pub fn bind(&self, bindings: &Bindings) -> anyhow::Result<Shape> {
+        let dims = self
+            .0
+            .iter()
+            .map(|d| d.bind(bindings))
+            .collect::<anyhow::Result<RVec<_>>>()?;
+        Ok(Shape::new(dims))
+    }
+
+    /// The shape with every symbol bound to its largest value.
+    pub fn max(&self) -> Shape {
+        Shape::new(self.0.iter().map(Dim::max).collect())
+    }
+}
+
+impl From<Shape> for SymbolicShape {
+    fn from(shape: Shape) -> Self {
+        Self::new(shape.iter().copied())
+    }
+}
+
+impl std::fmt::Display for SymbolicShape {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[")?;
+        for (i, d) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", d)?;
+        }
+        write!(f, "]")
+    }
+}
+
+/// The value of each [Symbol] of a graph.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Bindings(RVec<(Symbol, usize)>);
+
+impl Bindings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Bind `symbol` to `value`, replacing any previous value.
+    pub fn bind(mut self, symbol: Symbol, value: usize) -> Self {
+        self.0.retain(|(s, _)| *s != symbol);
+        self.0.push((symbol, value));
+        self
+    }
+
+    pub fn get(&self, symbol: Symbol) -> Option<usize> {
+        self.0.iter().find(|(s, _)| *s == symbol).map(|(_, v)| *v)
+    }
+
+    /// Every symbol bound to its largest value.
+    fn largest(symbols: &[Symbol]) -> Self {
+        symbols
+            .iter()
+            .fold(Self::new(), |bindings, &s| bindings.bind(s, s.max()))
+    }
+
+    fn validate(&self, symbols: &[Symbol]) -> anyhow::Result<()> {
+        for &symbol in symbols {
+            match self.get(symbol) {
+                Some(value) if (1..=symbol.max()).contains(&value) => {}
+                Some(value) => anyhow::bail!(
+                    "{} = {} is out of range, expected 1..={}",
+                    symbol,
+                    value,
+                    symbol.max()
+                ),
+                None => anyhow::bail!("{} is not bound", symbol),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::ops::Index<Symbol> for Bindings {
+    type Output = usize;
+
+    fn index(&self, symbol: Symbol) -> &Self::Output {
+        self.0
+            .iter()
+            .find(|(s, _)| *s == symbol)
+            .map(|(_, v)| v)
+            .unwrap_or_else(|| panic!("{} is not bound", symbol))
+    }
+}
+
+/// # Dynamic Executable
+///
+/// A graph with [Symbol]ic dimensions, compiled once for a range of values.
+///
+/// The graph is rebuilt by `build` for every binding, which is cheap, but not recompiled.
+/// Kernels read shapes from their metadata, so the pipelines and buffers captured for one
+/// binding can compute the graph of another: only the uniform and the number of workgroups
+/// change. Buffers are reserved for the largest binding of every symbol.
+///
+/// Some kernels are chosen or vectorised according to a symbolic dimension, e.g a softmax over
+/// `seq_len` uses `vec4` if `seq_len % 4 == 0`. A binding whose kernel keys differ from every
+/// captured [Executable] is compiled into a new one, so a range is served by a few
+/// specializations.
+///
+/// The graph built for every binding must have the same structure, and the same operations
+/// on the same dtypes. Graphs are optimized by [PassManager::symbolic], whose rewrites don't
+/// depend on the bound shapes, so this holds after the passes too. Tensors held across builds
+/// (weights, KV caches) are bound directly, other inputs are copied into the captured ones.
+///
+/// ```ignore
+/// let seq_len = Symbol::new("seq_len", 448);
+/// let input = SymbolicShape::new([Dim::from(1), seq_len.into()]);
+/// let mut exe = DynamicExecutable::new(vec![seq_len], |b| {
+///     let tokens = Tensor::from_data(&tokens[..b[seq_len]], input.bind(b)?, device.clone());
+///     decoder.schedule(tokens)
+/// })?;
+/// let logits = exe.run(&Bindings::new().bind(seq_len, 12))?;
+/// ```
+pub struct DynamicExecutable<F> {
+    symbols: Vec<Symbol>,
+    build: F,
+    /// Id and size of every tensor of the graph built for the largest binding, in execution
+    /// order.
+    reserved: Vec<(TensorId, usize)>,
+    specializations: Vec<Executable>,
+}
+
+impl<F> DynamicExecutable<F>
+where
+    F: FnMut(&Bindings) -> anyhow::Result<Tensor>,
+{
+    /// Build the graph for the largest binding, to size the buffers of every specialization.
+    pub fn new(symbols: Vec<Symbol>, mut build: F) -> anyhow::Result<Self> {
+        let graph = build(&Bindings::largest(&symbols))?.optimize_with(PassManager::symbolic())?;
+        anyhow::ensure!(graph.device().is_gpu(), "Only GPU graphs can be compiled");
+        let reserved = graph
+            .execution_order()
+            .iter()
+            .map(|t| (t.id(), t.num_bytes()))
+            .collect();
+        Ok(Self {
+            symbols,
+            build,
+            reserved,
+            specializations: vec![],
+        })
+    }
+
+    pub fn num_specializations(&self) -> usize {
+        self.specializations.len()
+    }
+
+    /// Build and compute the graph for `bindings`, which must bind every symbol.
+    ///
+    /// The output is bound to the buffer of a captured [Executable], it is overwritten by later
+    /// runs using the same specialization.
+    pub fn run(&mut self, bindings: &Bindings) -> anyhow::Result<Tensor> {
+        bindings.validate(&self.symbols)?;
+        let graph = (self.build)(bindings)?.optimize_with(PassManager::symbolic())?;
+        for executable in self.specializations.iter() {
+            if let Some(output) = executable.rebind(&graph)? {
+                return Ok(output);
+            }
+        }
+
+        log::debug!(
+            "Compiling specialization {} for {:?}",
+            self.specializations.len(),
+            bindings
+        );
+        let executable = self.specialize(&graph)?;
+        let device = graph.device().try_gpu()?;
+        let index = executable.dispatch(device)?;
+        device.poll(wgpu::MaintainBase::WaitForSubmissionIndex(index));
+        self.specializations.push(executable);
+        Ok(graph)
+    }
+
+    /// Compile `graph`, reserving the size of the largest binding for every tensor.
+    fn specialize(&self, graph: &Tensor) -> anyhow::Result<Executable> {
+        let order = graph.execution_order();
+        anyhow::ensure!(
+            order.len() == self.reserved.len(),
+            "The graph has {} nodes, {} for the largest binding",
+            order.len(),
+            self.reserved.len()
+        );
+
+        let device = graph.device().try_gpu()?;
+        let mut reserved = FxHashMap::default();
+        for (t, &(max_id, bytes)) in order.iter().zip(self.reserved.iter()) {
+            t.materialize()?;
+            if !t.resolved() {
+                reserved.insert(t.id(), bytes);
+            } else if t.id() != max_id && bytes > t.num_bytes() {
+                //Inputs built for every binding are copied into this one by later runs
+                reserve(t, bytes, device)?;
+            }
+        }
+        Ok(graph.lower_gpu(device, &reserved, false)?)
+    }
+}
+
+/// Move the contents of a resolved tensor into a buffer of `bytes`.
+fn reserve(t: &Tensor, bytes: usize, device: &WgpuDevice) -> anyhow::Result<()> {
+    let descriptor =
+        BufferDescriptor::new(bytes.align_for_copy() as _, BufferUsages::standard(), false);
+    let buffer = device.get_or_create_buffer(&descriptor, true)?;
+    {
+        let storage = t.storage();
+        let src = storage
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("{:?} has no storage", t.id()))?
+            .try_gpu()?;
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        encoder.copy_buffer_to_buffer(
+            &src.inner,
+            src.offset(),
+            &buffer.inner,
+            0,
+            t.num_bytes().align_for_copy() as _,
+        );
+        device.queue().submit(Some(encoder.finish()));
+    }
+    t.update_storage(Storage::GPU(GPUBuffer::new(buffer, t.dt().size_of())));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Bindings, Dim, DynamicExecutable, Symbol, SymbolicShape};
+    use crate::{shape, Device, DeviceRequest, Tensor};
+
+    #[test]
+    fn validates_bindings() {
+        let (seq_len, batch) = (Symbol::new("seq_len", 448), Symbol::new("batch", 4));
+        let symbols = [seq_len, batch];
+
+        let bindings = Bindings::new().bind(seq_len, 12).bind(batch, 1);
+        assert!(bindings.validate(&symbols).is_ok());
+        assert_eq!(bindings[seq_len], 12);
+        assert_eq!(bindings.clone().bind(seq_len, 13)[seq_len], 13);
+
+        assert!(Bindings::new()
+            .bind(seq_len, 12)
+            .validate(&symbols)
+            .is_err());
+        assert!(bindings.clone().bind(batch, 0).validate(&symbols).is_err());
+        assert!(bindings.bind(seq_len, 449).validate(&symbols).is_err());
+        assert!(Bindings::largest(&symbols).validate(&symbols).is_ok());
+    }
+
+    #[test]
+    fn binds_symbolic_shapes() -> anyhow::Result<()> {
+        let seq_len = Symbol::new("seq_len", 448);
+        let shape = SymbolicShape::new([Dim::from(1), seq_len.into(), 384.into()]);
+        assert_eq!(shape.to_string(), "[1, seq_len, 384]");
+        assert_eq!(shape.symbols().collect::<Vec<_>>(), vec![seq_len]);
+        assert_eq!(shape.max(), shape![1, 448, 384]);
+        assert_eq!(
+            shape.bind(&Bindings::new().bind(seq_len, 12))?,
+            shape![1, 12, 384]
+        );
+        assert!(shape.bind(&Bindings::new()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn runs_across_bindings() -> anyhow::Result<()> {
+        let device = Device::request_device(DeviceRequest::GPU)?;
+        const MAX: usize = 16;
+        let seq_len = Symbol::new("seq_len", MAX);
+        let input = SymbolicShape::new([seq_len.into(), Dim::from(MAX)]);
+
+        let x = Tensor::randn::<f32>(shape![MAX, MAX], Device::CPU);
+        let mask = Tensor::randn::<f32>(shape![MAX, MAX], Device::CPU);
+        let w = Tensor::randn::<f32>(shape![MAX, 8], Device::CPU);
+        //The mask slice is an identity at the largest binding only, and the softmax over
+        //`seq_len` is vectorised for some bindings
+        let graph = |x: Tensor, mask: &Tensor, w: &Tensor| -> anyhow::Result<Tensor> {
+            let n = x.shape()[0];
+            x.add(mask.clone().slice(&[0..n, 0..MAX])?)?
+                .matmul(w.clone(), false, false)?
+                .permute(&[1, 0])?
+                .softmax(1)
+        };
+        let rows = |n: usize| -> anyhow::Result<Tensor> {
+            Ok(x.clone().slice(&[0..n, 0..MAX])?.resolve()?)
+        };
+
+        let (gpu_mask, gpu_w) = (mask.to(&device)?, w.to(&device)?);
+        let mut exe = DynamicExecutable::new(vec![seq_len], |b| {
+            let x = rows(input.bind(b)?[0])?.to(&device)?;
+            graph(x, &gpu_mask, &gpu_w)
+        })?;
+
+        let mut check = |n: usize| -> anyhow::Result<usize> {
+            let result = exe.run(&Bindings::new().bind(seq_len, n))?;
+            let ground = graph(rows(n)?, &mask, &w)?.resolve()?;
+            ground.all_close(&result.to(&Device::CPU)?, 1e-4, 1e-4)?;
+            Ok(exe.num_specializations())
+        };
+        for n in [16, 12, 8, 5, 3] {
+            check(n)?;
+        }
+        let compiled = check(16)?;
+        for n in [12, 5, 3, 8, 16] {
+            assert_eq!(check(n)?, compiled);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,19 @@
+#![cfg(any(feature = "profiling", feature = "gpu-profiling"))]
+//! Formatting shared by the runtime tables of the [Tracer](crate::Tracer) and the GPU profiler.
+use tabled::settings::{object::Rows, Alignment, Modify, Panel, Style};
+use tabled::{Table, Tabled};
+
+//used for formatting table cells
+pub(crate) fn float2(n: &f64) -> String {
+    format!("{:.2}", n)
+}
+
+/// A table of `entries`, with the total runtime in the footer.
+pub(crate) fn runtime_table<T: Tabled>(entries: &[T], total_us: u128) -> Table {
+    Table::new(entries)
+        .with(Style::modern())
+        .with(Modify::new(Rows::first()).with(Alignment::center()))
+        .with(Modify::new(Rows::new(1..)).with(Alignment::left()))
+        .with(Panel::footer(format!("{} total runtime (μs)", total_us)))
+        .to_owned()
+}
@@ -1,13 +1,16 @@
-use crate::gpu::{BindGroupEntry, CpuUniform, WgpuDevice};
+use crate::gpu::{Align, BindGroupEntry, CpuUniform, WgpuDevice};
 use crate::{
     cpu, ops::*, rvec, BufferSegment, CPUBuffer, CompiledOp, DType, Device, DeviceStorage,
-    Executable, GPUBuffer, GPUOperation, InvariantError, LazyOp, Operation, OperationError, RVec,
-    RawCPUBuffer, Shape, Storage, Strides, TensorDType, TensorId,
+    Executable, GPUBuffer, GPUOperation, InvariantError, LazyOp, Operation, OperationError,
+    PassManager, RVec, RawCPUBuffer, Shape, Specialization, Storage, Strides, TensorDType,
+    TensorId, MIN_STORAGE_BUFFER_SIZE,
 };
 use derive_new::new;
 use npyz::WriterBuilder;
-use parking_lot::{RwLock, RwLockReadGuard};
+use parking_lot::{Mutex, RwLock, RwLockReadGuard};
+use rustc_hash::FxHashMap;
 use std::collections::HashSet;
+use std::future::Future;
 use std::io::{BufRead, Seek};
 use std::ops::Bound;
 use std::path::Path;
@@ -35,6 +38,10 @@ pub enum TensorError {
     TransferError,
     #[error(transparent)]
     OperationError(#[from] OperationError),
+    #[error("Failed to load deferred tensor {0:?}: {1}")]
+    DeferredLoadError(TensorId, #[source] anyhow::Error),
+    #[error(transparent)]
+    ExecutionError(#[from] crate::ExecutionError),
 }
 
 /// A multi-dimensional array of data.
@@ -79,6 +86,132 @@ impl Tensor {
     pub(crate) fn update_storage(&self, storage: Storage) {
         *self.inner.storage.write() = Some(storage);
     }
+
+    /// Rebuild this operation on top of `srcs`, given in the same order as `op().srcs()`.
+    ///
+    /// The result starts out unresolved, except for a `View`, which aliases its new input.
+    pub(crate) fn with_srcs(&self, srcs: RVec<Tensor>) -> Tensor {
+        let op = self.op_with_srcs(srcs);
+        match &op {
+            LazyOp::View(v) => {
+                let storage = v.input().storage.clone();
+                Tensor::shallow(op, self.view.clone(), storage, self.device.clone())
+            }
+            _ => Tensor::lazy(op, self.view.clone(), self.device.clone()),
+        }
+    }
+
+    /// Like [Tensor::with_srcs], but the result writes into this tensor's storage, so that any
+    /// handle to `self` observes the result once it is resolved.
+    pub(crate) fn alias_with_srcs(&self, srcs: RVec<Tensor>) -> Tensor {
+        assert!(
+            !matches!(self.op(), LazyOp::View(_)),
+            "Views alias their input"
+        );
+        let op = self.op_with_srcs(srcs);
+        Tensor::shallow(
+            op,
+            self.view.clone(),
+            self.storage.clone(),
+            self.device.clone(),
+        )
+    }
+
+    /// A tensor of the same shape and type as this one, computed by `op` instead.
+    pub(crate) fn with_op(&self, op: LazyOp) -> Tensor {
+        Tensor::lazy(op, self.view.clone(), self.device.clone())
+    }
+
+    fn op_with_srcs(&self, srcs: RVec<Tensor>) -> LazyOp {
+        let mut op = self.op().clone();
+        let mut dsts = op.srcs_mut();
+        assert_eq!(dsts.len(), srcs.len());
+        for (dst, src) in dsts.iter_mut().zip(srcs) {
+            **dst = src;
+        }
+        op
+    }
+
+    /// A `Const` sharing this tensor's storage, detaching it from the graph that produced it.
+    pub(crate) fn as_const(&self) -> Tensor {
+        Tensor::shallow(
+            LazyOp::Const,
+            self.view.clone(),
+            self.storage.clone(),
+            self.device.clone(),
+        )
+    }
+
+    /// Whether this tensor currently holds storage, without loading deferred tensors.
+    pub(crate) fn has_storage(&self) -> bool {
+        self.inner.storage.read().is_some()
+    }
+
+    /// A storage-less scalar, used to stand in for the sources of an operation when comparing
+    /// operations structurally.
+    pub(crate) fn placeholder() -> Tensor {
+        let shape = crate::shape![1];
+        let strides = Strides::from(&shape);
+        let meta = StorageView::new(shape, DType::U32, strides);
+        Tensor::new(LazyOp::Const, meta, None, Device::CPU)
+    }
+
+    /// Create a tensor whose storage is only loaded when first needed, e.g when it is first
+    /// resolved as part of a graph.
+    ///
+    /// `load` must produce a resolved tensor of the same `dt` & `shape` on `device`.
+    pub fn deferred<F>(dt: DType, shape: Shape, device: Device, load: F) -> Tensor
+    where
+        F: FnOnce() -> anyhow::Result<Tensor> + Send + 'static,
+    {
+        let strides = Strides::from(&shape);
+        let meta = StorageView::new(shape, dt, strides);
+        let mut inner = Inner::new(LazyOp::Const, meta, None, device);
+        inner.deferred = Some(Deferred(Mutex::new(Some(Box::new(load)))));
+        Self {
+            inner: Arc::new(inner),
+        }
+    }
+
+    /// Returns true if the tensor was created by [Tensor::deferred] and is yet to be loaded.
+    pub fn is_deferred(&self) -> bool {
+        self.inner
+            .deferred
+            .as_ref()
+            .is_some_and(|deferred| deferred.0.lock().is_some())
+    }
+
+    /// Load the storage of a deferred tensor, a no-op for every other tensor.
+    ///
+    /// Only the resolve paths load, [Tensor::storage] never does.
+    pub(crate) fn materialize(&self) -> Result<(), TensorError> {
+        let Some(deferred) = &self.inner.deferred else {
+            return Ok(());
+        };
+        let mut pending = deferred.0.lock();
+        let Some(load) = pending.take() else {
+            return Ok(());
+        };
+        let err = |e: anyhow::Error| TensorError::DeferredLoadError(self.id(), e);
+        let loaded = load().map_err(err)?;
+        if loaded.dt() != self.dt() || loaded.shape() != self.shape() {
+            return Err(err(anyhow::anyhow!(
+                "expected {:?} {:?}, loaded {:?} {:?}",
+                self.dt(),
+                self.shape(),
+                loaded.dt(),
+                loaded.shape()
+            )));
+        }
+        if loaded.device() != self.device() {
+            return Err(err(anyhow::anyhow!("loaded tensor is on the wrong device")));
+        }
+        let storage = loaded.inner.storage.write().take();
+        let storage =
+            storage.ok_or_else(|| err(anyhow::anyhow!("loaded tensor has no storage")))?;
+        self.update_storage(storage);
+        Ok(())
+    }
 }
 
 impl std::fmt::Debug for Tensor {
@@ -137,7 +270,21 @@ pub struct StorageView {
 
 impl StorageView {
     pub fn is_contiguous(&self) -> bool {
-        todo!()
+        self.strides == Strides::from(&self.shape)
+    }
+}
+
+/// Produces the storage of a deferred tensor, see [Tensor::deferred].
+type DeferredLoad = Box<dyn FnOnce() -> anyhow::Result<Tensor> + Send>;
+
+struct Deferred(Mutex<Option<DeferredLoad>>);
+
+impl std::fmt::Debug for Deferred {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let pending = self.0.lock().is_some();
+        f.debug_struct("Deferred")
+            .field("pending", &pending)
+            .finish()
     }
 }
 
@@ -148,6 +295,7 @@ pub struct Inner {
     device: Device,
     view: StorageView,
     storage: Arc<RwLock<Option<Storage>>>,
+    deferred: Option<Deferred>,
 }
 
 impl AsRef<Inner> for Inner {
@@ -164,6 +312,7 @@ impl Inner {
             op,
             device,
             storage: Arc::new(RwLock::new(storage)),
+            deferred: None,
         }
     }
 
@@ -179,6 +328,7 @@ impl Inner {
             op,
             device,
             storage,
+            deferred: None,
         }
     }
 }
@@ -217,6 +367,9 @@ impl Tensor {
         &self.device
     }
 
+    /// The storage of the tensor, `None` until it is resolved.
+    ///
+    /// Deferred tensors are loaded when resolved, see [Tensor::deferred].
     pub fn storage(&self) -> RwLockReadGuard<Option<Storage>> {
         self.inner.storage.read()
     }
@@ -389,6 +542,45 @@ impl Tensor {
         Ok(Tensor::lazy(LazyOp::Softmax(softmax), new_view, device))
     }
 
+    /// Index of the largest element along `dim`, which must be the last dimension.
+    ///
+    /// Returns an `I32` tensor without `dim`, e.g the greedily sampled token of each row of
+    /// logits, which can be fed to the next step without leaving the device.
+    pub fn argmax(self, dim: usize) -> anyhow::Result<Tensor> {
+        let device = self.device.clone();
+        let argmax = ArgMax::new(self, dim);
+        let new_view = argmax.compute_view()?;
+        Ok(Tensor::lazy(LazyOp::ArgMax(argmax), new_view, device))
+    }
+
+    /// Fused scaled dot product attention.
+    ///
+    /// `self` is the query of shape [B, H, L, D], `key` and `value` are [B, KVH, S, D].
+    pub fn attention(
+        self,
+        key: Tensor,
+        value: Tensor,
+        mask: AttentionMask,
+        scale: f32,
+    ) -> anyhow::Result<Tensor> {
+        self.softcapped_attention(key, value, mask, scale, None)
+    }
+
+    /// Fused scaled dot product attention, with `tanh` soft-capping of the attention logits.
+    pub fn softcapped_attention(
+        self,
+        key: Tensor,
+        value: Tensor,
+        mask: AttentionMask,
+        scale: f32,
+        softcap: Option<f32>,
+    ) -> anyhow::Result<Tensor> {
+        let device = self.device.clone();
+        let attention = Attention::new(self, key, value, mask, scale, softcap);
+        let new_view = attention.compute_view()?;
+        Ok(Tensor::lazy(LazyOp::Attention(attention), new_view, device))
+    }
+
     pub fn rope(self, dim: usize, base: f32, offset: usize) -> anyhow::Result<Tensor> {
         let device = self.device.clone();
         let rope = RoPE::new(self, dim, base, offset);
@@ -597,6 +789,23 @@ impl Tensor {
         Ok(Tensor::new(LazyOp::Const, meta, Some(storage), device))
     }
 
+    /// Create a tensor from `range` of `source`, without copying on the CPU.
+    ///
+    /// GPU tensors are uploaded directly from `source`, e.g a memory mapped file.
+    pub fn from_mapped(
+        source: Arc<dyn AsRef<[u8]> + Send + Sync>,
+        range: std::ops::Range<usize>,
+        alignment: usize,
+        dt: DType,
+        shape: Shape,
+        device: Device,
+    ) -> Tensor {
+        let storage = Storage::from_mapped(source, range, alignment, &device);
+        let strides = Strides::from(&shape);
+        let meta = StorageView::new(shape, dt, strides);
+        Tensor::new(LazyOp::Const, meta, Some(storage), device)
+    }
+
     /// # Safety
     ///
     /// If the tensor has more than 1 reference, you die.
@@ -663,7 +872,7 @@ impl Tensor {
         self.segments()
             .iter()
             .fold(rvec![], |mut entries, segment| {
-                let (offset, size) = (segment.offset, segment.size);
+                let (offset, size) = (gpu_buf.offset() + segment.offset, segment.size);
                 entries.push(BindGroupEntry {
                     handle,
                     offset,
@@ -749,14 +958,17 @@ impl Tensor {
         match self.op() {
             LazyOp::Binary(b) => b.compile_gpu(self, uniform, device, can_ip, debug).ok(),
             LazyOp::Cast(c) => c.compile_gpu(self, uniform, device, can_ip, debug).ok(),
+            LazyOp::Attention(a) => a.compile_gpu(self, uniform, device, can_ip, debug).ok(),
             LazyOp::Matmul(m) => m.compile_gpu(self, uniform, device, can_ip, debug).ok(),
             LazyOp::Softmax(s) => s.compile_gpu(self, uniform, device, can_ip, debug).ok(),
+            LazyOp::ArgMax(a) => a.compile_gpu(self, uniform, device, can_ip, debug).ok(),
             LazyOp::RoPE(r) => r.compile_gpu(self, uniform, device, can_ip, debug).ok(),
             LazyOp::Unary(u) => u.compile_gpu(self, uniform, device, can_ip, debug).ok(),
             LazyOp::Reindex(r) => r.compile_gpu(self, uniform, device, can_ip, debug).ok(),
             LazyOp::Concat(c) => c.compile_gpu(self, uniform, device, can_ip, debug).ok(),
             LazyOp::Norm(n) => n.compile_gpu(self, uniform, device, can_ip, debug).ok(),
             LazyOp::Conv(c) => c.compile_gpu(self, uniform, device, can_ip, debug).ok(),
+            LazyOp::Fused(f) => f.compile_gpu(self, uniform, device, can_ip, debug).ok(),
             LazyOp::Select(i) => i.compile_gpu(self, uniform, device, can_ip, debug).ok(),
             LazyOp::IndexWrite(i) => i.compile_gpu(self, uniform, device, can_ip, debug).ok(),
             LazyOp::Cache(c) => c.compile_gpu(self, uniform, device, can_ip, debug).ok(),
@@ -765,11 +977,62 @@ impl Tensor {
         }
     }
 
+    /// Specialize the kernel computing this tensor, see [GPUOperation::specialize].
+    pub(crate) fn specialize(
+        &self,
+        uniform: &mut CpuUniform,
+        can_inplace: bool,
+    ) -> Result<Specialization, OperationError> {
+        match self.op() {
+            LazyOp::Binary(b) => b.specialize(self, uniform, can_inplace),
+            LazyOp::Cast(c) => c.specialize(self, uniform, can_inplace),
+            LazyOp::Attention(a) => a.specialize(self, uniform, can_inplace),
+            LazyOp::Matmul(m) => m.specialize(self, uniform, can_inplace),
+            LazyOp::Softmax(s) => s.specialize(self, uniform, can_inplace),
+            LazyOp::ArgMax(a) => a.specialize(self, uniform, can_inplace),
+            LazyOp::RoPE(r) => r.specialize(self, uniform, can_inplace),
+            LazyOp::Unary(u) => u.specialize(self, uniform, can_inplace),
+            LazyOp::Reindex(r) => r.specialize(self, uniform, can_inplace),
+            LazyOp::Concat(c) => c.specialize(self, uniform, can_inplace),
+            LazyOp::Norm(n) => n.specialize(self, uniform, can_inplace),
+            LazyOp::Conv(c) => c.specialize(self, uniform, can_inplace),
+            LazyOp::Fused(f) => f.specialize(self, uniform, can_inplace),
+            LazyOp::Select(i) => i.specialize(self, uniform, can_inplace),
+            LazyOp::IndexWrite(i) => i.specialize(self, uniform, can_inplace),
+            LazyOp::Cache(c) => c.specialize(self, uniform, can_inplace),
+            LazyOp::Const | LazyOp::View(_) => Err(OperationError::CompileError(format!(
+                "{} has no kernel to specialize",
+                self.op().name()
+            ))),
+        }
+    }
+
     pub fn cpu_apply(self, dst: Tensor) -> Option<Tensor> {
         cpu::apply_operation(self.op().clone(), dst).ok()
     }
 
     fn resolve_inner(self, debug: bool) -> Result<Tensor, TensorError> {
+        self.optimize()?.execute(debug)
+    }
+
+    /// Run the default [PassManager] pipeline, unless `RATCHET_DISABLE_PASSES` is set.
+    pub(crate) fn optimize(self) -> Result<Tensor, TensorError> {
+        self.optimize_with(PassManager::default())
+    }
+
+    /// Run `passes`, unless `RATCHET_DISABLE_PASSES` is set.
+    pub(crate) fn optimize_with(self, passes: PassManager) -> Result<Tensor, TensorError> {
+        if std::env::var_os("RATCHET_DISABLE_PASSES").is_some() {
+            Ok(self)
+        } else {
+            #[cfg(feature = "profiling")]
+            let _span = crate::Tracer::global().span("optimize", crate::TraceCategory::Optimize);
+            passes.run(self)
+        }
+    }
+
+    /// Execute the graph rooted at this tensor as-is, without running any passes.
+    pub(crate) fn execute(self, debug: bool) -> Result<Tensor, TensorError> {
         match self.device().clone() {
             Device::CPU => self.resolve_cpu(),
             Device::GPU(device) => self.resolve_gpu(&device, debug),
@@ -778,7 +1041,14 @@ impl Tensor {
 
     fn resolve_cpu(self) -> Result<Tensor, TensorError> {
         let mut tensor = self.clone();
+        #[cfg(feature = "profiling")]
+        let build = crate::Tracer::global().span("build", crate::TraceCategory::Build);
         let execution_order = self.execution_order();
+        for t in execution_order.iter() {
+            t.materialize()?;
+        }
+        #[cfg(feature = "profiling")]
+        drop(build);
 
         for t in execution_order.into_iter() {
             log::debug!("Running: {:?}", t.op().name());
@@ -786,6 +1056,8 @@ impl Tensor {
             if t.resolved() {
                 continue;
             }
+            #[cfg(feature = "profiling")]
+            let _span = crate::Tracer::global().op_span(t, crate::TraceCategory::Op);
             tensor = tensor.cpu_apply(t.clone()).unwrap();
         }
 
@@ -793,19 +1065,63 @@ impl Tensor {
     }
 
     fn resolve_gpu(self, gpu_device: &WgpuDevice, debug: bool) -> Result<Tensor, TensorError> {
+        let index = self.dispatch_gpu(gpu_device, debug)?;
+        gpu_device.poll(wgpu::MaintainBase::WaitForSubmissionIndex(index));
+        Ok(self)
+    }
+
+    /// Allocate, compile & dispatch the graph, without waiting for the GPU to execute it.
+    fn dispatch_gpu(
+        &self,
+        gpu_device: &WgpuDevice,
+        debug: bool,
+    ) -> Result<wgpu::SubmissionIndex, TensorError> {
+        let executable = self.lower_gpu(gpu_device, &FxHashMap::default(), debug)?;
+        #[cfg(feature = "profiling")]
+        let _span = crate::Tracer::global().span("dispatch", crate::TraceCategory::Dispatch);
+
+        #[cfg(feature = "debug")]
+        let index = if debug {
+            executable.dispatch_debugging(gpu_device)?
+        } else {
+            executable.dispatch(gpu_device)?
+        };
+        #[cfg(not(feature = "debug"))]
+        let index = executable.dispatch(gpu_device)?;
+        Ok(index)
+    }
+
+    /// Allocate and compile every operation of the graph, without dispatching them.
+    ///
+    /// Tensors in `reserved` are given at least that many bytes.
+    pub(crate) fn lower_gpu(
+        &self,
+        gpu_device: &WgpuDevice,
+        reserved: &FxHashMap<TensorId, usize>,
+        debug: bool,
+    ) -> Result<Executable, TensorError> {
+        #[cfg(feature = "profiling")]
+        let build = crate::Tracer::global().span("build", crate::TraceCategory::Build);
         let execution_order = self.execution_order();
         let mut uniform = CpuUniform::new();
         let mut compiled_ops = Vec::with_capacity(execution_order.len());
+        let mut compute_dsts = Vec::with_capacity(execution_order.len());
+        for t in execution_order.iter() {
+            t.materialize()?;
+        }
+        #[cfg(feature = "profiling")]
+        drop(build);
 
         gpu_device.begin_pass();
-        let mut allocations = gpu_device.allocate_cfg(&execution_order, gpu_device)?;
+        #[cfg(feature = "profiling")]
+        let alloc = crate::Tracer::global().span("allocate", crate::TraceCategory::Alloc);
+        let mut allocations = gpu_device.allocate_cfg(&execution_order, reserved, gpu_device)?;
+        #[cfg(feature = "profiling")]
+        drop(alloc);
 
         #[cfg(feature = "plotting")]
         crate::plot::render_to_file(execution_order.last().unwrap(), "prealloc.svg").unwrap();
 
-        #[cfg(feature = "debug")]
-        let mut compute_dsts = Vec::new();
-
         for t in execution_order.iter() {
             log::debug!("Compiling: {:?}", t.op().name());
             assert!(t.device().is_gpu());
@@ -814,18 +1130,28 @@ impl Tensor {
             }
 
             let id = t.id();
-            let inner = allocations.remove(&id).ok_or(TensorError::NoStorage(id))?;
-            t.update_storage(Storage::GPU(GPUBuffer {
-                inner,
-                alignment: t.dt().size_of(),
-            }));
+            let allocation = allocations.remove(&id).ok_or(TensorError::NoStorage(id))?;
+            let num_bytes = reserved.get(&id).copied().unwrap_or_default();
+            let size = (num_bytes
+                .max(t.num_bytes())
+                .max(MIN_STORAGE_BUFFER_SIZE)
+                .align_for_copy() as u64)
+                .min(allocation.buffer.size() - allocation.offset);
+            t.update_storage(Storage::GPU(GPUBuffer::sub_range(
+                allocation.buffer,
+                allocation.offset,
+                size,
+                t.dt().size_of(),
+            )));
 
             let to_modify = t.op().srcs()[0];
             let can_inplace = t.op().supports_inplace() && to_modify.strong_count() == 1;
 
+            #[cfg(feature = "profiling")]
+            let _span = crate::Tracer::global().op_span(t, crate::TraceCategory::Compile);
+
             if let Some(compiled_op) = t.compile_gpu(&mut uniform, gpu_device, can_inplace, debug) {
                 compiled_ops.push(compiled_op);
-                #[cfg(feature = "debug")]
                 compute_dsts.push(*t);
             } else {
                 log::warn!("Compilation failed for operation: {:?}", t.op().name());
@@ -834,30 +1160,38 @@ impl Tensor {
         #[cfg(feature = "plotting")]
         crate::plot::render_to_file(execution_order.last().unwrap(), "alloc.svg").unwrap();
 
-        let executable = Executable::new(
+        //Handles are only taken once every inplace decision is made, they count as consumers
+        let compute_dsts = compute_dsts.into_iter().cloned().collect();
+        Ok(Executable::new(
             compiled_ops,
             uniform.into_gpu(gpu_device)?,
-            #[cfg(feature = "debug")]
             compute_dsts,
-        );
-
-        #[cfg(feature = "debug")]
-        let index = if debug {
-            if cfg!(feature = "debug") {
-                executable.dispatch_debugging(device).unwrap()
-            } else {
-                panic!("Debugging is only available in debug builds. Call `resolve()` instead of `resolve_debug()`.")
-            }
-        } else {
-            executable.dispatch(device).unwrap()
-        };
-        #[cfg(not(feature = "debug"))]
-        let index = executable.dispatch(gpu_device).unwrap();
-        gpu_device.poll(wgpu::MaintainBase::WaitForSubmissionIndex(index));
-
-        Ok(self)
+            self.clone(),
+            gpu_device.clone(),
+        ))
     }
 
+    /// Capture the graph rooted at this tensor into an [Executable].
+    ///
+    /// The executable keeps the buffers, bind groups and pipelines of the graph, so it can be
+    /// run again with new inputs without rebuilding, allocating or compiling anything. Only
+    /// graphs on the GPU can be captured.
+    ///
+    /// ```ignore
+    /// let x = Tensor::zeros::<f32>(&shape![1, 80, 3000], &device);
+    /// let exe = encoder.schedule(x.clone())?.compile()?;
+    /// let features = exe.run(&[(&x, &mel)])?.to(&Device::CPU)?;
+    /// ```
+    pub fn compile(self) -> Result<Executable, TensorError> {
+        let graph = self.optimize()?;
+        let device = graph.device().try_gpu()?.clone();
+        graph.lower_gpu(&device, &FxHashMap::default(), false)
+    }
+
+    /// Resolves the tensor computations.
+    ///
+    /// The graph is first optimised by the default [PassManager] pipeline, set
+    /// `RATCHET_DISABLE_PASSES` to run it as built.
     pub fn resolve(self) -> Result<Tensor, TensorError> {
         self.resolve_inner(false)
     }
@@ -871,6 +1205,69 @@ impl Tensor {
         self.resolve_inner(true)
     }
 
+    /// Dispatches the tensor computations without waiting for them.
+    ///
+    /// The returned tensor is detached from the graph, and its storage is only written once the
+    /// GPU reaches it. It can be used as an input of later graphs, which the queue runs in order,
+    /// or copied to the host with [Tensor::to_cpu_async]. On the CPU, this resolves the tensor.
+    pub fn submit(self) -> Result<Tensor, TensorError> {
+        let tensor = self.optimize()?;
+        match tensor.device().clone() {
+            Device::CPU => Ok(tensor.resolve_cpu()?.as_const()),
+            Device::GPU(gpu_device) => {
+                tensor.dispatch_gpu(&gpu_device, false)?;
+                Ok(tensor.as_const())
+            }
+        }
+    }
+
+    /// Resolves the tensor computations without blocking the calling thread.
+    ///
+    /// The returned future completes once the GPU has executed the graph. Host work, e.g
+    /// tokenizing the next request, can run in the meantime, and several sessions can share a
+    /// device. On native, the device is polled by a background thread.
+    pub async fn resolve_async(self) -> Result<Tensor, TensorError> {
+        let tensor = self.optimize()?;
+        let gpu_device = match tensor.device().clone() {
+            Device::CPU => return tensor.resolve_cpu(),
+            Device::GPU(gpu_device) => gpu_device,
+        };
+        tensor.dispatch_gpu(&gpu_device, false)?;
+        gpu_device.work_done().await;
+        Ok(tensor)
+    }
+
+    /// Copies a resolved GPU tensor to the CPU without blocking the calling thread.
+    ///
+    /// Equivalent to `to(&Device::CPU)`. The copy is submitted when this is called, so work
+    /// submitted afterwards, e.g the next decoding step, doesn't delay the readback.
+    pub fn to_cpu_async(&self) -> impl Future<Output = Result<Tensor, TensorError>> {
+        let tensor = self.clone();
+        let download = (tensor.device().is_gpu() && tensor.resolved())
+            .then(|| -> Result<_, TensorError> {
+                let storage = tensor.storage();
+                let gpu_buf = storage
+                    .as_ref()
+                    .ok_or(TensorError::TransferError)?
+                    .try_gpu()?;
+                Ok(gpu_buf.download(tensor.device().try_gpu()?)?)
+            })
+            .transpose();
+
+        async move {
+            let Some(download) = download? else {
+                log::error!("Tensor may not have been resolved, try calling `resolve()` first.");
+                return Ok(tensor);
+            };
+            Ok(Tensor::new(
+                LazyOp::Const,
+                tensor.view.clone(),
+                Some(Storage::CPU(download.await?)),
+                Device::CPU,
+            ))
+        }
+    }
+
     fn to_gpu(&self, dst_device: &Device) -> Result<Tensor, TensorError> {
         if self.device().is_gpu() || !self.resolved() {
             return Ok(self.clone());
@@ -1110,7 +1507,7 @@ impl Tensor {
         if self.num_bytes() != 0 {
             let storage_guard = self.storage();
             let buffer = storage_guard.as_ref().unwrap().try_cpu().unwrap();
-            let (ptr, _) = buffer.inner().into_raw_parts();
+            let ptr = buffer.inner().as_bytes().as_ptr();
             unsafe { ArrayViewD::from_shape_ptr(shape, ptr as *const T) }
         } else {
             ArrayViewD::from_shape(shape, &[]).unwrap()
@@ -1196,7 +1593,7 @@ impl<T: TensorDType> From<ArrayD<T>> for Tensor {
 
 #[cfg(test)]
 mod tests {
-    use crate::{rvec, shape, Device, Tensor};
+    use crate::{rvec, shape, DType, Device, Tensor, TensorError};
 
     #[test]
     fn has_nan_works() {
@@ -1213,4 +1610,48 @@ mod tests {
         println!("RESULT: {:?}", result);
         assert!(result.has_nan::<f32>());
     }
+
+    #[test]
+    fn resolves_asynchronously() -> anyhow::Result<()> {
+        let device = Device::request_device(crate::DeviceRequest::GPU)?;
+        let a = Tensor::randn::<f32>(shape![64, 128], Device::CPU);
+        let b = Tensor::randn::<f32>(shape![128, 32], Device::CPU);
+        let ground = a.clone().matmul(b.clone(), false, false)?.resolve()?;
+
+        let (a, b) = (a.to(&device)?, b.to(&device)?);
+        let result = pollster::block_on(async {
+            let c = a.matmul(b, false, false)?.resolve_async().await?;
+            Ok::<_, anyhow::Error>(c.to_cpu_async().await?)
+        })?;
+        ground.all_close(&result, 1e-4, 1e-4)?;
+        Ok(())
+    }
+
+    #[test]
+    fn loads_deferred_tensors_on_resolve() -> anyhow::Result<()> {
+        let data = vec![1f32, 2., 3., 4.];
+        let loaded = data.clone();
+        let deferred = Tensor::deferred(DType::F32, shape![2, 2], Device::CPU, move || {
+            Ok(Tensor::from_data(loaded, shape![2, 2], Device::CPU))
+        });
+        assert!(deferred.storage().is_none());
+        assert!(deferred.is_deferred());
+
+        let result = deferred.clone().mul(deferred.clone())?.resolve()?;
+        assert!(!deferred.is_deferred());
+        let squares = data.iter().map(|x| x * x).collect::<Vec<_>>();
+        assert_eq!(result.to_vec::<f32>()?, squares);
+        Ok(())
+    }
+
+    #[test]
+    fn propagates_deferred_load_errors() {
+        let deferred = Tensor::deferred(DType::F32, shape![2, 2], Device::CPU, || {
+            anyhow::bail!("missing shard")
+        });
+        let err = deferred.clone().exp().unwrap().resolve().unwrap_err();
+        assert!(matches!(err, TensorError::DeferredLoadError(id, _) if id == deferred.id()));
+        //Nothing panics when the failed tensor is inspected
+        assert!(deferred.storage().is_none());
+    }
 }
@@ -0,0 +1,491 @@
+#![cfg(feature = "profiling")]
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use serde_json::json;
+use tabled::{Table, Tabled};
+use web_time::Instant;
+
+use crate::table::{float2, runtime_table};
+use crate::{FusedStep, LazyOp, NormOp, Tensor, TensorId};
+
+/// What a [TraceEvent] measured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TraceCategory {
+    /// Ordering the graph and loading its weights.
+    Build,
+    /// Running the optimization passes.
+    Optimize,
+    /// Planning and allocating GPU buffers.
+    Alloc,
+    /// Compiling an operation to a GPU kernel.
+    Compile,
+    /// Submitting compiled kernels to the GPU.
+    Dispatch,
+    /// Executing an operation on the CPU.
+    Op,
+}
+
+impl TraceCategory {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TraceCategory::Build => "build",
+            TraceCategory::Optimize => "optimize",
+            TraceCategory::Alloc => "alloc",
+            TraceCategory::Compile => "compile",
+            TraceCategory::Dispatch => "dispatch",
+            TraceCategory::Op => "op",
+        }
+    }
+}
+
+/// A timed region of work, recorded by the [Tracer].
+#[derive(Debug, Clone)]
+pub struct TraceEvent {
+    pub name: String,
+    pub category: TraceCategory,
+    /// The tensor computed or compiled, if any.
+    pub node: Option<TensorId>,
+    /// Time since the [Tracer] was started.
+    pub start: Duration,
+    pub duration: Duration,
+    /// Estimated floating point operations, see [estimate_flops].
+    pub flops: u64,
+    /// Bytes read from the sources and written to the destination.
+    pub bytes: u64,
+    pub thread: u64,
+}
+
+/// # Tracer
+///
+/// Records where time goes when tensors are resolved, on any device:
+/// - On the CPU, every operation is timed, with its FLOPs and bytes moved.
+/// - On the GPU, graph building, allocation, compilation and dispatch are timed. Kernel
+///   execution times are measured by the `gpu-profiling` feature instead.
+///
+/// Recording is global and off until [Tracer::start]. Setting `RATCHET_TRACE` starts it at
+/// startup. At most [Tracer::MAX_EVENTS] are kept, later events are only counted.
+#[derive(Debug)]
+pub struct Tracer {
+    recording: AtomicBool,
+    epoch: Mutex<Instant>,
+    events: Mutex<Vec<TraceEvent>>,
+    dropped: AtomicUsize,
+}
+
+impl Tracer {
+    pub const MAX_EVENTS: usize = 1 << 20;
+
+    pub fn global() -> &'static Tracer {
+        static TRACER: OnceLock<Tracer> = OnceLock::new();
+        TRACER.get_or_init(|| Tracer {
+            recording: AtomicBool::new(std::env::var_os("RATCHET_TRACE").is_some()),
+            epoch: Mutex::new(Instant::now()),
+            events: Mutex::new(Vec::new()),
+            dropped: AtomicUsize::new(0),
+        })
+    }
+
+    /// Start recording, discarding any event recorded before.
+    pub fn start(&self) {
+        *self.epoch.lock() = Instant::now();
+        self.events.lock().clear();
+        self.dropped.store(0, Ordering::Relaxed);
+        self.recording.store(true, Ordering::Release);
+    }
+
+    /// Stop recording, and return the events recorded since [Tracer::start].
+    pub fn stop(&self) -> Trace {
+        self.recording.store(false, Ordering::Release);
+        let events = std::mem::take(&mut *self.events.lock());
+        let dropped = self.dropped.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            log::warn!(
+                "Trace is limited to {} events, {} were dropped",
+                Self::MAX_EVENTS,
+                dropped
+            );
+        }
+        Trace::new(events, dropped)
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.load(Ordering::Acquire)
+    }
+
+    /// Time a region until the returned span is dropped. `None` when not recording.
+    pub(crate) fn span(&'static self, name: &str, category: TraceCategory) -> Option<Span> {
+        if !self.is_recording() {
+            return None;
+        }
+        Some(Span {
+            tracer: self,
+            name: name.to_string(),
+            category,
+            node: None,
+            flops: 0,
+            bytes: 0,
+            start: Instant::now(),
+        })
+    }
+
+    /// Time work on the operation computing `dst`.
+    ///
+    /// Only executions carry the FLOPs and bytes moved, so compiling doesn't count as throughput.
+    pub(crate) fn op_span(&'static self, dst: &Tensor, category: TraceCategory) -> Option<Span> {
+        let op = dst.op();
+        let mut span = self.span(op.name(), category)?;
+        span.node = Some(dst.id());
+        if category == TraceCategory::Op {
+            span.flops = estimate_flops(op, dst);
+            span.bytes = bytes_moved(op, dst);
+        }
+        Some(span)
+    }
+
+    fn record(&self, span: &Span) {
+        let end = Instant::now();
+        let epoch = *self.epoch.lock();
+        let event = TraceEvent {
+            name: span.name.clone(),
+            category: span.category,
+            node: span.node,
+            start: span.start.saturating_duration_since(epoch),
+            duration: end.saturating_duration_since(span.start),
+            flops: span.flops,
+            bytes: span.bytes,
+            thread: thread_index(),
+        };
+        if !self.is_recording() {
+            return;
+        }
+        let mut events = self.events.lock();
+        if events.len() < Self::MAX_EVENTS {
+            events.push(event);
+        } else {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// A region being timed, recorded when dropped.
+#[derive(Debug)]
+pub(crate) struct Span {
+    tracer: &'static Tracer,
+    name: String,
+    category: TraceCategory,
+    node: Option<TensorId>,
+    flops: u64,
+    bytes: u64,
+    start: Instant,
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        self.tracer.record(self);
+    }
+}
+
+/// Small, stable ids for the threads recording events.
+pub(crate) fn thread_index() -> u64 {
+    static NEXT: AtomicU64 = AtomicU64::new(1);
+    thread_local! {
+        static INDEX: u64 = NEXT.fetch_add(1, Ordering::Relaxed);
+    }
+    INDEX.with(|i| *i)
+}
+
+/// Estimated floating point operations to compute `dst` with `op`.
+///
+/// Multiply-adds count as 2. Data movement is free, and the normalizations, softmax and RoPE
+/// are counted by their arithmetic per element.
+pub fn estimate_flops(op: &LazyOp, dst: &Tensor) -> u64 {
+    let numel = dst.shape().numel() as u64;
+    match op {
+        LazyOp::Matmul(m) => {
+            let lhs = m.lhs.shape();
+            let rank = lhs.rank();
+            let k = if rank == 1 {
+                lhs[0]
+            } else if m.trans_lhs {
+                lhs[rank - 2]
+            } else {
+                lhs[rank - 1]
+            };
+            2 * numel * k as u64
+        }
+        LazyOp::Conv(c) => {
+            let [_, c_in, kernel_size]: [usize; 3] =
+                c.weight.shape().try_into().unwrap_or_default();
+            2 * numel * (c_in * kernel_size) as u64
+        }
+        LazyOp::Attention(a) => {
+            //QK^T and PV, the softmax is left out
+            let (q, k) = (a.query.shape(), a.key.shape());
+            let s = k[k.rank() - 2] as u64;
+            let d = q[q.rank() - 1] as u64;
+            let rows = (q.numel() / q[q.rank() - 1]) as u64;
+            4 * rows * s * d
+        }
+        LazyOp::Binary(_) | LazyOp::Unary(_) | LazyOp::Cast(_) => numel,
+        LazyOp::ArgMax(a) => a.input.shape().numel() as u64,
+        LazyOp::Softmax(_) => 4 * numel,
+        LazyOp::Norm(NormOp::RMSNorm(_)) => 4 * numel,
+        LazyOp::Norm(_) => 7 * numel,
+        LazyOp::RoPE(_) => 3 * numel,
+        LazyOp::Fused(f) => {
+            let steps = f
+                .steps()
+                .iter()
+                .filter(|s| !matches!(s, FusedStep::Input(_)))
+                .count();
+            steps as u64 * numel
+        }
+        LazyOp::Const
+        | LazyOp::Reindex(_)
+        | LazyOp::Concat(_)
+        | LazyOp::View(_)
+        | LazyOp::Select(_)
+        | LazyOp::IndexWrite(_)
+        | LazyOp::Cache(_) => 0,
+    }
+}
+
+/// Bytes read from the sources of `op` plus the bytes of `dst`. Views and constants move none.
+fn bytes_moved(op: &LazyOp, dst: &Tensor) -> u64 {
+    match op {
+        LazyOp::Const | LazyOp::View(_) => 0,
+        _ => {
+            let srcs = op.srcs().iter().map(|s| s.num_bytes()).sum::<usize>();
+            (srcs + dst.num_bytes()) as u64
+        }
+    }
+}
+
+#[derive(Tabled)]
+struct TraceTableEntry {
+    #[tabled(rename = "Category")]
+    category: &'static str,
+    #[tabled(rename = "Name")]
+    name: String,
+    #[tabled(rename = "Elapsed Time (μs)")]
+    elapsed: u128,
+    #[tabled(rename = "Count")]
+    count: usize,
+    #[tabled(rename = "Avg. Time (μs)")]
+    avg_elapsed: u128,
+    #[tabled(rename = "% of Runtime", display_with = "float2")]
+    percent_runtime: f64,
+    #[tabled(rename = "GFLOP/s", display_with = "float2")]
+    gflops: f64,
+    #[tabled(rename = "GB/s", display_with = "float2")]
+    bandwidth: f64,
+}
+
+/// Events recorded between [Tracer::start] and [Tracer::stop].
+#[derive(Debug, Clone, derive_new::new)]
+pub struct Trace {
+    events: Vec<TraceEvent>,
+    /// Events not kept, beyond [Tracer::MAX_EVENTS].
+    dropped: usize,
+}
+
+impl Trace {
+    pub fn events(&self) -> &[TraceEvent] {
+        &self.events
+    }
+
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    /// The events in the Chrome trace event format, for `chrome://tracing` or Perfetto.
+    pub fn chrome_json(&self) -> String {
+        let events = self
+            .events
+            .iter()
+            .map(|e| {
+                json!({
+                    "name": e.name,
+                    "cat": e.category.as_str(),
+                    "ph": "X",
+                    "ts": e.start.as_secs_f64() * 1e6,
+                    "dur": e.duration.as_secs_f64() * 1e6,
+                    "pid": 0,
+                    "tid": e.thread,
+                    "args": {
+                        "node": e.node.map(|id| format!("{:?}", id)),
+                        "flops": e.flops,
+                        "bytes": e.bytes,
+                    },
+                })
+            })
+            .collect::<Vec<_>>();
+        json!({ "traceEvents": events, "displayTimeUnit": "ms" }).to_string()
+    }
+
+    /// Write [Trace::chrome_json] to `path`.
+    pub fn write_chrome<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        std::fs::write(path, self.chrome_json())
+    }
+
+    /// Time, throughput and share of the runtime of every kind of event, slowest first.
+    pub fn summary(&self) -> Table {
+        let mut totals: HashMap<(TraceCategory, &str), (Duration, usize, u64, u64)> =
+            HashMap::new();
+        for e in self.events.iter() {
+            let entry = totals.entry((e.category, &e.name)).or_default();
+            entry.0 += e.duration;
+            entry.1 += 1;
+            entry.2 += e.flops;
+            entry.3 += e.bytes;
+        }
+        let total = totals.values().map(|t| t.0).sum::<Duration>();
+
+        let mut entries = totals
+            .into_iter()
+            .map(|((category, name), (elapsed, count, flops, bytes))| {
+                let secs = elapsed.as_secs_f64().max(f64::MIN_POSITIVE);
+                TraceTableEntry {
+                    category: category.as_str(),
+                    name: name.to_string(),
+                    elapsed: elapsed.as_micros(),
+                    count,
+                    avg_elapsed: elapsed.as_micros() / count as u128,
+                    percent_runtime: elapsed.as_secs_f64() / total.as_secs_f64().max(f64::EPSILON)
+                        * 100.0,
+                    gflops: flops as f64 / secs / 1e9,
+                    bandwidth: bytes as f64 / secs / 1e9,
+                }
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by(|a, b| b.elapsed.cmp(&a.elapsed));
+
+        runtime_table(&entries, total.as_micros())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{estimate_flops, thread_index, Trace, TraceCategory, TraceEvent, Tracer};
+    use crate::{
+        shape, AttentionMask, Device, DeviceRequest, FusedElementwise, FusedStep, LazyOp, Tensor,
+    };
+
+    //Tests run in parallel, keep only the events of this one
+    fn own_events(trace: &Trace) -> Vec<&TraceEvent> {
+        let thread = thread_index();
+        trace
+            .events()
+            .iter()
+            .filter(|e| e.thread == thread)
+            .collect()
+    }
+
+    #[test]
+    fn traces_cpu_ops() -> anyhow::Result<()> {
+        let (M, N, K) = (16, 32, 8);
+        let a = Tensor::randn::<f32>(shape![M, K], Device::CPU);
+        let b = Tensor::randn::<f32>(shape![K, N], Device::CPU);
+
+        let tracer = Tracer::global();
+        tracer.start();
+        a.matmul(b, false, false)?.relu()?.resolve()?;
+        let trace = tracer.stop();
+
+        let events = own_events(&trace);
+        let ops = events
+            .iter()
+            .filter(|e| e.category == TraceCategory::Op)
+            .collect::<Vec<_>>();
+        assert!(events.iter().any(|e| e.category == TraceCategory::Build));
+
+        let matmul = ops.iter().find(|e| e.name == "Matmul").unwrap();
+        assert_eq!(matmul.flops, 2 * (M * N * K) as u64);
+        assert_eq!(matmul.bytes, 4 * (M * K + K * N + M * N) as u64);
+        assert!(matmul.node.is_some());
+
+        let json: serde_json::Value = serde_json::from_str(&trace.chrome_json())?;
+        let traced = json["traceEvents"].as_array().unwrap();
+        assert_eq!(traced.len(), trace.events().len());
+        assert!(traced.iter().all(|e| e["ph"] == "X"));
+
+        let summary = trace.summary().to_string();
+        assert!(summary.contains("Matmul"));
+        Ok(())
+    }
+
+    #[test]
+    fn traces_gpu_resolution() -> anyhow::Result<()> {
+        let device = Device::request_device(DeviceRequest::GPU)?;
+        let a = Tensor::randn::<f32>(shape![16, 8], Device::CPU).to(&device)?;
+        let b = Tensor::randn::<f32>(shape![8, 32], Device::CPU).to(&device)?;
+
+        let tracer = Tracer::global();
+        tracer.start();
+        a.matmul(b, false, false)?.relu()?.resolve()?;
+        let trace = tracer.stop();
+
+        let events = own_events(&trace);
+        let has = |category| events.iter().any(|e| e.category == category);
+        assert!(has(TraceCategory::Build));
+        assert!(has(TraceCategory::Alloc));
+        assert!(has(TraceCategory::Dispatch));
+        assert!(!has(TraceCategory::Op));
+
+        //Compiling a kernel isn't throughput
+        let compiled = events
+            .iter()
+            .filter(|e| e.category == TraceCategory::Compile)
+            .collect::<Vec<_>>();
+        assert!(compiled.iter().any(|e| e.name == "Matmul"));
+        assert!(compiled
+            .iter()
+            .all(|e| e.node.is_some() && e.flops == 0 && e.bytes == 0));
+        assert_eq!(trace.dropped(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn estimates_conv_attention_and_fused_flops() -> anyhow::Result<()> {
+        let flops = |t: &Tensor| estimate_flops(t.op(), t);
+
+        //2 * [1, 4, 8] outputs * 2 input channels * kernel of 3
+        let x = Tensor::randn::<f32>(shape![1, 2, 8], Device::CPU);
+        let w = Tensor::randn::<f32>(shape![4, 2, 3], Device::CPU);
+        let bias = Tensor::randn::<f32>(shape![4], Device::CPU);
+        let conv = x.conv1d(w, Some(bias), 1, 1)?;
+        assert_eq!(conv.shape(), &shape![1, 4, 8]);
+        assert_eq!(flops(&conv), 2 * 32 * 6);
+
+        //QK^T and PV over 8 query rows, 4 keys and a head dim of 8
+        let qkv = || Tensor::randn::<f32>(shape![1, 2, 4, 8], Device::CPU);
+        let attention = qkv().attention(qkv(), qkv(), AttentionMask::Causal, 1.)?;
+        assert_eq!(flops(&attention), 4 * 8 * 4 * 8);
+
+        //Inputs are loads, the 3 other steps cost a FLOP per element
+        let input = || Tensor::randn::<f32>(shape![2, 8], Device::CPU);
+        let ab = input().mul(input())?;
+        let abc = ab.clone().add(input())?;
+        let root = abc.clone().silu()?;
+        let program = |t: &Tensor| FusedElementwise::from_op(t.op()).unwrap();
+        let fused = program(&root)
+            .inline(abc.id(), &program(&abc))
+            .inline(ab.id(), &program(&ab));
+        assert_eq!(fused.inputs().len(), 3);
+        assert_eq!(
+            fused
+                .steps()
+                .iter()
+                .filter(|s| !matches!(s, FusedStep::Input(_)))
+                .count(),
+            3
+        );
+        assert_eq!(flops(&root.with_op(LazyOp::Fused(fused))), 3 * 16);
+        Ok(())
+    }
+}
@@ -0,0 +1,601 @@
+//! # Download
+//!
+//! Resumable, checksummed downloads over HTTP range requests.
+//!
+//! Files are fetched in fixed size chunks and handed to a [ChunkSink] in order, so a multi-GB
+//! model never has to be held in memory. The [DownloadState] is persisted by the sink after every
+//! chunk, an interrupted download resumes from the last stored chunk. Range requests carry an
+//! `If-Range` header, if the remote file changes between sessions the download restarts.
+use std::ops::Range;
+
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum DownloadError {
+    #[error("request failed with status {0}")]
+    Http(u16),
+    #[error("network error: {0}")]
+    Network(String),
+    #[error("invalid range response: {0}")]
+    InvalidRange(String),
+    #[error("remote file changed during download")]
+    Changed,
+    #[error("checksum mismatch: expected {expected}, got {actual}")]
+    ChecksumMismatch { expected: String, actual: String },
+    #[error("failed to store chunk: {0}")]
+    Sink(String),
+}
+
+/// Size & validator of a remote file, see [RangeFetcher::head].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteFile {
+    pub size: u64,
+    pub etag: Option<String>,
+}
+
+impl RemoteFile {
+    /// The Hub serves LFS files with their SHA-256 as the ETag.
+    pub fn sha256(&self) -> Option<String> {
+        let etag = self.etag.as_deref()?;
+        let etag = etag.trim_start_matches("W/").trim_matches('"');
+        (etag.len() == 64 && etag.bytes().all(|b| b.is_ascii_hexdigit()))
+            .then(|| etag.to_lowercase())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DownloadState {
+    pub size: u64,
+    pub etag: Option<String>,
+    pub chunk_size: u64,
+    /// Number of leading chunks already handed to the sink.
+    pub completed: u64,
+    /// Set once every chunk has been stored & the checksum verified.
+    pub verified: bool,
+}
+
+impl DownloadState {
+    pub fn new(remote: &RemoteFile, chunk_size: u64) -> Self {
+        Self {
+            size: remote.size,
+            etag: remote.etag.clone(),
+            chunk_size,
+            completed: 0,
+            verified: false,
+        }
+    }
+
+    pub fn n_chunks(&self) -> u64 {
+        self.size.div_ceil(self.chunk_size)
+    }
+
+    /// Byte range of chunk `index` within the file.
+    pub fn chunk_range(&self, index: u64) -> Range<u64> {
+        let start = index * self.chunk_size;
+        start..(start + self.chunk_size).min(self.size)
+    }
+
+    pub fn downloaded_bytes(&self) -> u64 {
+        (self.completed * self.chunk_size).min(self.size)
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.completed == self.n_chunks()
+    }
+
+    /// Whether the stored chunks belong to a different version of `remote`.
+    pub fn is_stale(&self, remote: &RemoteFile) -> bool {
+        self.size != remote.size || self.etag != remote.etag
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DownloadOptions {
+    pub chunk_size: u64,
+    /// Number of chunks requested concurrently, chunks are still stored in order.
+    pub concurrency: usize,
+    /// Attempts per chunk before giving up, the download can be resumed later.
+    pub max_retries: usize,
+    /// Expected SHA-256 of the file, takes precedence over the ETag.
+    pub sha256: Option<String>,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        Self {
+            chunk_size: 16 << 20,
+            concurrency: 4,
+            max_retries: 5,
+            sha256: None,
+        }
+    }
+}
+
+/// Transport used to fetch byte ranges of a single remote file.
+#[allow(async_fn_in_trait)]
+pub trait RangeFetcher {
+    async fn head(&self) -> Result<RemoteFile, DownloadError>;
+
+    /// Fetch `range` of the file. If `if_range` is provided & no longer matches the remote,
+    /// [DownloadError::Changed] is returned.
+    async fn fetch(
+        &self,
+        range: Range<u64>,
+        if_range: Option<&str>,
+    ) -> Result<Vec<u8>, DownloadError>;
+}
+
+/// Persistent storage for downloaded chunks, e.g IndexedDB.
+#[allow(async_fn_in_trait)]
+pub trait ChunkSink {
+    /// Store chunk `index` along with the updated `state`.
+    async fn write(
+        &mut self,
+        index: u64,
+        bytes: Vec<u8>,
+        state: &DownloadState,
+    ) -> Result<(), DownloadError>;
+
+    /// Read back a stored chunk, used to checksum previously stored chunks when resuming.
+    async fn read(&self, index: u64) -> Result<Vec<u8>, DownloadError>;
+
+    /// Discard every stored chunk.
+    async fn clear(&mut self) -> Result<(), DownloadError>;
+
+    /// Persist `state` without writing a chunk.
+    async fn save_state(&mut self, state: &DownloadState) -> Result<(), DownloadError>;
+}
+
+/// Parses a `Content-Range: bytes start-end/total` header, `total` may be `*`.
+pub fn parse_content_range(value: &str) -> Option<(u64, u64, Option<u64>)> {
+    let (range, total) = value.trim().strip_prefix("bytes ")?.split_once('/')?;
+    let (start, end) = range.split_once('-')?;
+    let total = match total {
+        "*" => None,
+        t => Some(t.parse().ok()?),
+    };
+    Some((start.parse().ok()?, end.parse().ok()?, total))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Download the file behind `fetcher` into `sink`, resuming from `state` if provided.
+///
+/// `progress` is called with the number of bytes stored so far & the file size.
+pub async fn download<F, S>(
+    fetcher: &F,
+    sink: &mut S,
+    state: Option<DownloadState>,
+    options: &DownloadOptions,
+    mut progress: impl FnMut(u64, u64),
+) -> Result<DownloadState, DownloadError>
+where
+    F: RangeFetcher,
+    S: ChunkSink,
+{
+    let remote = fetcher.head().await?;
+    let mut state = match state {
+        Some(state) if !state.is_stale(&remote) => state,
+        Some(_) => {
+            log::warn!("Remote file changed, restarting download");
+            sink.clear().await?;
+            DownloadState::new(&remote, options.chunk_size)
+        }
+        None => DownloadState::new(&remote, options.chunk_size),
+    };
+    if state.verified {
+        return Ok(state);
+    }
+
+    let expected = options.sha256.clone().or_else(|| remote.sha256());
+    let mut hasher = expected.as_ref().map(|_| Sha256::new());
+    if let Some(hasher) = hasher.as_mut() {
+        for index in 0..state.completed {
+            hasher.update(sink.read(index).await?);
+        }
+    }
+    progress(state.downloaded_bytes(), state.size);
+
+    let concurrency = options.concurrency.max(1) as u64;
+    while !state.is_complete() {
+        let batch = state.completed..(state.completed + concurrency).min(state.n_chunks());
+        let chunks = join_all(batch.map(|index| {
+            let range = state.chunk_range(index);
+            let etag = state.etag.clone();
+            async move { fetch_with_retry(fetcher, range, etag.as_deref(), options).await }
+        }))
+        .await;
+
+        for chunk in chunks {
+            let bytes = match chunk {
+                Ok(bytes) => bytes,
+                Err(DownloadError::Changed) => {
+                    sink.clear().await?;
+                    return Err(DownloadError::Changed);
+                }
+                Err(e) => return Err(e),
+            };
+            if let Some(hasher) = hasher.as_mut() {
+                hasher.update(&bytes);
+            }
+            let index = state.completed;
+            state.completed += 1;
+            sink.write(index, bytes, &state).await?;
+            progress(state.downloaded_bytes(), state.size);
+        }
+    }
+
+    if let (Some(expected), Some(hasher)) = (expected, hasher) {
+        let actual = hex(&hasher.finalize());
+        if actual != expected {
+            sink.clear().await?;
+            return Err(DownloadError::ChecksumMismatch { expected, actual });
+        }
+    }
+    state.verified = true;
+    sink.save_state(&state).await?;
+    Ok(state)
+}
+
+async fn fetch_with_retry<F: RangeFetcher>(
+    fetcher: &F,
+    range: Range<u64>,
+    etag: Option<&str>,
+    options: &DownloadOptions,
+) -> Result<Vec<u8>, DownloadError> {
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let result = fetcher.fetch(range.clone(), etag).await.and_then(|bytes| {
+            if bytes.len() as u64 != range.end - range.start {
+                return Err(DownloadError::InvalidRange(format!(
+                    "expected {} bytes for {:?}, got {}",
+                    range.end - range.start,
+                    range,
+                    bytes.len()
+                )));
+            }
+            Ok(bytes)
+        });
+        match result {
+            Ok(bytes) => return Ok(bytes),
+            Err(e @ (DownloadError::Changed | DownloadError::Http(400..=499))) => return Err(e),
+            Err(e) if attempt >= options.max_retries => return Err(e),
+            Err(e) => log::warn!("Fetching {:?} failed (attempt {}): {}", range, attempt, e),
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    /// A local stand-in for the Hub, serving a single file with range support.
+    ///
+    /// The first `flaky` responses are cut off halfway through the body.
+    struct StandIn {
+        addr: String,
+        body: Arc<Mutex<Vec<u8>>>,
+        etag: Arc<Mutex<String>>,
+        flaky: Arc<AtomicUsize>,
+    }
+
+    impl StandIn {
+        fn serve(body: Vec<u8>, etag: &str, flaky: usize) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap().to_string();
+            let body = Arc::new(Mutex::new(body));
+            let etag = Arc::new(Mutex::new(etag.to_string()));
+            let flaky = Arc::new(AtomicUsize::new(flaky));
+            let (b, e, f) = (body.clone(), etag.clone(), flaky.clone());
+            std::thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let _ = Self::respond(stream.unwrap(), &b, &e, &f);
+                }
+            });
+            Self {
+                addr,
+                body,
+                etag,
+                flaky,
+            }
+        }
+
+        fn respond(
+            mut stream: TcpStream,
+            body: &Mutex<Vec<u8>>,
+            etag: &Mutex<String>,
+            flaky: &AtomicUsize,
+        ) -> std::io::Result<()> {
+            let mut headers = vec![];
+            let mut reader = BufReader::new(stream.try_clone()?);
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line)?;
+                if line.trim().is_empty() {
+                    break;
+                }
+                headers.push(line.trim().to_lowercase());
+            }
+            let header = |name: &str| {
+                headers
+                    .iter()
+                    .find_map(|h| h.strip_prefix(&format!("{name}: ")).map(str::to_string))
+            };
+            let body = body.lock().unwrap().clone();
+            let etag = etag.lock().unwrap().clone();
+            let (start, end) = header("range")
+                .and_then(|r| {
+                    let (s, e) = r.strip_prefix("bytes=")?.split_once('-')?;
+                    Some((s.parse::<usize>().ok()?, e.parse::<usize>().ok()?))
+                })
+                .unwrap();
+            if header("if-range").is_some_and(|v| v != etag) {
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nETag: {etag}\r\n\r\n",
+                    body.len()
+                )?;
+                return stream.write_all(&body);
+            }
+            let slice = &body[start..=end.min(body.len() - 1)];
+            write!(
+                stream,
+                "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\nContent-Range: bytes {}-{}/{}\r\nETag: {etag}\r\n\r\n",
+                slice.len(),
+                start,
+                start + slice.len() - 1,
+                body.len()
+            )?;
+            let cut = flaky
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                .is_ok();
+            match cut {
+                true => stream.write_all(&slice[..slice.len() / 2]),
+                false => stream.write_all(slice),
+            }
+        }
+    }
+
+    /// Status, lowercased headers & body of a stand-in response.
+    type Response = (u16, Vec<(String, String)>, Vec<u8>);
+
+    /// Minimal blocking HTTP/1.1 client for the stand-in.
+    struct TcpFetcher {
+        addr: String,
+    }
+
+    impl TcpFetcher {
+        fn request(
+            &self,
+            range: Range<u64>,
+            if_range: Option<&str>,
+        ) -> Result<Response, DownloadError> {
+            let net = |e: std::io::Error| DownloadError::Network(e.to_string());
+            let mut stream = TcpStream::connect(&self.addr).map_err(net)?;
+            let if_range = if_range
+                .map(|e| format!("If-Range: {e}\r\n"))
+                .unwrap_or_default();
+            write!(
+                stream,
+                "GET /file HTTP/1.1\r\nHost: {}\r\nRange: bytes={}-{}\r\n{if_range}\r\n",
+                self.addr,
+                range.start,
+                range.end - 1
+            )
+            .map_err(net)?;
+            let mut reader = BufReader::new(stream);
+            let mut status = String::new();
+            reader.read_line(&mut status).map_err(net)?;
+            let status = status.split_whitespace().nth(1).unwrap().parse().unwrap();
+            let mut headers = vec![];
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).map_err(net)?;
+                match line.trim().split_once(": ") {
+                    Some((k, v)) => headers.push((k.to_lowercase(), v.to_string())),
+                    None => break,
+                }
+            }
+            let mut body = vec![];
+            reader.read_to_end(&mut body).map_err(net)?;
+            Ok((status, headers, body))
+        }
+    }
+
+    fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+        headers
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    impl RangeFetcher for TcpFetcher {
+        async fn head(&self) -> Result<RemoteFile, DownloadError> {
+            let (_, headers, _) = self.request(0..1, None)?;
+            let (_, _, total) = parse_content_range(header(&headers, "content-range").unwrap())
+                .ok_or_else(|| DownloadError::InvalidRange("content-range".into()))?;
+            Ok(RemoteFile {
+                size: total.unwrap(),
+                etag: header(&headers, "etag").map(str::to_string),
+            })
+        }
+
+        async fn fetch(
+            &self,
+            range: Range<u64>,
+            if_range: Option<&str>,
+        ) -> Result<Vec<u8>, DownloadError> {
+            let (status, _, body) = self.request(range, if_range)?;
+            match status {
+                206 => Ok(body),
+                200 => Err(DownloadError::Changed),
+                s => Err(DownloadError::Http(s)),
+            }
+        }
+    }
+
+    /// In memory sink that can simulate a crash after `fail_after` writes.
+    #[derive(Default)]
+    struct MemorySink {
+        chunks: Vec<Vec<u8>>,
+        state: Option<DownloadState>,
+        fail_after: Option<usize>,
+    }
+
+    impl ChunkSink for MemorySink {
+        async fn write(
+            &mut self,
+            index: u64,
+            bytes: Vec<u8>,
+            state: &DownloadState,
+        ) -> Result<(), DownloadError> {
+            if self.fail_after == Some(self.chunks.len()) {
+                return Err(DownloadError::Sink("quota exceeded".into()));
+            }
+            assert_eq!(index as usize, self.chunks.len());
+            self.chunks.push(bytes);
+            self.state = Some(state.clone());
+            Ok(())
+        }
+
+        async fn read(&self, index: u64) -> Result<Vec<u8>, DownloadError> {
+            Ok(self.chunks[index as usize].clone())
+        }
+
+        async fn clear(&mut self) -> Result<(), DownloadError> {
+            self.chunks.clear();
+            self.state = None;
+            Ok(())
+        }
+
+        async fn save_state(&mut self, state: &DownloadState) -> Result<(), DownloadError> {
+            self.state = Some(state.clone());
+            Ok(())
+        }
+    }
+
+    fn file(len: usize) -> (Vec<u8>, String) {
+        let body = (0..len).map(|i| (i * 31 % 251) as u8).collect::<Vec<_>>();
+        let sha = hex(&Sha256::digest(&body));
+        (body, format!("\"{sha}\""))
+    }
+
+    fn options() -> DownloadOptions {
+        DownloadOptions {
+            chunk_size: 1000,
+            concurrency: 3,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_content_range() {
+        assert_eq!(
+            parse_content_range("bytes 0-99/1234"),
+            Some((0, 99, Some(1234)))
+        );
+        assert_eq!(parse_content_range("bytes 5-9/*"), Some((5, 9, None)));
+        assert_eq!(parse_content_range("items 0-1/2"), None);
+    }
+
+    #[test]
+    fn test_flaky_download() {
+        let (body, etag) = file(10_500);
+        let server = StandIn::serve(body.clone(), &etag, 3);
+        let fetcher = TcpFetcher { addr: server.addr };
+        let mut sink = MemorySink::default();
+
+        let state =
+            pollster::block_on(download(&fetcher, &mut sink, None, &options(), |_, _| {})).unwrap();
+        assert!(state.verified);
+        assert_eq!(state.n_chunks(), 11);
+        assert_eq!(sink.chunks.concat(), body);
+        assert_eq!(server.flaky.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_resume() {
+        let (body, etag) = file(10_500);
+        let server = StandIn::serve(body.clone(), &etag, 0);
+        let fetcher = TcpFetcher { addr: server.addr };
+        let mut sink = MemorySink {
+            fail_after: Some(4),
+            ..Default::default()
+        };
+
+        let result = pollster::block_on(download(&fetcher, &mut sink, None, &options(), |_, _| {}));
+        assert!(matches!(result, Err(DownloadError::Sink(_))));
+        let state = sink.state.clone().unwrap();
+        assert_eq!(state.completed, 4);
+
+        sink.fail_after = None;
+        let mut resumed_from = None;
+        let state = pollster::block_on(download(
+            &fetcher,
+            &mut sink,
+            Some(state),
+            &options(),
+            |loaded, _| {
+                resumed_from.get_or_insert(loaded);
+            },
+        ))
+        .unwrap();
+        assert_eq!(resumed_from, Some(4000));
+        assert!(state.verified);
+        assert_eq!(sink.chunks.concat(), body);
+    }
+
+    #[test]
+    fn test_remote_changed() {
+        let (body, etag) = file(4_500);
+        let server = StandIn::serve(body, &etag, 0);
+        let fetcher = TcpFetcher {
+            addr: server.addr.clone(),
+        };
+        let mut sink = MemorySink {
+            fail_after: Some(2),
+            ..Default::default()
+        };
+        let _ = pollster::block_on(download(&fetcher, &mut sink, None, &options(), |_, _| {}));
+        let state = sink.state.clone().unwrap();
+
+        let (new_body, new_etag) = file(5_500);
+        *server.body.lock().unwrap() = new_body.clone();
+        *server.etag.lock().unwrap() = new_etag;
+        sink.fail_after = None;
+        let state = pollster::block_on(download(
+            &fetcher,
+            &mut sink,
+            Some(state),
+            &options(),
+            |_, _| {},
+        ))
+        .unwrap();
+        assert_eq!(state.size, 5_500);
+        assert_eq!(sink.chunks.concat(), new_body);
+    }
+
+    #[test]
+    fn test_checksum_mismatch() {
+        let (body, _) = file(2_500);
+        let bad_etag = format!("\"{}\"", "0".repeat(64));
+        let server = StandIn::serve(body, &bad_etag, 0);
+        let fetcher = TcpFetcher { addr: server.addr };
+        let mut sink = MemorySink::default();
+        let result = pollster::block_on(download(&fetcher, &mut sink, None, &options(), |_, _| {}));
+        assert!(matches!(
+            result,
+            Err(DownloadError::ChecksumMismatch { .. })
+        ));
+        assert!(sink.chunks.is_empty());
+    }
+}
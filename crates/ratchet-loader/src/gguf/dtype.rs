@@ -1,7 +1,7 @@
 #![allow(non_camel_case_types)]
 use half::f16;
 use ratchet::{DType, Device, Padding, Shape, Tensor};
-use ratchet::{Q4_KF, Q4_KH, Q8_0F, Q8_0H};
+use ratchet::{Q2_K, Q3_K, Q4_0, Q4_1, Q4_KF, Q4_KH, Q5_0, Q5_1, Q5_K, Q6_K, Q8_0F, Q8_0H};
 
 use crate::k_quants::*;
 
@@ -197,6 +197,47 @@ impl GGUFInterop for Q8_0H {
     }
 }
 
+/// GGML blocks Ratchet stores verbatim, so transcoding is a straight copy of the block bytes.
+macro_rules! ggml_interop {
+    ($ratchet_ty:ident, $block:ty, $numel:expr) => {
+        impl GGUFInterop for $ratchet_ty {
+            type GGUF_TYPE = $block;
+            const BLCK_NUMEL: usize = $numel;
+
+            fn transcode(
+                data: &[Self::GGUF_TYPE],
+                n_blocks: usize,
+                shape: Shape,
+                device: &Device,
+            ) -> anyhow::Result<Tensor> {
+                let n_bytes = n_blocks * Self::TYPE_SIZE;
+                let raw =
+                    unsafe { std::slice::from_raw_parts(data.as_ptr() as *const u8, n_bytes) };
+                let mut bytes = raw.to_vec();
+                let _ = bytes.pad_to_copy();
+                let casted = bytemuck::cast_slice::<u8, u32>(&bytes);
+                unsafe {
+                    Ok(Tensor::from_quantized::<u32, _>(
+                        casted,
+                        DType::$ratchet_ty($ratchet_ty),
+                        shape,
+                        device.clone(),
+                    ))
+                }
+            }
+        }
+    };
+}
+
+ggml_interop!(Q4_0, BlockQ4_0, QK4_0);
+ggml_interop!(Q4_1, BlockQ4_1, QK4_1);
+ggml_interop!(Q5_0, BlockQ5_0, QK5_0);
+ggml_interop!(Q5_1, BlockQ5_1, QK5_1);
+ggml_interop!(Q2_K, BlockQ2K, QK_K);
+ggml_interop!(Q3_K, BlockQ3K, QK_K);
+ggml_interop!(Q5_K, BlockQ5K, QK_K);
+ggml_interop!(Q6_K, BlockQ6K, QK_K);
+
 impl GGUFInterop for f32 {
     type GGUF_TYPE = f32;
     const BLCK_NUMEL: usize = 1;
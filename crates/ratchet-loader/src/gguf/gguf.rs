@@ -3,10 +3,15 @@
 //! Adapted from https://github.com/huggingface/candle/blob/5ebcfeaf0f5af69bb2f74385e8d6b020d4a3b8df/candle-core/src/quantized/gguf_file.rs
 
 use super::dtype::GGUFInterop;
-use crate::{error::Result, GgmlDType};
-
-use byteorder::{LittleEndian, ReadBytesExt};
-use ratchet::{Device, Shape, Tensor, Q4_KF, Q4_KH, Q8_0F, Q8_0H};
+use crate::{error::Result, GgmlDType, LoadError};
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::MappedFile;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use ratchet::{
+    Device, Shape, Tensor, Q2_K, Q3_K, Q4_0, Q4_1, Q4_KF, Q4_KH, Q5_0, Q5_1, Q5_K, Q6_K, Q8_0F,
+    Q8_0H,
+};
 use std::collections::HashMap;
 use std::ops::Range;
 
@@ -74,13 +79,65 @@ impl TensorInfo {
         )
         }
 
-        let tensor_blocks = tensor_elems / block_numel;
-        let size_in_bytes = tensor_blocks * self.ggml_dtype.type_size();
+        let raw_data = self.read_bytes(reader, tensor_data_offset)?;
+        ratchet_from_gguf(self.ggml_dtype, &raw_data, self.shape.clone(), device)
+    }
 
-        let mut raw_data = vec![0u8; size_in_bytes]; //TODO: MaybeUninit
+    /// Create the tensor from a memory mapped file.
+    ///
+    /// Types Ratchet consumes verbatim (F32, F16 & GGML blocks) are borrowed from the map,
+    /// anything else is transcoded directly from the mapped bytes.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn read_mapped(
+        &self,
+        file: &MappedFile,
+        tensor_data_offset: u64,
+        device: &Device,
+    ) -> anyhow::Result<Tensor> {
+        let range = self.byte_range(tensor_data_offset);
+        let shape = self.shape.clone();
+        let f16_supported = match device {
+            Device::CPU => true,
+            Device::GPU(gpu) => gpu.compute_features().SHADER_F16,
+        };
+        let verbatim = match self.ggml_dtype {
+            GgmlDType::F32 => Some((ratchet::DType::F32, 4)),
+            GgmlDType::F16 if f16_supported => Some((ratchet::DType::F16, 2)),
+            GgmlDType::Q4_0
+            | GgmlDType::Q4_1
+            | GgmlDType::Q5_0
+            | GgmlDType::Q5_1
+            | GgmlDType::Q2K
+            | GgmlDType::Q3K
+            | GgmlDType::Q5K
+            | GgmlDType::Q6K => Some((ratchet::DType::from(self.ggml_dtype), 4)),
+            _ => None,
+        };
+        match verbatim {
+            Some((dt, alignment)) => {
+                let block_numel = self.ggml_dtype.block_numel();
+                if self.shape.numel() % block_numel != 0 {
+                    anyhow::bail!(
+                        "the number of elements {} is not divisible by the block size {block_numel}",
+                        self.shape.numel()
+                    )
+                }
+                file.tensor(range, alignment, dt, shape, device)
+            }
+            None => ratchet_from_gguf(self.ggml_dtype, file.slice(range)?, shape, device),
+        }
+    }
+
+    /// Read the raw GGUF encoded bytes of the tensor, without transcoding.
+    pub fn read_bytes<R: std::io::Seek + std::io::Read>(
+        &self,
+        reader: &mut R,
+        tensor_data_offset: u64,
+    ) -> anyhow::Result<Vec<u8>> {
+        let mut raw_data = vec![0u8; self.size_in_bytes()]; //TODO: MaybeUninit
         reader.seek(std::io::SeekFrom::Start(tensor_data_offset + self.offset))?;
         reader.read_exact(&mut raw_data)?;
-        ratchet_from_gguf(self.ggml_dtype, &raw_data, self.shape.clone(), device)
+        Ok(raw_data)
     }
 
     pub fn byte_range(&self, tensor_data_offset: u64) -> Range<u64> {
@@ -151,6 +208,14 @@ pub fn ratchet_from_gguf(
             }
             _ => panic!("Loading from GGUF -> Ratchet using CPU device, no way of knowing if F16 is supported"),
         },
+        GgmlDType::Q4_0 => from_raw_data::<Q4_0>(raw_data, size_in_bytes, shape, device),
+        GgmlDType::Q4_1 => from_raw_data::<Q4_1>(raw_data, size_in_bytes, shape, device),
+        GgmlDType::Q5_0 => from_raw_data::<Q5_0>(raw_data, size_in_bytes, shape, device),
+        GgmlDType::Q5_1 => from_raw_data::<Q5_1>(raw_data, size_in_bytes, shape, device),
+        GgmlDType::Q2K => from_raw_data::<Q2_K>(raw_data, size_in_bytes, shape, device),
+        GgmlDType::Q3K => from_raw_data::<Q3_K>(raw_data, size_in_bytes, shape, device),
+        GgmlDType::Q5K => from_raw_data::<Q5_K>(raw_data, size_in_bytes, shape, device),
+        GgmlDType::Q6K => from_raw_data::<Q6_K>(raw_data, size_in_bytes, shape, device),
         _ => anyhow::bail!("unsupported ggml dtype {ggml_dtype:?}"),
     }
 }
@@ -165,6 +230,16 @@ impl Metadata {
             .get(key)
             .ok_or_else(|| anyhow::anyhow!("missing key {key}"))
     }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Value)> {
+        self.0.iter()
+    }
+}
+
+impl FromIterator<(String, Value)> for Metadata {
+    fn from_iter<I: IntoIterator<Item = (String, Value)>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
 }
 
 #[cfg_attr(target_arch = "wasm32", derive(serde::Serialize, serde::Deserialize))]
@@ -173,9 +248,20 @@ pub struct Header {
     pub magic: VersionedMagic,
     pub metadata: Metadata,
     pub tensor_infos: HashMap<String, TensorInfo>,
+    /// Tensors whose GGML type id we don't know, e.g BF16 or the IQ quantizations.
+    /// They are listed so the file can still be inspected, but cannot be loaded.
+    pub unknown_tensors: HashMap<String, UnknownTensorInfo>,
     pub tensor_data_offset: u64,
 }
 
+#[cfg_attr(target_arch = "wasm32", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone)]
+pub struct UnknownTensorInfo {
+    pub dtype_id: u32,
+    pub shape: Shape,
+    pub offset: u64,
+}
+
 fn read_string<R: std::io::Read>(reader: &mut R, magic: &VersionedMagic) -> Result<String> {
     let len = match magic {
         VersionedMagic::GgufV1 => reader.read_u32::<LittleEndian>()? as usize,
@@ -193,6 +279,12 @@ fn read_string<R: std::io::Read>(reader: &mut R, magic: &VersionedMagic) -> Resu
     Ok(String::from_utf8_lossy(&v).into_owned())
 }
 
+pub(crate) fn write_string<W: std::io::Write>(writer: &mut W, s: &str) -> Result<()> {
+    writer.write_u64::<LittleEndian>(s.len() as u64)?;
+    writer.write_all(s.as_bytes())?;
+    Ok(())
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum ValueType {
     // The value is a 8-bit unsigned integer.
@@ -394,9 +486,58 @@ impl Value {
         };
         Ok(v)
     }
+
+    /// Write the value in the GGUF v3 encoding, excluding the leading value type.
+    pub(crate) fn write<W: std::io::Write>(&self, writer: &mut W) -> Result<()> {
+        match self {
+            Self::U8(v) => writer.write_u8(*v)?,
+            Self::I8(v) => writer.write_i8(*v)?,
+            Self::U16(v) => writer.write_u16::<LittleEndian>(*v)?,
+            Self::I16(v) => writer.write_i16::<LittleEndian>(*v)?,
+            Self::U32(v) => writer.write_u32::<LittleEndian>(*v)?,
+            Self::I32(v) => writer.write_i32::<LittleEndian>(*v)?,
+            Self::U64(v) => writer.write_u64::<LittleEndian>(*v)?,
+            Self::I64(v) => writer.write_i64::<LittleEndian>(*v)?,
+            Self::F32(v) => writer.write_f32::<LittleEndian>(*v)?,
+            Self::F64(v) => writer.write_f64::<LittleEndian>(*v)?,
+            Self::Bool(v) => writer.write_u8(*v as u8)?,
+            Self::String(v) => write_string(writer, v)?,
+            Self::Array(vs) => {
+                //Empty arrays carry no type information, any type will do
+                let value_type = vs.first().map_or(ValueType::U8, |v| v.value_type());
+                if let Some(v) = vs.iter().find(|v| v.value_type() != value_type) {
+                    crate::bail!("heterogeneous array, expected {value_type:?} got {v:?}");
+                }
+                writer.write_u32::<LittleEndian>(value_type.to_u32())?;
+                writer.write_u64::<LittleEndian>(vs.len() as u64)?;
+                for v in vs {
+                    v.write(writer)?;
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 impl ValueType {
+    pub(crate) fn to_u32(self) -> u32 {
+        match self {
+            Self::U8 => 0,
+            Self::I8 => 1,
+            Self::U16 => 2,
+            Self::I16 => 3,
+            Self::U32 => 4,
+            Self::I32 => 5,
+            Self::F32 => 6,
+            Self::Bool => 7,
+            Self::String => 8,
+            Self::Array => 9,
+            Self::U64 => 10,
+            Self::I64 => 11,
+            Self::F64 => 12,
+        }
+    }
+
     fn from_u32(v: u32) -> Result<Self> {
         let v = match v {
             0 => Self::U8,
@@ -418,6 +559,32 @@ impl ValueType {
     }
 }
 
+/// # Tensor Reader
+///
+/// Source of the tensors described by a [Header]. Any `Read + Seek` over the GGUF file is a
+/// reader, as are the preloaded `Weights` of a `WeightLoader` on native targets.
+pub trait TensorReader {
+    fn read_tensor(
+        &mut self,
+        header: &Header,
+        name: &str,
+        device: &Device,
+    ) -> anyhow::Result<Tensor>;
+}
+
+impl<R: std::io::Seek + std::io::Read> TensorReader for R {
+    fn read_tensor(
+        &mut self,
+        header: &Header,
+        name: &str,
+        device: &Device,
+    ) -> anyhow::Result<Tensor> {
+        let tensor_info = header.tensor_info(name)?;
+        log::info!("Loading tensor {tensor_info:#?}");
+        tensor_info.read(self, header.tensor_data_offset, device)
+    }
+}
+
 impl Header {
     pub fn read<R: std::io::Seek + std::io::Read>(reader: &mut R) -> Result<Self> {
         let magic = VersionedMagic::read(reader)?;
@@ -444,6 +611,7 @@ impl Header {
             metadata.insert(key, value);
         }
         let mut tensor_infos = HashMap::new();
+        let mut unknown_tensors = HashMap::new();
         for _idx in 0..tensor_count {
             let tensor_name = read_string(reader, &magic)?;
             let n_dimensions = reader.read_u32::<LittleEndian>()?;
@@ -462,8 +630,19 @@ impl Header {
             };
 
             dimensions.reverse();
-            let ggml_dtype = GgmlDType::try_from(reader.read_u32::<LittleEndian>()?).unwrap();
+            let dtype_id = reader.read_u32::<LittleEndian>()?;
             let offset = reader.read_u64::<LittleEndian>()?;
+            let Ok(ggml_dtype) = GgmlDType::try_from(dtype_id) else {
+                unknown_tensors.insert(
+                    tensor_name,
+                    UnknownTensorInfo {
+                        dtype_id,
+                        shape: Shape::from(dimensions),
+                        offset,
+                    },
+                );
+                continue;
+            };
             tensor_infos.insert(
                 tensor_name,
                 TensorInfo {
@@ -488,23 +667,81 @@ impl Header {
             magic,
             metadata: Metadata(metadata),
             tensor_infos,
+            unknown_tensors,
             tensor_data_offset,
         })
     }
 
     /// # Tensor
     /// Load the GGUF tensor from the reader into memory.
-    pub fn tensor<R: std::io::Seek + std::io::Read>(
+    pub fn tensor<R: TensorReader>(
         &self,
         reader: &mut R,
         name: &str,
         device: &Device,
     ) -> anyhow::Result<Tensor> {
-        let tensor_info = match self.tensor_infos.get(name) {
-            Some(tensor_info) => tensor_info,
-            None => anyhow::bail!("cannot find tensor info for {name}"),
-        };
-        log::info!("Loading tensor {tensor_info:#?}");
-        tensor_info.read(reader, self.tensor_data_offset, device)
+        reader.read_tensor(self, name, device)
+    }
+
+    /// # Tensor Mapped
+    /// Load the GGUF tensor from a memory mapped file, without staging it in memory.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn tensor_mapped(
+        &self,
+        file: &MappedFile,
+        name: &str,
+        device: &Device,
+    ) -> anyhow::Result<Tensor> {
+        let tensor_info = self.tensor_info(name)?;
+        log::info!("Mapping tensor {tensor_info:#?}");
+        tensor_info.read_mapped(file, self.tensor_data_offset, device)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use ratchet::shape;
+
+    use super::*;
+    use crate::gguf::Writer;
+
+    #[test]
+    fn test_reads_unknown_dtypes() -> anyhow::Result<()> {
+        const BF16_ID: u32 = 30;
+        let mut writer = Writer::new(Cursor::new(vec![]));
+        writer.add_tensor_info("embd.weight", GgmlDType::F32, shape![2, 16])?;
+        writer.add_tensor_info("bf16.weight", GgmlDType::F16, shape![2, 16])?;
+        writer.write_tensor_data("embd.weight", &[0; 2 * 16 * 4])?;
+        writer.write_tensor_data("bf16.weight", &[0; 2 * 16 * 2])?;
+        let mut bytes = writer.finish()?.into_inner();
+
+        //Rewrite the type id, which follows the name, rank & dims of the tensor info
+        let name = b"bf16.weight";
+        let start = bytes.windows(name.len()).position(|w| w == name).unwrap();
+        let dtype_at = start + name.len() + 4 + 2 * 8;
+        assert_eq!(
+            bytes[dtype_at..dtype_at + 4],
+            GgmlDType::F16.to_u32().to_le_bytes()
+        );
+        bytes[dtype_at..dtype_at + 4].copy_from_slice(&BF16_ID.to_le_bytes());
+
+        let mut reader = Cursor::new(bytes);
+        let header = Header::read(&mut reader)?;
+        assert!(header.tensor_infos.contains_key("embd.weight"));
+        let unknown = &header.unknown_tensors["bf16.weight"];
+        assert_eq!(unknown.dtype_id, BF16_ID);
+        assert_eq!(unknown.shape, shape![2, 16]);
+
+        header.tensor(&mut reader, "embd.weight", &Device::CPU)?;
+        let err = header
+            .tensor(&mut reader, "bf16.weight", &Device::CPU)
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<LoadError>(),
+            Some(LoadError::UnsupportedDType { dtype: BF16_ID, .. })
+        ));
+        Ok(())
     }
 }
@@ -0,0 +1,291 @@
+//! Parallel, optionally lazy, loading of GGUF weights from a memory mapped file.
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use ratchet::{DType, Device, Tensor};
+
+use super::gguf::{Header, TensorReader};
+use crate::{GgmlDType, LoadError, MappedFile};
+
+/// Bytes of tensor data loaded so far, out of the total to be loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadProgress {
+    pub loaded_bytes: u64,
+    pub total_bytes: u64,
+}
+
+type ProgressFn = Arc<dyn Fn(LoadProgress) + Send + Sync>;
+
+/// # Weight Loader
+///
+/// Loads every tensor of a GGUF file in parallel, reporting byte-level progress as each tensor
+/// completes. Tensors selected with [WeightLoader::defer] are not loaded up front, but when they
+/// are first resolved.
+///
+/// The resulting [Weights] are a [TensorReader], so can be passed to any model `load`.
+///
+/// ```ignore
+/// let file = MappedFile::open("phi2_q8_0.gguf")?;
+/// let header = Header::read(&mut file.reader())?;
+/// let mut weights = WeightLoader::new(&header, &file)
+///     .with_progress(|p| println!("{}/{}", p.loaded_bytes, p.total_bytes))
+///     .load(&device)?;
+/// let model = Phi2::load(header, &mut weights, &device)?;
+/// ```
+pub struct WeightLoader<'a> {
+    header: &'a Header,
+    file: MappedFile,
+    n_threads: usize,
+    progress: Option<ProgressFn>,
+    defer: Option<Box<dyn Fn(&str) -> bool + 'a>>,
+}
+
+impl<'a> WeightLoader<'a> {
+    pub fn new(header: &'a Header, file: &MappedFile) -> Self {
+        let n_threads = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1);
+        Self {
+            header,
+            file: file.clone(),
+            n_threads,
+            progress: None,
+            defer: None,
+        }
+    }
+
+    pub fn with_threads(mut self, n_threads: usize) -> Self {
+        self.n_threads = n_threads.max(1);
+        self
+    }
+
+    /// Called from the loading threads each time a tensor completes.
+    pub fn with_progress<F: Fn(LoadProgress) + Send + Sync + 'static>(mut self, f: F) -> Self {
+        self.progress = Some(Arc::new(f));
+        self
+    }
+
+    /// Defer loading of the tensors matching `predicate` until they are first resolved.
+    pub fn defer<F: Fn(&str) -> bool + 'a>(mut self, predicate: F) -> Self {
+        self.defer = Some(Box::new(predicate));
+        self
+    }
+
+    pub fn load(self, device: &Device) -> anyhow::Result<Weights> {
+        let Self {
+            header,
+            file,
+            n_threads,
+            progress,
+            defer,
+        } = self;
+        let is_deferred = |name: &str| defer.as_ref().is_some_and(|defer| defer(name));
+        if let Some((name, unknown)) = header.unknown_tensors.iter().next() {
+            return Err(LoadError::UnsupportedDType {
+                name: name.clone(),
+                dtype: unknown.dtype_id,
+            }
+            .into());
+        }
+
+        //Largest first, so no thread is left with a large tensor at the end
+        let mut eager = header
+            .tensor_infos
+            .iter()
+            .filter(|(name, _)| !is_deferred(name))
+            .collect::<Vec<_>>();
+        eager.sort_by_key(|(_, info)| std::cmp::Reverse(info.size_in_bytes()));
+        let total_bytes = eager
+            .iter()
+            .map(|(_, info)| info.size_in_bytes() as u64)
+            .sum();
+
+        let next = AtomicUsize::new(0);
+        let loaded_bytes = AtomicU64::new(0);
+        let failed = AtomicBool::new(false);
+        let tensors = Mutex::new(HashMap::with_capacity(eager.len()));
+        let errors = Mutex::new(vec![]);
+        std::thread::scope(|s| {
+            for _ in 0..n_threads.min(eager.len()) {
+                s.spawn(|| {
+                    while !failed.load(Ordering::Relaxed) {
+                        let Some((name, info)) = eager.get(next.fetch_add(1, Ordering::Relaxed))
+                        else {
+                            break;
+                        };
+                        match header.tensor_mapped(&file, name, device) {
+                            Ok(tensor) => {
+                                tensors.lock().unwrap().insert(name.to_string(), tensor);
+                            }
+                            Err(e) => {
+                                failed.store(true, Ordering::Relaxed);
+                                errors
+                                    .lock()
+                                    .unwrap()
+                                    .push(e.context(format!("loading {name}")));
+                                break;
+                            }
+                        }
+                        let size = info.size_in_bytes() as u64;
+                        let loaded = loaded_bytes.fetch_add(size, Ordering::Relaxed) + size;
+                        if let Some(progress) = &progress {
+                            progress(LoadProgress {
+                                loaded_bytes: loaded,
+                                total_bytes,
+                            });
+                        }
+                    }
+                });
+            }
+        });
+        if let Some(e) = errors.into_inner().unwrap().pop() {
+            return Err(e);
+        }
+
+        let mut tensors = tensors.into_inner().unwrap();
+        let mut deferred = HashSet::new();
+        for (name, info) in header.tensor_infos.iter() {
+            if !is_deferred(name) {
+                continue;
+            }
+            let (file, info, offset) = (file.clone(), info.clone(), header.tensor_data_offset);
+            let device = device.clone();
+            let dt = loaded_dtype(info.ggml_dtype, &device)?;
+            let tensor = Tensor::deferred(dt, info.shape.clone(), device.clone(), move || {
+                info.read_mapped(&file, offset, &device)
+            });
+            tensors.insert(name.clone(), tensor);
+            deferred.insert(name.clone());
+        }
+        Ok(Weights { tensors, deferred })
+    }
+}
+
+/// The Ratchet dtype a GGUF tensor is loaded as on `device`, mirroring `ratchet_from_gguf`.
+fn loaded_dtype(ggml_dtype: GgmlDType, device: &Device) -> anyhow::Result<DType> {
+    let f16_supported = match device {
+        Device::CPU => None,
+        Device::GPU(gpu) => Some(gpu.compute_features().SHADER_F16),
+    };
+    Ok(match (ggml_dtype, f16_supported) {
+        (GgmlDType::F16, Some(false)) => DType::F32,
+        (GgmlDType::Q8_0, Some(true)) => DType::Q8_0H(Default::default()),
+        (GgmlDType::Q8_0, Some(false)) => DType::Q8_0F(Default::default()),
+        (GgmlDType::Q4K, Some(true)) => DType::Q4_KH(Default::default()),
+        (GgmlDType::Q4K, Some(false)) => DType::Q4_KF(Default::default()),
+        (
+            GgmlDType::F32
+            | GgmlDType::F16
+            | GgmlDType::Q4_0
+            | GgmlDType::Q4_1
+            | GgmlDType::Q5_0
+            | GgmlDType::Q5_1
+            | GgmlDType::Q2K
+            | GgmlDType::Q3K
+            | GgmlDType::Q5K
+            | GgmlDType::Q6K,
+            _,
+        ) => DType::from(ggml_dtype),
+        (dt, _) => anyhow::bail!("cannot load {dt:?} on {device:?}"),
+    })
+}
+
+/// # Weights
+///
+/// Tensors produced by a [WeightLoader], handed out by name.
+#[derive(Debug)]
+pub struct Weights {
+    tensors: HashMap<String, Tensor>,
+    deferred: HashSet<String>,
+}
+
+impl Weights {
+    /// Take the tensor `name`, each tensor can only be taken once.
+    pub fn take(&mut self, name: &str) -> anyhow::Result<Tensor> {
+        self.tensors
+            .remove(name)
+            .ok_or_else(|| anyhow::anyhow!("cannot find tensor {name}"))
+    }
+
+    pub fn is_deferred(&self, name: &str) -> bool {
+        self.deferred.contains(name)
+    }
+
+    pub fn len(&self) -> usize {
+        self.tensors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tensors.is_empty()
+    }
+}
+
+impl TensorReader for Weights {
+    fn read_tensor(&mut self, _: &Header, name: &str, _: &Device) -> anyhow::Result<Tensor> {
+        self.take(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::BufWriter;
+
+    use ratchet::{shape, Device, Tensor};
+
+    use super::*;
+    use crate::gguf::Writer;
+
+    #[test]
+    fn test_weight_loader() -> anyhow::Result<()> {
+        let tensors = (0..8)
+            .map(|i| {
+                let name = format!("blk.{i}.weight");
+                (
+                    name,
+                    Tensor::randn::<f32>(shape![4 * (i + 1), 32], Device::CPU),
+                )
+            })
+            .collect::<Vec<_>>();
+        let path = std::env::temp_dir().join(format!("ratchet-load-{}.gguf", std::process::id()));
+        let mut writer = Writer::new(BufWriter::new(File::create(&path)?));
+        for (name, tensor) in &tensors {
+            writer.add_tensor_info(name, GgmlDType::F32, tensor.shape().clone())?;
+        }
+        for (name, tensor) in &tensors {
+            writer.write_tensor_data(name, bytemuck::cast_slice(&tensor.to_vec::<f32>()?))?;
+        }
+        writer.finish()?;
+
+        let file = MappedFile::open(&path)?;
+        let header = Header::read(&mut file.reader())?;
+        let last = Arc::new(Mutex::new(None));
+        let mut weights = WeightLoader::new(&header, &file)
+            .with_threads(3)
+            .with_progress({
+                let last = last.clone();
+                move |progress| *last.lock().unwrap() = Some(progress)
+            })
+            .defer(|name| name == "blk.7.weight")
+            .load(&Device::CPU)?;
+        std::fs::remove_file(&path)?;
+
+        //Only eager tensors are reported
+        let eager_bytes = (0..7).map(|i| 4 * (i + 1) * 32 * 4).sum::<u64>();
+        let progress = last.lock().unwrap().unwrap();
+        assert_eq!(progress.loaded_bytes, eager_bytes);
+        assert_eq!(progress.total_bytes, eager_bytes);
+
+        assert!(weights.is_deferred("blk.7.weight"));
+        for (name, expected) in &tensors {
+            let tensor = header.tensor(&mut weights, name, &Device::CPU)?;
+            assert_eq!(tensor.is_deferred(), name == "blk.7.weight");
+            let tensor = tensor.resolve()?;
+            expected.all_close(&tensor, 0.0, 0.0)?;
+            assert!(!tensor.is_deferred());
+        }
+        assert!(weights.is_empty());
+        Ok(())
+    }
+}
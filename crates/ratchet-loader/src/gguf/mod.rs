@@ -1,3 +1,10 @@
 pub mod dtype;
 pub mod gguf;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod loader;
 pub mod utils;
+pub mod writer;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use loader::{LoadProgress, WeightLoader, Weights};
+pub use writer::{ratchet_to_gguf, Writer};
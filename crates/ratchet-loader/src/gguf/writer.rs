@@ -0,0 +1,299 @@
+//! GGUF v3 writer.
+//! Spec: https://github.com/philpax/ggml/blob/gguf-spec/docs/gguf.md
+
+use std::io::Write;
+
+use byteorder::{LittleEndian, WriteBytesExt};
+use half::f16;
+use ratchet::{DType, Shape, Tensor};
+
+use super::gguf::{write_string, TensorInfo, Value, DEFAULT_ALIGNMENT};
+use crate::{error::Result, k_quants::QK8_0, GgmlDType, STORAGE_BUFFER_ALIGN};
+
+const GGUF_MAGIC: u32 = 0x46554747;
+const GGUF_VERSION: u32 = 3;
+
+/// # Writer
+///
+/// Streams a GGUF v3 file: metadata and tensor infos are registered up front, then tensor data
+/// is written in registration order. Tensor data never needs to be held in memory all at once.
+///
+/// ```ignore
+/// let mut writer = Writer::new(file);
+/// writer.add_metadata("general.architecture", Value::String("llama".into()));
+/// writer.add_tensor_info("token_embd.weight", GgmlDType::F32, shape![32000, 4096])?;
+/// writer.write_tensor_data("token_embd.weight", &bytes)?;
+/// writer.finish()?;
+/// ```
+pub struct Writer<W: Write> {
+    inner: W,
+    alignment: u64,
+    metadata: Vec<(String, Value)>,
+    tensor_infos: Vec<(String, TensorInfo)>,
+    data_size: u64,
+    position: u64,
+    header_written: bool,
+    n_written: usize,
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            alignment: DEFAULT_ALIGNMENT,
+            metadata: vec![],
+            tensor_infos: vec![],
+            data_size: 0,
+            position: 0,
+            header_written: false,
+            n_written: 0,
+        }
+    }
+
+    /// Tensor data alignment, recorded as `general.alignment` if not the default.
+    pub fn with_alignment(mut self, alignment: u64) -> Self {
+        assert!(
+            alignment > 0 && alignment % 8 == 0,
+            "alignment must be a multiple of 8"
+        );
+        self.alignment = alignment;
+        self
+    }
+
+    /// Add a metadata key, replacing any previous value.
+    pub fn add_metadata(&mut self, key: impl Into<String>, value: Value) {
+        let key = key.into();
+        match self.metadata.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, v)) => *v = value,
+            None => self.metadata.push((key, value)),
+        }
+    }
+
+    /// Register a tensor, data must be provided through [Writer::write_tensor_data] in the same
+    /// order tensors are registered.
+    pub fn add_tensor_info(
+        &mut self,
+        name: impl Into<String>,
+        ggml_dtype: GgmlDType,
+        shape: Shape,
+    ) -> Result<()> {
+        let name = name.into();
+        if self.header_written {
+            crate::bail!("cannot add tensor {name} after the header has been written");
+        }
+        if self.tensor_infos.iter().any(|(n, _)| *n == name) {
+            crate::bail!("duplicate tensor {name}");
+        }
+        //Blocks may not straddle rows, so the innermost dimension must be a multiple
+        let block_numel = ggml_dtype.block_numel();
+        let row_numel = shape.rank().checked_sub(1).map_or(1, |d| shape[d]);
+        if row_numel % block_numel != 0 {
+            crate::bail!(
+                "tensor {name} has rows of {row_numel} elements, not divisible by the {ggml_dtype:?} block size {block_numel}"
+            );
+        }
+        let info = TensorInfo {
+            ggml_dtype,
+            shape,
+            offset: self.data_size,
+        };
+        self.data_size += self.pad(info.size_in_bytes() as u64);
+        self.tensor_infos.push((name, info));
+        Ok(())
+    }
+
+    /// Write the raw GGUF encoded bytes of the next tensor.
+    pub fn write_tensor_data(&mut self, name: &str, data: &[u8]) -> Result<()> {
+        if !self.header_written {
+            self.write_header()?;
+        }
+        let Some((expected, info)) = self.tensor_infos.get(self.n_written) else {
+            crate::bail!("tensor {name} was never registered");
+        };
+        if expected != name {
+            crate::bail!("expected data for tensor {expected}, got {name}");
+        }
+        let size_in_bytes = info.size_in_bytes();
+        if data.len() != size_in_bytes {
+            crate::bail!(
+                "tensor {name} should be {size_in_bytes} bytes, got {}",
+                data.len()
+            );
+        }
+        self.write_all(data)?;
+        self.write_padding()?;
+        self.n_written += 1;
+        Ok(())
+    }
+
+    /// Finish the file, returning the inner writer.
+    pub fn finish(mut self) -> Result<W> {
+        if !self.header_written {
+            self.write_header()?;
+        }
+        if self.n_written != self.tensor_infos.len() {
+            let (missing, _) = &self.tensor_infos[self.n_written];
+            crate::bail!("missing data for tensor {missing}");
+        }
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn write_header(&mut self) -> Result<()> {
+        if self.alignment != DEFAULT_ALIGNMENT {
+            self.add_metadata("general.alignment", Value::U32(self.alignment as u32));
+        }
+
+        let mut header = vec![];
+        header.write_u32::<LittleEndian>(GGUF_MAGIC)?;
+        header.write_u32::<LittleEndian>(GGUF_VERSION)?;
+        header.write_u64::<LittleEndian>(self.tensor_infos.len() as u64)?;
+        header.write_u64::<LittleEndian>(self.metadata.len() as u64)?;
+        for (key, value) in &self.metadata {
+            write_string(&mut header, key)?;
+            header.write_u32::<LittleEndian>(value.value_type().to_u32())?;
+            value.write(&mut header)?;
+        }
+        for (name, info) in &self.tensor_infos {
+            write_string(&mut header, name)?;
+            header.write_u32::<LittleEndian>(info.shape.rank() as u32)?;
+            //GGUF dimensions are innermost first
+            for &dim in info.shape.iter().rev() {
+                header.write_u64::<LittleEndian>(dim as u64)?;
+            }
+            header.write_u32::<LittleEndian>(info.ggml_dtype.to_u32())?;
+            header.write_u64::<LittleEndian>(info.offset)?;
+        }
+        self.write_all(&header)?;
+        self.write_padding()?;
+        self.header_written = true;
+        Ok(())
+    }
+
+    fn pad(&self, n: u64) -> u64 {
+        n.div_ceil(self.alignment) * self.alignment
+    }
+
+    fn write_all(&mut self, data: &[u8]) -> Result<()> {
+        self.inner.write_all(data)?;
+        self.position += data.len() as u64;
+        Ok(())
+    }
+
+    fn write_padding(&mut self) -> Result<()> {
+        let padding = self.pad(self.position) - self.position;
+        self.write_all(&vec![0u8; padding as usize])
+    }
+}
+
+/// Encode a CPU resident Ratchet tensor into GGUF, the inverse of `ratchet_from_gguf`.
+pub fn ratchet_to_gguf(tensor: &Tensor) -> anyhow::Result<(GgmlDType, Vec<u8>)> {
+    let dt = tensor.dt();
+    let numel = tensor.shape().numel();
+    if !tensor.device().is_cpu() {
+        anyhow::bail!("tensor must be on the CPU to be written to GGUF");
+    }
+    let bytes = unsafe { tensor.deep_clone().into_bytes()? };
+    match dt {
+        DType::F32 => Ok((GgmlDType::F32, bytes[..numel * 4].to_vec())),
+        DType::F16 => Ok((GgmlDType::F16, bytes[..numel * 2].to_vec())),
+        DType::Q8_0F(_) | DType::Q8_0H(_) => {
+            //Ratchet stores all quants, followed by all scales at the next aligned offset
+            let scales_offset = numel.div_ceil(STORAGE_BUFFER_ALIGN) * STORAGE_BUFFER_ALIGN;
+            let scale = |block: usize| match dt {
+                DType::Q8_0F(_) => {
+                    let start = scales_offset + block * 4;
+                    let d = f32::from_le_bytes(bytes[start..start + 4].try_into().unwrap());
+                    f16::from_f32(d)
+                }
+                _ => {
+                    let start = scales_offset + block * 2;
+                    f16::from_le_bytes(bytes[start..start + 2].try_into().unwrap())
+                }
+            };
+            let n_blocks = numel / QK8_0;
+            let mut blocks = Vec::with_capacity(n_blocks * GgmlDType::Q8_0.type_size());
+            for (block, qs) in bytes[..numel].chunks_exact(QK8_0).enumerate() {
+                blocks.extend_from_slice(&scale(block).to_le_bytes());
+                blocks.extend_from_slice(qs);
+            }
+            Ok((GgmlDType::Q8_0, blocks))
+        }
+        dt if dt.is_ggml_block() => {
+            let ggml_dtype = match dt {
+                DType::Q4_0(_) => GgmlDType::Q4_0,
+                DType::Q4_1(_) => GgmlDType::Q4_1,
+                DType::Q5_0(_) => GgmlDType::Q5_0,
+                DType::Q5_1(_) => GgmlDType::Q5_1,
+                DType::Q2_K(_) => GgmlDType::Q2K,
+                DType::Q3_K(_) => GgmlDType::Q3K,
+                DType::Q5_K(_) => GgmlDType::Q5K,
+                _ => GgmlDType::Q6K,
+            };
+            let size_in_bytes = numel / ggml_dtype.block_numel() * ggml_dtype.type_size();
+            Ok((ggml_dtype, bytes[..size_in_bytes].to_vec()))
+        }
+        dt => anyhow::bail!("{dt} has no GGUF equivalent"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use ratchet::{quantize, shape, Device, Tensor, Q8_0F};
+
+    use super::*;
+    use crate::gguf::gguf::Header;
+
+    #[test]
+    fn test_gguf_roundtrip() -> anyhow::Result<()> {
+        let embedding = Tensor::randn::<f32>(shape![16, 64], Device::CPU);
+        let weight = quantize::<Q8_0F>(&Tensor::randn::<f32>(shape![64, 64], Device::CPU));
+        let (weight_dtype, weight_bytes) = ratchet_to_gguf(&weight)?;
+        assert_eq!(weight_dtype, GgmlDType::Q8_0);
+
+        let mut writer = Writer::new(Cursor::new(vec![])).with_alignment(64);
+        writer.add_metadata("general.architecture", Value::String("test".to_string()));
+        writer.add_metadata(
+            "test.tokens",
+            Value::Array(vec![Value::String("a".into()), Value::String("b".into())]),
+        );
+        writer.add_metadata("test.eps", Value::F32(1e-5));
+        writer.add_tensor_info("embd.weight", GgmlDType::F32, shape![16, 64])?;
+        writer.add_tensor_info("blk.0.weight", weight_dtype, shape![64, 64])?;
+        writer.write_tensor_data(
+            "embd.weight",
+            bytemuck::cast_slice(&embedding.to_vec::<f32>()?),
+        )?;
+        writer.write_tensor_data("blk.0.weight", &weight_bytes)?;
+        let mut reader = Cursor::new(writer.finish()?.into_inner());
+
+        let header = Header::read(&mut reader)?;
+        assert_eq!(header.tensor_data_offset % 64, 0);
+        assert_eq!(header.metadata.get("test.eps")?.to_f32()?, 1e-5);
+        assert_eq!(header.metadata.get("test.tokens")?.to_vec()?.len(), 2);
+
+        let read_embedding = header.tensor(&mut reader, "embd.weight", &Device::CPU)?;
+        assert_eq!(read_embedding.shape(), &shape![16, 64]);
+        embedding.all_close(&read_embedding, 0.0, 0.0)?;
+
+        let info = &header.tensor_infos["blk.0.weight"];
+        assert_eq!(info.ggml_dtype, GgmlDType::Q8_0);
+        assert_eq!(info.offset % 64, 0);
+        let read_weight = info.read_bytes(&mut reader, header.tensor_data_offset)?;
+        assert_eq!(read_weight, weight_bytes);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejects_straddling_blocks() -> anyhow::Result<()> {
+        let mut writer = Writer::new(Cursor::new(vec![]));
+        //64 elements fill 2 blocks, but each row of 16 is half a block
+        assert!(writer
+            .add_tensor_info("blk.0.weight", GgmlDType::Q8_0, shape![4, 16])
+            .is_err());
+        writer.add_tensor_info("blk.0.weight", GgmlDType::Q8_0, shape![2, 32])?;
+        Ok(())
+    }
+}
@@ -148,6 +148,36 @@ impl GGType for BlockQ4_0 {
     const BLCK_NUMEL: usize = QK4_0;
 }
 
+impl GGType for BlockQ4_1 {
+    const DTYPE: GgmlDType = GgmlDType::Q4_1;
+    const BLCK_NUMEL: usize = QK4_1;
+}
+
+impl GGType for BlockQ5_0 {
+    const DTYPE: GgmlDType = GgmlDType::Q5_0;
+    const BLCK_NUMEL: usize = QK5_0;
+}
+
+impl GGType for BlockQ5_1 {
+    const DTYPE: GgmlDType = GgmlDType::Q5_1;
+    const BLCK_NUMEL: usize = QK5_1;
+}
+
+impl GGType for BlockQ2K {
+    const DTYPE: GgmlDType = GgmlDType::Q2K;
+    const BLCK_NUMEL: usize = QK_K;
+}
+
+impl GGType for BlockQ3K {
+    const DTYPE: GgmlDType = GgmlDType::Q3K;
+    const BLCK_NUMEL: usize = QK_K;
+}
+
+impl GGType for BlockQ5K {
+    const DTYPE: GgmlDType = GgmlDType::Q5K;
+    const BLCK_NUMEL: usize = QK_K;
+}
+
 impl GGType for BlockQ8_0 {
     const DTYPE: GgmlDType = GgmlDType::Q8_0;
     const BLCK_NUMEL: usize = QK8_0;
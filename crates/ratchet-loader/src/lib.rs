@@ -1,6 +1,12 @@
 mod error;
 pub mod gguf;
 mod k_quants;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod mmap;
+pub mod safetensors;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use mmap::MappedFile;
 
 pub const STORAGE_BUFFER_ALIGN: usize = 256;
 
@@ -48,6 +54,14 @@ impl From<GgmlDType> for ratchet::DType {
         match val {
             GgmlDType::F32 => ratchet::DType::F32,
             GgmlDType::F16 => ratchet::DType::F16,
+            GgmlDType::Q4_0 => ratchet::DType::Q4_0(ratchet::Q4_0),
+            GgmlDType::Q4_1 => ratchet::DType::Q4_1(ratchet::Q4_1),
+            GgmlDType::Q5_0 => ratchet::DType::Q5_0(ratchet::Q5_0),
+            GgmlDType::Q5_1 => ratchet::DType::Q5_1(ratchet::Q5_1),
+            GgmlDType::Q2K => ratchet::DType::Q2_K(ratchet::Q2_K),
+            GgmlDType::Q3K => ratchet::DType::Q3_K(ratchet::Q3_K),
+            GgmlDType::Q5K => ratchet::DType::Q5_K(ratchet::Q5_K),
+            GgmlDType::Q6K => ratchet::DType::Q6_K(ratchet::Q6_K),
             //TODO: disambiguate F and H variants
             _ => unimplemented!(),
         }
@@ -139,4 +153,9 @@ impl GgmlDType {
     pub fn tensor_size(&self, numel: usize) -> usize {
         numel * self.type_size() / self.block_numel()
     }
+
+    /// Whether Ratchet can load tensors of this type, see `ratchet_from_gguf`.
+    pub fn is_supported(&self) -> bool {
+        !matches!(self, Self::Q8_1 | Self::Q8K)
+    }
 }
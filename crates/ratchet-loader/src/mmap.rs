@@ -0,0 +1,120 @@
+//! Memory mapped, zero-copy tensor loading for native targets.
+use std::{fs::File, io::Cursor, ops::Range, path::Path, sync::Arc};
+
+use memmap2::Mmap;
+use ratchet::{DType, Device, Shape, Tensor};
+
+/// # Mapped File
+///
+/// A read-only memory mapping of a GGUF or safetensors file.
+///
+/// Tensors created from the mapping share it: CPU tensors borrow their bytes directly from the
+/// file, and GPU tensors are uploaded straight from it, so weights are never staged in an
+/// intermediate buffer.
+///
+/// ```ignore
+/// let file = MappedFile::open("model.gguf")?;
+/// let header = gguf::Header::read(&mut file.reader())?;
+/// let weight = header.tensor_mapped(&file, "token_embd.weight", &device)?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct MappedFile {
+    mmap: Arc<Mmap>,
+}
+
+impl MappedFile {
+    /// Map the file at `path`.
+    ///
+    /// The file must not be modified while it, or any tensor borrowed from it, is alive.
+    pub fn open<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let file = File::open(path)?;
+        let mmap = unsafe { Mmap::map(&file)? };
+        Ok(Self {
+            mmap: Arc::new(mmap),
+        })
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.mmap
+    }
+
+    /// A reader over the mapping, for parsing headers.
+    pub fn reader(&self) -> Cursor<&[u8]> {
+        Cursor::new(self.as_bytes())
+    }
+
+    /// The bytes in `range`, checked against the size of the file.
+    pub fn slice(&self, range: Range<u64>) -> anyhow::Result<&[u8]> {
+        let len = self.mmap.len();
+        self.mmap
+            .get(range.start as usize..range.end as usize)
+            .ok_or_else(|| anyhow::anyhow!("range {range:?} is out of bounds for {len} bytes"))
+    }
+
+    /// Create a tensor from `range` of the file, without copying.
+    pub fn tensor(
+        &self,
+        range: Range<u64>,
+        alignment: usize,
+        dt: DType,
+        shape: Shape,
+        device: &Device,
+    ) -> anyhow::Result<Tensor> {
+        self.slice(range.clone())?;
+        let range = range.start as usize..range.end as usize;
+        Ok(Tensor::from_mapped(
+            self.mmap.clone(),
+            range,
+            alignment,
+            dt,
+            shape,
+            device.clone(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::BufWriter;
+
+    use ratchet::{shape, Device, Tensor};
+
+    use super::*;
+    use crate::{
+        gguf::{gguf::Header, Writer},
+        GgmlDType,
+    };
+
+    #[test]
+    fn test_mapped_gguf() -> anyhow::Result<()> {
+        let embedding = Tensor::randn::<f32>(shape![16, 64], Device::CPU);
+        let blocks = (0..3 * GgmlDType::Q4_0.type_size())
+            .map(|i| i as u8)
+            .collect::<Vec<_>>();
+
+        let path = std::env::temp_dir().join(format!("ratchet-mmap-{}.gguf", std::process::id()));
+        let mut writer = Writer::new(BufWriter::new(File::create(&path)?));
+        writer.add_tensor_info("embd.weight", GgmlDType::F32, shape![16, 64])?;
+        writer.add_tensor_info("blk.0.weight", GgmlDType::Q4_0, shape![3, 32])?;
+        writer.write_tensor_data(
+            "embd.weight",
+            bytemuck::cast_slice(&embedding.to_vec::<f32>()?),
+        )?;
+        writer.write_tensor_data("blk.0.weight", &blocks)?;
+        writer.finish()?;
+
+        let file = MappedFile::open(&path)?;
+        let header = Header::read(&mut file.reader())?;
+        let read_embedding = header.tensor_mapped(&file, "embd.weight", &Device::CPU)?;
+        let read_weight = header.tensor_mapped(&file, "blk.0.weight", &Device::CPU)?;
+        std::fs::remove_file(&path)?;
+
+        for tensor in [&read_embedding, &read_weight] {
+            let storage = tensor.storage();
+            assert!(storage.as_ref().unwrap().try_cpu()?.is_mapped());
+        }
+        embedding.all_close(&read_embedding, 0.0, 0.0)?;
+        assert_eq!(unsafe { read_weight.deep_clone().into_bytes()? }, blocks);
+        Ok(())
+    }
+}
@@ -0,0 +1,487 @@
+//! Support for the safetensors file format.
+//! Spec: https://github.com/huggingface/safetensors#format
+
+use std::collections::HashMap;
+use std::io::{Read, Seek};
+use std::ops::Range;
+
+use half::{bf16, f16};
+use ratchet::{Device, Shape, Tensor};
+use serde::Deserialize;
+
+use crate::{gguf::gguf::ratchet_from_gguf, GgmlDType};
+
+/// Headers larger than this are almost certainly not safetensors.
+const MAX_HEADER_SIZE: u64 = 100_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum SafetensorsDType {
+    #[serde(rename = "BOOL")]
+    Bool,
+    U8,
+    I8,
+    I16,
+    U16,
+    F16,
+    BF16,
+    I32,
+    U32,
+    F32,
+    F64,
+    I64,
+    U64,
+}
+
+impl SafetensorsDType {
+    pub fn size_of(self) -> usize {
+        match self {
+            Self::Bool | Self::U8 | Self::I8 => 1,
+            Self::I16 | Self::U16 | Self::F16 | Self::BF16 => 2,
+            Self::I32 | Self::U32 | Self::F32 => 4,
+            Self::F64 | Self::I64 | Self::U64 => 8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TensorInfo {
+    pub dtype: SafetensorsDType,
+    pub shape: Vec<usize>,
+    /// Byte range relative to the start of the data section.
+    pub data_offsets: (u64, u64),
+}
+
+impl TensorInfo {
+    pub fn shape(&self) -> Shape {
+        Shape::from(self.shape.clone())
+    }
+
+    pub fn byte_range(&self, tensor_data_offset: u64) -> Range<u64> {
+        let (start, end) = self.data_offsets;
+        tensor_data_offset + start..tensor_data_offset + end
+    }
+
+    pub fn read_bytes<R: Seek + Read>(
+        &self,
+        reader: &mut R,
+        tensor_data_offset: u64,
+    ) -> anyhow::Result<Vec<u8>> {
+        let range = self.byte_range(tensor_data_offset);
+        let mut raw_data = vec![0u8; (range.end - range.start) as usize];
+        reader.seek(std::io::SeekFrom::Start(range.start))?;
+        reader.read_exact(&mut raw_data)?;
+        Ok(raw_data)
+    }
+
+    pub fn read<R: Seek + Read>(
+        &self,
+        reader: &mut R,
+        tensor_data_offset: u64,
+        device: &Device,
+    ) -> anyhow::Result<Tensor> {
+        let shape = self.shape();
+        let expected = shape.numel() * self.dtype.size_of();
+        let (start, end) = self.data_offsets;
+        if (end - start) as usize != expected {
+            anyhow::bail!(
+                "tensor of shape {shape:?} & dtype {:?} should be {expected} bytes, got {}",
+                self.dtype,
+                end - start
+            );
+        }
+        let raw_data = self.read_bytes(reader, tensor_data_offset)?;
+        ratchet_from_safetensors(self.dtype, &raw_data, shape, device)
+    }
+
+    /// Create the tensor from a memory mapped file.
+    ///
+    /// F32, F16 & I32 are borrowed from the map, BF16 is converted from the mapped bytes.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn read_mapped(
+        &self,
+        file: &crate::MappedFile,
+        tensor_data_offset: u64,
+        device: &Device,
+    ) -> anyhow::Result<Tensor> {
+        let shape = self.shape();
+        let range = self.byte_range(tensor_data_offset);
+        let expected = shape.numel() * self.dtype.size_of();
+        if (range.end - range.start) as usize != expected {
+            anyhow::bail!(
+                "tensor of shape {shape:?} & dtype {:?} should be {expected} bytes, got {}",
+                self.dtype,
+                range.end - range.start
+            );
+        }
+        let f16_supported = match device {
+            Device::CPU => true,
+            Device::GPU(gpu) => gpu.compute_features().SHADER_F16,
+        };
+        let verbatim = match self.dtype {
+            SafetensorsDType::F32 => Some(ratchet::DType::F32),
+            SafetensorsDType::F16 if f16_supported => Some(ratchet::DType::F16),
+            SafetensorsDType::I32 => Some(ratchet::DType::I32),
+            _ => None,
+        };
+        match verbatim {
+            Some(dt) => file.tensor(range, dt.size_of(), dt, shape, device),
+            None => {
+                //Safetensors does not align tensor data, so transcoding needs an aligned copy
+                let raw_data = file.slice(range)?;
+                if raw_data.as_ptr() as usize % self.dtype.size_of() == 0 {
+                    ratchet_from_safetensors(self.dtype, raw_data, shape, device)
+                } else {
+                    ratchet_from_safetensors(self.dtype, &raw_data.to_vec(), shape, device)
+                }
+            }
+        }
+    }
+}
+
+pub fn ratchet_from_safetensors(
+    dtype: SafetensorsDType,
+    raw_data: &[u8],
+    shape: Shape,
+    device: &Device,
+) -> anyhow::Result<Tensor> {
+    match dtype {
+        //F16 shares the GGUF path, which handles devices without SHADER_F16
+        SafetensorsDType::F32 => ratchet_from_gguf(GgmlDType::F32, raw_data, shape, device),
+        SafetensorsDType::F16 => ratchet_from_gguf(GgmlDType::F16, raw_data, shape, device),
+        SafetensorsDType::BF16 => {
+            let data = bytemuck::pod_collect_to_vec::<u8, bf16>(raw_data)
+                .into_iter()
+                .map(bf16::to_f32)
+                .collect::<Vec<_>>();
+            match device {
+                Device::GPU(gpu) if gpu.compute_features().SHADER_F16 => {
+                    log::info!("Device supports F16, loading BF16 as F16");
+                    let data = data.into_iter().map(f16::from_f32).collect::<Vec<_>>();
+                    Ok(Tensor::from_data(data, shape, device.clone()))
+                }
+                _ => Ok(Tensor::from_data(data, shape, device.clone())),
+            }
+        }
+        SafetensorsDType::I32 => {
+            let data = bytemuck::pod_collect_to_vec::<u8, i32>(raw_data);
+            Ok(Tensor::from_data(data, shape, device.clone()))
+        }
+        dt => anyhow::bail!("unsupported safetensors dtype {dt:?}"),
+    }
+}
+
+/// # Header
+///
+/// The parsed JSON header of a single `.safetensors` file.
+#[derive(Debug, Clone)]
+pub struct Header {
+    pub metadata: HashMap<String, String>,
+    pub tensor_infos: HashMap<String, TensorInfo>,
+    pub tensor_data_offset: u64,
+}
+
+impl Header {
+    pub fn read<R: Seek + Read>(reader: &mut R) -> anyhow::Result<Self> {
+        let mut len = [0u8; 8];
+        reader.read_exact(&mut len)?;
+        let header_size = u64::from_le_bytes(len);
+        if header_size > MAX_HEADER_SIZE {
+            anyhow::bail!("safetensors header of {header_size} bytes is too large");
+        }
+        let mut header = vec![0u8; header_size as usize];
+        reader.read_exact(&mut header)?;
+
+        let mut json: HashMap<String, serde_json::Value> = serde_json::from_slice(&header)?;
+        let metadata = match json.remove("__metadata__") {
+            Some(metadata) => serde_json::from_value(metadata)?,
+            None => HashMap::new(),
+        };
+        let tensor_infos = json
+            .into_iter()
+            .map(|(name, info)| {
+                let info = serde_json::from_value::<TensorInfo>(info)
+                    .map_err(|e| anyhow::anyhow!("invalid tensor info for {name}: {e}"))?;
+                Ok((name, info))
+            })
+            .collect::<anyhow::Result<HashMap<_, _>>>()?;
+
+        Ok(Self {
+            metadata,
+            tensor_infos,
+            tensor_data_offset: 8 + header_size,
+        })
+    }
+
+    /// # Tensor
+    /// Load the safetensors tensor from the reader into memory.
+    pub fn tensor<R: Seek + Read>(
+        &self,
+        reader: &mut R,
+        name: &str,
+        device: &Device,
+    ) -> anyhow::Result<Tensor> {
+        let tensor_info = match self.tensor_infos.get(name) {
+            Some(tensor_info) => tensor_info,
+            None => anyhow::bail!("cannot find tensor info for {name}"),
+        };
+        log::info!("Loading tensor {tensor_info:#?}");
+        tensor_info.read(reader, self.tensor_data_offset, device)
+    }
+
+    /// # Tensor Mapped
+    /// Load the safetensors tensor from a memory mapped file, without staging it in memory.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn tensor_mapped(
+        &self,
+        file: &crate::MappedFile,
+        name: &str,
+        device: &Device,
+    ) -> anyhow::Result<Tensor> {
+        let tensor_info = match self.tensor_infos.get(name) {
+            Some(tensor_info) => tensor_info,
+            None => anyhow::bail!("cannot find tensor info for {name}"),
+        };
+        log::info!("Mapping tensor {tensor_info:#?}");
+        tensor_info.read_mapped(file, self.tensor_data_offset, device)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use native::*;
+
+#[cfg(not(target_arch = "wasm32"))]
+mod native {
+    use std::fs::File;
+    use std::io::BufReader;
+    use std::path::{Path, PathBuf};
+
+    use super::*;
+
+    #[derive(Debug, Deserialize)]
+    struct Index {
+        weight_map: HashMap<String, String>,
+    }
+
+    /// # Safetensors
+    ///
+    /// A single `.safetensors` file, or a sharded checkpoint described by a
+    /// `model.safetensors.index.json`, with name-based tensor lookup across all shards.
+    ///
+    /// ```ignore
+    /// let mut st = Safetensors::open("model.safetensors.index.json")?;
+    /// let lt = |name: &str| st.tensor(name, &device);
+    /// ```
+    #[derive(Debug)]
+    pub struct Safetensors {
+        shards: Vec<(Header, BufReader<File>)>,
+        /// Tensor name to shard index
+        weight_map: HashMap<String, usize>,
+    }
+
+    impl Safetensors {
+        /// Open a `.safetensors` file, or a sharded checkpoint through its `.json` index.
+        pub fn open<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+            let path = path.as_ref();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                Self::open_index(path)
+            } else {
+                Self::open_shards(&[path.to_path_buf()])
+            }
+        }
+
+        fn open_index(path: &Path) -> anyhow::Result<Self> {
+            let index: Index = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+            let dir = path.parent().unwrap_or(Path::new("."));
+            let mut files = index.weight_map.values().collect::<Vec<_>>();
+            files.sort();
+            files.dedup();
+            let paths = files.iter().map(|f| dir.join(f)).collect::<Vec<_>>();
+            let st = Self::open_shards(&paths)?;
+
+            for name in index.weight_map.keys() {
+                if !st.weight_map.contains_key(name) {
+                    anyhow::bail!("tensor {name} is in the index but missing from its shard");
+                }
+            }
+            Ok(st)
+        }
+
+        fn open_shards(paths: &[PathBuf]) -> anyhow::Result<Self> {
+            let mut shards = Vec::with_capacity(paths.len());
+            let mut weight_map = HashMap::new();
+            for (index, path) in paths.iter().enumerate() {
+                let mut reader = BufReader::new(File::open(path)?);
+                let header = Header::read(&mut reader)
+                    .map_err(|e| anyhow::anyhow!("failed to read {}: {e}", path.display()))?;
+                for name in header.tensor_infos.keys() {
+                    if weight_map.insert(name.clone(), index).is_some() {
+                        anyhow::bail!("tensor {name} appears in multiple shards");
+                    }
+                }
+                shards.push((header, reader));
+            }
+            Ok(Self { shards, weight_map })
+        }
+
+        /// Metadata of every shard, merged.
+        pub fn metadata(&self) -> HashMap<String, String> {
+            self.shards
+                .iter()
+                .flat_map(|(header, _)| header.metadata.clone())
+                .collect()
+        }
+
+        pub fn tensor_infos(&self) -> impl Iterator<Item = (&String, &TensorInfo)> {
+            self.shards
+                .iter()
+                .flat_map(|(header, _)| header.tensor_infos.iter())
+        }
+
+        pub fn contains(&self, name: &str) -> bool {
+            self.weight_map.contains_key(name)
+        }
+
+        fn shard(&mut self, name: &str) -> anyhow::Result<&mut (Header, BufReader<File>)> {
+            let index = *self
+                .weight_map
+                .get(name)
+                .ok_or_else(|| anyhow::anyhow!("cannot find tensor info for {name}"))?;
+            Ok(&mut self.shards[index])
+        }
+
+        pub fn tensor(&mut self, name: &str, device: &Device) -> anyhow::Result<Tensor> {
+            let (header, reader) = self.shard(name)?;
+            header.tensor(reader, name, device)
+        }
+
+        /// Read the raw bytes of a tensor, without conversion.
+        pub fn tensor_bytes(&mut self, name: &str) -> anyhow::Result<Vec<u8>> {
+            let (header, reader) = self.shard(name)?;
+            header.tensor_infos[name].read_bytes(reader, header.tensor_data_offset)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use ratchet::{shape, Device, Tensor};
+
+    use super::*;
+
+    fn build_file(tensors: &[(&str, &str, Vec<usize>, Vec<u8>)]) -> Vec<u8> {
+        let mut json = serde_json::Map::new();
+        json.insert(
+            "__metadata__".to_string(),
+            serde_json::json!({ "format": "pt" }),
+        );
+        let mut data = vec![];
+        for (name, dtype, shape, bytes) in tensors {
+            let start = data.len();
+            data.extend_from_slice(bytes);
+            json.insert(
+                name.to_string(),
+                serde_json::json!({
+                    "dtype": dtype,
+                    "shape": shape,
+                    "data_offsets": [start, data.len()],
+                }),
+            );
+        }
+        let header = serde_json::to_vec(&json).unwrap();
+        let mut file = (header.len() as u64).to_le_bytes().to_vec();
+        file.extend(header);
+        file.extend(data);
+        file
+    }
+
+    #[test]
+    fn test_safetensors_read() -> anyhow::Result<()> {
+        let weight = (0..12).map(|x| x as f32).collect::<Vec<_>>();
+        let bf16_weight = weight
+            .iter()
+            .map(|&x| bf16::from_f32(x))
+            .collect::<Vec<_>>();
+        let ids = vec![1i32, -2, 3];
+        let file = build_file(&[
+            (
+                "w",
+                "F32",
+                vec![3, 4],
+                bytemuck::cast_slice(&weight).to_vec(),
+            ),
+            (
+                "b",
+                "BF16",
+                vec![3, 4],
+                bytemuck::cast_slice(&bf16_weight).to_vec(),
+            ),
+            ("ids", "I32", vec![3], bytemuck::cast_slice(&ids).to_vec()),
+        ]);
+        let mut reader = Cursor::new(file);
+        let header = Header::read(&mut reader)?;
+        assert_eq!(header.metadata["format"], "pt");
+
+        let ground = Tensor::from_data(&weight, shape![3, 4], Device::CPU);
+        let w = header.tensor(&mut reader, "w", &Device::CPU)?;
+        ground.all_close(&w, 0.0, 0.0)?;
+        let b = header.tensor(&mut reader, "b", &Device::CPU)?;
+        ground.all_close(&b, 0.0, 0.0)?;
+        let read_ids = header.tensor(&mut reader, "ids", &Device::CPU)?;
+        assert_eq!(read_ids.to_vec::<i32>()?, ids);
+        assert!(header.tensor(&mut reader, "missing", &Device::CPU).is_err());
+        Ok(())
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn test_sharded_index() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let a = (0..6).map(|x| x as f32).collect::<Vec<_>>();
+        let b = (6..10).map(|x| x as f32).collect::<Vec<_>>();
+        let shard = |name: &str, shape: Vec<usize>, data: &[f32]| {
+            build_file(&[(name, "F32", shape, bytemuck::cast_slice(data).to_vec())])
+        };
+        std::fs::write(
+            dir.path().join("model-00001-of-00002.safetensors"),
+            shard("a", vec![2, 3], &a),
+        )?;
+        std::fs::write(
+            dir.path().join("model-00002-of-00002.safetensors"),
+            shard("b", vec![4], &b),
+        )?;
+
+        let index = dir.path().join("model.safetensors.index.json");
+        let weight_map = serde_json::json!({
+            "metadata": { "total_size": 40 },
+            "weight_map": {
+                "a": "model-00001-of-00002.safetensors",
+                "b": "model-00002-of-00002.safetensors",
+            },
+        });
+        std::fs::write(&index, weight_map.to_string())?;
+
+        let mut st = Safetensors::open(&index)?;
+        assert!(st.contains("a") && st.contains("b"));
+        assert_eq!(st.tensor_infos().count(), 2);
+        assert_eq!(st.metadata()["format"], "pt");
+        let read_a = st.tensor("a", &Device::CPU)?;
+        assert_eq!(read_a.shape(), &shape![2, 3]);
+        assert_eq!(read_a.to_vec::<f32>()?, a);
+        assert_eq!(st.tensor("b", &Device::CPU)?.to_vec::<f32>()?, b);
+        assert_eq!(st.tensor_bytes("b")?, bytemuck::cast_slice::<f32, u8>(&b));
+        assert!(st.tensor("c", &Device::CPU).is_err());
+
+        //Every tensor of the index must be in its shard
+        let missing = serde_json::json!({
+            "weight_map": {
+                "a": "model-00001-of-00002.safetensors",
+                "c": "model-00002-of-00002.safetensors",
+            },
+        });
+        std::fs::write(&index, missing.to_string())?;
+        assert!(Safetensors::open(&index).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,119 @@
+use ratchet_loader::gguf::gguf::Metadata;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PositionEncoding {
+    /// Learned position embeddings added to the token embeddings (BERT, bge).
+    Absolute,
+    /// Linear biases added to the attention logits (jina-bert-v2).
+    Alibi,
+    /// Rotary embeddings applied to queries & keys (nomic-bert).
+    Rotary { base: f32 },
+}
+
+/// Reduction from per-token hidden states to a single sentence embedding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pooling {
+    Mean,
+    Cls,
+}
+
+#[derive(Debug, Clone)]
+pub struct BertConfig {
+    /// `bert`, `nomic-bert` or `jina-bert-v2`
+    pub architecture: String,
+    pub n_layers: usize,
+    pub d_model: usize,
+    pub n_heads: usize,
+    pub norm_eps: f32,
+    pub context_length: usize,
+    pub position_encoding: PositionEncoding,
+    pub pooling: Pooling,
+    /// Whether the sentence embeddings are L2 normalised, from `<arch>.normalize_embeddings`.
+    /// Defaults to true, as the exported sentence-transformers checkpoints normalise.
+    pub normalize: bool,
+}
+
+impl BertConfig {
+    pub fn from_metadata(metadata: &Metadata) -> anyhow::Result<Self> {
+        let architecture = metadata.get("general.architecture")?.to_string()?.clone();
+        let arch = architecture.as_str();
+        let get = |key: &str| metadata.get(&format!("{arch}.{key}"));
+
+        let position_encoding = match arch {
+            "bert" => PositionEncoding::Absolute,
+            "jina-bert-v2" => PositionEncoding::Alibi,
+            "nomic-bert" => {
+                let base = match get("rope.freq_base") {
+                    Ok(v) => v.to_f32()?,
+                    Err(_) => 10000.0,
+                };
+                PositionEncoding::Rotary { base }
+            }
+            other => anyhow::bail!("unsupported encoder architecture: {other}"),
+        };
+        //0: none, 1: mean, 2: cls
+        let pooling = match get("pooling_type") {
+            Ok(v) if v.to_u32()? == 2 => Pooling::Cls,
+            _ => Pooling::Mean,
+        };
+        let normalize = match get("normalize_embeddings") {
+            Ok(v) => v.to_bool()?,
+            Err(_) => true,
+        };
+
+        Ok(Self {
+            n_layers: get("block_count")?.to_u32()? as usize,
+            d_model: get("embedding_length")?.to_u32()? as usize,
+            n_heads: get("attention.head_count")?.to_u32()? as usize,
+            norm_eps: get("attention.layer_norm_epsilon")?.to_f32()?,
+            context_length: get("context_length")?.to_u32()? as usize,
+            position_encoding,
+            pooling,
+            normalize,
+            architecture,
+        })
+    }
+
+    pub fn head_dim(&self) -> usize {
+        self.d_model / self.n_heads
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BertConfig, Pooling, PositionEncoding};
+    use crate::config::tests::metadata;
+    use ratchet_loader::gguf::gguf::Value;
+
+    #[test]
+    fn reads_bert_config() -> anyhow::Result<()> {
+        let config = BertConfig::from_metadata(&metadata("bert", &[]))?;
+        assert_eq!(config.position_encoding, PositionEncoding::Absolute);
+        assert_eq!(config.pooling, Pooling::Mean);
+        assert!(config.normalize);
+        assert_eq!((config.n_layers, config.head_dim()), (2, 16));
+
+        let overrides = [
+            ("pooling_type", Value::U32(2)),
+            ("normalize_embeddings", Value::Bool(false)),
+        ];
+        let config = BertConfig::from_metadata(&metadata("bert", &overrides))?;
+        assert_eq!(config.pooling, Pooling::Cls);
+        assert!(!config.normalize);
+        Ok(())
+    }
+
+    #[test]
+    fn reads_position_encoding() -> anyhow::Result<()> {
+        let config = BertConfig::from_metadata(&metadata("jina-bert-v2", &[]))?;
+        assert_eq!(config.position_encoding, PositionEncoding::Alibi);
+        let overrides = [("rope.freq_base", Value::F32(1000.0))];
+        let config = BertConfig::from_metadata(&metadata("nomic-bert", &overrides))?;
+        assert_eq!(
+            config.position_encoding,
+            PositionEncoding::Rotary { base: 1000.0 }
+        );
+        assert!(BertConfig::from_metadata(&metadata("roberta", &[])).is_err());
+        Ok(())
+    }
+}
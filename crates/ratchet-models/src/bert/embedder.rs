@@ -0,0 +1,154 @@
+use ratchet::{shape, Device, RVec, Tensor};
+use ratchet_loader::gguf::gguf::{Header, TensorReader};
+use ratchet_nn::Module;
+use tokenizers::Tokenizer;
+
+use super::{Bert, Pooling};
+
+#[cfg(target_arch = "wasm32")]
+use crate::TensorMap;
+
+/// # Embedder
+///
+/// Turns sentences into fixed size embeddings, pooling the final hidden states of a [Bert]
+/// encoder according to the model's pooling type.
+#[derive(Debug)]
+pub struct Embedder {
+    pub model: Bert,
+    pub tokenizer: Tokenizer,
+}
+
+impl Embedder {
+    pub fn new(model: Bert, tokenizer: Tokenizer) -> Self {
+        Self { model, tokenizer }
+    }
+
+    pub fn load<R: TensorReader>(
+        header: Header,
+        reader: &mut R,
+        tokenizer: Tokenizer,
+        device: &Device,
+    ) -> anyhow::Result<Self> {
+        Ok(Self::new(Bert::load(header, reader, device)?, tokenizer))
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub async fn from_web(
+        header: Header,
+        tensors: TensorMap,
+        tokenizer: Tokenizer,
+    ) -> anyhow::Result<Self> {
+        Ok(Self::new(Bert::from_web(header, tensors).await?, tokenizer))
+    }
+
+    pub fn dim(&self) -> usize {
+        self.model.config.d_model
+    }
+
+    /// Embeds each text, returning a resolved `[N, D]` F32 tensor on the model device.
+    ///
+    /// Texts are run one at a time to avoid padding, and truncated to the context length.
+    pub fn embed<S: AsRef<str>>(&self, texts: &[S]) -> anyhow::Result<Tensor> {
+        anyhow::ensure!(!texts.is_empty(), "no texts to embed");
+        let embeddings = texts
+            .iter()
+            .map(|text| self.embed_one(text.as_ref()))
+            .collect::<anyhow::Result<RVec<_>>>()?;
+        let embeddings = if embeddings.len() == 1 {
+            embeddings.into_iter().next().unwrap()
+        } else {
+            Tensor::cat(embeddings, 0)?
+        };
+        Ok(embeddings.resolve()?)
+    }
+
+    fn embed_one(&self, text: &str) -> anyhow::Result<Tensor> {
+        let config = &self.model.config;
+        let device = &self.model.device;
+        let encoding = self
+            .tokenizer
+            .encode(text, true)
+            .map_err(|e| anyhow::anyhow!(e))?;
+        let mut tokens = encoding
+            .get_ids()
+            .iter()
+            .map(|&x| x as i32)
+            .collect::<Vec<_>>();
+        tokens.truncate(config.context_length);
+        let seq_len = tokens.len();
+        anyhow::ensure!(seq_len > 0, "text produced no tokens");
+
+        let input = Tensor::from_data(tokens, shape![1, seq_len], device.clone());
+        let hidden = self.model.schedule(input)?;
+        pool(hidden, config.pooling, config.normalize)
+    }
+}
+
+/// Reduces `[1, L, D]` hidden states to a `[1, D]` embedding.
+fn pool(hidden: Tensor, pooling: Pooling, normalize: bool) -> anyhow::Result<Tensor> {
+    let hidden = hidden.full()?;
+    let [_, seq_len, d_model]: [usize; 3] = hidden.shape().try_into()?;
+    let device = hidden.device().clone();
+
+    let pooled = match pooling {
+        Pooling::Cls => hidden.slice(&[0..1, 0..1, 0..d_model])?,
+        Pooling::Mean => {
+            let weights = vec![1.0 / seq_len as f32; seq_len];
+            let weights = Tensor::from_data(weights, shape![1, 1, seq_len], device.clone());
+            weights.matmul(hidden, false, false)?
+        }
+    };
+    let pooled = pooled.view(shape![1, d_model])?;
+    if !normalize {
+        return Ok(pooled);
+    }
+
+    //x / ||x|| == rms_norm(x) / sqrt(D)
+    let scale = vec![1.0 / (d_model as f32).sqrt(); d_model];
+    let scale = Tensor::from_data(scale, shape![d_model], device);
+    pooled.rms_norm(scale, 1e-12)
+}
+
+#[cfg(all(test, not(target_arch = "wasm32"), feature = "pyo3"))]
+mod tests {
+    use super::pool;
+    use crate::bert::Pooling;
+    use ratchet::{shape, Device, DeviceRequest, Tensor};
+
+    fn pooled(pooling: Pooling, normalize: bool) -> anyhow::Result<Vec<f32>> {
+        let device = Device::request_device(DeviceRequest::GPU)?;
+        //3 tokens of 4 features
+        let hidden = vec![
+            3., 0., 4., 0., //
+            1., 2., 2., 4., //
+            2., 1., 0., 2.,
+        ];
+        let hidden = Tensor::from_data(hidden, shape![1, 3, 4], device);
+        let pooled = pool(hidden, pooling, normalize)?.resolve()?;
+        assert_eq!(pooled.shape(), &shape![1, 4]);
+        pooled.to(&Device::CPU)?.to_vec::<f32>()
+    }
+
+    fn assert_close(actual: &[f32], expected: &[f32]) {
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-5, "{actual:?} != {expected:?}");
+        }
+    }
+
+    #[test]
+    fn pools_cls_token() -> anyhow::Result<()> {
+        assert_close(&pooled(Pooling::Cls, false)?, &[3., 0., 4., 0.]);
+        assert_close(&pooled(Pooling::Cls, true)?, &[0.6, 0., 0.8, 0.]);
+        Ok(())
+    }
+
+    #[test]
+    fn pools_token_mean() -> anyhow::Result<()> {
+        assert_close(&pooled(Pooling::Mean, false)?, &[2., 1., 2., 2.]);
+        //The mean has norm sqrt(13)
+        let norm = 13f32.sqrt();
+        let expected = [2. / norm, 1. / norm, 2. / norm, 2. / norm];
+        assert_close(&pooled(Pooling::Mean, true)?, &expected);
+        Ok(())
+    }
+}
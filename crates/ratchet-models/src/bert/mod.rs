@@ -0,0 +1,7 @@
+mod config;
+mod embedder;
+mod model;
+
+pub use config::{BertConfig, Pooling, PositionEncoding};
+pub use embedder::Embedder;
+pub use model::Bert;
@@ -0,0 +1,385 @@
+use ratchet::{shape, AttentionMask, Device, Tensor};
+use ratchet_loader::gguf::gguf::{Header, TensorReader};
+use ratchet_nn::{
+    AttentionInput, Embedding, GroupedQueryAttention, LayerNorm, Linear, Module, RotaryEmbedding,
+    RotaryInput,
+};
+
+use super::{BertConfig, PositionEncoding};
+
+#[cfg(target_arch = "wasm32")]
+use {crate::ratchet_from_gguf_web, crate::TensorMap};
+
+#[derive(Debug)]
+enum QKV {
+    /// nomic-bert stores the projections as a single matrix
+    Fused(Linear),
+    Split(Linear, Linear, Linear),
+}
+
+#[derive(Debug)]
+pub struct EncoderAttention {
+    qkv: QKV,
+    o: Linear,
+    sdpa: GroupedQueryAttention,
+    rope: Option<RotaryEmbedding>,
+}
+
+impl Module for EncoderAttention {
+    type Input = (Tensor, AttentionMask);
+
+    fn schedule(&self, (input, mask): Self::Input) -> anyhow::Result<Tensor> {
+        let [batch_size, seq_len, n_state]: [usize; 3] = input.shape().try_into()?;
+        let (q, k, v) = match &self.qkv {
+            QKV::Fused(qkv) => {
+                let qkv = qkv.schedule(input)?;
+                let r = |i: usize| i * n_state..(i + 1) * n_state;
+                (
+                    qkv.clone().slice(&[0..batch_size, 0..seq_len, r(0)])?,
+                    qkv.clone().slice(&[0..batch_size, 0..seq_len, r(1)])?,
+                    qkv.slice(&[0..batch_size, 0..seq_len, r(2)])?,
+                )
+            }
+            QKV::Split(q, k, v) => (
+                q.schedule(input.clone())?,
+                k.schedule(input.clone())?,
+                v.schedule(input)?,
+            ),
+        };
+        let q_dt = q.dt();
+        let mut q = self.sdpa.split_heads(q)?;
+        let mut k = self.sdpa.split_heads(k)?;
+        let v = self.sdpa.split_heads(v)?;
+
+        if let Some(rope) = &self.rope {
+            let rotate = |x: Tensor| -> anyhow::Result<Tensor> {
+                rope.schedule(RotaryInput {
+                    input: x.full()?,
+                    offset: 0,
+                })?
+                .cast(q_dt)
+            };
+            q = rotate(q)?;
+            k = rotate(k)?;
+        }
+
+        let attn_output = self.sdpa.schedule(AttentionInput {
+            query: q,
+            key: k,
+            value: v.cast(q_dt)?,
+            mask,
+            cache: None,
+        })?;
+        self.o.schedule(attn_output)
+    }
+}
+
+#[derive(Debug)]
+pub enum FeedForward {
+    /// `down(gelu(up(x)))`
+    Gelu { up: Linear, down: Linear },
+    /// `down(act(gate(x)) * up(x))`, GeGLU for jina-bert-v2 & SwiGLU for nomic-bert
+    Gated {
+        gate: Linear,
+        up: Linear,
+        down: Linear,
+        silu: bool,
+    },
+}
+
+impl Module for FeedForward {
+    type Input = Tensor;
+
+    fn schedule(&self, input: Self::Input) -> anyhow::Result<Tensor> {
+        let input_dt = input.dt();
+        match self {
+            FeedForward::Gelu { up, down } => down.schedule(up.schedule(input)?.gelu()?),
+            FeedForward::Gated {
+                gate,
+                up,
+                down,
+                silu,
+            } => {
+                let gate = gate.schedule(input.clone())?.full()?;
+                let gate = if *silu { gate.silu()? } else { gate.gelu()? };
+                let up_states = up.schedule(input)?.mul(gate.cast(input_dt)?)?;
+                down.schedule(up_states)
+            }
+        }
+    }
+}
+
+/// Post-LN transformer encoder layer.
+#[derive(Debug)]
+pub struct EncoderLayer {
+    attn: EncoderAttention,
+    attn_norm: LayerNorm,
+    ffn: FeedForward,
+    ffn_norm: LayerNorm,
+}
+
+impl EncoderLayer {
+    fn load_inner<F>(
+        header: &Header,
+        config: &BertConfig,
+        layer_index: usize,
+        mut lt: F,
+    ) -> anyhow::Result<Self>
+    where
+        F: FnMut(&str) -> anyhow::Result<Tensor>,
+    {
+        let has = |name: &str| {
+            header
+                .tensor_infos
+                .contains_key(&format!("blk.{}.{}", layer_index, name))
+        };
+        let mut linear = |name: &str| -> anyhow::Result<Linear> {
+            let w = lt(&format!("{}.weight", name))?;
+            let b = if has(&format!("{}.bias", name)) {
+                Some(lt(&format!("{}.bias", name))?)
+            } else {
+                None
+            };
+            Ok(Linear::new(w, b))
+        };
+
+        let qkv = if has("attn_qkv.weight") {
+            QKV::Fused(linear("attn_qkv")?)
+        } else {
+            QKV::Split(linear("attn_q")?, linear("attn_k")?, linear("attn_v")?)
+        };
+        let o = linear("attn_output")?;
+        let ffn = if has("ffn_gate.weight") {
+            FeedForward::Gated {
+                gate: linear("ffn_gate")?,
+                up: linear("ffn_up")?,
+                down: linear("ffn_down")?,
+                silu: config.architecture == "nomic-bert",
+            }
+        } else {
+            FeedForward::Gelu {
+                up: linear("ffn_up")?,
+                down: linear("ffn_down")?,
+            }
+        };
+
+        let rope = match config.position_encoding {
+            PositionEncoding::Rotary { base } => {
+                Some(RotaryEmbedding::new(config.head_dim(), false, base, 1.0))
+            }
+            _ => None,
+        };
+        let sdpa = GroupedQueryAttention::with_default_scale(
+            config.n_heads,
+            config.n_heads,
+            config.head_dim(),
+        );
+
+        let mut norm = |name: &str| -> anyhow::Result<LayerNorm> {
+            let w = lt(&format!("{}.weight", name))?;
+            let b = lt(&format!("{}.bias", name))?;
+            Ok(LayerNorm::new(w, Some(b), config.norm_eps))
+        };
+        Ok(Self {
+            attn: EncoderAttention { qkv, o, sdpa, rope },
+            attn_norm: norm("attn_output_norm")?,
+            ffn,
+            ffn_norm: norm("layer_output_norm")?,
+        })
+    }
+}
+
+impl Module for EncoderLayer {
+    type Input = (Tensor, AttentionMask);
+
+    fn schedule(&self, (x, mask): Self::Input) -> anyhow::Result<Tensor> {
+        let attn_output = self.attn.schedule((x.clone(), mask))?;
+        let x = self.attn_norm.schedule(x.add(attn_output)?)?;
+        let ffn_output = self.ffn.schedule(x.clone())?;
+        self.ffn_norm.schedule(x.add(ffn_output)?)
+    }
+}
+
+/// # Bert
+///
+/// Bidirectional transformer encoder, covering the `bert` (BERT, bge), `nomic-bert` &
+/// `jina-bert-v2` GGUF architectures.
+///
+/// Input is a `[1, L]` tensor of token ids, output the `[1, L, D]` final hidden states.
+#[derive(Debug)]
+pub struct Bert {
+    pub token_embedding: Embedding,
+    pub token_type_embedding: Option<Embedding>,
+    pub position_embedding: Option<Embedding>,
+    pub embedding_norm: LayerNorm,
+    pub layers: Vec<EncoderLayer>,
+    pub config: BertConfig,
+    pub device: Device,
+}
+
+impl Module for Bert {
+    type Input = Tensor;
+
+    fn schedule(&self, input: Self::Input) -> anyhow::Result<Tensor> {
+        let [batch_size, seq_len]: [usize; 2] = input.shape().try_into()?;
+        let mut x = self.token_embedding.schedule(input)?;
+        //Single segment, so every token has type 0
+        if let Some(tte) = &self.token_type_embedding {
+            let types = vec![0i32; batch_size * seq_len];
+            let types = Tensor::from_data(types, shape![batch_size, seq_len], self.device.clone());
+            x = x.add(tte.schedule(types)?)?;
+        }
+        if let Some(pe) = &self.position_embedding {
+            let positions = (0..seq_len as i32).collect::<Vec<_>>();
+            let positions = Tensor::from_data(positions, shape![1, seq_len], self.device.clone());
+            x = x.add(pe.schedule(positions)?)?;
+        }
+        let mut x = self.embedding_norm.schedule(x)?;
+
+        let mask = match self.config.position_encoding {
+            PositionEncoding::Alibi => {
+                let bias = Self::alibi_bias(self.config.n_heads, seq_len, &self.device);
+                AttentionMask::Additive(bias.cast(x.dt())?)
+            }
+            _ => AttentionMask::None,
+        };
+        for layer in &self.layers {
+            x = layer.schedule((x, mask.clone()))?;
+        }
+        Ok(x)
+    }
+}
+
+impl Bert {
+    pub fn load<R: TensorReader>(
+        header: Header,
+        reader: &mut R,
+        device: &Device,
+    ) -> anyhow::Result<Self> {
+        let lt = |name: &str| header.tensor(reader, name, device);
+        Self::load_inner(&header, lt, device)
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub async fn from_web(header: Header, mut tensors: TensorMap) -> anyhow::Result<Self> {
+        let device = Device::request_device(ratchet::DeviceRequest::GPU).await?;
+        let lt = |name: &str| {
+            let tensor = tensors
+                .remove(name)
+                .ok_or_else(|| anyhow::anyhow!("missing tensor {name}"))?;
+            ratchet_from_gguf_web(tensor, &device)
+        };
+        Self::load_inner(&header, lt, &device)
+    }
+
+    fn load_inner<F>(header: &Header, mut lt: F, device: &Device) -> anyhow::Result<Self>
+    where
+        F: FnMut(&str) -> anyhow::Result<Tensor>,
+    {
+        let config = BertConfig::from_metadata(&header.metadata)?;
+        let has = |name: &str| header.tensor_infos.contains_key(name);
+
+        let token_embedding = Embedding::new(lt("token_embd.weight")?);
+        let token_type_embedding = if has("token_types.weight") {
+            Some(Embedding::new(lt("token_types.weight")?))
+        } else {
+            None
+        };
+        let position_embedding = match config.position_encoding {
+            PositionEncoding::Absolute => Some(Embedding::new(lt("position_embd.weight")?)),
+            _ => None,
+        };
+        let embedding_norm = LayerNorm::new(
+            lt("token_embd_norm.weight")?,
+            Some(lt("token_embd_norm.bias")?),
+            config.norm_eps,
+        );
+
+        let layers = (0..config.n_layers)
+            .map(|i| {
+                EncoderLayer::load_inner(header, &config, i, |name: &str| {
+                    lt(&format!("blk.{}.{}", i, name))
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        Ok(Self {
+            token_embedding,
+            token_type_embedding,
+            position_embedding,
+            embedding_norm,
+            layers,
+            config,
+            device: device.clone(),
+        })
+    }
+
+    /// Symmetric ALiBi bias of shape `[H, L, L]`, `-slope_h * |i - j|`.
+    ///
+    /// https://arxiv.org/abs/2108.12409
+    pub fn alibi_bias(n_heads: usize, seq_len: usize, device: &Device) -> Tensor {
+        //Slopes are a geometric sequence, interleaved for non power of 2 head counts
+        let closest = 2usize.pow(n_heads.ilog2());
+        let geometric = |n: usize, step: usize| {
+            let base = 2f32.powf(-8.0 / n as f32);
+            (1..=n)
+                .step_by(step)
+                .map(move |i| base.powi(i as i32))
+                .collect::<Vec<_>>()
+        };
+        let mut slopes = geometric(closest, 1);
+        slopes.extend(
+            geometric(2 * closest, 2)
+                .into_iter()
+                .take(n_heads - closest),
+        );
+
+        let bias = slopes
+            .iter()
+            .flat_map(|slope| {
+                (0..seq_len).flat_map(move |i| {
+                    (0..seq_len).map(move |j| -slope * (i as f32 - j as f32).abs())
+                })
+            })
+            .collect::<Vec<_>>();
+        Tensor::from_data(bias, shape![n_heads, seq_len, seq_len], device.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Bert;
+    use ratchet::{shape, Device};
+
+    fn slopes(n_heads: usize) -> anyhow::Result<Vec<f32>> {
+        //Adjacent tokens are biased by exactly -slope
+        let bias = Bert::alibi_bias(n_heads, 2, &Device::CPU);
+        assert_eq!(bias.shape(), &shape![n_heads, 2, 2]);
+        let bias = bias.to_vec::<f32>()?;
+        for head in bias.chunks_exact(4) {
+            assert_eq!((head[0], head[3]), (0., 0.));
+            assert_eq!(head[1], head[2]);
+        }
+        Ok(bias.chunks_exact(4).map(|head| -head[1]).collect())
+    }
+
+    #[test]
+    fn alibi_slopes() -> anyhow::Result<()> {
+        let powers =
+            |exponents: &[f32]| exponents.iter().map(|e| 2f32.powf(-e)).collect::<Vec<_>>();
+        assert_eq!(slopes(4)?, powers(&[2., 4., 6., 8.]));
+        //Non powers of 2 take every other slope of the next power of 2
+        assert_eq!(slopes(6)?, powers(&[2., 4., 6., 8., 1., 3.]));
+        Ok(())
+    }
+
+    #[test]
+    fn alibi_grows_with_distance() -> anyhow::Result<()> {
+        let bias = Bert::alibi_bias(1, 4, &Device::CPU).to_vec::<f32>()?;
+        let slope = 2f32.powf(-8.);
+        let row = |i: usize| bias[i * 4..(i + 1) * 4].to_vec();
+        assert_eq!(row(0), [0., -slope, -2. * slope, -3. * slope]);
+        assert_eq!(row(2), [-2. * slope, -slope, 0., -slope]);
+        Ok(())
+    }
+}
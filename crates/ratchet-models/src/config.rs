@@ -0,0 +1,156 @@
+use ratchet_loader::gguf::gguf::Metadata;
+
+/// The normalisation of a decoder, which decides the GGUF key of its epsilon.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NormKind {
+    /// `attention.layer_norm_epsilon`
+    LayerNorm,
+    /// `attention.layer_norm_rms_epsilon`
+    RMSNorm,
+}
+
+impl NormKind {
+    fn eps_key(&self) -> &'static str {
+        match self {
+            NormKind::LayerNorm => "attention.layer_norm_epsilon",
+            NormKind::RMSNorm => "attention.layer_norm_rms_epsilon",
+        }
+    }
+}
+
+/// Hyperparameters shared by the decoder-only checkpoints, read from the `<arch>.*` GGUF
+/// metadata keys.
+#[derive(Debug, Clone)]
+pub struct DecoderConfig {
+    pub n_layers: usize,
+    pub d_model: usize,
+    pub n_heads: usize,
+    pub n_kv_heads: usize,
+    pub head_dim: usize,
+    pub rope_dim: usize,
+    pub rope_base: f32,
+    pub context_length: usize,
+    pub norm_eps: f32,
+}
+
+impl DecoderConfig {
+    pub fn from_metadata(metadata: &Metadata, arch: &str, norm: NormKind) -> anyhow::Result<Self> {
+        let get = |key: &str| metadata.get(&format!("{arch}.{key}"));
+
+        let n_layers = get("block_count")?.to_u32()? as usize;
+        let d_model = get("embedding_length")?.to_u32()? as usize;
+        let n_heads = get("attention.head_count")?.to_u32()? as usize;
+        let n_kv_heads = match get("attention.head_count_kv") {
+            Ok(v) => v.to_u32()? as usize,
+            Err(_) => n_heads,
+        };
+        if n_heads == 0 || d_model % n_heads != 0 {
+            anyhow::bail!(
+                "{arch}.embedding_length ({d_model}) must be a multiple of {arch}.attention.head_count ({n_heads})"
+            );
+        }
+        if n_kv_heads == 0 || n_heads % n_kv_heads != 0 {
+            anyhow::bail!(
+                "{arch}.attention.head_count ({n_heads}) must be a multiple of {arch}.attention.head_count_kv ({n_kv_heads})"
+            );
+        }
+        let head_dim = d_model / n_heads;
+        //RoPE covers the whole head unless stated otherwise
+        let rope_dim = match get("rope.dimension_count") {
+            Ok(v) => v.to_u32()? as usize,
+            Err(_) => head_dim,
+        };
+        if rope_dim > head_dim {
+            anyhow::bail!(
+                "{arch}.rope.dimension_count ({rope_dim}) exceeds the head dimension ({head_dim})"
+            );
+        }
+        let rope_base = match get("rope.freq_base") {
+            Ok(v) => v.to_f32()?,
+            Err(_) => 10000.0,
+        };
+        let context_length = get("context_length")?.to_u32()? as usize;
+        let norm_eps = get(norm.eps_key())?.to_f32()?;
+
+        Ok(Self {
+            n_layers,
+            d_model,
+            n_heads,
+            n_kv_heads,
+            head_dim,
+            rope_dim,
+            rope_base,
+            context_length,
+            norm_eps,
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{DecoderConfig, NormKind};
+    use ratchet_loader::gguf::gguf::{Metadata, Value};
+
+    /// The metadata of a small `arch` decoder, with `overrides` replacing or adding keys.
+    pub(crate) fn metadata(arch: &str, overrides: &[(&str, Value)]) -> Metadata {
+        let defaults = [
+            ("block_count", Value::U32(2)),
+            ("embedding_length", Value::U32(64)),
+            ("attention.head_count", Value::U32(4)),
+            ("rope.dimension_count", Value::U32(8)),
+            ("context_length", Value::U32(128)),
+            ("attention.layer_norm_epsilon", Value::F32(1e-5)),
+            ("attention.layer_norm_rms_epsilon", Value::F32(1e-6)),
+        ];
+        let mut metadata = defaults
+            .into_iter()
+            .chain(overrides.iter().cloned())
+            .map(|(k, v)| (format!("{arch}.{k}"), v))
+            .collect::<Vec<_>>();
+        metadata.push((
+            "general.architecture".to_string(),
+            Value::String(arch.to_string()),
+        ));
+        metadata.into_iter().collect()
+    }
+
+    #[test]
+    fn reads_decoder_config() -> anyhow::Result<()> {
+        let config =
+            DecoderConfig::from_metadata(&metadata("phi2", &[]), "phi2", NormKind::LayerNorm)?;
+        assert_eq!((config.n_layers, config.d_model), (2, 64));
+        assert_eq!(
+            (config.n_heads, config.n_kv_heads, config.head_dim),
+            (4, 4, 16)
+        );
+        assert_eq!((config.rope_dim, config.rope_base), (8, 10000.0));
+        assert_eq!(config.context_length, 128);
+        assert_eq!(config.norm_eps, 1e-5);
+
+        let overrides = [
+            ("attention.head_count_kv", Value::U32(2)),
+            ("rope.freq_base", Value::F32(500000.0)),
+        ];
+        let config =
+            DecoderConfig::from_metadata(&metadata("phi3", &overrides), "phi3", NormKind::RMSNorm)?;
+        assert_eq!(config.n_kv_heads, 2);
+        assert_eq!(config.rope_base, 500000.0);
+        assert_eq!(config.norm_eps, 1e-6);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_invalid_decoder_config() {
+        let read = |overrides: &[(&str, Value)]| {
+            DecoderConfig::from_metadata(&metadata("phi2", overrides), "phi2", NormKind::LayerNorm)
+        };
+        assert!(read(&[("attention.head_count", Value::U32(5))]).is_err());
+        assert!(read(&[("attention.head_count_kv", Value::U32(3))]).is_err());
+        assert!(read(&[("rope.dimension_count", Value::U32(32))]).is_err());
+        //Keys of another architecture are not read
+        assert!(
+            DecoderConfig::from_metadata(&metadata("phi3", &[]), "phi2", NormKind::LayerNorm)
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,126 @@
+use ratchet::{shape, AttentionMask, Device, Tensor};
+use ratchet_nn::{
+    AttentionInput, GroupedQueryAttention, KVEntry, Linear, Module, RotaryEmbedding, RotaryInput,
+};
+
+use super::GemmaConfig;
+
+#[derive(Debug)]
+pub struct GemmaSelfAttention {
+    q: Linear,
+    k: Linear,
+    v: Linear,
+    o: Linear,
+    rope: RotaryEmbedding,
+    sdpa: GroupedQueryAttention,
+    sliding_window: Option<usize>,
+}
+
+impl GemmaSelfAttention {
+    pub fn load<F>(config: &GemmaConfig, layer_index: usize, mut lt: F) -> anyhow::Result<Self>
+    where
+        F: FnMut(&str) -> anyhow::Result<Tensor>,
+    {
+        let q = Linear::new(lt("attn_q.weight")?, None);
+        let k = Linear::new(lt("attn_k.weight")?, None);
+        let v = Linear::new(lt("attn_v.weight")?, None);
+        let o = Linear::new(lt("attn_output.weight")?, None);
+
+        let rope = RotaryEmbedding::new(config.head_dim, false, config.rope_base, 1.0);
+        let mut sdpa = GroupedQueryAttention::new(
+            config.n_heads,
+            config.n_kv_heads,
+            config.head_dim,
+            1.0 / config.query_pre_attn_scalar.sqrt(),
+        );
+        if let Some(cap) = config.attn_logit_softcap {
+            sdpa = sdpa.with_softcap(cap);
+        }
+        Ok(Self {
+            q,
+            k,
+            v,
+            o,
+            rope,
+            sdpa,
+            sliding_window: config.sliding_window(layer_index),
+        })
+    }
+
+    /// Causal mask restricted to the most recent `window` positions.
+    /// Queries are the last `q_len` of the `kv_len` positions.
+    fn sliding_window_mask(q_len: usize, kv_len: usize, window: usize, device: &Device) -> Tensor {
+        let offset = kv_len - q_len;
+        let mask: Vec<_> = (0..q_len)
+            .flat_map(|i| {
+                let pos = offset + i;
+                (0..kv_len).map(move |j| {
+                    if j > pos || pos - j >= window {
+                        f32::NEG_INFINITY
+                    } else {
+                        0f32
+                    }
+                })
+            })
+            .collect();
+        Tensor::from_data(mask, shape![q_len, kv_len], device.clone())
+    }
+}
+
+pub struct GemmaAttnInput {
+    pub input: Tensor,
+    pub cache: Option<KVEntry>,
+}
+
+impl Module for GemmaSelfAttention {
+    type Input = GemmaAttnInput;
+
+    fn schedule(&self, input: Self::Input) -> anyhow::Result<Tensor> {
+        let GemmaAttnInput { input, cache } = input;
+        let device = input.device().clone();
+
+        let query_states = self.q.schedule(input.clone())?;
+        let key_states = self.k.schedule(input.clone())?;
+        let value_states = self.v.schedule(input)?;
+
+        let query_states = self.sdpa.split_heads(query_states)?;
+        let key_states = self.sdpa.split_heads(key_states)?;
+        let value_states = self.sdpa.split_heads(value_states)?;
+
+        let offset = cache.as_ref().map(|kv| kv.entries).unwrap_or(0);
+        let q_dt = query_states.dt();
+        let q_len = query_states.shape()[2];
+        let query_states = self
+            .rope
+            .schedule(RotaryInput {
+                input: query_states.full()?,
+                offset,
+            })?
+            .cast(q_dt)?;
+        let key_states = self
+            .rope
+            .schedule(RotaryInput {
+                input: key_states.full()?,
+                offset,
+            })?
+            .cast(q_dt)?;
+
+        //Local layers only differ from global ones once the window is exceeded
+        let kv_len = offset + q_len;
+        let mask = match self.sliding_window {
+            Some(window) if kv_len > window => AttentionMask::Additive(
+                Self::sliding_window_mask(q_len, kv_len, window, &device).cast(q_dt)?,
+            ),
+            _ => AttentionMask::Causal,
+        };
+
+        let attn_output = self.sdpa.schedule(AttentionInput {
+            query: query_states,
+            key: key_states,
+            value: value_states.cast(q_dt)?,
+            mask,
+            cache,
+        })?;
+        self.o.schedule(attn_output)
+    }
+}
@@ -0,0 +1,161 @@
+use ratchet_loader::gguf::gguf::Metadata;
+
+#[derive(Debug, Clone)]
+pub struct GemmaConfig {
+    /// `gemma` or `gemma2`
+    pub architecture: String,
+    pub n_layers: usize,
+    pub d_model: usize,
+    pub n_heads: usize,
+    pub n_kv_heads: usize,
+    pub head_dim: usize,
+    pub rope_base: f32,
+    pub context_length: usize,
+    pub norm_eps: f32,
+    /// Softmax scale is `query_pre_attn_scalar^-0.5`.
+    pub query_pre_attn_scalar: f32,
+    /// Gemma 2 only.
+    pub attn_logit_softcap: Option<f32>,
+    /// Gemma 2 only.
+    pub final_logit_softcap: Option<f32>,
+    /// Gemma 2 only, applies to every other layer starting from the first.
+    pub sliding_window: Option<usize>,
+    pub eos_token_id: Option<u32>,
+}
+
+impl GemmaConfig {
+    pub fn from_metadata(metadata: &Metadata) -> anyhow::Result<Self> {
+        let architecture = metadata.get("general.architecture")?.to_string()?.clone();
+        anyhow::ensure!(
+            architecture == "gemma" || architecture == "gemma2",
+            "unsupported gemma architecture: {architecture}"
+        );
+        let arch = architecture.as_str();
+        let get = |key: &str| metadata.get(&format!("{arch}.{key}"));
+        let get_opt_f32 = |key: &str| -> anyhow::Result<Option<f32>> {
+            match get(key) {
+                Ok(v) => Ok(Some(v.to_f32()?)),
+                Err(_) => Ok(None),
+            }
+        };
+
+        let n_layers = get("block_count")?.to_u32()? as usize;
+        let d_model = get("embedding_length")?.to_u32()? as usize;
+        let n_heads = get("attention.head_count")?.to_u32()? as usize;
+        let n_kv_heads = match get("attention.head_count_kv") {
+            Ok(v) => v.to_u32()? as usize,
+            Err(_) => n_heads,
+        };
+        //Gemma 7B has head_dim != d_model / n_heads
+        let head_dim = match get("attention.key_length") {
+            Ok(v) => v.to_u32()? as usize,
+            Err(_) => d_model / n_heads,
+        };
+        let rope_base = get_opt_f32("rope.freq_base")?.unwrap_or(10000.0);
+        let context_length = get("context_length")?.to_u32()? as usize;
+        let norm_eps = get("attention.layer_norm_rms_epsilon")?.to_f32()?;
+        //Without the key, only Gemma 2 27B, the one with 46 layers, scales by d_model / n_heads
+        let query_pre_attn_scalar = match get_opt_f32("attention.query_pre_attn_scalar")? {
+            Some(scalar) => scalar,
+            None if architecture == "gemma2" && n_layers == 46 => (d_model / n_heads) as f32,
+            None => head_dim as f32,
+        };
+        let sliding_window = match get("attention.sliding_window") {
+            Ok(v) => Some(v.to_u32()? as usize),
+            Err(_) => None,
+        };
+        let eos_token_id = match metadata.get("tokenizer.ggml.eos_token_id") {
+            Ok(v) => Some(v.to_u32()?),
+            Err(_) => None,
+        };
+
+        Ok(Self {
+            n_layers,
+            d_model,
+            n_heads,
+            n_kv_heads,
+            head_dim,
+            rope_base,
+            context_length,
+            norm_eps,
+            query_pre_attn_scalar,
+            attn_logit_softcap: get_opt_f32("attn_logit_softcapping")?,
+            final_logit_softcap: get_opt_f32("final_logit_softcapping")?,
+            sliding_window,
+            eos_token_id,
+            architecture,
+        })
+    }
+
+    pub fn is_gemma2(&self) -> bool {
+        self.architecture == "gemma2"
+    }
+
+    /// Gemma 2 alternates between local (sliding window) and global attention.
+    pub fn sliding_window(&self, layer_index: usize) -> Option<usize> {
+        if layer_index % 2 == 0 {
+            self.sliding_window
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GemmaConfig;
+    use crate::config::tests::metadata;
+    use ratchet_loader::gguf::gguf::Value;
+
+    #[test]
+    fn reads_gemma_config() -> anyhow::Result<()> {
+        let config = GemmaConfig::from_metadata(&metadata(
+            "gemma",
+            &[("attention.key_length", Value::U32(32))],
+        ))?;
+        assert!(!config.is_gemma2());
+        assert_eq!((config.n_heads, config.head_dim), (4, 32));
+        assert_eq!(config.query_pre_attn_scalar, 32.);
+        assert_eq!(config.sliding_window(0), None);
+
+        let overrides = [
+            ("attention.query_pre_attn_scalar", Value::F32(16.)),
+            ("attention.sliding_window", Value::U32(64)),
+            ("attn_logit_softcapping", Value::F32(50.)),
+        ];
+        let config = GemmaConfig::from_metadata(&metadata("gemma2", &overrides))?;
+        assert_eq!(config.query_pre_attn_scalar, 16.);
+        assert_eq!(config.attn_logit_softcap, Some(50.));
+        assert_eq!(config.final_logit_softcap, None);
+        assert_eq!(
+            (config.sliding_window(0), config.sliding_window(1)),
+            (Some(64), None)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn scales_gemma2_27b_by_model_width() -> anyhow::Result<()> {
+        let twenty_seven_b = [
+            ("block_count", Value::U32(46)),
+            ("embedding_length", Value::U32(4608)),
+            ("attention.head_count", Value::U32(32)),
+            ("attention.head_count_kv", Value::U32(16)),
+            ("attention.key_length", Value::U32(128)),
+        ];
+        let config = GemmaConfig::from_metadata(&metadata("gemma2", &twenty_seven_b))?;
+        assert_eq!(config.head_dim, 128);
+        assert_eq!(config.query_pre_attn_scalar, 144.);
+
+        //9B uses the head dimension
+        let nine_b = [
+            ("block_count", Value::U32(42)),
+            ("embedding_length", Value::U32(3584)),
+            ("attention.head_count", Value::U32(16)),
+            ("attention.key_length", Value::U32(256)),
+        ];
+        let config = GemmaConfig::from_metadata(&metadata("gemma2", &nine_b))?;
+        assert_eq!(config.query_pre_attn_scalar, 256.);
+        Ok(())
+    }
+}
@@ -0,0 +1,101 @@
+use crate::gemma::Gemma;
+use crate::{PipelinedDecoder, TokenOutputStream};
+use ratchet::Tensor;
+use ratchet_nn::Module;
+use tokenizers::Tokenizer;
+
+const MAX_TOKENS: usize = 2048;
+
+#[cfg(target_arch = "wasm32")]
+pub async fn generate(
+    model: &mut Gemma,
+    tokenizer: Tokenizer,
+    prompt: String,
+    callback: impl Fn(String),
+) -> anyhow::Result<()> {
+    use web_time::Instant;
+    log::warn!("Prompt: {}", prompt);
+
+    let mut tos = TokenOutputStream::new(tokenizer);
+    let eos = model.config.eos_token_id.map(|t| t as i32);
+
+    let encoding = tos.tokenizer().encode(prompt, true).unwrap();
+    let tokens = encoding
+        .get_ids()
+        .iter()
+        .map(|&x| x as i32)
+        .collect::<Vec<_>>();
+    let device = model.device.clone();
+    let max_cache = model.max_cache();
+    let step = |input: Tensor| -> anyhow::Result<Tensor> {
+        let seq_len = input.shape()[1];
+        let logits = model.schedule(input)?;
+        model.cache_mut().update(seq_len);
+        Ok(logits)
+    };
+    let on_token = |token: i32| -> anyhow::Result<()> {
+        if let Some(t) = tos.next_token(token as u32)? {
+            callback(t);
+        }
+        Ok(())
+    };
+    let decoder = PipelinedDecoder::new(eos, MAX_TOKENS.saturating_sub(tokens.len()))
+        .within_cache(tokens.len(), max_cache)?;
+    let start = Instant::now();
+    let generated = decoder.run(&tokens, &device, step, on_token).await?;
+    let elapsed = start.elapsed();
+    log::warn!("Elapsed: {:?}", elapsed);
+    log::warn!(
+        "Tok/s {}",
+        (tokens.len() + generated.len()) as f64 / elapsed.as_secs_f64()
+    );
+    model.reset();
+    Ok(())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn generate(
+    model: &mut Gemma,
+    tokenizer: Tokenizer,
+    prompt: String,
+    callback: impl Fn(String),
+) -> anyhow::Result<()> {
+    use web_time::Instant;
+    log::warn!("Prompt: {}", prompt);
+
+    let mut tos = TokenOutputStream::new(tokenizer);
+    let eos = model.config.eos_token_id.map(|t| t as i32);
+
+    let encoding = tos.tokenizer().encode(prompt, true).unwrap();
+    let tokens = encoding
+        .get_ids()
+        .iter()
+        .map(|&x| x as i32)
+        .collect::<Vec<_>>();
+    let device = model.device.clone();
+    let max_cache = model.max_cache();
+    let step = |input: Tensor| -> anyhow::Result<Tensor> {
+        let seq_len = input.shape()[1];
+        let logits = model.schedule(input)?;
+        model.cache_mut().update(seq_len);
+        Ok(logits)
+    };
+    let on_token = |token: i32| -> anyhow::Result<()> {
+        if let Some(t) = tos.next_token(token as u32)? {
+            callback(t);
+        }
+        Ok(())
+    };
+    let decoder = PipelinedDecoder::new(eos, MAX_TOKENS.saturating_sub(tokens.len()))
+        .within_cache(tokens.len(), max_cache)?;
+    let start = Instant::now();
+    let generated = pollster::block_on(decoder.run(&tokens, &device, step, on_token))?;
+    let elapsed = start.elapsed();
+    log::warn!("Elapsed: {:?}", elapsed);
+    log::warn!(
+        "Tok/s {}",
+        (tokens.len() + generated.len()) as f64 / elapsed.as_secs_f64()
+    );
+    model.reset();
+    Ok(())
+}
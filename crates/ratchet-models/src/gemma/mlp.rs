@@ -0,0 +1,24 @@
+use ratchet::Tensor;
+use ratchet_nn::{Linear, Module};
+
+/// GeGLU feed forward network.
+///
+/// `down(gelu(gate(x)) * up(x))`
+#[derive(Debug, derive_new::new)]
+pub struct MLP {
+    gate_proj: Linear,
+    up_proj: Linear,
+    down_proj: Linear,
+}
+
+impl Module for MLP {
+    type Input = Tensor;
+
+    fn schedule(&self, input: Self::Input) -> anyhow::Result<Tensor> {
+        let input_dt = input.dt();
+        let gate = self.gate_proj.schedule(input.clone())?;
+        let up_states = self.up_proj.schedule(input)?;
+        let up_states = up_states.mul(gate.full()?.gelu()?.cast(input_dt)?)?;
+        self.down_proj.schedule(up_states)
+    }
+}
@@ -0,0 +1,9 @@
+mod attn;
+mod config;
+mod generate;
+mod mlp;
+mod model;
+
+pub use config::GemmaConfig;
+pub use generate::generate;
+pub use model::Gemma;
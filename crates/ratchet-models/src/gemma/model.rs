@@ -0,0 +1,239 @@
+use half::f16;
+use ratchet::{shape, DType, Device, Tensor};
+use ratchet_loader::gguf::gguf::{Header, TensorReader};
+#[cfg(not(target_arch = "wasm32"))]
+use ratchet_loader::MappedFile;
+use ratchet_nn::{Embedding, KVCache, KVEntry, Linear, Module, RMSNorm};
+
+use super::{
+    attn::{GemmaAttnInput, GemmaSelfAttention},
+    mlp::MLP,
+    GemmaConfig,
+};
+
+#[cfg(target_arch = "wasm32")]
+use {crate::ratchet_from_gguf_web, crate::TensorMap};
+
+// Gemma scales RMSNorm by `(1 + weight)`. llama.cpp folds the `+ 1` into the stored weights
+// when exporting to GGUF, so the norms below are plain `RMSNorm`s.
+#[derive(Debug)]
+pub struct DecoderLayer {
+    input_norm: RMSNorm,
+    self_attn: GemmaSelfAttention,
+    post_attn_norm: Option<RMSNorm>,
+    ffn_norm: RMSNorm,
+    mlp: MLP,
+    post_ffn_norm: Option<RMSNorm>,
+}
+
+impl DecoderLayer {
+    fn load_inner<F>(config: &GemmaConfig, layer_index: usize, mut lt: F) -> anyhow::Result<Self>
+    where
+        F: FnMut(&str) -> anyhow::Result<Tensor>,
+    {
+        let self_attn = GemmaSelfAttention::load(config, layer_index, &mut lt)?;
+
+        let eps = config.norm_eps;
+        let input_norm = RMSNorm::new(lt("attn_norm.weight")?, eps);
+        let ffn_norm = RMSNorm::new(lt("ffn_norm.weight")?, eps);
+        let (post_attn_norm, post_ffn_norm) = if config.is_gemma2() {
+            (
+                Some(RMSNorm::new(lt("post_attention_norm.weight")?, eps)),
+                Some(RMSNorm::new(lt("post_ffw_norm.weight")?, eps)),
+            )
+        } else {
+            (None, None)
+        };
+
+        let mlp = MLP::new(
+            Linear::new(lt("ffn_gate.weight")?, None),
+            Linear::new(lt("ffn_up.weight")?, None),
+            Linear::new(lt("ffn_down.weight")?, None),
+        );
+        Ok(Self {
+            input_norm,
+            self_attn,
+            post_attn_norm,
+            ffn_norm,
+            mlp,
+            post_ffn_norm,
+        })
+    }
+}
+
+pub struct DecoderLayerInput {
+    pub x: Tensor,
+    pub cache: Option<KVEntry>,
+}
+
+impl Module for DecoderLayer {
+    type Input = DecoderLayerInput;
+
+    fn schedule(&self, input: Self::Input) -> anyhow::Result<Tensor> {
+        let DecoderLayerInput { x, cache } = input;
+        let residual = x.clone();
+        let xs = self.input_norm.schedule(x)?;
+        let mut attn_output = self
+            .self_attn
+            .schedule(GemmaAttnInput { input: xs, cache })?;
+        if let Some(norm) = &self.post_attn_norm {
+            attn_output = norm.schedule(attn_output)?;
+        }
+        let xs = residual.add(attn_output)?;
+        let residual = xs.clone();
+        let xs = self.ffn_norm.schedule(xs)?;
+        let mut xs = self.mlp.schedule(xs)?;
+        if let Some(norm) = &self.post_ffn_norm {
+            xs = norm.schedule(xs)?;
+        }
+        let xs = residual.add(xs)?;
+        Ok(xs)
+    }
+}
+
+/// # Gemma
+///
+/// Serves both the `gemma` & `gemma2` GGUF architectures.
+/// Gemma 2 adds sandwich norms, logit soft-capping & alternating local/global attention.
+#[derive(Debug)]
+pub struct Gemma {
+    pub embedding: Embedding,
+    pub embedding_scale: Tensor,
+    pub layers: Vec<DecoderLayer>,
+    pub ln_post: RMSNorm,
+    pub lm_head: Linear,
+    pub kv_cache: KVCache,
+    pub config: GemmaConfig,
+    pub device: Device,
+}
+
+impl Module for Gemma {
+    type Input = Tensor;
+
+    fn schedule(&self, input: Self::Input) -> anyhow::Result<Tensor> {
+        let x = self.embedding.schedule(input)?;
+        let x_dt = x.dt();
+        let mut x = x.mul(self.embedding_scale.clone().cast(x_dt)?)?;
+        let [_, seq_len, n_state]: [usize; 3] = x.shape().try_into()?;
+
+        for (layer_idx, layer) in self.layers.iter().enumerate() {
+            let input = DecoderLayerInput {
+                x,
+                cache: Some(self.kv_cache[layer_idx].clone()),
+            };
+            x = layer.schedule(input)?;
+        }
+        x = self.ln_post.schedule(x)?;
+        x = x.slice(&[0..1, seq_len - 1..seq_len, 0..n_state])?;
+        let logits = self.lm_head.schedule(x)?;
+
+        match self.config.final_logit_softcap {
+            Some(cap) => {
+                let cap = Tensor::from_data([cap], shape![1], self.device.clone());
+                logits.full()?.div(cap.clone())?.tanh()?.mul(cap)
+            }
+            None => Ok(logits),
+        }
+    }
+}
+
+impl Gemma {
+    const MAX_CACHE: usize = 4096; //TODO: configurable
+
+    pub fn load<R: TensorReader>(
+        header: Header,
+        reader: &mut R,
+        device: &Device,
+    ) -> anyhow::Result<Self> {
+        let lt = |name: &str| header.tensor(reader, name, device);
+        Self::load_inner(&header, lt, device)
+    }
+
+    /// Load from a memory mapped GGUF file, borrowing weights from the map where possible.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load_mapped(header: Header, file: &MappedFile, device: &Device) -> anyhow::Result<Self> {
+        let lt = |name: &str| header.tensor_mapped(file, name, device);
+        Self::load_inner(&header, lt, device)
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub async fn from_web(header: Header, mut tensors: TensorMap) -> anyhow::Result<Self> {
+        let device = Device::request_device(ratchet::DeviceRequest::GPU).await?;
+        let lt = |name: &str| {
+            let tensor = tensors
+                .remove(name)
+                .ok_or_else(|| anyhow::anyhow!("missing tensor {name}"))?;
+            ratchet_from_gguf_web(tensor, &device)
+        };
+        Self::load_inner(&header, lt, &device)
+    }
+
+    fn load_inner<F>(header: &Header, mut lt: F, device: &Device) -> anyhow::Result<Self>
+    where
+        F: FnMut(&str) -> anyhow::Result<Tensor>,
+    {
+        let config = GemmaConfig::from_metadata(&header.metadata)?;
+
+        //Output projection is always tied to the token embeddings
+        let token_embd = lt("token_embd.weight")?;
+        let embedding = Embedding::new(token_embd.clone());
+        let lm_head = Linear::new(token_embd, None);
+        let embedding_scale =
+            Tensor::from_data([(config.d_model as f32).sqrt()], shape![1], device.clone());
+
+        let layers = (0..config.n_layers)
+            .map(|i| {
+                DecoderLayer::load_inner(&config, i, |name: &str| {
+                    lt(&format!("blk.{}.{}", i, name))
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let ln_post = RMSNorm::new(lt("output_norm.weight")?, config.norm_eps);
+
+        let max_cache = config.context_length.min(Self::MAX_CACHE);
+        let kv_cache = match device.compute_precision() {
+            DType::F16 => KVCache::with_kv_heads::<f16>(
+                config.n_layers as _,
+                1,
+                config.n_kv_heads,
+                max_cache,
+                config.head_dim,
+                device,
+            ),
+            DType::F32 => KVCache::with_kv_heads::<f32>(
+                config.n_layers as _,
+                1,
+                config.n_kv_heads,
+                max_cache,
+                config.head_dim,
+                device,
+            ),
+            dt => anyhow::bail!("unsupported compute precision {dt:?}"),
+        };
+
+        Ok(Self {
+            embedding,
+            embedding_scale,
+            layers,
+            ln_post,
+            lm_head,
+            kv_cache,
+            config,
+            device: device.clone(),
+        })
+    }
+
+    pub fn reset(&mut self) {
+        self.kv_cache.reset();
+    }
+
+    pub fn cache_mut(&mut self) -> &mut KVCache {
+        &mut self.kv_cache
+    }
+
+    /// The number of positions held by the KV cache.
+    pub fn max_cache(&self) -> usize {
+        self.config.context_length.min(Self::MAX_CACHE)
+    }
+}
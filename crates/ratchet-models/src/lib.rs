@@ -1,10 +1,19 @@
 #![allow(clippy::upper_case_acronyms)]
+pub mod bert;
+mod config;
+pub mod gemma;
+pub mod llama;
 pub mod moondream;
 pub mod phi2;
 pub mod phi3;
+mod pipeline;
 pub mod registry;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod store;
 mod token_stream;
 pub mod whisper;
+pub use config::{DecoderConfig, NormKind};
+pub use pipeline::PipelinedDecoder;
 pub use token_stream::TokenOutputStream;
 
 #[cfg(target_arch = "wasm32")]
@@ -0,0 +1,123 @@
+use ratchet::{shape, AttentionMask, Tensor};
+use ratchet_nn::{
+    AttentionInput, GroupedQueryAttention, KVEntry, Linear, Module, RotaryEmbedding, RotaryInput,
+};
+
+use super::LlamaConfig;
+
+#[derive(Debug)]
+pub struct LlamaSelfAttention {
+    q: Linear,
+    k: Linear,
+    v: Linear,
+    o: Linear,
+    rope: RotaryEmbedding,
+    sdpa: GroupedQueryAttention,
+    interleaved_rope: bool,
+}
+
+impl LlamaSelfAttention {
+    pub fn load<F>(config: &LlamaConfig, mut lt: F, has_bias: bool) -> anyhow::Result<Self>
+    where
+        F: FnMut(&str) -> anyhow::Result<Tensor>,
+    {
+        let mut linear = |name: &str| -> anyhow::Result<Linear> {
+            let w = lt(&format!("{}.weight", name))?;
+            let b = if has_bias && name != "attn_output" {
+                Some(lt(&format!("{}.bias", name))?)
+            } else {
+                None
+            };
+            Ok(Linear::new(w, b))
+        };
+        let q = linear("attn_q")?;
+        let k = linear("attn_k")?;
+        let v = linear("attn_v")?;
+        let o = linear("attn_output")?;
+
+        let rope = RotaryEmbedding::new(config.rope_dim, false, config.rope_base, 1.0);
+        let sdpa = GroupedQueryAttention::with_default_scale(
+            config.n_heads,
+            config.n_kv_heads,
+            config.head_dim,
+        );
+        Ok(Self {
+            q,
+            k,
+            v,
+            o,
+            rope,
+            sdpa,
+            interleaved_rope: config.architecture.interleaved_rope(),
+        })
+    }
+
+    /// Reorders each head from adjacent pairs `[x0, x1, x2, x3, ..]` into halves
+    /// `[x0, x2, .., x1, x3, ..]`, so the half-rotation RoPE kernel can be used.
+    ///
+    /// The same permutation is applied to Q & K, so their dot product is unchanged.
+    fn deinterleave(&self, x: Tensor, n_heads: usize) -> anyhow::Result<Tensor> {
+        let [batch_size, seq_len, n_state]: [usize; 3] = x.shape().try_into()?;
+        let hdim = self.sdpa.head_dim();
+        if hdim % 2 != 0 || n_state != n_heads * hdim {
+            anyhow::bail!(
+                "cannot deinterleave {n_state} features into {n_heads} heads of even dimension, got {hdim}"
+            );
+        }
+        x.view(shape![batch_size, seq_len * n_heads, hdim / 2, 2])?
+            .permute(&[0, 1, 3, 2])?
+            .view(shape![batch_size, seq_len, n_state])
+    }
+}
+
+pub struct LlamaAttnInput {
+    pub input: Tensor,
+    pub cache: Option<KVEntry>,
+}
+
+impl Module for LlamaSelfAttention {
+    type Input = LlamaAttnInput;
+
+    fn schedule(&self, input: Self::Input) -> anyhow::Result<Tensor> {
+        let LlamaAttnInput { input, cache } = input;
+        let (n_heads, n_kv_heads) = (self.sdpa.n_heads(), self.sdpa.n_kv_heads());
+
+        let mut query_states = self.q.schedule(input.clone())?;
+        let mut key_states = self.k.schedule(input.clone())?;
+        let value_states = self.v.schedule(input)?;
+        if self.interleaved_rope {
+            query_states = self.deinterleave(query_states, n_heads)?;
+            key_states = self.deinterleave(key_states, n_kv_heads)?;
+        }
+
+        let query_states = self.sdpa.split_heads(query_states)?;
+        let key_states = self.sdpa.split_heads(key_states)?;
+        let value_states = self.sdpa.split_heads(value_states)?;
+
+        let offset = cache.as_ref().map(|kv| kv.entries).unwrap_or(0);
+        let q_dt = query_states.dt();
+        let query_states = self
+            .rope
+            .schedule(RotaryInput {
+                input: query_states.full()?,
+                offset,
+            })?
+            .cast(q_dt)?;
+        let key_states = self
+            .rope
+            .schedule(RotaryInput {
+                input: key_states.full()?,
+                offset,
+            })?
+            .cast(q_dt)?;
+
+        let attn_output = self.sdpa.schedule(AttentionInput {
+            query: query_states,
+            key: key_states,
+            value: value_states.cast(q_dt)?,
+            mask: AttentionMask::Causal,
+            cache,
+        })?;
+        self.o.schedule(attn_output)
+    }
+}
@@ -0,0 +1,147 @@
+use crate::config::{DecoderConfig, NormKind};
+use ratchet_loader::gguf::gguf::Metadata;
+
+/// GGUF `general.architecture` values served by the Llama implementation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LlamaArchitecture {
+    Llama,
+    Mistral,
+    Qwen2,
+}
+
+impl LlamaArchitecture {
+    pub fn from_gguf(architecture: &str) -> anyhow::Result<Self> {
+        match architecture {
+            "llama" => Ok(Self::Llama),
+            "mistral" => Ok(Self::Mistral),
+            "qwen2" => Ok(Self::Qwen2),
+            other => anyhow::bail!("unsupported llama architecture: {other}"),
+        }
+    }
+
+    /// Prefix of the architecture specific metadata keys, e.g `llama.block_count`.
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            Self::Llama => "llama",
+            Self::Mistral => "mistral",
+            Self::Qwen2 => "qwen2",
+        }
+    }
+
+    /// llama.cpp permutes the Q & K projections of Llama & Mistral checkpoints so that RoPE
+    /// rotates adjacent pairs. Qwen2 is exported untouched and rotates the two halves.
+    pub fn interleaved_rope(&self) -> bool {
+        matches!(self, Self::Llama | Self::Mistral)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LlamaConfig {
+    pub architecture: LlamaArchitecture,
+    pub n_layers: usize,
+    pub d_model: usize,
+    pub n_heads: usize,
+    pub n_kv_heads: usize,
+    pub head_dim: usize,
+    pub rope_dim: usize,
+    pub rope_base: f32,
+    pub context_length: usize,
+    pub norm_eps: f32,
+    pub bos_token_id: Option<u32>,
+    pub eos_token_id: Option<u32>,
+}
+
+impl LlamaConfig {
+    pub fn from_metadata(metadata: &Metadata) -> anyhow::Result<Self> {
+        let architecture =
+            LlamaArchitecture::from_gguf(metadata.get("general.architecture")?.to_string()?)?;
+        let DecoderConfig {
+            n_layers,
+            d_model,
+            n_heads,
+            n_kv_heads,
+            head_dim,
+            rope_dim,
+            rope_base,
+            context_length,
+            norm_eps,
+        } = DecoderConfig::from_metadata(metadata, architecture.prefix(), NormKind::RMSNorm)?;
+        //Deinterleaving permutes whole heads, in pairs
+        if architecture.interleaved_rope() && (rope_dim != head_dim || head_dim % 2 != 0) {
+            anyhow::bail!(
+                "{architecture:?} rotates pairs, so RoPE must cover an even head dimension ({head_dim}), got {rope_dim}"
+            );
+        }
+
+        let token_id = |key: &str| -> anyhow::Result<Option<u32>> {
+            match metadata.get(key) {
+                Ok(v) => Ok(Some(v.to_u32()?)),
+                Err(_) => Ok(None),
+            }
+        };
+
+        Ok(Self {
+            architecture,
+            n_layers,
+            d_model,
+            n_heads,
+            n_kv_heads,
+            head_dim,
+            rope_dim,
+            rope_base,
+            context_length,
+            norm_eps,
+            bos_token_id: token_id("tokenizer.ggml.bos_token_id")?,
+            eos_token_id: token_id("tokenizer.ggml.eos_token_id")?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LlamaArchitecture, LlamaConfig};
+    use crate::config::tests::metadata;
+    use ratchet_loader::gguf::gguf::Value;
+
+    #[test]
+    fn reads_llama_config() -> anyhow::Result<()> {
+        let metadata = metadata(
+            "llama",
+            &[
+                ("attention.head_count_kv", Value::U32(2)),
+                ("rope.dimension_count", Value::U32(16)),
+            ],
+        );
+        let eos = ("tokenizer.ggml.eos_token_id".to_string(), Value::U32(2));
+        let metadata = metadata
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .chain([eos])
+            .collect();
+        let config = LlamaConfig::from_metadata(&metadata)?;
+        assert_eq!(config.architecture, LlamaArchitecture::Llama);
+        assert_eq!(
+            (config.n_heads, config.n_kv_heads, config.head_dim),
+            (4, 2, 16)
+        );
+        assert_eq!((config.rope_dim, config.rope_base), (16, 10000.0));
+        assert_eq!(config.norm_eps, 1e-6);
+        assert_eq!((config.bos_token_id, config.eos_token_id), (None, Some(2)));
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_partial_interleaved_rope() -> anyhow::Result<()> {
+        //Rotating 8 of 16 features is fine for halves, not for pairs
+        let config = LlamaConfig::from_metadata(&metadata("qwen2", &[]))?;
+        assert_eq!(config.rope_dim, 8);
+        assert!(LlamaConfig::from_metadata(&metadata("llama", &[])).is_err());
+        assert!(LlamaConfig::from_metadata(&metadata("mistral", &[])).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_unknown_architecture() {
+        assert!(LlamaConfig::from_metadata(&metadata("gpt2", &[])).is_err());
+    }
+}
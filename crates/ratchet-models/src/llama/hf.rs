@@ -0,0 +1,297 @@
+use ratchet_loader::gguf::gguf::Value;
+use serde::Deserialize;
+
+use super::LlamaArchitecture;
+
+/// The `config.json` of a Hugging Face `transformers` checkpoint.
+#[derive(Debug, Clone, Deserialize)]
+pub struct HfLlamaConfig {
+    pub model_type: String,
+    pub hidden_size: usize,
+    pub intermediate_size: usize,
+    pub num_hidden_layers: usize,
+    pub num_attention_heads: usize,
+    pub num_key_value_heads: Option<usize>,
+    pub head_dim: Option<usize>,
+    pub rms_norm_eps: f32,
+    pub rope_theta: Option<f32>,
+    pub max_position_embeddings: usize,
+    pub bos_token_id: Option<u32>,
+    /// A single id, or a list of ids of which the first is used.
+    pub eos_token_id: Option<serde_json::Value>,
+}
+
+/// # HF Llama
+///
+/// Converts a Hugging Face checkpoint of the `llama`, `mistral` or `qwen2` architecture to the
+/// GGUF layout read by [Llama](super::Llama): tensor names, hyperparameters, and the Q & K
+/// permutation llama.cpp applies for interleaved RoPE.
+#[derive(Debug, Clone)]
+pub struct HfLlama {
+    pub architecture: LlamaArchitecture,
+    pub config: HfLlamaConfig,
+}
+
+impl HfLlama {
+    pub fn from_config(config: HfLlamaConfig) -> anyhow::Result<Self> {
+        let architecture = LlamaArchitecture::from_gguf(&config.model_type)?;
+        let head_dim = config.hidden_size / config.num_attention_heads;
+        if config.hidden_size % config.num_attention_heads != 0 {
+            anyhow::bail!(
+                "hidden_size {} is not divisible by {} heads",
+                config.hidden_size,
+                config.num_attention_heads
+            );
+        }
+        if config.head_dim.is_some_and(|d| d != head_dim) {
+            anyhow::bail!("head_dim differs from hidden_size / num_attention_heads");
+        }
+        Ok(Self {
+            architecture,
+            config,
+        })
+    }
+
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        //Checked first, the configs of other architectures may lack the fields we require
+        #[derive(Deserialize)]
+        struct ModelType {
+            model_type: String,
+        }
+        let ModelType { model_type } = serde_json::from_str(json)?;
+        if LlamaArchitecture::from_gguf(&model_type).is_err() {
+            anyhow::bail!(
+                "cannot convert a {model_type} checkpoint, only llama, mistral & qwen2 tensor names are mapped to GGUF"
+            );
+        }
+        Self::from_config(serde_json::from_str(json)?)
+    }
+
+    fn n_kv_heads(&self) -> usize {
+        self.config
+            .num_key_value_heads
+            .unwrap_or(self.config.num_attention_heads)
+    }
+
+    fn head_dim(&self) -> usize {
+        self.config.hidden_size / self.config.num_attention_heads
+    }
+
+    /// The GGUF metadata read by [LlamaConfig::from_metadata](super::LlamaConfig::from_metadata).
+    pub fn metadata(&self) -> anyhow::Result<Vec<(String, Value)>> {
+        let c = &self.config;
+        let arch = self.architecture.prefix();
+        let u32 = |v: usize| Value::U32(v as u32);
+        let mut metadata = vec![
+            (
+                "general.architecture".to_string(),
+                Value::String(arch.into()),
+            ),
+            (format!("{arch}.block_count"), u32(c.num_hidden_layers)),
+            (format!("{arch}.embedding_length"), u32(c.hidden_size)),
+            (
+                format!("{arch}.feed_forward_length"),
+                u32(c.intermediate_size),
+            ),
+            (
+                format!("{arch}.context_length"),
+                u32(c.max_position_embeddings),
+            ),
+            (
+                format!("{arch}.attention.head_count"),
+                u32(c.num_attention_heads),
+            ),
+            (
+                format!("{arch}.attention.head_count_kv"),
+                u32(self.n_kv_heads()),
+            ),
+            (format!("{arch}.rope.dimension_count"), u32(self.head_dim())),
+            (
+                format!("{arch}.rope.freq_base"),
+                Value::F32(c.rope_theta.unwrap_or(10000.0)),
+            ),
+            (
+                format!("{arch}.attention.layer_norm_rms_epsilon"),
+                Value::F32(c.rms_norm_eps),
+            ),
+        ];
+        if let Some(bos) = c.bos_token_id {
+            metadata.push(("tokenizer.ggml.bos_token_id".into(), Value::U32(bos)));
+        }
+        let eos = match &c.eos_token_id {
+            Some(serde_json::Value::Array(ids)) => ids.first().and_then(|id| id.as_u64()),
+            Some(id) => id.as_u64(),
+            None => None,
+        };
+        if let Some(eos) = eos {
+            let eos = u32::try_from(eos)?;
+            metadata.push(("tokenizer.ggml.eos_token_id".into(), Value::U32(eos)));
+        }
+        Ok(metadata)
+    }
+
+    /// The GGUF name of a `transformers` tensor, or `None` for tensors which aren't weights,
+    /// e.g the rotary frequencies some checkpoints store.
+    pub fn tensor_name(&self, name: &str) -> anyhow::Result<Option<String>> {
+        if name.ends_with("rotary_emb.inv_freq") {
+            return Ok(None);
+        }
+        let mapped = match name {
+            "model.embed_tokens.weight" => Some("token_embd.weight".to_string()),
+            "model.norm.weight" => Some("output_norm.weight".to_string()),
+            "lm_head.weight" => Some("output.weight".to_string()),
+            _ => name
+                .strip_prefix("model.layers.")
+                .and_then(|rest| rest.split_once('.'))
+                .and_then(|(layer, rest)| {
+                    let layer = layer.parse::<usize>().ok()?;
+                    let (module, kind) = rest.rsplit_once('.')?;
+                    let module = match module {
+                        "input_layernorm" => "attn_norm",
+                        "post_attention_layernorm" => "ffn_norm",
+                        "self_attn.q_proj" => "attn_q",
+                        "self_attn.k_proj" => "attn_k",
+                        "self_attn.v_proj" => "attn_v",
+                        "self_attn.o_proj" => "attn_output",
+                        "mlp.gate_proj" => "ffn_gate",
+                        "mlp.up_proj" => "ffn_up",
+                        "mlp.down_proj" => "ffn_down",
+                        _ => return None,
+                    };
+                    matches!(kind, "weight" | "bias")
+                        .then(|| format!("blk.{layer}.{module}.{kind}"))
+                }),
+        };
+        match mapped {
+            Some(mapped) => Ok(Some(mapped)),
+            None => anyhow::bail!(
+                "unknown tensor {name} for the {} architecture",
+                self.architecture.prefix()
+            ),
+        }
+    }
+
+    /// Reorder the rows of a tensor if llama.cpp expects them permuted.
+    ///
+    /// `transformers` rotates the two halves of each head, llama.cpp rotates adjacent pairs:
+    /// the Q & K projections of interleaved architectures are permuted from halves
+    /// `[x0, x2, .., x1, x3, ..]` to pairs `[x0, x1, x2, x3, ..]`, which the attention of
+    /// [Llama](super::Llama) undoes on load. Only whole rows move, so `data` may be the raw
+    /// bytes of any unquantized dtype.
+    pub fn permute<T: Copy>(&self, gguf_name: &str, data: Vec<T>) -> anyhow::Result<Vec<T>> {
+        if !self.architecture.interleaved_rope() {
+            return Ok(data);
+        }
+        let n_heads = if gguf_name.ends_with("attn_q.weight") || gguf_name.ends_with("attn_q.bias")
+        {
+            self.config.num_attention_heads
+        } else if gguf_name.ends_with("attn_k.weight") || gguf_name.ends_with("attn_k.bias") {
+            self.n_kv_heads()
+        } else {
+            return Ok(data);
+        };
+        let head_dim = self.head_dim();
+        let rows = n_heads * head_dim;
+        if head_dim % 2 != 0 || data.len() % rows != 0 {
+            anyhow::bail!("{gguf_name} does not have {n_heads} heads of {head_dim}");
+        }
+        let cols = data.len() / rows;
+        let half = head_dim / 2;
+        let mut permuted = Vec::with_capacity(data.len());
+        for head in 0..n_heads {
+            for pair in 0..half {
+                for side in 0..2 {
+                    let row = head * head_dim + side * half + pair;
+                    permuted.extend_from_slice(&data[row * cols..(row + 1) * cols]);
+                }
+            }
+        }
+        Ok(permuted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HfLlama;
+    use crate::llama::{LlamaArchitecture, LlamaConfig};
+    use ratchet_loader::gguf::gguf::Metadata;
+
+    const CONFIG: &str = r#"{
+        "model_type": "llama",
+        "hidden_size": 8,
+        "intermediate_size": 16,
+        "num_hidden_layers": 2,
+        "num_attention_heads": 2,
+        "num_key_value_heads": 1,
+        "rms_norm_eps": 1e-5,
+        "rope_theta": 500000.0,
+        "max_position_embeddings": 128,
+        "bos_token_id": 1,
+        "eos_token_id": [2, 3]
+    }"#;
+
+    #[test]
+    fn maps_metadata() -> anyhow::Result<()> {
+        let hf = HfLlama::from_json(CONFIG)?;
+        let metadata = hf.metadata()?.into_iter().collect::<Metadata>();
+        let config = LlamaConfig::from_metadata(&metadata)?;
+        assert_eq!(config.architecture, LlamaArchitecture::Llama);
+        assert_eq!((config.n_layers, config.d_model), (2, 8));
+        assert_eq!(
+            (config.n_heads, config.n_kv_heads, config.head_dim),
+            (2, 1, 4)
+        );
+        assert_eq!(config.rope_base, 500000.0);
+        assert_eq!(config.context_length, 128);
+        assert_eq!(
+            (config.bos_token_id, config.eos_token_id),
+            (Some(1), Some(2))
+        );
+
+        let unknown = CONFIG.replace("\"llama\"", "\"gpt2\"");
+        assert!(HfLlama::from_json(&unknown).is_err());
+        let err = HfLlama::from_json(r#"{"model_type": "gemma2"}"#).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("cannot convert a gemma2 checkpoint"));
+        Ok(())
+    }
+
+    #[test]
+    fn maps_tensor_names() -> anyhow::Result<()> {
+        let hf = HfLlama::from_json(CONFIG)?;
+        let name = |n: &str| hf.tensor_name(n);
+        assert_eq!(
+            name("model.embed_tokens.weight")?.as_deref(),
+            Some("token_embd.weight")
+        );
+        assert_eq!(
+            name("model.layers.1.self_attn.o_proj.weight")?.as_deref(),
+            Some("blk.1.attn_output.weight")
+        );
+        assert_eq!(
+            name("model.layers.0.mlp.gate_proj.weight")?.as_deref(),
+            Some("blk.0.ffn_gate.weight")
+        );
+        assert_eq!(name("model.layers.0.self_attn.rotary_emb.inv_freq")?, None);
+        assert!(name("model.layers.0.self_attn.q_norm.weight").is_err());
+        assert!(name("vision_tower.patch_embed.weight").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn permutes_rope_projections() -> anyhow::Result<()> {
+        let hf = HfLlama::from_json(CONFIG)?;
+        //2 query heads of 4 rows, 1 column
+        let q = (0..8).map(|x| x as f32).collect::<Vec<_>>();
+        let permuted = hf.permute("blk.0.attn_q.weight", q.clone())?;
+        assert_eq!(permuted, vec![0., 2., 1., 3., 4., 6., 5., 7.]);
+        //1 key head
+        let k = (0..8).map(|x| x as f32).collect::<Vec<_>>();
+        let permuted = hf.permute("blk.0.attn_k.weight", k)?;
+        assert_eq!(permuted, vec![0., 1., 4., 5., 2., 3., 6., 7.]);
+        assert_eq!(hf.permute("blk.0.attn_v.weight", q.clone())?, q);
+        assert!(hf.permute("blk.0.attn_q.weight", vec![0.; 7]).is_err());
+        Ok(())
+    }
+}
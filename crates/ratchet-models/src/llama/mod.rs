@@ -0,0 +1,11 @@
+mod attn;
+mod config;
+mod generate;
+mod hf;
+mod mlp;
+mod model;
+
+pub use config::{LlamaArchitecture, LlamaConfig};
+pub use generate::generate;
+pub use hf::{HfLlama, HfLlamaConfig};
+pub use model::Llama;
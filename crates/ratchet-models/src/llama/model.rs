@@ -0,0 +1,217 @@
+use half::f16;
+use ratchet::{DType, Device, Tensor};
+use ratchet_loader::gguf::gguf::{Header, TensorReader};
+#[cfg(not(target_arch = "wasm32"))]
+use ratchet_loader::MappedFile;
+use ratchet_nn::{Embedding, KVCache, KVEntry, Linear, Module, RMSNorm};
+
+use super::{
+    attn::{LlamaAttnInput, LlamaSelfAttention},
+    mlp::MLP,
+    LlamaConfig,
+};
+
+#[cfg(target_arch = "wasm32")]
+use {crate::ratchet_from_gguf_web, crate::TensorMap};
+
+#[derive(Debug)]
+pub struct DecoderLayer {
+    input_norm: RMSNorm,
+    self_attn: LlamaSelfAttention,
+    ffn_norm: RMSNorm,
+    mlp: MLP,
+}
+
+impl DecoderLayer {
+    fn load_inner<F>(
+        header: &Header,
+        config: &LlamaConfig,
+        layer_index: usize,
+        mut lt: F,
+    ) -> anyhow::Result<Self>
+    where
+        F: FnMut(&str) -> anyhow::Result<Tensor>,
+    {
+        let has_bias = header
+            .tensor_infos
+            .contains_key(&format!("blk.{}.attn_q.bias", layer_index));
+        let self_attn = LlamaSelfAttention::load(config, &mut lt, has_bias)?;
+
+        let input_norm = RMSNorm::new(lt("attn_norm.weight")?, config.norm_eps);
+        let ffn_norm = RMSNorm::new(lt("ffn_norm.weight")?, config.norm_eps);
+
+        let mlp = MLP::new(
+            Linear::new(lt("ffn_gate.weight")?, None),
+            Linear::new(lt("ffn_up.weight")?, None),
+            Linear::new(lt("ffn_down.weight")?, None),
+        );
+        Ok(Self {
+            input_norm,
+            self_attn,
+            ffn_norm,
+            mlp,
+        })
+    }
+}
+
+pub struct DecoderLayerInput {
+    pub x: Tensor,
+    pub cache: Option<KVEntry>,
+}
+
+impl Module for DecoderLayer {
+    type Input = DecoderLayerInput;
+
+    fn schedule(&self, input: Self::Input) -> anyhow::Result<Tensor> {
+        let DecoderLayerInput { x, cache } = input;
+        let residual = x.clone();
+        let xs = self.input_norm.schedule(x)?;
+        let attn_output = self
+            .self_attn
+            .schedule(LlamaAttnInput { input: xs, cache })?;
+        let xs = residual.add(attn_output)?;
+        let residual = xs.clone();
+        let xs = self.ffn_norm.schedule(xs)?;
+        let xs = self.mlp.schedule(xs)?;
+        let xs = residual.add(xs)?;
+        Ok(xs)
+    }
+}
+
+/// # Llama
+///
+/// Decoder-only transformer shared by the `llama`, `mistral` & `qwen2` GGUF architectures.
+/// RMSNorm, SwiGLU feed forward & grouped-query attention.
+#[derive(Debug)]
+pub struct Llama {
+    pub embedding: Embedding,
+    pub layers: Vec<DecoderLayer>,
+    pub ln_post: RMSNorm,
+    pub lm_head: Linear,
+    pub kv_cache: KVCache,
+    pub config: LlamaConfig,
+    pub device: Device,
+}
+
+impl Module for Llama {
+    type Input = Tensor;
+
+    fn schedule(&self, input: Self::Input) -> anyhow::Result<Tensor> {
+        let mut x = self.embedding.schedule(input)?;
+        let [_, seq_len, n_state]: [usize; 3] = x.shape().try_into()?;
+
+        for (layer_idx, layer) in self.layers.iter().enumerate() {
+            let input = DecoderLayerInput {
+                x,
+                cache: Some(self.kv_cache[layer_idx].clone()),
+            };
+            x = layer.schedule(input)?;
+        }
+        x = self.ln_post.schedule(x)?;
+        x = x.slice(&[0..1, seq_len - 1..seq_len, 0..n_state])?;
+        let logits = self.lm_head.schedule(x)?;
+        Ok(logits)
+    }
+}
+
+impl Llama {
+    const MAX_CACHE: usize = 4096; //TODO: configurable
+
+    pub fn load<R: TensorReader>(
+        header: Header,
+        reader: &mut R,
+        device: &Device,
+    ) -> anyhow::Result<Self> {
+        let lt = |name: &str| header.tensor(reader, name, device);
+        Self::load_inner(&header, lt, device)
+    }
+
+    /// Load from a memory mapped GGUF file, borrowing weights from the map where possible.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load_mapped(header: Header, file: &MappedFile, device: &Device) -> anyhow::Result<Self> {
+        let lt = |name: &str| header.tensor_mapped(file, name, device);
+        Self::load_inner(&header, lt, device)
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub async fn from_web(header: Header, mut tensors: TensorMap) -> anyhow::Result<Self> {
+        let device = Device::request_device(ratchet::DeviceRequest::GPU).await?;
+        let lt = |name: &str| {
+            let tensor = tensors
+                .remove(name)
+                .ok_or_else(|| anyhow::anyhow!("missing tensor {name}"))?;
+            ratchet_from_gguf_web(tensor, &device)
+        };
+        Self::load_inner(&header, lt, &device)
+    }
+
+    fn load_inner<F>(header: &Header, mut lt: F, device: &Device) -> anyhow::Result<Self>
+    where
+        F: FnMut(&str) -> anyhow::Result<Tensor>,
+    {
+        let config = LlamaConfig::from_metadata(&header.metadata)?;
+
+        let token_embd = lt("token_embd.weight")?;
+        let embedding = Embedding::new(token_embd.clone());
+
+        let layers = (0..config.n_layers)
+            .map(|i| {
+                DecoderLayer::load_inner(header, &config, i, |name: &str| {
+                    lt(&format!("blk.{}.{}", i, name))
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let ln_post = RMSNorm::new(lt("output_norm.weight")?, config.norm_eps);
+        //Smaller checkpoints tie the output projection to the token embeddings
+        let lm_head = if header.tensor_infos.contains_key("output.weight") {
+            Linear::new(lt("output.weight")?, None)
+        } else {
+            Linear::new(token_embd, None)
+        };
+
+        let max_cache = config.context_length.min(Self::MAX_CACHE);
+        let kv_cache = match device.compute_precision() {
+            DType::F16 => KVCache::with_kv_heads::<f16>(
+                config.n_layers as _,
+                1,
+                config.n_kv_heads,
+                max_cache,
+                config.head_dim,
+                device,
+            ),
+            DType::F32 => KVCache::with_kv_heads::<f32>(
+                config.n_layers as _,
+                1,
+                config.n_kv_heads,
+                max_cache,
+                config.head_dim,
+                device,
+            ),
+            dt => anyhow::bail!("unsupported compute precision {dt:?}"),
+        };
+
+        Ok(Self {
+            embedding,
+            layers,
+            ln_post,
+            lm_head,
+            kv_cache,
+            config,
+            device: device.clone(),
+        })
+    }
+
+    pub fn reset(&mut self) {
+        self.kv_cache.reset();
+    }
+
+    pub fn cache_mut(&mut self) -> &mut KVCache {
+        &mut self.kv_cache
+    }
+
+    /// The number of positions held by the KV cache.
+    pub fn max_cache(&self) -> usize {
+        self.config.context_length.min(Self::MAX_CACHE)
+    }
+}
@@ -0,0 +1,136 @@
+use crate::config::{DecoderConfig, NormKind};
+use ratchet_loader::gguf::gguf::Metadata;
+
+/// Hyperparameters of a Moondream checkpoint, read from the `moondream.*` GGUF metadata keys.
+///
+/// The first Moondream exports carry no `general.architecture`, those fall back to the
+/// moondream2 defaults.
+#[derive(Debug, Clone)]
+pub struct MoondreamConfig {
+    pub n_layers: usize,
+    pub d_model: usize,
+    pub n_heads: usize,
+    pub n_kv_heads: usize,
+    pub head_dim: usize,
+    pub rope_dim: usize,
+    pub rope_base: f32,
+    pub context_length: usize,
+    pub norm_eps: f32,
+    pub vision_n_layers: usize,
+    pub vision_d_model: usize,
+    pub vision_n_heads: usize,
+    pub vision_norm_eps: f32,
+}
+
+impl Default for MoondreamConfig {
+    fn default() -> Self {
+        Self {
+            n_layers: 24,
+            d_model: 2048,
+            n_heads: 32,
+            n_kv_heads: 32,
+            head_dim: 64,
+            rope_dim: 32,
+            rope_base: 10000.0,
+            context_length: 2048,
+            norm_eps: 1e-5,
+            vision_n_layers: 27,
+            vision_d_model: 1152,
+            vision_n_heads: 16,
+            vision_norm_eps: 1e-5,
+        }
+    }
+}
+
+impl MoondreamConfig {
+    pub fn from_metadata(metadata: &Metadata) -> anyhow::Result<Self> {
+        let Ok(architecture) = metadata.get("general.architecture") else {
+            return Ok(Self::default());
+        };
+        let architecture = architecture.to_string()?;
+        if architecture != "moondream" {
+            anyhow::bail!("unsupported moondream architecture: {architecture}");
+        }
+        let DecoderConfig {
+            n_layers,
+            d_model,
+            n_heads,
+            n_kv_heads,
+            head_dim,
+            rope_dim,
+            rope_base,
+            context_length,
+            norm_eps,
+        } = DecoderConfig::from_metadata(metadata, "moondream", NormKind::LayerNorm)?;
+        let get = |key: &str| metadata.get(&format!("moondream.{key}"));
+
+        let vision_n_layers = get("vision.block_count")?.to_u32()? as usize;
+        let vision_d_model = get("vision.embedding_length")?.to_u32()? as usize;
+        let vision_n_heads = get("vision.attention.head_count")?.to_u32()? as usize;
+        if vision_n_heads == 0 || vision_d_model % vision_n_heads != 0 {
+            anyhow::bail!(
+                "moondream.vision.embedding_length ({vision_d_model}) must be a multiple of moondream.vision.attention.head_count ({vision_n_heads})"
+            );
+        }
+        let vision_norm_eps = get("vision.attention.layer_norm_epsilon")?.to_f32()?;
+
+        Ok(Self {
+            n_layers,
+            d_model,
+            n_heads,
+            n_kv_heads,
+            head_dim,
+            rope_dim,
+            rope_base,
+            context_length,
+            norm_eps,
+            vision_n_layers,
+            vision_d_model,
+            vision_n_heads,
+            vision_norm_eps,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MoondreamConfig;
+    use crate::config::tests::metadata;
+    use ratchet_loader::gguf::gguf::{Metadata, Value};
+
+    #[test]
+    fn reads_moondream_config() -> anyhow::Result<()> {
+        let vision = [
+            ("vision.block_count", Value::U32(3)),
+            ("vision.embedding_length", Value::U32(32)),
+            ("vision.attention.head_count", Value::U32(2)),
+            ("vision.attention.layer_norm_epsilon", Value::F32(1e-6)),
+        ];
+        let config = MoondreamConfig::from_metadata(&metadata("moondream", &vision))?;
+        assert_eq!(
+            (config.n_layers, config.d_model, config.head_dim),
+            (2, 64, 16)
+        );
+        assert_eq!(
+            (
+                config.vision_n_layers,
+                config.vision_d_model,
+                config.vision_n_heads
+            ),
+            (3, 32, 2)
+        );
+        assert_eq!(config.vision_norm_eps, 1e-6);
+
+        //Text hyperparameters alone are not enough
+        assert!(MoondreamConfig::from_metadata(&metadata("moondream", &[])).is_err());
+        assert!(MoondreamConfig::from_metadata(&metadata("phi2", &vision)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn defaults_without_architecture() -> anyhow::Result<()> {
+        let config = MoondreamConfig::from_metadata(&Metadata::from_iter(std::iter::empty()))?;
+        assert_eq!((config.n_layers, config.d_model), (24, 2048));
+        Ok(())
+    }
+}
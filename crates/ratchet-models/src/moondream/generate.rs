@@ -49,7 +49,7 @@ pub fn generate(
         .text_model
         .embedding
         .schedule(Tensor::from_data([50256], shape![1], device.clone()))?
-        .view(shape![1, 1, 2048])?;
+        .view(shape![1, 1, model.config.d_model])?;
 
     let encoding = tos.tokenizer().encode(prompt, false).unwrap();
 
@@ -146,7 +146,7 @@ pub async fn generate(
         .text_model
         .embedding
         .schedule(Tensor::from_data([50256], shape![1], device.clone()))?
-        .view(shape![1, 1, 2048])?;
+        .view(shape![1, 1, model.config.d_model])?;
 
     let encoding = tos.tokenizer().encode(prompt, false).unwrap();
 
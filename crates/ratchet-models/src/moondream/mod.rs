@@ -1,8 +1,10 @@
+mod config;
 mod generate;
 mod mlp;
 pub mod model;
 mod text_model;
 mod vision_encoder;
 
+pub use config::MoondreamConfig;
 pub use generate::generate;
 pub use model::Moondream;
@@ -1,9 +1,7 @@
-use std::io::{BufRead, Seek};
-
 use anyhow::Ok;
 use half::f16;
 use ratchet::{shape, DType, Device, Tensor};
-use ratchet_loader::gguf::gguf::Header;
+use ratchet_loader::gguf::gguf::{Header, TensorReader};
 use ratchet_nn::{Embedding, KVCache, LayerNorm, Linear, RotaryEmbedding};
 
 #[cfg(target_arch = "wasm32")]
@@ -16,16 +14,20 @@ use super::{
         Attention, LinearPatchEmbedding, VisionEncoder, VisionProjection, VisionTransformer,
         VitBlock,
     },
+    MoondreamConfig,
 };
 
 #[derive(Debug)]
 pub struct Moondream {
     pub vision_encoder: VisionEncoder,
     pub text_model: TextModel,
+    pub config: MoondreamConfig,
 }
 
 impl Moondream {
-    pub fn load<R: BufRead + Seek>(
+    const MAX_CACHE: usize = 4096; //TODO: configurable
+
+    pub fn load<R: TensorReader>(
         header: Header,
         reader: &mut R,
         device: &Device,
@@ -46,30 +48,26 @@ impl Moondream {
         Self::load_inner(&header, lt, &device)
     }
 
-    fn load_inner<F>(_header: &Header, mut lt: F, device: &Device) -> anyhow::Result<Self>
+    fn load_inner<F>(header: &Header, mut lt: F, device: &Device) -> anyhow::Result<Self>
     where
         F: FnMut(&str) -> Tensor,
     {
-        let n_layers = 24_i32;
-        let dim = 2048_f32;
-        let n_heads = 32_u32;
-        let n_kv_heads = 32_u32;
-        let rope_base = 10000.0f32;
-        let rope_dim = 32_u32;
-        let ln_eps = 1e-05;
-        let hdim = dim / n_heads as f32;
+        let config = MoondreamConfig::from_metadata(&header.metadata)?;
+        let ln_eps = config.norm_eps;
+        let hdim = config.head_dim as f32;
         let softmax_scale = Tensor::from_data([1.0 / hdim.sqrt()], shape![1], device.clone());
-        let cache_shape = shape![1, 32, 4096, 64];
+        let max_cache = config.context_length.min(Self::MAX_CACHE);
+        let cache_shape = shape![1, config.n_kv_heads, max_cache, config.head_dim];
 
         let kv_cache = match device.compute_precision() {
-            DType::F16 => KVCache::new::<f16>(n_layers as _, cache_shape, device),
-            DType::F32 => KVCache::new::<f32>(n_layers as _, cache_shape, device),
-            _ => unimplemented!(),
+            DType::F16 => KVCache::new::<f16>(config.n_layers as _, cache_shape, device),
+            DType::F32 => KVCache::new::<f32>(config.n_layers as _, cache_shape, device),
+            dt => anyhow::bail!("unsupported compute precision {dt:?}"),
         };
 
         let text_model = TextModel::new(
             Embedding::new(lt("text_model.transformer.embd.wte.weight")),
-            (0..n_layers)
+            (0..config.n_layers)
                 .map(|i| {
                     DecoderLayer::new(
                         LayerNorm::new(
@@ -95,10 +93,10 @@ impl Moondream {
                                     i
                                 ))),
                             ),
-                            RotaryEmbedding::new(rope_dim as usize, false, rope_base, 1.0),
-                            n_heads,
+                            RotaryEmbedding::new(config.rope_dim, false, config.rope_base, 1.0),
+                            config.n_heads as _,
                             softmax_scale.clone(),
-                            n_kv_heads,
+                            config.n_kv_heads as _,
                         ),
                         MLP::new(
                             Linear::new(
@@ -142,19 +140,19 @@ impl Moondream {
                 Linear::new(lt("vision_encoder.encoder.model.visual.patch_embed.linear.weight"), Some(lt("vision_encoder.encoder.model.visual.patch_embed.linear.bias"))),
             ),
             lt("vision_encoder.encoder.model.visual.pos_embed"),
-            (0..27)
+            (0..config.vision_n_layers)
                 .map(|layer| {
                     let qkvw = lt(&format!("vision_encoder.encoder.model.visual.blocks.{}.attn.qkv.weight", layer));
                     let qkvb = lt(&format!("vision_encoder.encoder.model.visual.blocks.{}.attn.qkv.bias", layer));
 
-                    let n_heads = 16;
-                    let dim = 1152;
+                    let n_heads = config.vision_n_heads;
+                    let dim = config.vision_d_model;
                     let h_dim = dim / n_heads;
                     let scale_factor =
                         Tensor::from_data([1.0 / (h_dim as f32).sqrt()], shape![1], device.clone());
 
                     VitBlock::new(
-                        1152,
+                        dim,
                         Attention::new(
                             n_heads,
                             dim,
@@ -178,22 +176,23 @@ impl Moondream {
                         LayerNorm::new(
                             lt(&format!("vision_encoder.encoder.model.visual.blocks.{}.norm1.weight", layer)),
                             Some(lt(&format!("vision_encoder.encoder.model.visual.blocks.{}.norm1.bias", layer))),
-                            ln_eps,
+                            config.vision_norm_eps,
                         ),
                         LayerNorm::new(
                             lt(&format!("vision_encoder.encoder.model.visual.blocks.{}.norm2.weight", layer)),
                             Some(lt(&format!("vision_encoder.encoder.model.visual.blocks.{}.norm2.bias", layer))),
-                            ln_eps,
+                            config.vision_norm_eps,
                         ),
                     )
                 }).collect::<Vec<_>>(),
-            LayerNorm::new(lt("vision_encoder.encoder.model.visual.norm.weight"), Some(lt("vision_encoder.encoder.model.visual.norm.bias")), ln_eps),
+            LayerNorm::new(lt("vision_encoder.encoder.model.visual.norm.weight"), Some(lt("vision_encoder.encoder.model.visual.norm.bias")), config.vision_norm_eps),
         );
 
         let vision_encoder = VisionEncoder::new(projection, transformer);
         Ok(Self {
             vision_encoder,
             text_model,
+            config,
         })
     }
 }
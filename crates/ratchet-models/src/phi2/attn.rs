@@ -1,9 +1,9 @@
-use std::io::{BufRead, Seek};
-
 use ratchet::{prelude::shape, rvec, Device, Tensor};
-use ratchet_loader::gguf::gguf::Header;
+use ratchet_loader::gguf::gguf::{Header, TensorReader};
 use ratchet_nn::{KVEntry, Linear, Module, RotaryEmbedding, RotaryInput};
 
+use super::Phi2Config;
+
 #[cfg(target_arch = "wasm32")]
 use crate::{ratchet_from_gguf_web, TensorMap};
 
@@ -20,9 +20,10 @@ pub struct PhiSelfAttention {
 }
 
 impl PhiSelfAttention {
-    pub fn load<R: BufRead + Seek>(
+    pub fn load<R: TensorReader>(
         disk_model: &Header,
         reader: &mut R,
+        config: &Phi2Config,
         layer_index: usize,
         device: &Device,
     ) -> anyhow::Result<Self> {
@@ -30,13 +31,13 @@ impl PhiSelfAttention {
             let key = format!("blk.{}.{}", layer_index, name);
             disk_model.tensor(reader, &key, device)
         };
-        Self::load_inner(disk_model, lt, device)
+        Self::load_inner(config, lt, device)
     }
 
     #[cfg(target_arch = "wasm32")]
     pub fn from_web(
-        header: &Header,
         tensors: &mut TensorMap,
+        config: &Phi2Config,
         layer_index: usize,
         device: &Device,
     ) -> anyhow::Result<Self> {
@@ -47,10 +48,10 @@ impl PhiSelfAttention {
                 .ok_or_else(|| anyhow::anyhow!("missing tensor"))?;
             ratchet_from_gguf_web(tensor, device)
         };
-        Self::load_inner(header, lt, device)
+        Self::load_inner(config, lt, device)
     }
 
-    fn load_inner<F>(header: &Header, mut lt: F, device: &Device) -> anyhow::Result<Self>
+    fn load_inner<F>(config: &Phi2Config, mut lt: F, device: &Device) -> anyhow::Result<Self>
     where
         F: FnMut(&str) -> anyhow::Result<Tensor>,
     {
@@ -59,32 +60,18 @@ impl PhiSelfAttention {
         let v = Linear::new(lt("attn_v.weight")?, Some(lt("attn_v.bias")?));
         let o = Linear::new(lt("attn_output.weight")?, Some(lt("attn_output.bias")?));
 
-        let n_heads = header
-            .metadata
-            .get("phi2.attention.head_count")
-            .unwrap()
-            .to_u32()?;
-        let n_kv_heads = header
-            .metadata
-            .get("phi2.attention.head_count_kv")
-            .unwrap()
-            .to_u32()?;
-
-        let scale_val = 1.0 / 80_f32.sqrt();
+        let scale_val = 1.0 / (config.head_dim as f32).sqrt();
         let softmax_scale = Tensor::from_data([scale_val], shape![1], device.clone());
-        //TODO: hardcoded for Phi2, should read from meta
-        let base = 10000.0;
-        let dim = (0.4 * (2560f64 / 32f64)) as usize;
-        let rope = RotaryEmbedding::new(dim, false, base, 1.0);
+        let rope = RotaryEmbedding::new(config.rope_dim, false, config.rope_base, 1.0);
         Ok(Self {
             q,
             k,
             v,
             o,
             rope,
-            n_heads,
+            n_heads: config.n_heads as _,
             softmax_scale,
-            n_kv_heads,
+            n_kv_heads: config.n_kv_heads as _,
         })
     }
 }
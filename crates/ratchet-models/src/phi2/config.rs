@@ -0,0 +1,9 @@
+use crate::config::{DecoderConfig, NormKind};
+use ratchet_loader::gguf::gguf::Metadata;
+
+/// Hyperparameters of a Phi2 checkpoint, read from the `phi2.*` GGUF metadata keys.
+pub type Phi2Config = DecoderConfig;
+
+pub(crate) fn from_metadata(metadata: &Metadata) -> anyhow::Result<Phi2Config> {
+    DecoderConfig::from_metadata(metadata, "phi2", NormKind::LayerNorm)
+}
@@ -1,8 +1,10 @@
 mod attn;
+mod config;
 mod generate;
 mod mlp;
 mod model;
 
+pub use config::Phi2Config;
 pub use model::Phi2;
 
 #[cfg(target_arch = "wasm32")]
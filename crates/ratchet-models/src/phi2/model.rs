@@ -1,12 +1,13 @@
 use super::{
     attn::{PhiAttnInput, PhiSelfAttention},
+    config,
     mlp::MLP,
+    Phi2Config,
 };
 use half::f16;
 use ratchet::{shape, DType, Device, Tensor};
-use ratchet_loader::gguf::gguf::Header;
+use ratchet_loader::gguf::gguf::{Header, TensorReader};
 use ratchet_nn::{Embedding, KVCache, KVEntry, LayerNorm, Linear, Module};
-use std::io::{BufRead, Seek};
 
 #[cfg(target_arch = "wasm32")]
 use {crate::ratchet_from_gguf_web, crate::TensorMap};
@@ -19,19 +20,24 @@ pub struct DecoderLayer {
 }
 
 impl DecoderLayer {
-    pub fn load<R: BufRead + Seek>(
+    pub fn load<R: TensorReader>(
         disk_model: &Header,
         reader: &mut R,
+        config: &Phi2Config,
         layer_index: usize,
         device: &Device,
     ) -> anyhow::Result<Self> {
-        let self_attn = PhiSelfAttention::load(disk_model, reader, layer_index, device)?;
+        let self_attn = PhiSelfAttention::load(disk_model, reader, config, layer_index, device)?;
         let mut lt = |name: &str| {
             let key = format!("blk.{}.{}", layer_index, name);
             disk_model.tensor(reader, &key, device)
         };
 
-        let ln = LayerNorm::new(lt("attn_norm.weight")?, Some(lt("attn_norm.bias")?), 1e-5);
+        let ln = LayerNorm::new(
+            lt("attn_norm.weight")?,
+            Some(lt("attn_norm.bias")?),
+            config.norm_eps,
+        );
 
         let mlp = MLP::new(
             Linear::new(lt("ffn_up.weight")?, Some(lt("ffn_up.bias")?)),
@@ -42,12 +48,12 @@ impl DecoderLayer {
 
     #[cfg(target_arch = "wasm32")]
     pub fn from_web(
-        header: &Header,
         tensors: &mut TensorMap,
+        config: &Phi2Config,
         layer_index: usize,
         device: &Device,
     ) -> anyhow::Result<Self> {
-        let self_attn = PhiSelfAttention::from_web(header, tensors, layer_index, device)?;
+        let self_attn = PhiSelfAttention::from_web(tensors, config, layer_index, device)?;
         let mut lt = |name: &str| {
             let key = format!("blk.{}.{}", layer_index, name);
             let tensor = tensors
@@ -56,7 +62,11 @@ impl DecoderLayer {
             ratchet_from_gguf_web(tensor, device)
         };
 
-        let ln = LayerNorm::new(lt("attn_norm.weight")?, Some(lt("attn_norm.bias")?), 1e-5);
+        let ln = LayerNorm::new(
+            lt("attn_norm.weight")?,
+            Some(lt("attn_norm.bias")?),
+            config.norm_eps,
+        );
 
         let mlp = MLP::new(
             Linear::new(lt("ffn_up.weight")?, Some(lt("ffn_up.bias")?)),
@@ -96,6 +106,7 @@ pub struct Phi2 {
     pub ln_post: LayerNorm,
     pub lm_head: Linear,
     pub kv_cache: KVCache,
+    pub config: Phi2Config,
     pub device: Device,
 }
 
@@ -130,7 +141,7 @@ impl Module for Phi2 {
 impl Phi2 {
     const MAX_CACHE: usize = 1024; //TODO: configurable
 
-    pub fn load<R: BufRead + Seek>(
+    pub fn load<R: TensorReader>(
         header: Header,
         reader: &mut R,
         device: &Device,
@@ -138,11 +149,11 @@ impl Phi2 {
         let token_embedding = header.tensor(reader, "token_embd.weight", device)?;
         let embedding = Embedding::new(token_embedding);
 
-        let n_layers = header.metadata.get("phi2.block_count").unwrap().to_u32()? as i32;
+        let config = config::from_metadata(&header.metadata)?;
 
-        let layers = (0..n_layers)
-            .fold(Vec::with_capacity(n_layers as _), |mut blocks, i| {
-                blocks.push(DecoderLayer::load(&header, reader, i as _, device));
+        let layers = (0..config.n_layers)
+            .fold(Vec::with_capacity(config.n_layers), |mut blocks, i| {
+                blocks.push(DecoderLayer::load(&header, reader, &config, i, device));
                 blocks
             })
             .into_iter()
@@ -153,14 +164,19 @@ impl Phi2 {
             header.tensor(reader, &key, device)
         };
 
-        let ln_post = LayerNorm::new(lt("_norm.weight")?, Some(lt("_norm.bias")?), 1e-5);
+        let ln_post = LayerNorm::new(
+            lt("_norm.weight")?,
+            Some(lt("_norm.bias")?),
+            config.norm_eps,
+        );
         let lm_head = Linear::new(lt(".weight")?, Some(lt(".bias")?));
 
-        let cache_shape = shape![1, 32, Self::MAX_CACHE, 80];
+        let max_cache = config.context_length.min(Self::MAX_CACHE);
+        let cache_shape = shape![1, config.n_kv_heads, max_cache, config.head_dim];
         let kv_cache = match device.compute_precision() {
-            DType::F16 => KVCache::new::<f16>(n_layers, cache_shape, device),
-            DType::F32 => KVCache::new::<f32>(n_layers, cache_shape, device),
-            _ => unimplemented!(),
+            DType::F16 => KVCache::new::<f16>(config.n_layers as _, cache_shape, device),
+            DType::F32 => KVCache::new::<f32>(config.n_layers as _, cache_shape, device),
+            dt => anyhow::bail!("unsupported compute precision {dt:?}"),
         };
 
         Ok(Self {
@@ -169,6 +185,7 @@ impl Phi2 {
             ln_post,
             lm_head,
             kv_cache,
+            config,
             device: device.clone(),
         })
     }
@@ -184,16 +201,11 @@ impl Phi2 {
             &device,
         )?);
 
-        let n_layers = header.metadata.get("phi2.block_count").unwrap().to_u32()? as i32;
+        let config = config::from_metadata(&header.metadata)?;
 
-        let layers = (0..n_layers)
-            .fold(Vec::with_capacity(n_layers as _), |mut blocks, i| {
-                blocks.push(DecoderLayer::from_web(
-                    &header,
-                    &mut tensors,
-                    i as _,
-                    &device,
-                ));
+        let layers = (0..config.n_layers)
+            .fold(Vec::with_capacity(config.n_layers), |mut blocks, i| {
+                blocks.push(DecoderLayer::from_web(&mut tensors, &config, i, &device));
                 blocks
             })
             .into_iter()
@@ -207,14 +219,19 @@ impl Phi2 {
             ratchet_from_gguf_web(tensor, &device)
         };
 
-        let ln_post = LayerNorm::new(lt("_norm.weight")?, Some(lt("_norm.bias")?), 1e-5);
+        let ln_post = LayerNorm::new(
+            lt("_norm.weight")?,
+            Some(lt("_norm.bias")?),
+            config.norm_eps,
+        );
         let lm_head = Linear::new(lt(".weight")?, Some(lt(".bias")?));
 
-        let cache_shape = shape![1, 32, Self::MAX_CACHE, 80];
+        let max_cache = config.context_length.min(Self::MAX_CACHE);
+        let cache_shape = shape![1, config.n_kv_heads, max_cache, config.head_dim];
         let kv_cache = match device.compute_precision() {
-            DType::F16 => KVCache::new::<f16>(n_layers, cache_shape, &device),
-            DType::F32 => KVCache::new::<f32>(n_layers, cache_shape, &device),
-            _ => unimplemented!(),
+            DType::F16 => KVCache::new::<f16>(config.n_layers as _, cache_shape, &device),
+            DType::F32 => KVCache::new::<f32>(config.n_layers as _, cache_shape, &device),
+            dt => anyhow::bail!("unsupported compute precision {dt:?}"),
         };
 
         Ok(Self {
@@ -223,6 +240,7 @@ impl Phi2 {
             ln_post,
             lm_head,
             kv_cache,
+            config,
             device,
         })
     }
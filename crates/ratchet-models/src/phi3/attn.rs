@@ -1,9 +1,9 @@
-use std::io::{BufRead, Seek};
-
 use ratchet::{prelude::shape, rvec, Device, Tensor};
-use ratchet_loader::gguf::gguf::Header;
+use ratchet_loader::gguf::gguf::{Header, TensorReader};
 use ratchet_nn::{KVEntry, Linear, Module, RotaryEmbedding, RotaryInput};
 
+use super::Phi3Config;
+
 #[cfg(target_arch = "wasm32")]
 use crate::{ratchet_from_gguf_web, TensorMap};
 
@@ -18,9 +18,10 @@ pub struct PhiSelfAttention {
 }
 
 impl PhiSelfAttention {
-    pub fn load<R: BufRead + Seek>(
+    pub fn load<R: TensorReader>(
         disk_model: &Header,
         reader: &mut R,
+        config: &Phi3Config,
         layer_index: usize,
         device: &Device,
     ) -> anyhow::Result<Self> {
@@ -28,13 +29,13 @@ impl PhiSelfAttention {
             let key = format!("blk.{}.{}", layer_index, name);
             disk_model.tensor(reader, &key, device)
         };
-        Self::load_inner(disk_model, lt, device)
+        Self::load_inner(config, lt, device)
     }
 
     #[cfg(target_arch = "wasm32")]
     pub fn from_web(
-        header: &Header,
         tensors: &mut TensorMap,
+        config: &Phi3Config,
         layer_index: usize,
         device: &Device,
     ) -> anyhow::Result<Self> {
@@ -45,33 +46,26 @@ impl PhiSelfAttention {
                 .ok_or_else(|| anyhow::anyhow!("missing tensor"))?;
             ratchet_from_gguf_web(tensor, device)
         };
-        Self::load_inner(header, lt, device)
+        Self::load_inner(config, lt, device)
     }
 
-    fn load_inner<F>(header: &Header, mut lt: F, device: &Device) -> anyhow::Result<Self>
+    fn load_inner<F>(config: &Phi3Config, mut lt: F, device: &Device) -> anyhow::Result<Self>
     where
         F: FnMut(&str) -> anyhow::Result<Tensor>,
     {
         let qkv = Linear::new(lt("attn_qkv.weight")?, None);
         let o = Linear::new(lt("attn_output.weight")?, None);
 
-        let metadata = &header.metadata;
-        let n_heads = metadata.get("phi3.attention.head_count")?.to_u32()?;
-        let n_kv_heads = metadata.get("phi3.attention.head_count_kv")?.to_u32()?;
-        let d_model = metadata.get("phi3.embedding_length")?.to_u32()?;
-        let rope_base = 10000.0f32;
-        let rope_dim = metadata.get("phi3.rope.dimension_count")?.to_u32()?;
-
-        let hdim = d_model as f32 / n_heads as f32;
+        let hdim = config.head_dim as f32;
         let softmax_scale = Tensor::from_data([1.0 / hdim.sqrt()], shape![1], device.clone());
-        let rope = RotaryEmbedding::new(rope_dim as _, false, rope_base, 1.0);
+        let rope = RotaryEmbedding::new(config.rope_dim, false, config.rope_base, 1.0);
         Ok(Self {
             qkv,
             o,
             rope,
-            n_heads,
+            n_heads: config.n_heads as _,
             softmax_scale,
-            n_kv_heads,
+            n_kv_heads: config.n_kv_heads as _,
         })
     }
 }
@@ -0,0 +1,9 @@
+use crate::config::{DecoderConfig, NormKind};
+use ratchet_loader::gguf::gguf::Metadata;
+
+/// Hyperparameters of a Phi3 checkpoint, read from the `phi3.*` GGUF metadata keys.
+pub type Phi3Config = DecoderConfig;
+
+pub(crate) fn from_metadata(metadata: &Metadata) -> anyhow::Result<Phi3Config> {
+    DecoderConfig::from_metadata(metadata, "phi3", NormKind::RMSNorm)
+}
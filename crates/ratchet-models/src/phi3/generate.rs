@@ -1,8 +1,6 @@
 use crate::phi3::Phi3;
-use crate::TokenOutputStream;
-use ndarray::Axis;
-use ndarray_stats::QuantileExt;
-use ratchet::{shape, Device, Tensor};
+use crate::{PipelinedDecoder, TokenOutputStream};
+use ratchet::Tensor;
 use ratchet_nn::Module;
 use tokenizers::Tokenizer;
 
@@ -32,32 +30,28 @@ pub async fn generate(
         .map(|&x| x as i32)
         .collect::<Vec<_>>();
     tokens.insert(0, 1);
-    let mut all_tokens = tokens.clone();
-    let start = Instant::now();
-    while tokens[tokens.len() - 1] != 32007 && all_tokens.len() < 2048 {
-        let input = Tensor::from_data(
-            tokens.clone(),
-            shape![1, tokens.len()],
-            model.device.clone(),
-        );
-        let result = model.schedule(input)?.resolve()?;
-        let logits = result.to(&Device::CPU).await?;
-        model.cache_mut().update(tokens.len());
-
-        tokens = logits
-            .to_ndarray_view::<f32>()
-            .map_axis(Axis(2), |row| row.argmax_skipnan().unwrap())
-            .iter()
-            .map(|&x| x as i32)
-            .collect::<Vec<_>>();
-        all_tokens.extend(tokens.clone());
-        if let Some(t) = tos.next_token(tokens[0] as u32)? {
+    let device = model.device.clone();
+    let step = |input: Tensor| -> anyhow::Result<Tensor> {
+        let seq_len = input.shape()[1];
+        let logits = model.schedule(input)?;
+        model.cache_mut().update(seq_len);
+        Ok(logits)
+    };
+    let on_token = |token: i32| -> anyhow::Result<()> {
+        if let Some(t) = tos.next_token(token as u32)? {
             callback(t);
         }
-    }
+        Ok(())
+    };
+    let decoder = PipelinedDecoder::new(Some(32007), 2048usize.saturating_sub(tokens.len()));
+    let start = Instant::now();
+    let generated = decoder.run(&tokens, &device, step, on_token).await?;
     let elapsed = start.elapsed();
     log::warn!("Elapsed: {:?}", elapsed);
-    log::warn!("Tok/s {}", all_tokens.len() as f64 / elapsed.as_secs_f64());
+    log::warn!(
+        "Tok/s {}",
+        (tokens.len() + generated.len()) as f64 / elapsed.as_secs_f64()
+    );
     model.reset();
     Ok(())
 }
@@ -88,32 +82,28 @@ pub fn generate(
         .map(|&x| x as i32)
         .collect::<Vec<_>>();
     tokens.insert(0, 1);
-    let mut all_tokens = tokens.clone();
-    let start = Instant::now();
-    while tokens[tokens.len() - 1] != 32007 && all_tokens.len() < 2048 {
-        let input = Tensor::from_data(
-            tokens.clone(),
-            shape![1, tokens.len()],
-            model.device.clone(),
-        );
-        let result = model.schedule(input)?.resolve()?;
-        let logits = result.to(&Device::CPU)?;
-        model.cache_mut().update(tokens.len());
-
-        tokens = logits
-            .to_ndarray_view::<f32>()
-            .map_axis(Axis(2), |row| row.argmax_skipnan().unwrap())
-            .iter()
-            .map(|&x| x as i32)
-            .collect::<Vec<_>>();
-        all_tokens.extend(tokens.clone());
-        if let Some(t) = tos.next_token(tokens[0] as u32)? {
+    let device = model.device.clone();
+    let step = |input: Tensor| -> anyhow::Result<Tensor> {
+        let seq_len = input.shape()[1];
+        let logits = model.schedule(input)?;
+        model.cache_mut().update(seq_len);
+        Ok(logits)
+    };
+    let on_token = |token: i32| -> anyhow::Result<()> {
+        if let Some(t) = tos.next_token(token as u32)? {
             callback(t);
         }
-    }
+        Ok(())
+    };
+    let decoder = PipelinedDecoder::new(Some(32007), 2048usize.saturating_sub(tokens.len()));
+    let start = Instant::now();
+    let generated = pollster::block_on(decoder.run(&tokens, &device, step, on_token))?;
     let elapsed = start.elapsed();
     log::warn!("Elapsed: {:?}", elapsed);
-    log::warn!("Tok/s {}", all_tokens.len() as f64 / elapsed.as_secs_f64());
+    log::warn!(
+        "Tok/s {}",
+        (tokens.len() + generated.len()) as f64 / elapsed.as_secs_f64()
+    );
     model.reset();
     Ok(())
 }
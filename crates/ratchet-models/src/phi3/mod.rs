@@ -1,7 +1,9 @@
 mod attn;
+mod config;
 mod generate;
 mod mlp;
 mod model;
 
+pub use config::Phi3Config;
 pub use generate::generate;
 pub use model::Phi3;
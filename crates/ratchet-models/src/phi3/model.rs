@@ -1,13 +1,13 @@
-use std::io::{BufRead, Seek};
-
 use half::f16;
 use ratchet::{shape, DType, Device, Tensor};
-use ratchet_loader::gguf::gguf::Header;
+use ratchet_loader::gguf::gguf::{Header, TensorReader};
 use ratchet_nn::{Embedding, KVCache, KVEntry, Linear, Module, RMSNorm};
 
 use super::{
     attn::{PhiAttnInput, PhiSelfAttention},
+    config,
     mlp::MLP,
+    Phi3Config,
 };
 
 #[cfg(target_arch = "wasm32")]
@@ -22,25 +22,21 @@ pub struct DecoderLayer {
 }
 
 impl DecoderLayer {
-    pub fn load<R: BufRead + Seek>(
+    pub fn load<R: TensorReader>(
         header: &Header,
         reader: &mut R,
+        config: &Phi3Config,
         layer_index: usize,
         device: &Device,
     ) -> anyhow::Result<Self> {
-        let self_attn = PhiSelfAttention::load(header, reader, layer_index, device)?;
+        let self_attn = PhiSelfAttention::load(header, reader, config, layer_index, device)?;
         let mut lt = |name: &str| {
             let key = format!("blk.{}.{}", layer_index, name);
             header.tensor(reader, &key, device)
         };
 
-        let norm_eps = header
-            .metadata
-            .get("phi3.attention.layer_norm_rms_epsilon")?
-            .to_f32()?;
-
-        let input_norm = RMSNorm::new(lt("attn_norm.weight")?, norm_eps);
-        let ffn_norm = RMSNorm::new(lt("ffn_norm.weight")?, norm_eps);
+        let input_norm = RMSNorm::new(lt("attn_norm.weight")?, config.norm_eps);
+        let ffn_norm = RMSNorm::new(lt("ffn_norm.weight")?, config.norm_eps);
 
         let mlp = MLP::new(
             Linear::new(lt("ffn_up.weight")?, None),
@@ -56,12 +52,12 @@ impl DecoderLayer {
 
     #[cfg(target_arch = "wasm32")]
     pub fn from_web(
-        header: &Header,
         tensors: &mut TensorMap,
+        config: &Phi3Config,
         layer_index: usize,
         device: &Device,
     ) -> anyhow::Result<Self> {
-        let self_attn = PhiSelfAttention::from_web(header, tensors, layer_index, device)?;
+        let self_attn = PhiSelfAttention::from_web(tensors, config, layer_index, device)?;
         let mut lt = |name: &str| {
             let key = format!("blk.{}.{}", layer_index, name);
             let tensor = tensors
@@ -70,13 +66,8 @@ impl DecoderLayer {
             ratchet_from_gguf_web(tensor, device)
         };
 
-        let norm_eps = header
-            .metadata
-            .get("phi3.attention.layer_norm_rms_epsilon")?
-            .to_f32()?;
-
-        let input_norm = RMSNorm::new(lt("attn_norm.weight")?, norm_eps);
-        let ffn_norm = RMSNorm::new(lt("ffn_norm.weight")?, norm_eps);
+        let input_norm = RMSNorm::new(lt("attn_norm.weight")?, config.norm_eps);
+        let ffn_norm = RMSNorm::new(lt("ffn_norm.weight")?, config.norm_eps);
 
         let mlp = MLP::new(
             Linear::new(lt("ffn_up.weight")?, None),
@@ -125,6 +116,7 @@ pub struct Phi3 {
     pub ln_post: RMSNorm,
     pub lm_head: Linear,
     pub kv_cache: KVCache,
+    pub config: Phi3Config,
     pub device: Device,
 }
 
@@ -159,18 +151,18 @@ impl Module for Phi3 {
 impl Phi3 {
     const MAX_CACHE: usize = 4096; //TODO: configurable
 
-    pub fn load<R: BufRead + Seek>(
+    pub fn load<R: TensorReader>(
         header: Header,
         reader: &mut R,
         device: &Device,
     ) -> anyhow::Result<Self> {
         let embedding = Embedding::new(header.tensor(reader, "token_embd.weight", device)?);
 
-        let n_layers = header.metadata.get("phi3.block_count")?.to_u32()? as i32;
+        let config = config::from_metadata(&header.metadata)?;
 
-        let layers = (0..n_layers)
-            .fold(Vec::with_capacity(n_layers as _), |mut blocks, i| {
-                blocks.push(DecoderLayer::load(&header, reader, i as _, device));
+        let layers = (0..config.n_layers)
+            .fold(Vec::with_capacity(config.n_layers), |mut blocks, i| {
+                blocks.push(DecoderLayer::load(&header, reader, &config, i, device));
                 blocks
             })
             .into_iter()
@@ -181,24 +173,15 @@ impl Phi3 {
             header.tensor(reader, &key, device)
         };
 
-        let metadata = &header.metadata;
-
-        let norm_eps = metadata
-            .get("phi3.attention.layer_norm_rms_epsilon")?
-            .to_f32()?;
-        let ln_post = RMSNorm::new(lt("_norm.weight")?, norm_eps);
+        let ln_post = RMSNorm::new(lt("_norm.weight")?, config.norm_eps);
         let lm_head = Linear::new(lt(".weight")?, None);
 
-        let n_layers = metadata.get("phi3.block_count")?.to_u32()?;
-        let d_model = metadata.get("phi3.embedding_length")?.to_u32()?;
-        let n_heads = metadata.get("phi3.attention.head_count")?.to_u32()?;
-        let hdim = d_model as f32 / n_heads as f32;
-
-        let cache_shape = shape![1, n_layers as _, Self::MAX_CACHE, hdim as _];
+        let max_cache = config.context_length.min(Self::MAX_CACHE);
+        let cache_shape = shape![1, config.n_kv_heads, max_cache, config.head_dim];
         let kv_cache = match device.compute_precision() {
-            DType::F16 => KVCache::new::<f16>(n_layers as _, cache_shape, device),
-            DType::F32 => KVCache::new::<f32>(n_layers as _, cache_shape, device),
-            _ => unimplemented!(),
+            DType::F16 => KVCache::new::<f16>(config.n_layers as _, cache_shape, device),
+            DType::F32 => KVCache::new::<f32>(config.n_layers as _, cache_shape, device),
+            dt => anyhow::bail!("unsupported compute precision {dt:?}"),
         };
 
         Ok(Self {
@@ -207,6 +190,7 @@ impl Phi3 {
             ln_post,
             lm_head,
             kv_cache,
+            config,
             device: device.clone(),
         })
     }
@@ -222,16 +206,11 @@ impl Phi3 {
             &device,
         )?);
 
-        let n_layers = header.metadata.get("phi3.block_count")?.to_u32()? as i32;
+        let config = config::from_metadata(&header.metadata)?;
 
-        let layers = (0..n_layers)
-            .fold(Vec::with_capacity(n_layers as _), |mut blocks, i| {
-                blocks.push(DecoderLayer::from_web(
-                    &header,
-                    &mut tensors,
-                    i as _,
-                    &device,
-                ));
+        let layers = (0..config.n_layers)
+            .fold(Vec::with_capacity(config.n_layers), |mut blocks, i| {
+                blocks.push(DecoderLayer::from_web(&mut tensors, &config, i, &device));
                 blocks
             })
             .into_iter()
@@ -245,26 +224,18 @@ impl Phi3 {
             ratchet_from_gguf_web(tensor, &device)
         };
 
-        let metadata = &header.metadata;
-
-        let norm_eps = metadata
-            .get("phi3.attention.layer_norm_rms_epsilon")?
-            .to_f32()?;
-        let ln_post = RMSNorm::new(lt("_norm.weight")?, norm_eps);
+        let ln_post = RMSNorm::new(lt("_norm.weight")?, config.norm_eps);
         let lm_head = Linear::new(lt(".weight")?, None);
 
-        let n_layers = metadata.get("phi3.block_count")?.to_u32()?;
-        let d_model = metadata.get("phi3.embedding_length")?.to_u32()?;
-        let n_heads = metadata.get("phi3.attention.head_count")?.to_u32()?;
-        let hdim = d_model as f32 / n_heads as f32;
-
-        let cache_shape = shape![1, n_layers as _, Self::MAX_CACHE, hdim as _];
+        let max_cache = config.context_length.min(Self::MAX_CACHE);
+        let cache_shape = shape![1, config.n_kv_heads, max_cache, config.head_dim];
         Ok(Self {
             embedding,
             layers,
             ln_post,
             lm_head,
-            kv_cache: KVCache::new::<f32>(n_layers as _, cache_shape, &device),
+            kv_cache: KVCache::new::<f32>(config.n_layers as _, cache_shape, &device),
+            config,
             device: device.clone(),
         })
     }
@@ -0,0 +1,137 @@
+use ratchet::{shape, Device, Tensor};
+
+/// # Pipelined Decoder
+///
+/// Greedy decoding which keeps sampled tokens on the device.
+///
+/// A naive decoding loop waits for every step, reads the logits back and samples on the host
+/// before it can build the next step, so the GPU idles for a host round trip per token.
+/// Here the token is sampled on the device by [Tensor::argmax] and fed to the next step as is.
+/// Step `n + 1` is submitted before the token of step `n` is read, and only token ids are read
+/// back, asynchronously.
+///
+/// The step after the last token is computed regardless, and writes to the KV cache: reset the
+/// model before reusing it.
+#[derive(Debug, Clone, derive_new::new)]
+pub struct PipelinedDecoder {
+    eos: Option<i32>,
+    max_tokens: usize,
+}
+
+impl PipelinedDecoder {
+    /// Bound `max_tokens` by a KV cache of `max_cache` positions.
+    ///
+    /// The cache holds the prompt, every token and the step run ahead of the last one, so at most
+    /// `max_cache - prompt_len - 1` tokens are decoded. Fails if the prompt alone doesn't fit.
+    pub fn within_cache(mut self, prompt_len: usize, max_cache: usize) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            prompt_len < max_cache,
+            "prompt of {prompt_len} tokens overflows the KV cache of {max_cache} positions"
+        );
+        self.max_tokens = self.max_tokens.min(max_cache - prompt_len - 1);
+        Ok(self)
+    }
+
+    /// Decode from `prompt` until `eos` is sampled, or `max_tokens` have been.
+    ///
+    /// `step` schedules the logits `[1, seq_len, vocab]` of the token ids `[1, seq_len]`, and
+    /// advances the cache by `seq_len`. `on_token` is called with every sampled token, in order.
+    pub async fn run<F, C>(
+        &self,
+        prompt: &[i32],
+        device: &Device,
+        mut step: F,
+        mut on_token: C,
+    ) -> anyhow::Result<Vec<i32>>
+    where
+        F: FnMut(Tensor) -> anyhow::Result<Tensor>,
+        C: FnMut(i32) -> anyhow::Result<()>,
+    {
+        let mut input = Tensor::from_data(prompt, shape![1, prompt.len()], device.clone());
+        let mut tokens = Vec::with_capacity(self.max_tokens);
+        //Readback of the token sampled by the previous step
+        let mut in_flight = None;
+        while tokens.len() < self.max_tokens {
+            let logits = step(input)?;
+            let [_, seq_len, vocab]: [usize; 3] = logits.shape().try_into()?;
+            let logits = if seq_len > 1 {
+                logits.slice(&[0..1, seq_len - 1..seq_len, 0..vocab])?
+            } else {
+                logits
+            };
+            let next = logits.argmax(2)?.submit()?;
+            let readback = next.to_cpu_async();
+            input = next;
+
+            let Some(previous) = in_flight.replace(readback) else {
+                continue;
+            };
+            let token = previous.await?.to_vec::<i32>()?[0];
+            tokens.push(token);
+            on_token(token)?;
+            if Some(token) == self.eos {
+                break;
+            }
+        }
+        Ok(tokens)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PipelinedDecoder;
+
+    #[test]
+    fn clamps_to_cache() -> anyhow::Result<()> {
+        let decoder = PipelinedDecoder::new(None, 2048).within_cache(100, 128)?;
+        assert_eq!(decoder.max_tokens, 27);
+        let decoder = PipelinedDecoder::new(None, 16).within_cache(100, 128)?;
+        assert_eq!(decoder.max_tokens, 16);
+        assert!(PipelinedDecoder::new(None, 16)
+            .within_cache(128, 128)
+            .is_err());
+        Ok(())
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32"), feature = "pyo3"))]
+mod gpu_tests {
+    use super::PipelinedDecoder;
+    use ratchet::{shape, Device, DeviceRequest, Tensor};
+
+    #[test]
+    fn decodes_on_device() -> anyhow::Result<()> {
+        let device = Device::request_device(DeviceRequest::GPU)?;
+        const VOCAB: usize = 8;
+        //Token t is always followed by t + 1
+        let mut table = vec![0f32; VOCAB * VOCAB];
+        for t in 0..VOCAB {
+            table[t * VOCAB + (t + 1) % VOCAB] = 1.;
+        }
+        let table = Tensor::from_data(table, shape![VOCAB, VOCAB], device.clone());
+
+        let mut steps = vec![];
+        let step = |input: Tensor| -> anyhow::Result<Tensor> {
+            let seq_len = input.shape()[1];
+            steps.push(seq_len);
+            let ids = input.view(shape![seq_len])?;
+            table
+                .clone()
+                .index_select(ids, 0)?
+                .view(shape![1, seq_len, VOCAB])
+        };
+        let mut sampled = vec![];
+        let on_token = |token: i32| -> anyhow::Result<()> {
+            sampled.push(token);
+            Ok(())
+        };
+
+        let decoder = PipelinedDecoder::new(Some(5), 16);
+        let tokens = pollster::block_on(decoder.run(&[3, 0], &device, step, on_token))?;
+        assert_eq!(tokens, vec![1, 2, 3, 4, 5]);
+        assert_eq!(sampled, tokens);
+        //The prompt, every token, and the step run ahead of the end of sequence
+        assert_eq!(steps, vec![2, 1, 1, 1, 1, 1]);
+        Ok(())
+    }
+}
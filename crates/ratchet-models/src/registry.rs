@@ -36,6 +36,22 @@ impl WhisperVariants {
             WhisperVariants::DistilLargeV3 => "FL33TW00D-HF/distil-whisper-large-v3",
         }
     }
+
+    /// Mel filterbank stored alongside the weights, v3 models use 128 mel bins.
+    pub fn mel_filters(&self) -> &str {
+        match self {
+            WhisperVariants::DistilLargeV3 | WhisperVariants::LargeV3 => "melfilters128.bytes",
+            _ => "melfilters.bytes",
+        }
+    }
+
+    /// Repository holding the `tokenizer.json` for this variant.
+    pub fn tokenizer_repo(&self) -> &str {
+        match self {
+            WhisperVariants::DistilLargeV3 | WhisperVariants::LargeV3 => "openai/whisper-large-v3",
+            _ => "openai/whisper-tiny",
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -51,6 +67,99 @@ pub enum PhiVariants {
     Phi3,
 }
 
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    target_arch = "wasm32",
+    derive(tsify::Tsify, serde::Serialize, serde::Deserialize),
+    tsify(from_wasm_abi),
+    serde(rename_all = "snake_case")
+)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(clap::ValueEnum))]
+pub enum LlamaVariants {
+    #[cfg_attr(not(target_arch = "wasm32"), value(name = "llama3.2-1b"))]
+    Llama3_2_1B,
+    #[cfg_attr(not(target_arch = "wasm32"), value(name = "llama3-8b"))]
+    Llama3_8B,
+    #[cfg_attr(not(target_arch = "wasm32"), value(name = "mistral-7b"))]
+    Mistral7B,
+    #[cfg_attr(not(target_arch = "wasm32"), value(name = "qwen2-1.5b"))]
+    Qwen2_1_5B,
+}
+
+impl LlamaVariants {
+    /// Repository holding the `tokenizer.json` for this variant.
+    ///
+    /// The Meta & Mistral repositories are gated, pulling from them needs a Hub access token for
+    /// an account which accepted the license.
+    pub fn tokenizer_repo(&self) -> &str {
+        match self {
+            LlamaVariants::Llama3_2_1B => "meta-llama/Llama-3.2-1B-Instruct",
+            LlamaVariants::Llama3_8B => "meta-llama/Meta-Llama-3-8B-Instruct",
+            LlamaVariants::Mistral7B => "mistralai/Mistral-7B-Instruct-v0.3",
+            LlamaVariants::Qwen2_1_5B => "Qwen/Qwen2-1.5B-Instruct",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    target_arch = "wasm32",
+    derive(tsify::Tsify, serde::Serialize, serde::Deserialize),
+    tsify(from_wasm_abi),
+    serde(rename_all = "snake_case")
+)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(clap::ValueEnum))]
+pub enum GemmaVariants {
+    #[cfg_attr(not(target_arch = "wasm32"), value(name = "gemma-2b"))]
+    Gemma2B,
+    #[cfg_attr(not(target_arch = "wasm32"), value(name = "gemma2-2b"))]
+    Gemma2_2B,
+    #[cfg_attr(not(target_arch = "wasm32"), value(name = "gemma2-9b"))]
+    Gemma2_9B,
+}
+
+impl GemmaVariants {
+    /// Repository holding the `tokenizer.json` for this variant.
+    ///
+    /// Every Gemma repository is gated, pulling from them needs a Hub access token for an
+    /// account which accepted the license.
+    pub fn tokenizer_repo(&self) -> &str {
+        match self {
+            GemmaVariants::Gemma2B => "google/gemma-2b-it",
+            GemmaVariants::Gemma2_2B => "google/gemma-2-2b-it",
+            GemmaVariants::Gemma2_9B => "google/gemma-2-9b-it",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    target_arch = "wasm32",
+    derive(tsify::Tsify, serde::Serialize, serde::Deserialize),
+    tsify(from_wasm_abi),
+    serde(rename_all = "snake_case")
+)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(clap::ValueEnum))]
+pub enum EmbedderVariants {
+    #[cfg_attr(not(target_arch = "wasm32"), value(name = "bge-small"))]
+    BgeSmallEn,
+    #[cfg_attr(not(target_arch = "wasm32"), value(name = "nomic-embed"))]
+    NomicEmbedText,
+    #[cfg_attr(not(target_arch = "wasm32"), value(name = "jina-embed-small"))]
+    JinaEmbeddingsSmallEn,
+}
+
+impl EmbedderVariants {
+    /// Repository holding the `tokenizer.json` for this variant.
+    pub fn tokenizer_repo(&self) -> &str {
+        match self {
+            EmbedderVariants::BgeSmallEn => "BAAI/bge-small-en-v1.5",
+            EmbedderVariants::NomicEmbedText => "nomic-ai/nomic-embed-text-v1.5",
+            EmbedderVariants::JinaEmbeddingsSmallEn => "jinaai/jina-embeddings-v2-small-en",
+        }
+    }
+}
+
 /// # Available Models
 ///
 /// This is a type safe way to surface models to users,
@@ -65,10 +174,16 @@ pub enum PhiVariants {
 pub enum AvailableModels {
     Whisper(WhisperVariants),
     Phi(PhiVariants),
+    Llama(LlamaVariants),
+    Gemma(GemmaVariants),
+    Embedder(EmbedderVariants),
     Moondream,
 }
 
 impl AvailableModels {
+    /// Repository holding the Ratchet-ready GGUF weights of this model.
+    ///
+    /// Any weights missing from it can be produced locally by `ratchet-cli convert`.
     pub fn repo_id(&self) -> String {
         let id = match self {
             AvailableModels::Whisper(w) => w.repo_id(),
@@ -76,11 +191,53 @@ impl AvailableModels {
                 PhiVariants::Phi2 => "FL33TW00D-HF/phi2",
                 PhiVariants::Phi3 => "FL33TW00D-HF/phi3",
             },
+            AvailableModels::Llama(l) => match l {
+                LlamaVariants::Llama3_2_1B => "ratchet-community/llama-3.2-1b-instruct",
+                LlamaVariants::Llama3_8B => "ratchet-community/llama-3-8b-instruct",
+                LlamaVariants::Mistral7B => "ratchet-community/mistral-7b-instruct-v0.3",
+                LlamaVariants::Qwen2_1_5B => "ratchet-community/qwen2-1.5b-instruct",
+            },
+            AvailableModels::Gemma(g) => match g {
+                GemmaVariants::Gemma2B => "ratchet-community/gemma-2b-it",
+                GemmaVariants::Gemma2_2B => "ratchet-community/gemma-2-2b-it",
+                GemmaVariants::Gemma2_9B => "ratchet-community/gemma-2-9b-it",
+            },
+            AvailableModels::Embedder(e) => match e {
+                EmbedderVariants::BgeSmallEn => "ratchet-community/bge-small-en-v1.5",
+                EmbedderVariants::NomicEmbedText => "ratchet-community/nomic-embed-text-v1.5",
+                EmbedderVariants::JinaEmbeddingsSmallEn => {
+                    "ratchet-community/jina-embeddings-v2-small-en"
+                }
+            },
             AvailableModels::Moondream => "ratchet-community/ratchet-moondream-2",
         };
         id.to_string()
     }
 
+    /// Repository holding the `tokenizer.json` for this model.
+    pub fn tokenizer_repo(&self) -> &str {
+        match self {
+            AvailableModels::Whisper(w) => w.tokenizer_repo(),
+            AvailableModels::Phi(p) => match p {
+                PhiVariants::Phi2 => "microsoft/phi-2",
+                PhiVariants::Phi3 => "microsoft/Phi-3-mini-4k-instruct",
+            },
+            AvailableModels::Llama(l) => l.tokenizer_repo(),
+            AvailableModels::Gemma(g) => g.tokenizer_repo(),
+            AvailableModels::Embedder(e) => e.tokenizer_repo(),
+            AvailableModels::Moondream => "tgestson/ratchet-moondream2",
+        }
+    }
+
+    /// The quantization used when none is requested, by both `models pull` and the model
+    /// subcommands of the CLI.
+    pub fn default_quantization(&self) -> Quantization {
+        match self {
+            AvailableModels::Whisper(_) => Quantization::F32,
+            _ => Quantization::Q8_0,
+        }
+    }
+
     pub fn model_id(&self, quantization: Quantization) -> String {
         let model_stem = match self {
             AvailableModels::Whisper(w) => match w {
@@ -96,6 +253,22 @@ impl AvailableModels {
                 PhiVariants::Phi2 => "phi2",
                 PhiVariants::Phi3 => "phi3-mini-4k",
             },
+            AvailableModels::Llama(l) => match l {
+                LlamaVariants::Llama3_2_1B => "llama-3.2-1b-instruct",
+                LlamaVariants::Llama3_8B => "llama-3-8b-instruct",
+                LlamaVariants::Mistral7B => "mistral-7b-instruct-v0.3",
+                LlamaVariants::Qwen2_1_5B => "qwen2-1.5b-instruct",
+            },
+            AvailableModels::Gemma(g) => match g {
+                GemmaVariants::Gemma2B => "gemma-2b-it",
+                GemmaVariants::Gemma2_2B => "gemma-2-2b-it",
+                GemmaVariants::Gemma2_9B => "gemma-2-9b-it",
+            },
+            AvailableModels::Embedder(e) => match e {
+                EmbedderVariants::BgeSmallEn => "bge-small-en-v1.5",
+                EmbedderVariants::NomicEmbedText => "nomic-embed-text-v1.5",
+                EmbedderVariants::JinaEmbeddingsSmallEn => "jina-embeddings-v2-small-en",
+            },
             AvailableModels::Moondream => "moondream",
         };
         match quantization {
@@ -0,0 +1,508 @@
+//! # Model Store
+//!
+//! A local store of model files under `RATCHET_HOME` (defaults to `~/.cache/ratchet`).
+//!
+//! Every file lives at `models/<repo_id>/<filename>` and is tracked in `registry.json` along
+//! with its size & SHA-256. Files are pulled from the Hugging Face Hub on first use, or can be
+//! copied into place by hand. Set `RATCHET_OFFLINE=1` to never touch the network.
+//!
+//! The tokenizers of Llama & Gemma live in gated repositories: accept the license on the Hub
+//! and log in with `huggingface-cli login` before pulling them, or pass `--tokenizer` to the CLI.
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use hf_hub::api::sync::ApiBuilder;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::registry::{AvailableModels, Quantization};
+
+/// A single file within a Hub repository.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Resource {
+    pub repo_id: String,
+    pub filename: String,
+}
+
+impl Resource {
+    pub fn new(repo_id: impl Into<String>, filename: impl Into<String>) -> Self {
+        Self {
+            repo_id: repo_id.into(),
+            filename: filename.into(),
+        }
+    }
+
+    fn key(&self) -> String {
+        format!("{}/{}", self.repo_id, self.filename)
+    }
+}
+
+/// Every file required to run `model` fully offline: weights, tokenizer & auxiliary resources.
+pub fn resources(model: &AvailableModels, quantization: Quantization) -> Vec<Resource> {
+    let repo_id = model.repo_id();
+    let mut resources = vec![Resource::new(&repo_id, model.model_id(quantization))];
+    if let AvailableModels::Whisper(w) = model {
+        resources.push(Resource::new(&repo_id, w.mel_filters()));
+        resources.push(Resource::new(&repo_id, "config.json"));
+    }
+    resources.push(Resource::new(model.tokenizer_repo(), "tokenizer.json"));
+    resources
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoreEntry {
+    pub repo_id: String,
+    pub filename: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Registry {
+    version: u32,
+    files: Vec<StoreEntry>,
+}
+
+#[derive(Debug)]
+pub struct ModelStore {
+    root: PathBuf,
+    entries: BTreeMap<String, StoreEntry>,
+}
+
+impl ModelStore {
+    const REGISTRY_VERSION: u32 = 1;
+
+    /// Open the store at `RATCHET_HOME`, falling back to the user cache directory.
+    pub fn open() -> anyhow::Result<Self> {
+        Self::open_at(default_root()?)
+    }
+
+    pub fn open_at(root: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let root = root.into();
+        std::fs::create_dir_all(root.join("models"))
+            .with_context(|| format!("failed to create model store at {}", root.display()))?;
+
+        let registry_path = root.join("registry.json");
+        let registry: Registry = if registry_path.exists() {
+            let bytes = std::fs::read(&registry_path)?;
+            serde_json::from_slice(&bytes)
+                .with_context(|| format!("corrupt registry {}", registry_path.display()))?
+        } else {
+            Registry::default()
+        };
+        if registry.version > Self::REGISTRY_VERSION {
+            anyhow::bail!(
+                "{} was written by a newer version of ratchet (registry version {})",
+                registry_path.display(),
+                registry.version
+            );
+        }
+
+        let entries = registry
+            .files
+            .into_iter()
+            .map(|e| (Resource::new(&e.repo_id, &e.filename).key(), e))
+            .collect();
+        Ok(Self { root, entries })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Whether network access is disabled through `RATCHET_OFFLINE` or `HF_HUB_OFFLINE`.
+    pub fn is_offline() -> bool {
+        ["RATCHET_OFFLINE", "HF_HUB_OFFLINE"]
+            .iter()
+            .any(|var| std::env::var(var).is_ok_and(|v| !v.is_empty() && v != "0"))
+    }
+
+    /// Where `resource` lives in the store, whether or not it is present.
+    pub fn path(&self, resource: &Resource) -> PathBuf {
+        self.root
+            .join("models")
+            .join(&resource.repo_id)
+            .join(&resource.filename)
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &StoreEntry> {
+        self.entries.values()
+    }
+
+    /// Returns the local path of `resource` if it is in the store.
+    ///
+    /// Registered files are hashed again and rejected if they don't match the registry, files
+    /// copied into place by hand are hashed & registered on first use.
+    pub fn get(&mut self, resource: &Resource) -> anyhow::Result<Option<PathBuf>> {
+        let path = self.path(resource);
+        if !path.is_file() {
+            if self.entries.remove(&resource.key()).is_some() {
+                self.save()?;
+            }
+            return Ok(None);
+        }
+
+        let size = std::fs::metadata(&path)?.len();
+        match self.entries.get(&resource.key()) {
+            Some(entry) if entry.size == size => self.verify(resource).with_context(|| {
+                format!(
+                    "remove it with `ratchet-cli models rm {}`, or pull it again",
+                    resource.repo_id
+                )
+            })?,
+            Some(entry) => anyhow::bail!(
+                "{} is {} bytes but the registry expects {}, remove it with `ratchet-cli models rm {}`",
+                path.display(),
+                size,
+                entry.size,
+                resource.repo_id
+            ),
+            None => self.register(resource, &path, None)?,
+        }
+        Ok(Some(path))
+    }
+
+    /// Returns the local path of `resource`, pulling it from the Hub if it is missing.
+    pub fn fetch(&mut self, resource: &Resource) -> anyhow::Result<PathBuf> {
+        if let Some(path) = self.get(resource)? {
+            return Ok(path);
+        }
+        if Self::is_offline() {
+            anyhow::bail!(
+                "{} is not in the model store at {} and network access is disabled, run `ratchet-cli models pull` first",
+                resource.key(),
+                self.root.display()
+            );
+        }
+        self.pull(resource)
+    }
+
+    /// Like [ModelStore::fetch], but a stored copy of `resource` failing verification is deleted
+    /// and pulled again instead of rejected.
+    pub fn fetch_verified(&mut self, resource: &Resource) -> anyhow::Result<PathBuf> {
+        self.discard_corrupt(resource)?;
+        self.fetch(resource)
+    }
+
+    /// Delete the stored copy of `resource` if it fails verification, returns whether it did.
+    fn discard_corrupt(&mut self, resource: &Resource) -> anyhow::Result<bool> {
+        if !self.entries.contains_key(&resource.key()) || !self.path(resource).is_file() {
+            return Ok(false);
+        }
+        match self.verify(resource) {
+            Ok(()) => Ok(false),
+            Err(e) => {
+                log::warn!("{e}, pulling it again");
+                self.remove(&resource.repo_id, Some(&resource.filename))?;
+                Ok(true)
+            }
+        }
+    }
+
+    /// Download `resource` from the Hub into the store, replacing any existing copy.
+    pub fn pull(&mut self, resource: &Resource) -> anyhow::Result<PathBuf> {
+        let api = ApiBuilder::new()
+            .with_cache_dir(self.root.join("hub"))
+            .with_progress(true)
+            .build()?;
+        let downloaded = api
+            .model(resource.repo_id.clone())
+            .download(&resource.filename)
+            .map_err(|e| download_error(resource, e))?;
+
+        // The Hub cache links snapshots to content addressed blobs, LFS blobs are named after
+        // the SHA-256 of their contents.
+        let blob = downloaded.canonicalize()?;
+        let expected = blob
+            .file_name()
+            .and_then(|n| n.to_str())
+            .filter(|n| n.len() == 64 && n.bytes().all(|b| b.is_ascii_hexdigit()))
+            .map(str::to_lowercase);
+
+        let path = self.path(resource);
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        if std::fs::rename(&blob, &path).is_err() {
+            std::fs::copy(&blob, &path)?;
+            std::fs::remove_file(&blob)?;
+        }
+        if downloaded != blob {
+            let _ = std::fs::remove_file(&downloaded);
+        }
+
+        if let Err(e) = self.register(resource, &path, expected.as_deref()) {
+            let _ = std::fs::remove_file(&path);
+            return Err(e);
+        }
+        Ok(path)
+    }
+
+    /// Pull every resource required to run `model` that is not already in the store.
+    pub fn pull_model(
+        &mut self,
+        model: &AvailableModels,
+        quantization: Quantization,
+    ) -> anyhow::Result<Vec<PathBuf>> {
+        resources(model, quantization)
+            .iter()
+            .map(|r| self.fetch(r))
+            .collect()
+    }
+
+    /// Re-hash `resource` and compare it against the registry.
+    pub fn verify(&self, resource: &Resource) -> anyhow::Result<()> {
+        let entry = self
+            .entries
+            .get(&resource.key())
+            .ok_or_else(|| anyhow::anyhow!("{} is not in the model store", resource.key()))?;
+        let actual = sha256_file(&self.path(resource))?;
+        if actual != entry.sha256 {
+            anyhow::bail!(
+                "checksum mismatch for {}: expected {}, got {}",
+                resource.key(),
+                entry.sha256,
+                actual
+            );
+        }
+        Ok(())
+    }
+
+    /// Remove every stored file of `repo_id`, or only `filename` if provided.
+    ///
+    /// Returns the removed entries.
+    pub fn remove(
+        &mut self,
+        repo_id: &str,
+        filename: Option<&str>,
+    ) -> anyhow::Result<Vec<StoreEntry>> {
+        let keys = self
+            .entries
+            .values()
+            .filter(|e| e.repo_id == repo_id && filename.map_or(true, |f| e.filename == f))
+            .map(|e| Resource::new(&e.repo_id, &e.filename).key())
+            .collect::<Vec<_>>();
+
+        let mut removed = Vec::with_capacity(keys.len());
+        for key in keys {
+            let entry = self.entries.remove(&key).unwrap();
+            let path = self.path(&Resource::new(&entry.repo_id, &entry.filename));
+            if path.exists() {
+                std::fs::remove_file(&path)?;
+            }
+            removed.push(entry);
+        }
+        let repo_dir = self.root.join("models").join(repo_id);
+        if repo_dir.is_dir() && std::fs::read_dir(&repo_dir)?.next().is_none() {
+            std::fs::remove_dir(&repo_dir)?;
+        }
+        self.save()?;
+        Ok(removed)
+    }
+
+    fn register(
+        &mut self,
+        resource: &Resource,
+        path: &Path,
+        expected_sha256: Option<&str>,
+    ) -> anyhow::Result<()> {
+        let sha256 = sha256_file(path)?;
+        if let Some(expected) = expected_sha256 {
+            if sha256 != expected {
+                anyhow::bail!(
+                    "checksum mismatch for {}: expected {}, got {}",
+                    resource.key(),
+                    expected,
+                    sha256
+                );
+            }
+        }
+        let entry = StoreEntry {
+            repo_id: resource.repo_id.clone(),
+            filename: resource.filename.clone(),
+            size: std::fs::metadata(path)?.len(),
+            sha256,
+        };
+        self.entries.insert(resource.key(), entry);
+        self.save()
+    }
+
+    fn save(&self) -> anyhow::Result<()> {
+        let registry = Registry {
+            version: Self::REGISTRY_VERSION,
+            files: self.entries.values().cloned().collect(),
+        };
+        let tmp = self.root.join("registry.json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(&registry)?)?;
+        std::fs::rename(tmp, self.root.join("registry.json"))?;
+        Ok(())
+    }
+}
+
+fn default_root() -> anyhow::Result<PathBuf> {
+    if let Some(home) = std::env::var_os("RATCHET_HOME") {
+        return Ok(PathBuf::from(home));
+    }
+    if let Some(cache) = std::env::var_os("XDG_CACHE_HOME") {
+        return Ok(PathBuf::from(cache).join("ratchet"));
+    }
+    std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(|home| PathBuf::from(home).join(".cache").join("ratchet"))
+        .ok_or_else(|| anyhow::anyhow!("could not locate a home directory, set RATCHET_HOME"))
+}
+
+/// Hex encoded SHA-256 of the file at `path`.
+pub fn sha256_file(path: &Path) -> anyhow::Result<String> {
+    let mut file =
+        std::fs::File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 1 << 20];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+/// Explains the Hub refusing a download, which plain status codes leave users guessing about.
+fn download_error(resource: &Resource, e: hf_hub::api::sync::ApiError) -> anyhow::Error {
+    let hint = match http_status(&e.to_string()) {
+        Some(401 | 403) => Some(format!(
+            "{} is gated, accept its license at https://huggingface.co/{} and log in with `huggingface-cli login`",
+            resource.repo_id, resource.repo_id
+        )),
+        Some(404) => Some(format!(
+            "{} was not found on the Hub, convert the checkpoint locally with `ratchet-cli convert`",
+            resource.key()
+        )),
+        _ => None,
+    };
+    let e = anyhow::Error::new(e).context(format!("failed to download {}", resource.key()));
+    match hint {
+        Some(hint) => e.context(hint),
+        None => e,
+    }
+}
+
+/// The HTTP status of a failed request, `ureq` reports them as `<url>: status code <code>`.
+fn http_status(message: &str) -> Option<u16> {
+    let (_, rest) = message.rsplit_once("status code ")?;
+    rest.get(..3)?.parse().ok()
+}
+
+/// Fetch `filename` from `repo_id` through the default [ModelStore].
+pub fn fetch(repo_id: &str, filename: &str) -> anyhow::Result<PathBuf> {
+    ModelStore::open()?.fetch(&Resource::new(repo_id, filename))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_copied_in_resources() -> anyhow::Result<()> {
+        let root = tempfile::tempdir()?;
+        let resource = Resource::new("ratchet-community/test", "weights.gguf");
+
+        let mut store = ModelStore::open_at(root.path())?;
+        assert!(store.get(&resource)?.is_none());
+
+        let path = store.path(&resource);
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        std::fs::write(&path, b"abc")?;
+        assert_eq!(store.get(&resource)?, Some(path.clone()));
+
+        // Registered entries survive reopening the store.
+        let store = ModelStore::open_at(root.path())?;
+        let entry = store.entries().next().unwrap();
+        assert_eq!(entry.size, 3);
+        assert_eq!(
+            entry.sha256,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        store.verify(&resource)?;
+
+        std::fs::write(&path, b"abd")?;
+        assert!(store.verify(&resource).is_err());
+
+        //A corrupt copy is discarded, so pulling fetches it again
+        let mut store = store;
+        assert!(store.discard_corrupt(&resource)?);
+        assert!(!path.exists());
+        assert_eq!(store.entries().count(), 0);
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        std::fs::write(&path, b"abc")?;
+        assert_eq!(store.fetch_verified(&resource)?, path);
+        assert!(!store.discard_corrupt(&resource)?);
+
+        let removed = store.remove("ratchet-community/test", None)?;
+        assert_eq!(removed.len(), 1);
+        assert!(!path.exists());
+        assert_eq!(store.entries().count(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejects_corrupt_copy() -> anyhow::Result<()> {
+        let root = tempfile::tempdir()?;
+        let resource = Resource::new("ratchet-community/test", "weights.gguf");
+        let mut store = ModelStore::open_at(root.path())?;
+
+        let path = store.path(&resource);
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        std::fs::write(&path, b"abc")?;
+        assert_eq!(store.get(&resource)?, Some(path.clone()));
+
+        //Same size, different contents
+        std::fs::write(&path, b"abd")?;
+        let err = store.get(&resource).unwrap_err();
+        assert!(format!("{err:#}").contains("checksum mismatch"));
+        assert!(store.fetch(&resource).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_whisper_bundle() {
+        use crate::registry::WhisperVariants;
+        let model = AvailableModels::Whisper(WhisperVariants::LargeV3);
+        let files = resources(&model, Quantization::F16)
+            .into_iter()
+            .map(|r| r.filename)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            files,
+            [
+                "large-v3_f16.gguf",
+                "melfilters128.bytes",
+                "config.json",
+                "tokenizer.json"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_phi2_bundle() {
+        use crate::registry::PhiVariants;
+        let model = AvailableModels::Phi(PhiVariants::Phi2);
+        let tokenizer = resources(&model, Quantization::Q8_0).pop().unwrap();
+        assert_eq!(
+            tokenizer,
+            Resource::new("microsoft/phi-2", "tokenizer.json")
+        );
+    }
+
+    #[test]
+    fn test_http_status() {
+        let message = "request error: https://huggingface.co/google/gemma-2b-it/resolve/main/tokenizer.json: status code 403";
+        assert_eq!(http_status(message), Some(403));
+        assert_eq!(http_status("request error: connection refused"), None);
+    }
+}
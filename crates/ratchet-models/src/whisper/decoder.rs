@@ -2,9 +2,8 @@ use super::config::Config;
 use crate::whisper::residual_block::*;
 use half::f16;
 use ratchet::{prelude::*, DType, TensorDType};
-use ratchet_loader::gguf::gguf::Header;
-use ratchet_nn::{Embedding, KVCache, LayerNorm, Module};
-use std::io::{BufRead, Seek};
+use ratchet_loader::gguf::gguf::{Header, TensorReader};
+use ratchet_nn::{Embedding, KVCache, KVEntry, LayerNorm, Module};
 
 #[cfg(target_arch = "wasm32")]
 use {crate::ratchet_from_gguf_web, crate::TensorMap};
@@ -16,7 +15,7 @@ pub(crate) struct DecoderStem {
 }
 
 impl DecoderStem {
-    pub fn load<R: BufRead + Seek>(
+    pub fn load<R: TensorReader>(
         header: &Header,
         reader: &mut R,
         device: &Device,
@@ -94,17 +93,33 @@ impl Module for WhisperDecoder {
 
     fn schedule(&self, input: Self::Input) -> anyhow::Result<Tensor> {
         let [audio_ctx, tokens] = input;
-        let mut x = self.stem.schedule(StemInput {
-            tokens,
-            offset: self.cache.entries(0),
-        })?;
+        self.schedule_at(audio_ctx, tokens, self.cache.entries(0))
+    }
+}
+
+impl WhisperDecoder {
+    pub const MAX_CACHE: usize = 512;
+
+    /// Schedule `tokens` as if `offset` entries were cached, without reading the entry count
+    /// of the cache, e.g to build the step of a [DynamicExecutable](ratchet::DynamicExecutable).
+    pub fn schedule_at(
+        &self,
+        audio_ctx: Tensor,
+        tokens: Tensor,
+        offset: usize,
+    ) -> anyhow::Result<Tensor> {
+        let mut x = self.stem.schedule(StemInput { tokens, offset })?;
 
         for (block_idx, block) in self.blocks.iter().enumerate() {
+            let cache = KVEntry {
+                entries: offset,
+                ..self.cache[block_idx].clone()
+            };
             let block_input = ResidualAttentionBlockInputs {
                 x,
                 xa: Some(audio_ctx.clone()),
                 mask: Some(self.mask.clone()),
-                cache: Some(self.cache[block_idx].clone()),
+                cache: Some(cache),
             };
             x = block.schedule(block_input)?;
         }
@@ -118,10 +133,11 @@ impl Module for WhisperDecoder {
             .full()?;
         Ok(logits)
     }
-}
 
-impl WhisperDecoder {
-    pub const MAX_CACHE: usize = 512;
+    /// The largest number of tokens a decoding can hold, positions and cache included.
+    pub fn max_tokens(&self) -> usize {
+        self.stem.pos_embed.shape()[0].min(Self::MAX_CACHE)
+    }
 
     pub fn cache_mut(&mut self) -> &mut KVCache {
         &mut self.cache
@@ -197,7 +213,7 @@ impl WhisperDecoder {
         })
     }
 
-    pub fn load<R: BufRead + Seek>(
+    pub fn load<R: TensorReader>(
         header: &Header,
         config: &Config,
         reader: &mut R,
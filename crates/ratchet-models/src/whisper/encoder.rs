@@ -1,7 +1,5 @@
-use std::io::{BufRead, Seek};
-
-use ratchet::{DType, Device, Tensor};
-use ratchet_loader::gguf::gguf::Header;
+use ratchet::{DType, Device, Executable, Tensor};
+use ratchet_loader::gguf::gguf::{Header, TensorReader};
 use ratchet_nn::{LayerNorm, Module};
 
 use super::{
@@ -56,7 +54,7 @@ impl Module for EncoderStem {
 }
 
 impl EncoderStem {
-    pub fn load<R: BufRead + Seek>(
+    pub fn load<R: TensorReader>(
         header: &Header,
         reader: &mut R,
         device: &Device,
@@ -164,7 +162,7 @@ impl WhisperEncoder {
         })
     }
 
-    pub fn load<R: BufRead + Seek>(
+    pub fn load<R: TensorReader>(
         header: &Header,
         config: &Config,
         reader: &mut R,
@@ -204,6 +202,36 @@ impl WhisperEncoder {
     }
 }
 
+/// # Segment Encoder
+///
+/// Encodes the 30s segments of a transcription with a single [Executable], compiled for the
+/// first segment and run again for every later one.
+pub(crate) struct SegmentEncoder<'a> {
+    encoder: &'a WhisperEncoder,
+    compiled: Option<(Tensor, Executable)>,
+}
+
+impl<'a> SegmentEncoder<'a> {
+    pub fn new(encoder: &'a WhisperEncoder) -> Self {
+        Self {
+            encoder,
+            compiled: None,
+        }
+    }
+
+    /// The returned features are overwritten by the next segment.
+    pub fn encode(&mut self, mel: Tensor) -> anyhow::Result<Tensor> {
+        let mel = mel.resolve()?;
+        if self.compiled.is_none() {
+            let input = Tensor::zeros::<f32>(mel.shape(), mel.device());
+            let executable = self.encoder.schedule(input.clone())?.compile()?;
+            self.compiled = Some((input, executable));
+        }
+        let (input, executable) = self.compiled.as_ref().expect("compiled above");
+        Ok(executable.run(&[(input, &mel)])?)
+    }
+}
+
 #[cfg(all(test, not(target_arch = "wasm32")))]
 mod tests {
     use hf_hub::api::sync::Api;
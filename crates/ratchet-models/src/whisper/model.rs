@@ -7,10 +7,7 @@ use ndarray_stats::QuantileExt;
 use ratchet::NDArrayExt;
 
 #[cfg(not(target_arch = "wasm32"))]
-use {
-    hf_hub::api::sync::Api,
-    std::io::{BufRead, Seek},
-};
+use ratchet_loader::gguf::gguf::TensorReader;
 
 #[cfg(target_arch = "wasm32")]
 use {crate::TensorMap, ratchet_hub::ApiBuilder, ratchet_hub::RepoType, wasm_bindgen::prelude::*};
@@ -33,17 +30,13 @@ pub struct Whisper {
 
 impl Whisper {
     #[cfg(not(target_arch = "wasm32"))]
-    pub fn load<R: BufRead + Seek>(
+    pub fn load<R: TensorReader>(
         header: Header,
         variant: WhisperVariants,
         reader: &mut R,
         device: Device,
     ) -> anyhow::Result<Self> {
-        let mel_fname = match variant {
-            WhisperVariants::DistilLargeV3 | WhisperVariants::LargeV3 => "melfilters128.bytes",
-            _ => "melfilters.bytes",
-        };
-        let mel_bytes = Self::fetch_resource(&variant, mel_fname)?;
+        let mel_bytes = Self::fetch_resource(&variant, variant.mel_filters())?;
         let mut mel_filters = vec![0f32; mel_bytes.len() / 4];
         <byteorder::LittleEndian as byteorder::ByteOrder>::read_f32_into(
             &mel_bytes,
@@ -72,11 +65,9 @@ impl Whisper {
         variant: WhisperVariants,
     ) -> anyhow::Result<Self> {
         let device = Device::request_device(ratchet::DeviceRequest::GPU).await?;
-        let mel_fname = match variant {
-            WhisperVariants::DistilLargeV3 | WhisperVariants::LargeV3 => "melfilters128.bytes",
-            _ => "melfilters.bytes",
-        };
-        let mel_bytes = Self::fetch_resource(&variant, mel_fname).await.unwrap();
+        let mel_bytes = Self::fetch_resource(&variant, variant.mel_filters())
+            .await
+            .unwrap();
         let mut mel_filters = vec![0f32; mel_bytes.len() / 4];
         <byteorder::LittleEndian as byteorder::ByteOrder>::read_f32_into(
             &mel_bytes,
@@ -113,13 +104,12 @@ impl Whisper {
         }
     }
 
+    /// Read `resource` through the local [ModelStore](crate::store::ModelStore), pulling it
+    /// from the Hub if it has not been stored yet.
     #[cfg(not(target_arch = "wasm32"))]
     pub fn fetch_resource(variant: &WhisperVariants, resource: &str) -> anyhow::Result<Vec<u8>> {
-        let api = Api::new().unwrap();
-        let repo_id = variant.repo_id();
-
-        let repo = api.model(repo_id.to_string());
-        Ok(std::fs::read(repo.get(resource).unwrap())?)
+        let path = crate::store::fetch(variant.repo_id(), resource)?;
+        Ok(std::fs::read(path)?)
     }
 }
 
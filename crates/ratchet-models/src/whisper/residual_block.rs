@@ -1,8 +1,7 @@
 use super::{mha::*, mlp::MLP};
 use ratchet::{Device, Tensor};
-use ratchet_loader::gguf::gguf::Header;
+use ratchet_loader::gguf::gguf::{Header, TensorReader};
 use ratchet_nn::{KVEntry, LayerNorm, Linear, Module};
-use std::io::{BufRead, Seek};
 
 #[cfg(target_arch = "wasm32")]
 use {crate::ratchet_from_gguf_web, crate::TensorMap};
@@ -52,7 +51,7 @@ impl Module for ResidualAttentionBlock {
 }
 
 impl ResidualAttentionBlock {
-    pub fn load<R: BufRead + Seek>(
+    pub fn load<R: TensorReader>(
         header: &Header,
         reader: &mut R,
         layer_index: usize,
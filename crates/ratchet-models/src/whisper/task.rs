@@ -3,9 +3,11 @@ use super::{
     tokenizer::WhisperTokenizer, transcript::*,
 };
 use crate::whisper::options::{DecodingOptions, Prompt};
+use crate::PipelinedDecoder;
 use ndarray::{s, Axis};
-use ratchet::{shape, Device, Tensor};
+use ratchet::{shape, Bindings, DType, Device, DynamicExecutable, Symbol, Tensor};
 use ratchet_nn::Module;
+use std::cell::Cell;
 
 #[derive(Debug, thiserror::Error)]
 pub enum DecodeError {
@@ -31,6 +33,9 @@ pub struct DecodingTask {
 impl DecodingTask {
     fn get_initial_tokens(&self) -> Vec<i32> {
         let mut init_tokens = self.tokenizer.sot_sequence();
+        if self.options.without_timestamps {
+            init_tokens.push(self.tokenizer.notimestamps());
+        }
         if let Some(prompt) = &self.options.prompt {
             let prompt_tokens = match prompt {
                 Prompt::Tokens(tokens) => tokens.clone(),
@@ -70,14 +75,51 @@ impl DecodingTask {
             max_initial_timestamp_index =
                 Some((max_initial_timestamp / precision).round() as usize);
         }
-        task.logit_mutators.push(Box::new(ApplyTimestampRules {
-            sample_begin: task.initial_tokens_len.unwrap(),
-            max_initial_timestamp_index,
-        }));
+        if !task.options.without_timestamps {
+            task.logit_mutators.push(Box::new(ApplyTimestampRules {
+                sample_begin: task.initial_tokens_len.unwrap(),
+                max_initial_timestamp_index,
+            }));
+        }
 
         task
     }
 
+    /// Without logit mutators nothing needs the logits on the host, so tokens are sampled on
+    /// the device by a [PipelinedDecoder].
+    async fn pipelined_loop(
+        &self,
+        decoder: &mut WhisperDecoder,
+        audio_ctx: Tensor,
+        callback: &Option<impl Fn(StreamedSegment)>,
+    ) -> Result<Vec<i32>, DecodeError> {
+        let prompt = self.get_initial_tokens();
+        let mut tokens = prompt.clone();
+        let vocab_size = self.tokenizer.vocab_size();
+        let device = audio_ctx.device().clone();
+        let mut timestamps_seen = 0;
+
+        let step = |input: Tensor| -> anyhow::Result<Tensor> {
+            let seq_len = input.shape()[1];
+            let logits = decoder
+                .schedule([audio_ctx.clone(), input])?
+                .cast(DType::F32)?;
+            decoder.cache_mut().update(seq_len);
+            //The embedding is padded beyond the vocabulary
+            logits.slice(&[0..1, 0..seq_len, 0..vocab_size])
+        };
+        let on_token = |token: i32| -> anyhow::Result<()> {
+            tokens.push(token);
+            if let Some(ref cb) = callback {
+                self.handle_callback(&self.tokenizer, &tokens, &mut timestamps_seen, cb);
+            }
+            Ok(())
+        };
+        let sampler = PipelinedDecoder::new(Some(WhisperTokenizer::EOT), self.sample_len as usize);
+        sampler.run(&prompt, &device, step, on_token).await?;
+        Ok(tokens)
+    }
+
     #[cfg(not(target_arch = "wasm32"))]
     fn main_loop(
         &self,
@@ -85,28 +127,33 @@ impl DecodingTask {
         audio_ctx: Tensor,
         callback: &Option<impl Fn(StreamedSegment)>,
     ) -> Result<Vec<i32>, DecodeError> {
-        use ratchet::DType;
+        if self.logit_mutators.is_empty() {
+            return pollster::block_on(self.pipelined_loop(decoder, audio_ctx, callback));
+        }
 
         let mut tokens = self.get_initial_tokens();
         let sliced_vocab_size = self.tokenizer.vocab_size();
         let device = audio_ctx.device().clone();
         let mut timestamps_seen = 0;
 
-        for _ in 0..self.sample_len {
-            let input = if tokens.len() > self.initial_tokens_len.unwrap() {
-                &tokens[tokens.len() - 1..]
-            } else {
-                &tokens
-            };
-            let input_t = Tensor::from_data(input, shape![1, input.len()], device.clone());
-
-            let logits = decoder
-                .schedule([audio_ctx.clone(), input_t])?
-                .cast(DType::F32)?
-                .resolve()?;
-            decoder.cache_mut().update(input.len());
+        let prompt_t = Tensor::from_data(&tokens, shape![1, tokens.len()], device.clone());
+        let mut cpu_logits = decoder
+            .schedule([audio_ctx.clone(), prompt_t])?
+            .cast(DType::F32)?
+            .resolve()?
+            .to(&Device::CPU)?;
+        decoder.cache_mut().update(tokens.len());
+
+        let (kv_len, last) = (Symbol::new("kv_len", decoder.max_tokens()), Cell::new(0));
+        let mut step = Self::single_token_step(decoder, &audio_ctx, kv_len, &last)?;
+        for i in 0..self.sample_len {
+            if i > 0 {
+                last.set(tokens[tokens.len() - 1]);
+                cpu_logits = step
+                    .run(&Bindings::new().bind(kv_len, tokens.len()))?
+                    .to(&Device::CPU)?;
+            }
 
-            let cpu_logits = logits.to(&Device::CPU)?;
             let mut logits = Self::slice_logits(cpu_logits, sliced_vocab_size);
             let token_t = Tensor::from_data(tokens.clone(), shape![1, tokens.len()], Device::CPU);
             for m in &self.logit_mutators {
@@ -134,23 +181,35 @@ impl DecodingTask {
         audio_ctx: Tensor,
         callback: &Option<impl Fn(StreamedSegment)>,
     ) -> Result<Vec<i32>, DecodeError> {
+        if self.logit_mutators.is_empty() {
+            return self.pipelined_loop(decoder, audio_ctx, callback).await;
+        }
+
         let mut tokens = self.get_initial_tokens();
         let device = audio_ctx.device().clone();
         let sliced_vocab_size = self.tokenizer.vocab_size();
         let mut timestamps_seen = 0;
 
-        for _ in 0..self.sample_len {
-            let input = if tokens.len() > self.initial_tokens_len.unwrap() {
-                &tokens[tokens.len() - 1..]
-            } else {
-                &tokens
-            };
-            let input_t = Tensor::from_data(input, shape![1, input.len()], device.clone());
-
-            let logits = decoder.schedule([audio_ctx.clone(), input_t])?.resolve()?;
-            decoder.cache_mut().update(input.len());
+        let prompt_t = Tensor::from_data(&tokens, shape![1, tokens.len()], device.clone());
+        let mut cpu_logits = decoder
+            .schedule([audio_ctx.clone(), prompt_t])?
+            .cast(DType::F32)?
+            .resolve()?
+            .to(&Device::CPU)
+            .await?;
+        decoder.cache_mut().update(tokens.len());
+
+        let (kv_len, last) = (Symbol::new("kv_len", decoder.max_tokens()), Cell::new(0));
+        let mut step = Self::single_token_step(decoder, &audio_ctx, kv_len, &last)?;
+        for i in 0..self.sample_len {
+            if i > 0 {
+                last.set(tokens[tokens.len() - 1]);
+                cpu_logits = step
+                    .run(&Bindings::new().bind(kv_len, tokens.len()))?
+                    .to(&Device::CPU)
+                    .await?;
+            }
 
-            let cpu_logits = logits.to(&Device::CPU).await?;
             let mut logits = Self::slice_logits(cpu_logits, sliced_vocab_size);
             let token_t = Tensor::from_data(tokens.clone(), shape![1, tokens.len()], Device::CPU);
             for m in &self.logit_mutators {
@@ -171,6 +230,28 @@ impl DecodingTask {
         Ok(tokens)
     }
 
+    /// The single token steps following the prompt, compiled once as a [DynamicExecutable]
+    /// over `kv_len`, the number of cached entries after the step. Each step decodes the
+    /// token in `last`.
+    ///
+    /// Steps don't advance the entry count of the cache, the decoder is reset after every
+    /// segment.
+    fn single_token_step<'a>(
+        decoder: &'a WhisperDecoder,
+        audio_ctx: &'a Tensor,
+        kv_len: Symbol,
+        last: &'a Cell<i32>,
+    ) -> anyhow::Result<DynamicExecutable<impl FnMut(&Bindings) -> anyhow::Result<Tensor> + 'a>>
+    {
+        let device = audio_ctx.device().clone();
+        DynamicExecutable::new(vec![kv_len], move |b| {
+            let token = Tensor::from_data([last.get()], shape![1, 1], device.clone());
+            decoder
+                .schedule_at(audio_ctx.clone(), token, b[kv_len] - 1)?
+                .cast(DType::F32)
+        })
+    }
+
     fn handle_callback(
         &self,
         tokenizer: &WhisperTokenizer,
@@ -1,8 +1,6 @@
 use crate::whisper::options::{Language, Task};
 use tokenizers::Tokenizer;
 
-#[cfg(not(target_arch = "wasm32"))]
-use hf_hub::api::sync::Api;
 #[cfg(target_arch = "wasm32")]
 use {ratchet_hub::ApiBuilder, ratchet_hub::RepoType, wasm_bindgen::JsError};
 
@@ -68,16 +66,13 @@ impl WhisperTokenizer {
 
     #[cfg(not(target_arch = "wasm32"))]
     pub fn fetch(v3: bool) -> Tokenizer {
-        let api = Api::new().unwrap();
-
         //TODO: dumb hack
         let repo_name = match v3 {
             true => "openai/whisper-large-v3",
             false => "openai/whisper-tiny",
         };
 
-        let tokenizer_repo = api.model(repo_name.to_string());
-        let tokenizer_path = tokenizer_repo.get("tokenizer.json").unwrap();
+        let tokenizer_path = crate::store::fetch(repo_name, "tokenizer.json").unwrap();
         Tokenizer::from_file(tokenizer_path).unwrap()
     }
 
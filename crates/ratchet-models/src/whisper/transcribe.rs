@@ -1,7 +1,8 @@
 use crate::whisper::model::Whisper;
 use crate::whisper::options::*;
-use crate::whisper::{spectrogram::*, task::*, tokenizer::*, transcript::*};
-use ratchet_nn::Module;
+use crate::whisper::{
+    encoder::SegmentEncoder, spectrogram::*, task::*, tokenizer::*, transcript::*,
+};
 use std::cmp::min;
 use web_time::Instant;
 
@@ -37,6 +38,7 @@ pub fn transcribe(
     let mut all_tokens = Vec::with_capacity(512);
     let mut all_segments = Vec::with_capacity(512);
     let prompt_since_reset = 0;
+    let mut encoder = SegmentEncoder::new(&model.encoder);
 
     while seek < content_frames {
         let mut decode_options = decode_options.clone();
@@ -54,7 +56,7 @@ pub fn transcribe(
             decode_options.prompt = Some(Prompt::Tokens(all_tokens[prompt_since_reset..].to_vec()));
         }
 
-        let hs = model.encoder.schedule(mel_segment)?.resolve()?;
+        let hs = encoder.encode(mel_segment)?;
 
         let task = DecodingTask::new(decode_options, tokenizer.clone());
         let decoded = task.run(&mut model.decoder, hs, &callback)?;
@@ -115,6 +117,7 @@ pub async fn transcribe(
     let mut all_tokens = Vec::with_capacity(512);
     let mut all_segments = Vec::with_capacity(512);
     let prompt_since_reset = 0;
+    let mut encoder = SegmentEncoder::new(&model.encoder);
 
     while seek < content_frames {
         let mut decode_options = decode_options.clone();
@@ -132,7 +135,7 @@ pub async fn transcribe(
             decode_options.prompt = Some(Prompt::Tokens(all_tokens[prompt_since_reset..].to_vec()));
         }
 
-        let hs = model.encoder.schedule(mel_segment)?.resolve()?;
+        let hs = encoder.encode(mel_segment)?;
 
         let dbg = hs.clone().to(&ratchet::Device::CPU).await;
         log::warn!("HS: {:?}", dbg);
@@ -0,0 +1,223 @@
+use ratchet::{shape, AttentionMask, Tensor};
+
+use crate::{KVEntry, Module};
+
+/// # Grouped Query Attention
+///
+/// Scaled dot product attention where `n_heads` query heads share `n_kv_heads` key/value heads.
+/// Multi-head attention is `n_kv_heads == n_heads`, multi-query attention is `n_kv_heads == 1`.
+///
+/// Key/value heads are never repeated in memory, the fused attention kernel maps each query head
+/// onto its group instead.
+#[derive(Clone, Debug, derive_new::new)]
+pub struct GroupedQueryAttention {
+    n_heads: usize,
+    n_kv_heads: usize,
+    head_dim: usize,
+    scale: f32,
+    #[new(default)]
+    softcap: Option<f32>,
+}
+
+impl GroupedQueryAttention {
+    /// Uses the standard `1 / sqrt(head_dim)` softmax scale.
+    pub fn with_default_scale(n_heads: usize, n_kv_heads: usize, head_dim: usize) -> Self {
+        Self::new(
+            n_heads,
+            n_kv_heads,
+            head_dim,
+            1.0 / (head_dim as f32).sqrt(),
+        )
+    }
+
+    /// Soft-caps the attention logits with `tanh(x / softcap) * softcap`.
+    pub fn with_softcap(mut self, softcap: f32) -> Self {
+        self.softcap = Some(softcap);
+        self
+    }
+
+    pub fn n_heads(&self) -> usize {
+        self.n_heads
+    }
+
+    pub fn n_kv_heads(&self) -> usize {
+        self.n_kv_heads
+    }
+
+    pub fn head_dim(&self) -> usize {
+        self.head_dim
+    }
+
+    /// Number of query heads per key/value head.
+    pub fn n_rep(&self) -> usize {
+        self.n_heads / self.n_kv_heads
+    }
+
+    /// [B, L, n_heads * head_dim] -> [B, n_heads, L, head_dim]
+    ///
+    /// The head count is inferred, so queries and keys/values are split alike.
+    pub fn split_heads(&self, x: Tensor) -> anyhow::Result<Tensor> {
+        let [batch_size, seq_len, n_state]: [usize; 3] = x.shape().try_into()?;
+        anyhow::ensure!(
+            n_state % self.head_dim == 0,
+            "{n_state} features cannot be split into heads of {}",
+            self.head_dim
+        );
+        let n_heads = n_state / self.head_dim;
+        x.view(shape![batch_size, seq_len, n_heads, self.head_dim])?
+            .permute(&[0, 2, 1, 3])
+    }
+}
+
+pub struct AttentionInput {
+    /// [B, n_heads, L, head_dim]
+    pub query: Tensor,
+    /// [B, n_kv_heads, L, head_dim]
+    pub key: Tensor,
+    /// [B, n_kv_heads, L, head_dim]
+    pub value: Tensor,
+    pub mask: AttentionMask,
+    pub cache: Option<KVEntry>,
+}
+
+impl Module for GroupedQueryAttention {
+    type Input = AttentionInput;
+
+    /// Returns the attention output with heads merged, [B, L, n_heads * head_dim].
+    fn schedule(&self, input: Self::Input) -> anyhow::Result<Tensor> {
+        let AttentionInput {
+            query,
+            key,
+            value,
+            mask,
+            cache,
+        } = input;
+        anyhow::ensure!(
+            self.n_kv_heads > 0 && self.n_heads % self.n_kv_heads == 0,
+            "n_heads ({}) must be a multiple of n_kv_heads ({})",
+            self.n_heads,
+            self.n_kv_heads
+        );
+        let [batch_size, n_heads, q_len, _]: [usize; 4] = query.shape().try_into()?;
+        anyhow::ensure!(n_heads == self.n_heads, "expected {} heads", self.n_heads);
+        anyhow::ensure!(
+            key.shape()[1] == self.n_kv_heads,
+            "expected {} kv heads",
+            self.n_kv_heads
+        );
+
+        let (key, value) = if let Some(kv) = cache {
+            let k_cache = kv.k_cache.cache(key, 2, kv.entries)?;
+            let v_cache = kv.v_cache.cache(value, 2, kv.entries)?;
+            (k_cache, v_cache)
+        } else {
+            (key, value)
+        };
+
+        query
+            .softcapped_attention(key, value, mask, self.scale, self.softcap)?
+            .permute(&[0, 2, 1, 3])?
+            .view(shape![batch_size, q_len, self.n_heads * self.head_dim])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AttentionInput, GroupedQueryAttention};
+    use crate::Module;
+    use ratchet::{shape, AttentionMask, Device, Tensor};
+
+    const HEAD_DIM: usize = 8;
+
+    #[test]
+    fn rejects_mismatched_heads() {
+        let input = |n_heads: usize, n_kv_heads: usize| AttentionInput {
+            query: Tensor::zeros::<f32>(&shape![1, n_heads, 3, HEAD_DIM], &Device::CPU),
+            key: Tensor::zeros::<f32>(&shape![1, n_kv_heads, 3, HEAD_DIM], &Device::CPU),
+            value: Tensor::zeros::<f32>(&shape![1, n_kv_heads, 3, HEAD_DIM], &Device::CPU),
+            mask: AttentionMask::None,
+            cache: None,
+        };
+        let gqa = GroupedQueryAttention::with_default_scale(4, 2, HEAD_DIM);
+        assert!(gqa.schedule(input(2, 2)).is_err());
+        assert!(gqa.schedule(input(4, 4)).is_err());
+        let indivisible = GroupedQueryAttention::with_default_scale(4, 3, HEAD_DIM);
+        assert!(indivisible.schedule(input(4, 3)).is_err());
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32"), feature = "pyo3"))]
+mod gpu_tests {
+    use super::{AttentionInput, GroupedQueryAttention};
+    use crate::Module;
+    use ratchet::{shape, AttentionMask, Device, DeviceRequest, Tensor};
+
+    const HEAD_DIM: usize = 8;
+
+    /// Repeats each of the `[1, n_kv_heads, L, D]` heads `n_rep` times.
+    fn repeat_heads(x: &Tensor, n_rep: usize) -> anyhow::Result<Tensor> {
+        let [b, n_kv_heads, l, d]: [usize; 4] = x.shape().try_into()?;
+        let repeated = x
+            .to_vec::<f32>()?
+            .chunks_exact(l * d)
+            .flat_map(|head| std::iter::repeat(head).take(n_rep).flatten().copied())
+            .collect::<Vec<_>>();
+        Ok(Tensor::from_data(
+            repeated,
+            shape![b, n_kv_heads * n_rep, l, d],
+            Device::CPU,
+        ))
+    }
+
+    fn attend(
+        sdpa: &GroupedQueryAttention,
+        query: &Tensor,
+        key: &Tensor,
+        value: &Tensor,
+        device: &Device,
+    ) -> anyhow::Result<Tensor> {
+        let output = sdpa.schedule(AttentionInput {
+            query: query.to(device)?,
+            key: key.to(device)?,
+            value: value.to(device)?,
+            mask: AttentionMask::Causal,
+            cache: None,
+        })?;
+        Ok(output.resolve()?.to(&Device::CPU)?)
+    }
+
+    #[test]
+    fn grouped_matches_repeated_heads() -> anyhow::Result<()> {
+        let device = Device::request_device(DeviceRequest::GPU)?;
+        let (n_heads, n_kv_heads, seq_len) = (4, 2, 5);
+        let query = Tensor::randn::<f32>(shape![1, n_heads, seq_len, HEAD_DIM], Device::CPU);
+        let key = Tensor::randn::<f32>(shape![1, n_kv_heads, seq_len, HEAD_DIM], Device::CPU);
+        let value = Tensor::randn::<f32>(shape![1, n_kv_heads, seq_len, HEAD_DIM], Device::CPU);
+
+        let gqa = GroupedQueryAttention::with_default_scale(n_heads, n_kv_heads, HEAD_DIM);
+        let grouped = attend(&gqa, &query, &key, &value, &device)?;
+        assert_eq!(grouped.shape(), &shape![1, seq_len, n_heads * HEAD_DIM]);
+
+        let mha = GroupedQueryAttention::with_default_scale(n_heads, n_heads, HEAD_DIM);
+        let n_rep = gqa.n_rep();
+        let (key, value) = (repeat_heads(&key, n_rep)?, repeat_heads(&value, n_rep)?);
+        let repeated = attend(&mha, &query, &key, &value, &device)?;
+        grouped.all_close(&repeated, 1e-5, 1e-5)?;
+        Ok(())
+    }
+
+    #[test]
+    fn splits_heads() -> anyhow::Result<()> {
+        let device = Device::request_device(DeviceRequest::GPU)?;
+        let gqa = GroupedQueryAttention::with_default_scale(2, 1, 2);
+        //2 tokens of 2 heads, feature f of head h is h * 2 + f
+        let x = Tensor::from_data([0f32, 1., 2., 3., 4., 5., 6., 7.], shape![1, 2, 4], device);
+        let heads = gqa.split_heads(x)?.resolve()?.to(&Device::CPU)?;
+        assert_eq!(heads.shape(), &shape![1, 2, 2, 2]);
+        assert_eq!(heads.to_vec::<f32>()?, [0., 1., 4., 5., 2., 3., 6., 7.]);
+
+        let kv = Tensor::zeros::<f32>(&shape![1, 2, 3], &Device::CPU);
+        assert!(gqa.split_heads(kv).is_err());
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-use ratchet::{Device, Shape, Tensor, TensorDType};
+use ratchet::{shape, Device, Shape, Tensor, TensorDType};
 
 #[derive(Clone, Debug)]
 pub struct KVEntry {
@@ -15,6 +15,12 @@ impl KVEntry {
             entries: 0,
         }
     }
+
+    /// Number of key/value heads held by this entry.
+    /// May be smaller than the number of query heads (GQA/MQA).
+    pub fn n_kv_heads(&self) -> usize {
+        self.k_cache.shape()[1]
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -37,6 +43,22 @@ impl KVCache {
         KVCache(entries)
     }
 
+    /// Allocates a cache of shape `[batch_size, n_kv_heads, max_seq_len, head_dim]` per layer.
+    ///
+    /// With grouped-query attention `n_kv_heads` is the number of key/value heads, not the
+    /// number of query heads.
+    pub fn with_kv_heads<T: TensorDType>(
+        n_layers: i32,
+        batch_size: usize,
+        n_kv_heads: usize,
+        max_seq_len: usize,
+        head_dim: usize,
+        device: &Device,
+    ) -> Self {
+        let shape = shape![batch_size, n_kv_heads, max_seq_len, head_dim];
+        Self::new::<T>(n_layers, shape, device)
+    }
+
     pub fn update(&mut self, offset: usize) {
         for entry in &mut self.0 {
             entry.entries += offset;
@@ -1,3 +1,4 @@
+mod attention;
 mod embedding;
 mod groupnorm;
 mod kv_cache;
@@ -5,6 +6,7 @@ mod linear;
 mod norm;
 mod rope;
 
+pub use attention::*;
 pub use embedding::*;
 pub use groupnorm::*;
 pub use kv_cache::*;
@@ -1,13 +1,17 @@
 use indexed_db_futures::prelude::*;
-use js_sys::Uint8Array;
+use js_sys::{Reflect, Uint8Array};
+use ratchet::ShaderCache;
+use ratchet_hub::download::{ChunkSink, DownloadError, DownloadState};
 use ratchet_loader::gguf::gguf::Header;
 use ratchet_models::{
     registry::{AvailableModels, Quantization},
     TensorMap, WebTensor,
 };
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 use uuid::Uuid;
 use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
 
 #[derive(Debug, thiserror::Error)]
 pub enum RatchetDBError {
@@ -19,6 +23,10 @@ pub enum RatchetDBError {
     },
     #[error(transparent)]
     SerdeError(#[from] serde_wasm_bindgen::Error),
+    #[error("Incomplete download for {0}")]
+    IncompleteDownload(String),
+    #[error("Storage estimate unavailable: {0}")]
+    StorageEstimate(String),
 }
 
 impl From<indexed_db_futures::web_sys::DomException> for RatchetDBError {
@@ -37,13 +45,67 @@ pub struct RatchetDB {
 
 type Result<A, E = RatchetDBError> = std::result::Result<A, E>;
 
+type Migration = fn(&IdbVersionChangeEvent) -> Result<(), JsValue>;
+
+fn create_store_if_needed(evt: &IdbVersionChangeEvent, store_key: &str) -> Result<(), JsValue> {
+    if let None = evt.db().object_store_names().find(|n| n == store_key) {
+        evt.db().create_object_store(store_key)?;
+    }
+    Ok(())
+}
+
+fn create_store_with_index_if_needed(
+    evt: &IdbVersionChangeEvent,
+    store_key: &str,
+    index_key: &str,
+) -> Result<(), JsValue> {
+    if let None = evt.db().object_store_names().find(|n| n == store_key) {
+        let store = evt.db().create_object_store(store_key)?;
+        store.create_index(index_key, &IdbKeyPath::str(index_key))?;
+    }
+    Ok(())
+}
+
+/// Models, tokenizers & per-tensor records.
+fn migrate_v1(evt: &IdbVersionChangeEvent) -> Result<(), JsValue> {
+    create_store_if_needed(evt, RatchetDB::MODEL_STORE)?;
+    create_store_if_needed(evt, RatchetDB::TOKENIZER_STORE)?;
+    create_store_with_index_if_needed(evt, RatchetDB::TENSOR_STORE, RatchetDB::TENSOR_INDEX)
+}
+
+/// Chunked, resumable downloads.
+fn migrate_v2(evt: &IdbVersionChangeEvent) -> Result<(), JsValue> {
+    create_store_with_index_if_needed(evt, RatchetDB::CHUNK_STORE, RatchetDB::CHUNK_INDEX)?;
+    create_store_if_needed(evt, RatchetDB::DOWNLOAD_STORE)
+}
+
+/// Last used times for LRU eviction.
+fn migrate_v3(evt: &IdbVersionChangeEvent) -> Result<(), JsValue> {
+    create_store_if_needed(evt, RatchetDB::USAGE_STORE)
+}
+
+/// Generated WGSL, see [ratchet::ShaderCache].
+fn migrate_v4(evt: &IdbVersionChangeEvent) -> Result<(), JsValue> {
+    create_store_if_needed(evt, RatchetDB::SHADER_STORE)
+}
+
 impl RatchetDB {
-    pub const DB_VERSION: u32 = 1;
+    /// `MIGRATIONS[v]` upgrades the schema from version `v` to `v + 1`.
+    /// Append a migration to change the schema, never edit a released one.
+    const MIGRATIONS: [Migration; 4] = [migrate_v1, migrate_v2, migrate_v3, migrate_v4];
+    pub const DB_VERSION: u32 = Self::MIGRATIONS.len() as u32;
     pub const DB_NAME: &'static str = "ratchet";
     pub const MODEL_STORE: &'static str = "models";
     pub const TOKENIZER_STORE: &'static str = "tokenizers";
     pub const TENSOR_STORE: &'static str = "tensors";
     pub const TENSOR_INDEX: &'static str = "model_key";
+    pub const CHUNK_STORE: &'static str = "chunks";
+    pub const CHUNK_INDEX: &'static str = "model_key";
+    pub const DOWNLOAD_STORE: &'static str = "downloads";
+    pub const USAGE_STORE: &'static str = "usage";
+    pub const SHADER_STORE: &'static str = "shaders";
+    /// Fraction of the quota kept free when evicting.
+    pub const QUOTA_HEADROOM: f64 = 0.1;
 
     fn serialize(o: &impl Serialize) -> Result<JsValue> {
         serde_wasm_bindgen::to_value(o).map_err(|e| e.into())
@@ -59,29 +121,10 @@ impl RatchetDB {
         let mut db_req: OpenDbRequest = IdbDatabase::open_u32(Self::DB_NAME, Self::DB_VERSION)?;
 
         db_req.set_on_upgrade_needed(Some(|evt: &IdbVersionChangeEvent| -> Result<(), JsValue> {
-            let create_store_if_needed =
-                |evt: &IdbVersionChangeEvent, store_key: &'static str| -> Result<(), JsValue> {
-                    if let None = evt.db().object_store_names().find(|n| n == store_key) {
-                        evt.db().create_object_store(store_key)?;
-                    }
-                    Ok(())
-                };
-
-            let create_store_with_index_if_needed = |evt: &IdbVersionChangeEvent,
-                                                     store_key: &'static str,
-                                                     index_key: &'static str|
-             -> Result<(), JsValue> {
-                if let None = evt.db().object_store_names().find(|n| n == store_key) {
-                    let store = evt.db().create_object_store(store_key)?;
-                    store.create_index(index_key, &IdbKeyPath::str(index_key))?;
-                }
-                Ok(())
-            };
-
-            create_store_if_needed(evt, Self::MODEL_STORE)?;
-            create_store_if_needed(evt, Self::TOKENIZER_STORE)?;
-            create_store_with_index_if_needed(evt, Self::TENSOR_STORE, Self::TENSOR_INDEX)?;
-
+            let old_version = evt.old_version() as usize;
+            for migration in Self::MIGRATIONS.iter().skip(old_version) {
+                migration(evt)?;
+            }
             Ok(())
         }));
 
@@ -131,6 +174,11 @@ impl RatchetDB {
         let index = store.index(Self::TENSOR_INDEX)?;
         let matches = index.get_all_with_key(&Self::serialize(key)?)?.await?;
 
+        //Models stored before chunked downloads were saved tensor by tensor
+        if matches.length() == 0 {
+            return self.get_tensors_from_chunks(key, &header).await;
+        }
+
         let mut map = TensorMap::new();
         for record in matches {
             let record: TensorRecord = Self::deserialize(Some(record))?.unwrap();
@@ -141,20 +189,327 @@ impl RatchetDB {
         Ok(map)
     }
 
-    pub async fn put_tensor(&self, tensor: TensorRecord) -> Result<()> {
+    /// Assemble the tensors of a model from the chunks of its GGUF file.
+    async fn get_tensors_from_chunks(&self, key: &ModelKey, header: &Header) -> Result<TensorMap> {
+        let state = self
+            .get_download(key)
+            .await?
+            .filter(|s| s.verified)
+            .ok_or_else(|| RatchetDBError::IncompleteDownload(key.to_string()))?;
+
+        let mut tensors = header
+            .tensor_infos
+            .iter()
+            .map(|(name, info)| {
+                let range = info.byte_range(header.tensor_data_offset);
+                let bytes = Uint8Array::new_with_length((range.end - range.start) as u32);
+                (name, info, range, bytes)
+            })
+            .collect::<Vec<_>>();
+        tensors.sort_by_key(|(_, _, range, _)| range.start);
+        let data_start = tensors.first().map(|(_, _, r, _)| r.start).unwrap_or(0);
+
+        for index in 0..state.n_chunks() {
+            let chunk_range = state.chunk_range(index);
+            if chunk_range.end <= data_start {
+                continue;
+            }
+            let chunk = self
+                .get_chunk(key, index)
+                .await?
+                .ok_or_else(|| RatchetDBError::IncompleteDownload(key.to_string()))?;
+            for (_, _, range, bytes) in tensors.iter() {
+                let start = range.start.max(chunk_range.start);
+                let end = range.end.min(chunk_range.end);
+                if start >= end {
+                    continue;
+                }
+                let src = chunk.bytes.subarray(
+                    (start - chunk_range.start) as u32,
+                    (end - chunk_range.start) as u32,
+                );
+                bytes.set(&src, (start - range.start) as u32);
+            }
+        }
+
+        Ok(tensors
+            .into_iter()
+            .map(|(name, info, _, bytes)| {
+                let tensor = WebTensor::new(info.ggml_dtype, bytes, info.shape.clone());
+                (name.clone(), tensor)
+            })
+            .collect())
+    }
+
+    pub async fn get_download(&self, key: &ModelKey) -> Result<Option<DownloadState>> {
         let tx = self
             .inner
-            .transaction_on_one_with_mode(Self::TENSOR_STORE, IdbTransactionMode::Readwrite)?;
-        let store = tx.object_store(Self::TENSOR_STORE)?;
+            .transaction_on_one_with_mode(Self::DOWNLOAD_STORE, IdbTransactionMode::Readonly)?;
+        let store = tx.object_store(Self::DOWNLOAD_STORE)?;
+        let req = store.get(&Self::serialize(key)?)?.await?;
+        Self::deserialize(req)
+    }
+
+    pub async fn put_download(&self, key: &ModelKey, state: &DownloadState) -> Result<()> {
+        let tx = self
+            .inner
+            .transaction_on_one_with_mode(Self::DOWNLOAD_STORE, IdbTransactionMode::Readwrite)?;
+        let store = tx.object_store(Self::DOWNLOAD_STORE)?;
         store
+            .put_key_val(&Self::serialize(key)?, &Self::serialize(state)?)?
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_chunk(&self, key: &ModelKey, index: u64) -> Result<Option<ChunkRecord>> {
+        let tx = self
+            .inner
+            .transaction_on_one_with_mode(Self::CHUNK_STORE, IdbTransactionMode::Readonly)?;
+        let store = tx.object_store(Self::CHUNK_STORE)?;
+        let req = store
+            .get(&Self::serialize(&ChunkRecord::id(key, index))?)?
+            .await?;
+        Self::deserialize(req)
+    }
+
+    /// Store a chunk & the updated download state in a single transaction, so the state never
+    /// refers to a chunk that wasn't written.
+    pub async fn put_chunk(&self, chunk: ChunkRecord, state: &DownloadState) -> Result<()> {
+        let tx = self.inner.transaction_on_multi_with_mode(
+            &[Self::CHUNK_STORE, Self::DOWNLOAD_STORE],
+            IdbTransactionMode::Readwrite,
+        )?;
+        let chunks = tx.object_store(Self::CHUNK_STORE)?;
+        let downloads = tx.object_store(Self::DOWNLOAD_STORE)?;
+        chunks
+            .put_key_val(
+                &Self::serialize(&ChunkRecord::id(&chunk.model_key, chunk.index))?,
+                &Self::serialize(&chunk)?,
+            )?
+            .await?;
+        downloads
             .put_key_val(
-                &Self::serialize(&tensor.tensor_id)?,
-                &Self::serialize(&tensor)?,
+                &Self::serialize(&chunk.model_key)?,
+                &Self::serialize(state)?,
             )?
             .await?;
         Ok(())
     }
 
+    /// Delete every chunk & the download state of a model.
+    pub async fn delete_chunks(&self, key: &ModelKey) -> Result<()> {
+        let tx = self.inner.transaction_on_multi_with_mode(
+            &[Self::CHUNK_STORE, Self::DOWNLOAD_STORE],
+            IdbTransactionMode::Readwrite,
+        )?;
+        let serial_key = Self::serialize(key)?;
+        Self::delete_indexed(&tx, Self::CHUNK_STORE, Self::CHUNK_INDEX, &serial_key).await?;
+        let downloads = tx.object_store(Self::DOWNLOAD_STORE)?;
+        downloads.delete(&serial_key)?.await?;
+        Ok(())
+    }
+
+    /// Delete every record of `store_key` whose `index_key` matches `key`.
+    async fn delete_indexed(
+        tx: &IdbTransaction<'_>,
+        store_key: &str,
+        index_key: &str,
+        key: &JsValue,
+    ) -> Result<()> {
+        let store = tx.object_store(store_key)?;
+        let ids = store.index(index_key)?.get_all_keys_with_key(key)?.await?;
+        for id in ids {
+            store.delete(&id)?.await?;
+        }
+        Ok(())
+    }
+
+    /// Delete a model along with its tensors, chunks & usage.
+    pub async fn delete_model(&self, key: &ModelKey) -> Result<()> {
+        let tx = self.inner.transaction_on_multi_with_mode(
+            &[
+                Self::MODEL_STORE,
+                Self::TENSOR_STORE,
+                Self::CHUNK_STORE,
+                Self::DOWNLOAD_STORE,
+                Self::USAGE_STORE,
+            ],
+            IdbTransactionMode::Readwrite,
+        )?;
+        let serial_key = Self::serialize(key)?;
+        Self::delete_indexed(&tx, Self::TENSOR_STORE, Self::TENSOR_INDEX, &serial_key).await?;
+        Self::delete_indexed(&tx, Self::CHUNK_STORE, Self::CHUNK_INDEX, &serial_key).await?;
+        for store_key in [Self::MODEL_STORE, Self::DOWNLOAD_STORE, Self::USAGE_STORE] {
+            tx.object_store(store_key)?.delete(&serial_key)?.await?;
+        }
+        Ok(())
+    }
+
+    /// Record that a model was just used, see [RatchetDB::evict].
+    pub async fn touch(&self, key: &ModelKey) -> Result<()> {
+        let tx = self
+            .inner
+            .transaction_on_one_with_mode(Self::USAGE_STORE, IdbTransactionMode::Readwrite)?;
+        let store = tx.object_store(Self::USAGE_STORE)?;
+        let usage = UsageRecord {
+            last_used: js_sys::Date::now(),
+        };
+        store
+            .put_key_val(&Self::serialize(key)?, &Self::serialize(&usage)?)?
+            .await?;
+        Ok(())
+    }
+
+    /// Every cached shader source generated by this version of ratchet.
+    ///
+    /// Sources generated by other versions are never used again, and are deleted.
+    pub async fn get_shaders(&self) -> Result<Vec<(String, String)>> {
+        let tx = self
+            .inner
+            .transaction_on_one_with_mode(Self::SHADER_STORE, IdbTransactionMode::Readwrite)?;
+        let store = tx.object_store(Self::SHADER_STORE)?;
+        let mut shaders = vec![];
+        for record in store.get_all()?.await? {
+            let record: ShaderRecord = Self::deserialize(Some(record))?.unwrap();
+            if ShaderCache::is_current(&record.key) {
+                shaders.push((record.key, record.source));
+            } else {
+                store.delete(&JsValue::from_str(&record.key))?.await?;
+            }
+        }
+        Ok(shaders)
+    }
+
+    /// Store sources taken from [ShaderCache::take_pending].
+    pub async fn put_shaders(&self, shaders: &[(String, Arc<str>)]) -> Result<()> {
+        let tx = self
+            .inner
+            .transaction_on_one_with_mode(Self::SHADER_STORE, IdbTransactionMode::Readwrite)?;
+        let store = tx.object_store(Self::SHADER_STORE)?;
+        for (key, source) in shaders {
+            let record = ShaderRecord {
+                key: key.clone(),
+                source: source.to_string(),
+            };
+            store
+                .put_key_val(&JsValue::from_str(key), &Self::serialize(&record)?)?
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Every model in the database, including partial downloads.
+    pub async fn list_models(&self) -> Result<Vec<CachedModel>> {
+        let tx = self.inner.transaction_on_multi_with_mode(
+            &[Self::MODEL_STORE, Self::DOWNLOAD_STORE, Self::USAGE_STORE],
+            IdbTransactionMode::Readonly,
+        )?;
+        let models = tx.object_store(Self::MODEL_STORE)?;
+        let downloads = tx.object_store(Self::DOWNLOAD_STORE)?;
+        let usage = tx.object_store(Self::USAGE_STORE)?;
+
+        let usage = &usage;
+        let last_used = |key: JsValue| async move {
+            let record: Option<UsageRecord> = Self::deserialize(usage.get(&key)?.await?)?;
+            Ok::<_, RatchetDBError>(record.map(|u| u.last_used).unwrap_or(0.))
+        };
+
+        let mut cached = vec![];
+        for record in models.get_all()?.await? {
+            let record: ModelRecord = Self::deserialize(Some(record))?.unwrap();
+            let size = match record.size {
+                //Records written before v3 carry no size
+                0 => serde_wasm_bindgen::from_value::<Header>(record.header.clone())
+                    .map(|h| ModelRecord::file_size(&h))?,
+                size => size,
+            };
+            let serial_key = Self::serialize(&record.key)?;
+            cached.push(CachedModel {
+                last_used: last_used(serial_key).await?,
+                key: record.key,
+                model: Some(record.model),
+                size,
+                complete: true,
+            });
+        }
+
+        for serial_key in downloads.get_all_keys()?.await? {
+            let key: ModelKey = Self::deserialize(Some(serial_key.clone()))?.unwrap();
+            if cached.iter().any(|c| c.key == key) {
+                continue;
+            }
+            let state: DownloadState = Self::deserialize(downloads.get(&serial_key)?.await?)?
+                .ok_or_else(|| RatchetDBError::IncompleteDownload(key.to_string()))?;
+            cached.push(CachedModel {
+                last_used: last_used(serial_key).await?,
+                key,
+                model: None,
+                size: state.downloaded_bytes(),
+                complete: false,
+            });
+        }
+        Ok(cached)
+    }
+
+    /// Usage & quota of the origin, from `navigator.storage.estimate()`.
+    pub async fn storage_estimate() -> Result<StorageEstimate> {
+        let to_err = |e: JsValue| RatchetDBError::StorageEstimate(format!("{:?}", e));
+        //`navigator` exists on both windows & workers
+        let navigator = Reflect::get(&js_sys::global(), &"navigator".into()).map_err(to_err)?;
+        let storage = Reflect::get(&navigator, &"storage".into())
+            .map_err(to_err)?
+            .dyn_into::<web_sys::StorageManager>()
+            .map_err(to_err)?;
+        let estimate = JsFuture::from(storage.estimate().map_err(to_err)?)
+            .await
+            .map_err(to_err)?;
+        let get = |field: &str| -> Result<u64> {
+            Reflect::get(&estimate, &field.into())
+                .map_err(to_err)?
+                .as_f64()
+                .map(|v| v as u64)
+                .ok_or_else(|| RatchetDBError::StorageEstimate(format!("missing {}", field)))
+        };
+        Ok(StorageEstimate {
+            usage: get("usage")?,
+            quota: get("quota")?,
+        })
+    }
+
+    /// Evict least recently used models until `required` bytes fit within the quota.
+    ///
+    /// `keep` is never evicted, returns the evicted models.
+    pub async fn evict(&self, required: u64, keep: &ModelKey) -> Result<Vec<ModelKey>> {
+        let estimate = Self::storage_estimate().await?;
+        let headroom = (estimate.quota as f64 * Self::QUOTA_HEADROOM) as u64;
+        let needed = required + headroom;
+        let mut available = estimate.quota.saturating_sub(estimate.usage);
+        if available >= needed {
+            return Ok(vec![]);
+        }
+
+        let candidates = self.list_models().await?;
+        let mut evicted = vec![];
+        for candidate in select_evictions(candidates, keep, available, needed) {
+            log::warn!(
+                "Evicting {} ({} bytes) to free storage",
+                candidate.key,
+                candidate.size
+            );
+            self.delete_model(&candidate.key).await?;
+            available += candidate.size;
+            evicted.push(candidate.key);
+        }
+        if available < needed {
+            log::warn!(
+                "Insufficient storage: {} bytes required, {} available",
+                needed,
+                available
+            );
+        }
+        Ok(evicted)
+    }
+
     pub async fn get_tokenizer<S: AsRef<str>>(
         &self,
         repo_id: S,
@@ -186,8 +541,76 @@ impl RatchetDB {
     }
 }
 
+/// The least recently used models to evict, in order, until `needed` bytes are `available`.
+///
+/// `keep` is never selected, so the selection may fall short.
+fn select_evictions(
+    mut candidates: Vec<CachedModel>,
+    keep: &ModelKey,
+    mut available: u64,
+    needed: u64,
+) -> Vec<CachedModel> {
+    candidates.retain(|c| &c.key != keep);
+    candidates.sort_by(|a, b| a.last_used.total_cmp(&b.last_used));
+    candidates
+        .into_iter()
+        .take_while(|c| {
+            let selected = available < needed;
+            available += c.size;
+            selected
+        })
+        .collect()
+}
+
+/// A [ChunkSink] writing into the chunk store of [RatchetDB].
+pub struct DbChunkSink<'a> {
+    db: &'a RatchetDB,
+    key: ModelKey,
+}
+
+impl<'a> DbChunkSink<'a> {
+    pub fn new(db: &'a RatchetDB, key: ModelKey) -> Self {
+        Self { db, key }
+    }
+}
+
+impl From<RatchetDBError> for DownloadError {
+    fn from(e: RatchetDBError) -> Self {
+        DownloadError::Sink(e.to_string())
+    }
+}
+
+impl ChunkSink for DbChunkSink<'_> {
+    async fn write(
+        &mut self,
+        index: u64,
+        bytes: Vec<u8>,
+        state: &DownloadState,
+    ) -> Result<(), DownloadError> {
+        let chunk = ChunkRecord::new(self.key.clone(), index, Uint8Array::from(bytes.as_slice()));
+        Ok(self.db.put_chunk(chunk, state).await?)
+    }
+
+    async fn read(&self, index: u64) -> Result<Vec<u8>, DownloadError> {
+        let chunk = self
+            .db
+            .get_chunk(&self.key, index)
+            .await?
+            .ok_or_else(|| DownloadError::Sink(format!("missing chunk {}", index)))?;
+        Ok(chunk.bytes.to_vec())
+    }
+
+    async fn clear(&mut self) -> Result<(), DownloadError> {
+        Ok(self.db.delete_chunks(&self.key).await?)
+    }
+
+    async fn save_state(&mut self, state: &DownloadState) -> Result<(), DownloadError> {
+        Ok(self.db.put_download(&self.key, state).await?)
+    }
+}
+
 #[wasm_bindgen]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ModelKey {
     repo_id: String,
     model_id: String,
@@ -215,6 +638,12 @@ impl<'de> serde::Deserialize<'de> for ModelKey {
     }
 }
 
+impl std::fmt::Display for ModelKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.repo_id, self.model_id)
+    }
+}
+
 impl ModelKey {
     pub fn from_available(av: &AvailableModels, quant: Quantization) -> Self {
         ModelKey {
@@ -248,13 +677,56 @@ pub struct ModelRecord {
     pub model: AvailableModels,
     #[serde(with = "serde_wasm_bindgen::preserve")]
     pub header: JsValue,
+    /// Size of the model file in bytes, 0 for records written before v3.
+    #[serde(default)]
+    pub size: u64,
 }
 
 impl ModelRecord {
     pub fn new(key: ModelKey, model: AvailableModels, header: Header) -> Self {
+        let size = Self::file_size(&header);
         let header = serde_wasm_bindgen::to_value(&header).unwrap();
-        Self { key, model, header }
+        Self {
+            key,
+            model,
+            header,
+            size,
+        }
     }
+
+    pub fn file_size(header: &Header) -> u64 {
+        header
+            .tensor_infos
+            .values()
+            .fold(header.tensor_data_offset, |acc, ti| {
+                acc + ti.size_in_bytes() as u64
+            })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UsageRecord {
+    /// Milliseconds since the epoch.
+    pub last_used: f64,
+}
+
+/// A model stored in [RatchetDB], see [RatchetDB::list_models].
+#[derive(Debug, Clone, Serialize)]
+pub struct CachedModel {
+    pub key: ModelKey,
+    /// `None` for partial downloads.
+    pub model: Option<AvailableModels>,
+    /// Bytes stored.
+    pub size: u64,
+    /// Milliseconds since the epoch, 0 if never used.
+    pub last_used: f64,
+    pub complete: bool,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct StorageEstimate {
+    pub usage: u64,
+    pub quota: u64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -277,6 +749,29 @@ impl TensorRecord {
     }
 }
 
+/// A fixed size chunk of a model file, see [DownloadState::chunk_range].
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChunkRecord {
+    pub model_key: ModelKey,
+    pub index: u64,
+    #[serde(with = "serde_wasm_bindgen::preserve")]
+    pub bytes: Uint8Array,
+}
+
+impl ChunkRecord {
+    pub fn new(model_key: ModelKey, index: u64, bytes: Uint8Array) -> Self {
+        Self {
+            model_key,
+            index,
+            bytes,
+        }
+    }
+
+    fn id(model_key: &ModelKey, index: u64) -> String {
+        format!("{}:{}", model_key, index)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TokenizerRecord {
     pub repo_id: String,
@@ -284,3 +779,68 @@ pub struct TokenizerRecord {
     #[serde(with = "serde_wasm_bindgen::preserve")]
     pub bytes: Uint8Array,
 }
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ShaderRecord {
+    /// See [ShaderCache::key].
+    pub key: String,
+    pub source: String,
+}
+
+#[cfg(all(test, target_arch = "wasm32"))]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+
+    fn cached(model_id: &str, size: u64, last_used: f64) -> CachedModel {
+        CachedModel {
+            key: ModelKey::new("repo".to_string(), model_id.to_string()),
+            model: None,
+            size,
+            last_used,
+            complete: true,
+        }
+    }
+
+    fn selected(
+        candidates: Vec<CachedModel>,
+        keep: &str,
+        available: u64,
+        needed: u64,
+    ) -> Vec<String> {
+        let keep = ModelKey::new("repo".to_string(), keep.to_string());
+        select_evictions(candidates, &keep, available, needed)
+            .into_iter()
+            .map(|c| c.key.model_id())
+            .collect()
+    }
+
+    fn models() -> Vec<CachedModel> {
+        vec![
+            cached("recent", 100, 30.),
+            cached("never", 10, 0.),
+            cached("old", 50, 10.),
+            cached("kept", 1000, 5.),
+        ]
+    }
+
+    #[wasm_bindgen_test]
+    fn evicts_least_recently_used_first() {
+        assert_eq!(selected(models(), "kept", 0, 60), ["never", "old"]);
+        assert_eq!(
+            selected(models(), "kept", 0, 61),
+            ["never", "old", "recent"]
+        );
+        //Already enough space
+        assert!(selected(models(), "kept", 60, 60).is_empty());
+    }
+
+    #[wasm_bindgen_test]
+    fn never_evicts_kept_model() {
+        assert_eq!(
+            selected(models(), "kept", 0, 10_000),
+            ["never", "old", "recent"]
+        );
+        assert_eq!(selected(models(), "old", 0, 150), ["never", "kept"]);
+    }
+}
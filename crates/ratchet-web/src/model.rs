@@ -1,13 +1,18 @@
 use crate::db::*;
-use futures::stream::TryStreamExt;
-use futures::StreamExt;
+use ratchet::{Device, ShaderCache};
+use ratchet_hub::download::{self, DownloadOptions};
 use ratchet_hub::{Api, ApiBuilder, RepoType};
-use ratchet_loader::gguf::gguf::{self, Header, TensorInfo};
+use ratchet_loader::gguf::gguf::{self, Header};
+use ratchet_models::bert::Embedder;
+use ratchet_models::gemma::{self, Gemma};
+use ratchet_models::llama::{self, Llama};
 use ratchet_models::moondream::{self, Moondream};
 use ratchet_models::phi2;
 use ratchet_models::phi2::Phi2;
 use ratchet_models::phi3::{self, Phi3};
-use ratchet_models::registry::{AvailableModels, PhiVariants, Quantization};
+use ratchet_models::registry::{
+    AvailableModels, GemmaVariants, LlamaVariants, PhiVariants, Quantization,
+};
 use ratchet_models::whisper::{transcribe::transcribe, transcript::StreamedSegment, Whisper};
 use ratchet_models::TensorMap;
 use tokenizers::Tokenizer;
@@ -18,6 +23,15 @@ pub enum WebModel {
     Whisper(Whisper),
     Phi2(Phi2),
     Phi3(Phi3),
+    Llama {
+        model: Llama,
+        variant: LlamaVariants,
+    },
+    Gemma {
+        model: Gemma,
+        variant: GemmaVariants,
+    },
+    Embedder(Embedder),
     Moondream(Moondream),
 }
 
@@ -75,6 +89,53 @@ impl WebModel {
                     .unwrap();
                 Ok(JsValue::NULL)
             }
+            WebModel::Llama { model, variant } => {
+                let input: LlamaInputs = serde_wasm_bindgen::from_value(input)?;
+                let rs_callback = |output: String| {
+                    let _ = input.callback.call1(&JsValue::NULL, &output.into());
+                };
+                let prompt = input.prompt;
+
+                let model_repo =
+                    ApiBuilder::from_hf(variant.tokenizer_repo(), RepoType::Model).build();
+                let model_bytes = model_repo.get("tokenizer.json").await?;
+                let tokenizer = Tokenizer::from_bytes(model_bytes.to_vec()).unwrap();
+                llama::generate(model, tokenizer, prompt, rs_callback)
+                    .await
+                    .unwrap();
+                Ok(JsValue::NULL)
+            }
+            WebModel::Gemma { model, variant } => {
+                let input: LlamaInputs = serde_wasm_bindgen::from_value(input)?;
+                let rs_callback = |output: String| {
+                    let _ = input.callback.call1(&JsValue::NULL, &output.into());
+                };
+                let prompt = input.prompt;
+
+                let model_repo =
+                    ApiBuilder::from_hf(variant.tokenizer_repo(), RepoType::Model).build();
+                let model_bytes = model_repo.get("tokenizer.json").await?;
+                let tokenizer = Tokenizer::from_bytes(model_bytes.to_vec()).unwrap();
+                gemma::generate(model, tokenizer, prompt, rs_callback)
+                    .await
+                    .unwrap();
+                Ok(JsValue::NULL)
+            }
+            WebModel::Embedder(model) => {
+                let input: EmbedderInputs = serde_wasm_bindgen::from_value(input)?;
+                let to_js = |e: anyhow::Error| JsValue::from_str(&e.to_string());
+                let embeddings = model.embed(&input.texts).map_err(to_js)?;
+                let embeddings = embeddings
+                    .to(&Device::CPU)
+                    .await
+                    .map_err(|e| to_js(e.into()))?;
+                let flat = embeddings.to_vec::<f32>().map_err(to_js)?;
+                let result = flat
+                    .chunks(model.dim())
+                    .map(|e| e.to_vec())
+                    .collect::<Vec<_>>();
+                serde_wasm_bindgen::to_value(&result).map_err(|e| e.into())
+            }
             WebModel::Moondream(model) => {
                 let input: MoondreamInputs = serde_wasm_bindgen::from_value(input)?;
                 let rs_callback = |output: String| {
@@ -118,6 +179,26 @@ impl WebModel {
                     Ok(WebModel::Phi3(model))
                 }
             },
+            AvailableModels::Llama(variant) => {
+                let model = Llama::from_web(header, tensor_map).await?;
+                Ok(WebModel::Llama { model, variant })
+            }
+            AvailableModels::Gemma(variant) => {
+                let model = Gemma::from_web(header, tensor_map).await?;
+                Ok(WebModel::Gemma { model, variant })
+            }
+            AvailableModels::Embedder(variant) => {
+                let tokenizer_repo =
+                    ApiBuilder::from_hf(variant.tokenizer_repo(), RepoType::Model).build();
+                let tokenizer_bytes = tokenizer_repo
+                    .get("tokenizer.json")
+                    .await
+                    .map_err(|e| anyhow::anyhow!("{:?}", JsValue::from(e)))?;
+                let tokenizer = Tokenizer::from_bytes(tokenizer_bytes.to_vec())
+                    .map_err(|e| anyhow::anyhow!(e))?;
+                let model = Embedder::from_web(header, tensor_map, tokenizer).await?;
+                Ok(WebModel::Embedder(model))
+            }
             AvailableModels::Moondream => {
                 let model = Moondream::from_web(header, tensor_map).await?;
                 Ok(WebModel::Moondream(model))
@@ -143,6 +224,18 @@ pub struct PhiInputs {
     pub callback: js_sys::Function,
 }
 
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct LlamaInputs {
+    pub prompt: String,
+    #[serde(with = "serde_wasm_bindgen::preserve")]
+    pub callback: js_sys::Function,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct EmbedderInputs {
+    pub texts: Vec<String>,
+}
+
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct MoondreamInputs {
     pub question: String,
@@ -211,7 +304,15 @@ impl Model {
             let header: gguf::Header = serde_wasm_bindgen::from_value(
                 model_repo.fetch_gguf_header(&model_key.model_id()).await?,
             )?;
-            Self::fetch_tensors(&db, &model_repo, &header, model_key.clone(), progress).await?;
+            let required = ModelRecord::file_size(&header);
+            match db.evict(required, &model_key).await {
+                Ok(evicted) if !evicted.is_empty() => {
+                    log::warn!("Evicted {} models to fit {}", evicted.len(), model_key)
+                }
+                Ok(_) => {}
+                Err(e) => log::warn!("Skipping eviction: {}", e),
+            }
+            Self::fetch_model(&db, &model_repo, model_key.clone(), progress).await?;
             let model_record = ModelRecord::new(model_key.clone(), model.clone(), header);
             db.put_model(&model_key, model_record).await.map_err(|e| {
                 let e: JsError = e.into();
@@ -221,6 +322,13 @@ impl Model {
 
         let model_record = db.get_model(&model_key).await.unwrap().unwrap();
         let tensors = db.get_tensors(&model_key).await.unwrap();
+        if let Err(e) = db.touch(&model_key).await {
+            log::warn!("Failed to record usage of {}: {}", model_key, e);
+        }
+        match db.get_shaders().await {
+            Ok(shaders) => ShaderCache::global().extend(shaders),
+            Err(e) => log::warn!("Failed to load cached shaders: {}", e),
+        }
 
         Ok(WebModel::from_stored(model_record, tensors).await.unwrap())
     }
@@ -229,60 +337,84 @@ impl Model {
     ///
     /// Untyped input is required unfortunately.
     pub async fn run(&mut self, input: JsValue) -> Result<JsValue, JsValue> {
-        self.inner.run(input).await
+        let output = self.inner.run(input).await?;
+        Self::persist_shaders().await;
+        Ok(output)
+    }
+
+    /// Store the shaders generated by the first runs, so later loads skip generating them.
+    async fn persist_shaders() {
+        let shaders = ShaderCache::global().take_pending();
+        if shaders.is_empty() {
+            return;
+        }
+        let persisted = match RatchetDB::open().await {
+            Ok(db) => db.put_shaders(&shaders).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = persisted {
+            log::warn!("Failed to cache {} shaders: {}", shaders.len(), e);
+        }
+    }
+
+    /// Lists the models stored in the browser, with their size in bytes & last used time.
+    #[wasm_bindgen]
+    pub async fn cached() -> Result<JsValue, JsValue> {
+        let db = RatchetDB::open().await.map_err(JsError::from)?;
+        let models = db.list_models().await.map_err(JsError::from)?;
+        serde_wasm_bindgen::to_value(&models).map_err(|e| e.into())
     }
 
-    async fn fetch_tensors(
+    /// Deletes a stored model along with all of its tensors.
+    #[wasm_bindgen]
+    pub async fn delete_cached(key: ModelKey) -> Result<(), JsValue> {
+        let db = RatchetDB::open().await.map_err(JsError::from)?;
+        db.delete_model(&key).await.map_err(JsError::from)?;
+        Ok(())
+    }
+
+    /// Storage usage & quota of the origin, in bytes.
+    #[wasm_bindgen]
+    pub async fn storage_estimate() -> Result<JsValue, JsValue> {
+        let estimate = RatchetDB::storage_estimate().await.map_err(JsError::from)?;
+        serde_wasm_bindgen::to_value(&estimate).map_err(|e| e.into())
+    }
+
+    /// Download the model file into the chunk store, resuming any previous attempt.
+    async fn fetch_model(
         db: &RatchetDB,
         model_repo: &Api,
-        header: &Header,
         model_key: ModelKey,
         progress: &js_sys::Function,
     ) -> Result<(), JsValue> {
-        let model_id = model_key.model_id();
-        let data_offset = header.tensor_data_offset;
-        let content_len = header
-            .tensor_infos
-            .values()
-            .fold(0, |acc, ti| acc + ti.size_in_bytes());
-
-        let mut tensor_infos: Vec<(String, TensorInfo)> =
-            header.tensor_infos.clone().into_iter().collect();
-        tensor_infos.sort_by(|(_, a), (_, b)| b.size_in_bytes().cmp(&a.size_in_bytes()));
-
-        let tensor_stream = futures::stream::iter(tensor_infos);
-
-        let mut total_progress = 0.0;
-
-        tensor_stream
-            .map(|(name, info): (String, TensorInfo)| {
-                let model_id = model_id.clone();
-                let model_key = model_key.clone();
-                async move {
-                    let range = info.byte_range(data_offset);
-                    let bytes = model_repo
-                        .fetch_range(&model_id, range.start, range.end)
-                        .await
-                        .unwrap();
-                    let length = bytes.length();
-                    let record =
-                        TensorRecord::new(name.clone().to_string(), model_key.clone(), bytes);
-                    db.put_tensor(record).await.map_err(|e| {
-                        let e: JsError = e.into();
-                        Into::<JsValue>::into(e)
-                    });
-                    length
-                }
-            })
-            .buffer_unordered(6)
-            .map(|num_bytes| {
-                let req_progress = (num_bytes as f64) / (content_len as f64) * 100.0;
-                total_progress += req_progress;
-                let _ = progress.call1(&JsValue::NULL, &total_progress.into());
-            })
-            .collect::<()>()
-            .await;
+        let to_js = |e: RatchetDBError| {
+            let e: JsError = e.into();
+            Into::<JsValue>::into(e)
+        };
+        let state = db.get_download(&model_key).await.map_err(to_js)?;
+        if let Some(state) = state.as_ref() {
+            log::warn!(
+                "Resuming download of {} at {} bytes",
+                model_key,
+                state.downloaded_bytes()
+            );
+        }
 
+        let fetcher = model_repo.fetcher(&model_key.model_id());
+        let mut sink = DbChunkSink::new(db, model_key);
+        let report = |downloaded: u64, total: u64| {
+            let percent = (downloaded as f64) / (total as f64) * 100.0;
+            let _ = progress.call1(&JsValue::NULL, &percent.into());
+        };
+        download::download(
+            &fetcher,
+            &mut sink,
+            state,
+            &DownloadOptions::default(),
+            report,
+        )
+        .await
+        .map_err(|e| JsError::new(&e.to_string()))?;
         Ok(())
     }
 }